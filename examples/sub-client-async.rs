@@ -3,40 +3,31 @@ extern crate mqtt;
 extern crate log;
 extern crate clap;
 extern crate env_logger;
-extern crate futures;
+extern crate futures_util;
 extern crate tokio;
 extern crate uuid;
 
 use std::env;
-use std::fmt::Debug;
-use std::io::Write;
-use std::net;
+use std::net::ToSocketAddrs;
 use std::str;
-use std::time::{Duration, Instant};
 
 use clap::{App, Arg};
 
 use uuid::Uuid;
 
-use futures::{future, Future, Stream};
+use futures_util::{future, StreamExt};
 
-use tokio::io::{self, AsyncRead};
-use tokio::net::TcpStream;
-use tokio::timer::Interval;
+use tokio::runtime::Runtime;
 
-use mqtt::control::variable_header::ConnectReturnCode;
+use mqtt::client::AsyncClient;
 use mqtt::packet::*;
 use mqtt::TopicFilter;
-use mqtt::{Decodable, Encodable, QualityOfService};
+use mqtt::QualityOfService;
 
 fn generate_client_id() -> String {
     format!("/MQTT/rust/{}", Uuid::new_v4())
 }
 
-fn alt_drop<E: Debug>(err: E) {
-    warn!("{:?}", err);
-}
-
 fn main() {
     // configure logging
     env::set_var("RUST_LOG", env::var_os("RUST_LOG").unwrap_or_else(|| "info".into()));
@@ -92,102 +83,33 @@ fn main() {
 
     let keep_alive = 10;
 
+    let server_addr = server_addr.to_socket_addrs().unwrap().next().unwrap();
     info!("Connecting to {:?} ... ", server_addr);
-    let mut stream = net::TcpStream::connect(server_addr).unwrap();
-    info!("Connected!");
 
     info!("Client identifier {:?}", client_id);
     let mut conn = ConnectPacket::new("MQTT", client_id);
     conn.set_clean_session(true);
     conn.set_keep_alive(keep_alive);
-    let mut buf = Vec::new();
-    conn.encode(&mut buf).unwrap();
-    stream.write_all(&buf[..]).unwrap();
-
-    let connack = ConnackPacket::decode(&mut stream).unwrap();
-    trace!("CONNACK {:?}", connack);
 
-    if connack.connect_return_code() != ConnectReturnCode::ConnectionAccepted {
-        panic!(
-            "Failed to connect to server, return code {:?}",
-            connack.connect_return_code()
-        );
-    }
+    // The client sends PINGREQ by itself and connects again if the connection is lost
+    let (client, messages) = AsyncClient::connect(server_addr, conn);
+    let runtime = Runtime::new().unwrap();
 
-    // const CHANNEL_FILTER: &'static str = "typing-speed-test.aoeu.eu";
     info!("Applying channel filters {:?} ...", channel_filters);
-    let sub = SubscribePacket::new(10, channel_filters);
-    let mut buf = Vec::new();
-    sub.encode(&mut buf).unwrap();
-    stream.write_all(&buf[..]).unwrap();
-
-    loop {
-        let packet = match VariablePacket::decode(&mut stream) {
-            Ok(pk) => pk,
-            Err(err) => {
-                error!("Error in receiving packet {:?}", err);
-                continue;
-            }
-        };
-        trace!("PACKET {:?}", packet);
-
-        if let VariablePacket::SubackPacket(ref ack) = packet {
-            if ack.packet_identifier() != 10 {
-                panic!("SUBACK packet identifier not match");
-            }
-
-            info!("Subscribed!");
-            break;
+    let codes = runtime.block_on(client.subscribe(channel_filters)).unwrap();
+    trace!("SUBACK {:?}", codes);
+    info!("Subscribed!");
+
+    let receiver = messages.for_each(|message| {
+        match message {
+            Ok(publ) => match str::from_utf8(&publ.payload_ref()[..]) {
+                Ok(msg) => info!("PUBLISH ({}): {}", publ.topic_name(), msg),
+                Err(err) => error!("Failed to decode publish message {:?}", err),
+            },
+            Err(err) => error!("Error in receiving packet {:?}", err),
         }
-    }
-
-    // connection made, start the async work
-    let program = future::ok(()).and_then(move |()| {
-        let stream = TcpStream::from_std(stream, &Default::default()).unwrap();
-        let (mqtt_read, mqtt_write) = stream.split();
-
-        let ping_time = Duration::new((keep_alive / 2) as u64, 0);
-        let ping_stream = Interval::new(Instant::now() + ping_time, ping_time);
-
-        let ping_sender = ping_stream.map_err(alt_drop).fold(mqtt_write, |mqtt_write, _| {
-            info!("Sending PINGREQ to broker");
-
-            let pingreq_packet = PingreqPacket::new();
-
-            let mut buf = Vec::new();
-            pingreq_packet.encode(&mut buf).unwrap();
-            io::write_all(mqtt_write, buf)
-                .map(|(mqtt_write, _buf)| mqtt_write)
-                .map_err(alt_drop)
-        });
-
-        let receiver = future::loop_fn::<_, (), _, _>(mqtt_read, |mqtt_read| {
-            VariablePacket::parse(mqtt_read).map(|(mqtt_read, packet)| {
-                trace!("PACKET {:?}", packet);
-
-                match packet {
-                    VariablePacket::PingrespPacket(..) => {
-                        info!("Receiving PINGRESP from broker ..");
-                    }
-                    VariablePacket::PublishPacket(ref publ) => {
-                        let msg = match str::from_utf8(&publ.payload_ref()[..]) {
-                            Ok(msg) => msg,
-                            Err(err) => {
-                                error!("Failed to decode publish message {:?}", err);
-                                return future::Loop::Continue(mqtt_read);
-                            }
-                        };
-                        info!("PUBLISH ({}): {}", publ.topic_name(), msg);
-                    }
-                    _ => {}
-                }
-
-                future::Loop::Continue(mqtt_read)
-            })
-        }).map_err(alt_drop);
-
-        ping_sender.join(receiver).map(alt_drop)
+        future::ready(())
     });
 
-    tokio::run(program);
+    runtime.block_on(receiver);
 }
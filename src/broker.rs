@@ -0,0 +1,880 @@
+//! Minimal in-process broker for tests
+//!
+//! `Broker` listens on a TCP port and serves every client on its own thread with a
+//! `ServerSession`. It supports QoS 0, 1 and 2, retained messages and wills. A client connecting
+//! without clean session resumes its subscriptions and unacknowledged messages from a
+//! `SessionStore`, kept in memory unless another one is given to `Broker::with_store`. Messages
+//! published while such a client is disconnected are not kept for it. `BrokerOptions` bound what
+//! a client may send and how long it may take to send its `CONNECT`.
+//!
+//! Connections are served with blocking threads instead of the futures-based `parse` helpers, so
+//! the broker needs no executor and can be used from a plain `#[test]`. Packets for a client are
+//! queued to a writer thread of its own, so a slow subscriber never holds up the others. Topic
+//! filters are kept in a `TopicTrie`, which finds the subscriptions matching a topic name in one
+//! walk of its levels, where `TopicFilterMatcher` would have to test every filter in turn.
+//!
+//! ```rust
+//! use std::net::TcpStream;
+//!
+//! use mqtt::{Decodable, Encodable};
+//! use mqtt::broker::Broker;
+//! use mqtt::packet::{ConnectPacket, VariablePacket};
+//!
+//! let broker = Broker::bind("127.0.0.1:0").unwrap();
+//!
+//! let mut stream = TcpStream::connect(broker.local_addr()).unwrap();
+//! ConnectPacket::new("MQTT", "client").encode(&mut stream).unwrap();
+//! match VariablePacket::decode(&mut stream).unwrap() {
+//!     VariablePacket::ConnackPacket(..) => {}
+//!     packet => panic!("unexpected {:?}", packet),
+//! }
+//!
+//! broker.shutdown();
+//! ```
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use decoder::{DecodeLimits, Decoded, PacketDecoder};
+use encodable::Encodable;
+use packet::{ConnectPacket, Packet, PublishPacket, QoSWithPacketIdentifier, SubscribePacket, UnsubscribePacket,
+             VariablePacket, VariablePacketError};
+use packet::suback::SubscribeReturnCode;
+use qos::QualityOfService;
+use retained::RetainedStore;
+use session::{MemorySessionStore, ServerEvent, ServerSession, SessionStore};
+use topic_filter::{TopicFilter, TopicFilterRef};
+use topic_name::TopicNameRef;
+use topic_trie::TopicTrie;
+
+type ConnectionId = u64;
+
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Limits applied to the clients of a `Broker`
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct BrokerOptions {
+    /// Limits of the packets received from a client, which is disconnected if one is exceeded
+    pub limits: DecodeLimits,
+    /// Time a client has to send its `CONNECT` after opening the connection
+    pub connect_timeout: Duration,
+}
+
+impl Default for BrokerOptions {
+    fn default() -> BrokerOptions {
+        BrokerOptions {
+            limits: DecodeLimits::default(),
+            connect_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// MQTT 3.1.1 broker running on background threads
+///
+/// Stopped by `shutdown` or when dropped.
+#[derive(Debug)]
+pub struct Broker {
+    local_addr: SocketAddr,
+    shared: Arc<Shared>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl Broker {
+    /// Listens on `addr`, such as "127.0.0.1:0" for any free port
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Broker> {
+        Broker::with_store(addr, MemorySessionStore::new())
+    }
+
+    /// Listens on `addr`, keeping the sessions of the clients in `store`
+    ///
+    /// With a `FileSessionStore` the sessions survive a restart of the broker.
+    pub fn with_store<A, S>(addr: A, store: S) -> io::Result<Broker>
+    where
+        A: ToSocketAddrs,
+        S: SessionStore + Send + 'static,
+    {
+        Broker::with_options(addr, store, BrokerOptions::default())
+    }
+
+    /// Listens on `addr`, keeping the sessions in `store` and serving clients with `options`
+    pub fn with_options<A, S>(addr: A, store: S, options: BrokerOptions) -> io::Result<Broker>
+    where
+        A: ToSocketAddrs,
+        S: SessionStore + Send + 'static,
+    {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+                                  options,
+                                  shutdown: AtomicBool::new(false),
+                                  state: Mutex::new(State::default()),
+                                  store: Arc::new(Mutex::new(Box::new(store))),
+                              });
+
+        let acceptor = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("mqtt-broker".to_owned())
+                .spawn(move || accept(&shared, &listener))?
+        };
+
+        Ok(Broker {
+               local_addr,
+               shared,
+               acceptor: Some(acceptor),
+           })
+    }
+
+    /// Address the broker listens on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Closes all connections and waits for the broker threads to finish
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        let acceptor = match self.acceptor.take() {
+            Some(acceptor) => acceptor,
+            None => return,
+        };
+
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        // Wakes the acceptor up from `accept`
+        let _ = TcpStream::connect(self.local_addr);
+        let _ = acceptor.join();
+    }
+}
+
+impl Drop for Broker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+type Store = Arc<Mutex<Box<dyn SessionStore + Send>>>;
+
+// Locked in this order: the state, then a connection, then the store
+#[derive(Debug)]
+struct Shared {
+    options: BrokerOptions,
+    shutdown: AtomicBool,
+    state: Mutex<State>,
+    store: Store,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_id: ConnectionId,
+    connections: HashMap<ConnectionId, Entry>,
+    // Subscribed connections and their maximum QoS, by topic filter
+    subscriptions: TopicTrie<HashMap<ConnectionId, QualityOfService>>,
+    retained: RetainedStore,
+}
+
+#[derive(Debug)]
+struct Entry {
+    client_id: Option<String>,
+    filters: HashSet<TopicFilter>,
+    // Kept outside of the connection lock to close it from any thread
+    stream: TcpStream,
+    connection: Arc<Mutex<Connection>>,
+}
+
+#[derive(Debug)]
+struct Connection {
+    session: ServerSession,
+    // Encoded packets for the writer thread of the connection
+    outgoing: Sender<Vec<u8>>,
+    store: Store,
+    // Client identifier the session is saved under, unless it is clean
+    saved_as: Option<String>,
+}
+
+impl Connection {
+    /// Accepts the `CONNECT`, resuming the saved session unless it asks for a clean session
+    ///
+    /// Returns the subscriptions of the resumed session.
+    fn accept(&mut self, connect: &ConnectPacket) -> Vec<(TopicFilter, QualityOfService)> {
+        let client_id = connect.client_identifier();
+        let mut store = self.store.lock().unwrap();
+        if connect.clean_session() {
+            self.saved_as = None;
+            let _ = store.remove(client_id);
+            self.session.accept(false);
+            return Vec::new();
+        }
+
+        self.saved_as = Some(client_id.to_owned());
+        self.session.clear();
+        let stored = match store.load(client_id) {
+            Ok(Some(stored)) => stored,
+            _ => {
+                self.session.accept(false);
+                return Vec::new();
+            }
+        };
+        if self.session.restore(stored.in_flight).is_err() {
+            // Not a session this broker could have saved
+            self.session.clear();
+            let _ = store.remove(client_id);
+            self.session.accept(false);
+            return Vec::new();
+        }
+        self.session.restore_received(stored.received);
+        self.session.accept(true);
+        self.session.resend();
+        stored.subscriptions
+    }
+
+    /// Sends a message to the client, saving it until acknowledged
+    fn publish(&mut self, message: PublishPacket) {
+        let mut saved = message.clone();
+        if let Ok(Some(pkid)) = self.session.publish(message) {
+            saved.set_qos(QoSWithPacketIdentifier::new(QualityOfService::from(saved.qos()), pkid));
+            saved.set_dup(false);
+            self.save(|store, client_id| store.save_publish(client_id, &saved));
+        }
+    }
+
+    /// Saves a change of the session, unless it is clean
+    fn save<F>(&self, change: F)
+    where
+        F: FnOnce(&mut dyn SessionStore, &str) -> io::Result<()>,
+    {
+        if let Some(ref client_id) = self.saved_as {
+            // A failure only loses the session if the broker restarts
+            let _ = change(&mut **self.store.lock().unwrap(), client_id);
+        }
+    }
+
+    /// Queues the packets to send and saves the changes of the session, returning the other events
+    ///
+    /// Never blocks, so it may be called while holding the state lock.
+    fn flush(&mut self) -> Vec<ServerEvent> {
+        let mut events = Vec::new();
+        while let Some(event) = self.session.poll_event() {
+            match event {
+                ServerEvent::Send(packet) => {
+                    let mut buf = Vec::with_capacity(packet.encoded_length() as usize);
+                    // A failed write shows up as a read error on the connection thread
+                    if packet.encode(&mut buf).is_ok() {
+                        let _ = self.outgoing.send(buf);
+                    }
+                }
+                ServerEvent::Published(pkid) => self.save(|store, client_id| store.remove_in_flight(client_id, pkid)),
+                ServerEvent::Released(pkid) => self.save(|store, client_id| store.save_pubrel(client_id, pkid)),
+                ServerEvent::Received(pkid) => self.save(|store, client_id| store.save_received(client_id, pkid)),
+                ServerEvent::ReceiveComplete(pkid) => {
+                    self.save(|store, client_id| store.remove_received(client_id, pkid))
+                }
+                event => events.push(event),
+            }
+        }
+        events
+    }
+}
+
+fn accept(shared: &Arc<Shared>, listener: &TcpListener) {
+    let mut handles: Vec<JoinHandle<()>> = Vec::new();
+
+    for stream in listener.incoming() {
+        if shared.shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(..) => continue,
+        };
+
+        let (sender, outgoing) = mpsc::channel();
+        let (id, connection) = match register(shared, &stream, sender) {
+            Ok(registered) => registered,
+            Err(..) => continue,
+        };
+        let shared = shared.clone();
+        let handle = thread::Builder::new()
+            .name(format!("mqtt-broker-{}", id))
+            .spawn(move || run(&shared, id, connection, outgoing, stream));
+        if let Ok(handle) = handle {
+            handles.retain(|handle| !handle.is_finished());
+            handles.push(handle);
+        }
+    }
+
+    for entry in shared.state.lock().unwrap().connections.values() {
+        let _ = entry.stream.shutdown(Shutdown::Both);
+    }
+    for handle in handles {
+        let _ = handle.join();
+    }
+}
+
+fn register(shared: &Shared,
+            stream: &TcpStream,
+            outgoing: Sender<Vec<u8>>)
+            -> io::Result<(ConnectionId, Arc<Mutex<Connection>>)> {
+    let connection = Arc::new(Mutex::new(Connection {
+                                             session: ServerSession::new(),
+                                             outgoing,
+                                             store: shared.store.clone(),
+                                             saved_as: None,
+                                         }));
+
+    let mut state = shared.state.lock().unwrap();
+    let id = state.next_id;
+    state.next_id += 1;
+    state.connections.insert(id,
+                             Entry {
+                                 client_id: None,
+                                 filters: HashSet::new(),
+                                 stream: stream.try_clone()?,
+                                 connection: connection.clone(),
+                             });
+    Ok((id, connection))
+}
+
+fn run(shared: &Shared,
+       id: ConnectionId,
+       connection: Arc<Mutex<Connection>>,
+       outgoing: Receiver<Vec<u8>>,
+       stream: TcpStream) {
+    let writer = stream.try_clone().and_then(|writer| {
+        thread::Builder::new()
+            .name(format!("mqtt-broker-{}-writer", id))
+            .spawn(move || write(writer, &outgoing))
+    });
+    if writer.is_ok() {
+        serve(shared, id, &connection, &stream);
+    }
+
+    {
+        let mut state = shared.state.lock().unwrap();
+        if let Some(entry) = state.connections.remove(&id) {
+            for filter in entry.filters {
+                remove_subscription(&mut state, id, &filter);
+            }
+        }
+    }
+
+    // The queue ends with the last `Connection`, once the writer has sent what is left in it
+    drop(connection);
+    if let Ok(writer) = writer {
+        let _ = writer.join();
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+fn write(mut stream: TcpStream, outgoing: &Receiver<Vec<u8>>) {
+    // A client that stops reading is disconnected instead of blocking its writer forever
+    let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+    for buf in outgoing {
+        if stream.write_all(&buf).is_err() {
+            // Ends the connection thread as well, which is blocked on reading
+            let _ = stream.shutdown(Shutdown::Both);
+            break;
+        }
+    }
+}
+
+fn serve(shared: &Shared, id: ConnectionId, connection: &Mutex<Connection>, stream: &TcpStream) {
+    let mut decoder: PacketDecoder = PacketDecoder::with_limits(shared.options.limits);
+    let connect_deadline = Instant::now() + shared.options.connect_timeout;
+
+    loop {
+        let deadline = {
+            let connection = connection.lock().unwrap();
+            if connection.session.is_connected() {
+                connection.session.next_timeout()
+            } else {
+                Some(connect_deadline)
+            }
+        };
+        let received = receive(&mut decoder, stream, deadline);
+
+        let mut closed = received.is_err();
+        let events = {
+            let mut connection = connection.lock().unwrap();
+            match received {
+                Ok(packet) => {
+                    let _ = connection.session.handle(packet, Instant::now());
+                }
+                Err(..) => {
+                    // Either the keep alive deadline passed or the connection is gone
+                    connection.session.handle_timeout(Instant::now());
+                    if connection.session.is_connected() {
+                        connection.session.connection_lost();
+                    }
+                }
+            }
+            connection.flush()
+        };
+
+        for event in events {
+            match event {
+                ServerEvent::Connect(connect) => {
+                    let mut state = shared.state.lock().unwrap();
+                    connect_client(&mut state, id, connect.client_identifier());
+                    let mut connection = connection.lock().unwrap();
+                    for (filter, qos) in connection.accept(&connect) {
+                        add_subscription(&mut state, id, &filter, qos);
+                    }
+                    connection.flush();
+                }
+                ServerEvent::Publish(publish) => route(&mut shared.state.lock().unwrap(), publish),
+                ServerEvent::Subscribe(subscribe) => {
+                    subscribe_client(&mut shared.state.lock().unwrap(), id, connection, &subscribe)
+                }
+                ServerEvent::Unsubscribe(unsubscribe) => {
+                    unsubscribe_client(&mut shared.state.lock().unwrap(), id, connection, &unsubscribe)
+                }
+                ServerEvent::Close(..) => closed = true,
+                // Handled by `Connection::flush`
+                ServerEvent::Send(..) |
+                ServerEvent::Published(..) |
+                ServerEvent::Released(..) |
+                ServerEvent::Received(..) |
+                ServerEvent::ReceiveComplete(..) => {}
+            }
+        }
+
+        if closed {
+            break;
+        }
+    }
+}
+
+/// Reads from `stream` until `decoder` has a packet, failing once `deadline` passes
+fn receive(decoder: &mut PacketDecoder, mut stream: &TcpStream, deadline: Option<Instant>)
+           -> Result<VariablePacket, VariablePacketError> {
+    let mut buf = [0u8; 4096];
+    loop {
+        if let Decoded::Packet(packet) = decoder.decode()? {
+            return Ok(packet);
+        }
+
+        let timeout = deadline.map(|deadline| {
+            // A zero timeout is rejected by `set_read_timeout`
+            cmp::max(deadline.saturating_duration_since(Instant::now()), Duration::from_millis(1))
+        });
+        stream.set_read_timeout(timeout)?;
+        let len = stream.read(&mut buf)?;
+        if len == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        decoder.feed(&buf[..len]);
+    }
+}
+
+fn connect_client(state: &mut State, id: ConnectionId, client_id: &str) {
+    // If the ClientId represents a Client already connected to the Server then the Server MUST
+    // disconnect the existing Client [MQTT-3.1.4-2].
+    if !client_id.is_empty() {
+        for entry in state.connections.values() {
+            if entry.client_id.as_ref().is_some_and(|other| other == client_id) {
+                let _ = entry.stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+    if let Some(entry) = state.connections.get_mut(&id) {
+        entry.client_id = Some(client_id.to_owned());
+    }
+}
+
+fn subscribe_client(state: &mut State, id: ConnectionId, connection: &Mutex<Connection>, subscribe: &SubscribePacket) {
+    let mut return_codes = Vec::new();
+    let mut retained = Vec::new();
+
+    let mut connection = connection.lock().unwrap();
+    for &(ref filter, qos) in subscribe.payload_ref().subscribes() {
+        add_subscription(state, id, filter, qos);
+        connection.save(|store, client_id| store.save_subscription(client_id, filter, qos));
+        return_codes.push(SubscribeReturnCode::from(qos));
+
+        retained.extend(state.retained.matches(filter).map(|message| with_qos(message, qos, true)));
+    }
+
+    connection.session.suback(subscribe.packet_identifier(), return_codes);
+    for message in retained {
+        connection.publish(message);
+    }
+    connection.flush();
+}
+
+fn unsubscribe_client(state: &mut State,
+                      id: ConnectionId,
+                      connection: &Mutex<Connection>,
+                      unsubscribe: &UnsubscribePacket) {
+    let connection = connection.lock().unwrap();
+    for filter in unsubscribe.payload_ref().subscribes() {
+        if let Some(entry) = state.connections.get_mut(&id) {
+            entry.filters.remove(filter);
+        }
+        remove_subscription(state, id, filter);
+        connection.save(|store, client_id| store.remove_subscription(client_id, filter));
+    }
+}
+
+fn add_subscription(state: &mut State, id: ConnectionId, filter: &TopicFilter, qos: QualityOfService) {
+    if state.subscriptions.get(filter).is_none() {
+        state.subscriptions.insert(filter, HashMap::new());
+    }
+    if let Some(subscribers) = state.subscriptions.get_mut(filter) {
+        subscribers.insert(id, qos);
+    }
+    if let Some(entry) = state.connections.get_mut(&id) {
+        entry.filters.insert(filter.clone());
+    }
+}
+
+fn remove_subscription(state: &mut State, id: ConnectionId, filter: &TopicFilterRef) {
+    let empty = match state.subscriptions.get_mut(filter) {
+        Some(subscribers) => {
+            subscribers.remove(&id);
+            subscribers.is_empty()
+        }
+        None => false,
+    };
+    if empty {
+        state.subscriptions.remove(filter);
+    }
+}
+
+/// Publishes a message from a client to the subscribers
+///
+/// A message without a valid topic name is dropped.
+fn route(state: &mut State, message: PublishPacket) {
+    let topic_name = match TopicNameRef::new(message.topic_name()) {
+        Ok(topic_name) => topic_name,
+        Err(..) => return,
+    };
+    if message.retain() {
+        state.retained.insert(message.clone());
+    }
+
+    // Overlapping subscriptions deliver the message once, with their maximum QoS
+    let mut subscribers: HashMap<ConnectionId, QualityOfService> = HashMap::new();
+    for matched in state.subscriptions.matches(topic_name) {
+        for (&id, &qos) in matched {
+            let max = subscribers.entry(id).or_insert(qos);
+            *max = cmp::max(*max, qos);
+        }
+    }
+
+    for (id, qos) in subscribers {
+        if let Some(entry) = state.connections.get(&id) {
+            let mut connection = entry.connection.lock().unwrap();
+            // Subscribers get the retain flag cleared [MQTT-3.3.1-9]
+            connection.publish(with_qos(&message, qos, false));
+            connection.flush();
+        }
+    }
+}
+
+fn with_qos(message: &PublishPacket, max_qos: QualityOfService, retain: bool) -> PublishPacket {
+    let qos = cmp::min(QualityOfService::from(message.qos()), max_qos);
+    let mut message = message.clone();
+    // The packet identifier is assigned by the session of the subscriber
+    message.set_qos(QoSWithPacketIdentifier::new(qos, 0));
+    message.set_retain(retain);
+    message
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use Decodable;
+    use control::variable_header::ConnectReturnCode;
+    use packet::{ConnackPacket, ConnectPacket, LastWill, PingreqPacket, PingrespPacket, PubackPacket, PubcompPacket,
+                 PubrecPacket, PubrelPacket, SubackPacket, UnsubackPacket};
+    use session::FileSessionStore;
+    use topic_name::TopicName;
+
+    fn connect(broker: &Broker, connect: ConnectPacket) -> TcpStream {
+        let mut stream = TcpStream::connect(broker.local_addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        send(&mut stream, connect);
+        match receive(&mut stream) {
+            VariablePacket::ConnackPacket(..) => stream,
+            packet => panic!("unexpected {:?}", packet),
+        }
+    }
+
+    fn send<P>(stream: &mut TcpStream, packet: P)
+    where
+        VariablePacket: From<P>,
+    {
+        VariablePacket::new(packet).encode(stream).unwrap();
+    }
+
+    fn receive(stream: &mut TcpStream) -> VariablePacket {
+        VariablePacket::decode(stream).unwrap()
+    }
+
+    fn subscribe(stream: &mut TcpStream, filter: &str, qos: QualityOfService) {
+        send(stream, SubscribePacket::new(1, vec![(TopicFilter::new(filter).unwrap(), qos)]));
+        assert_eq!(receive(stream),
+                   VariablePacket::new(SubackPacket::new(1, vec![SubscribeReturnCode::from(qos)])));
+    }
+
+    fn message(topic_name: &str, qos: QoSWithPacketIdentifier, payload: &str) -> PublishPacket {
+        PublishPacket::new(TopicName::new(topic_name).unwrap(), qos, payload)
+    }
+
+    fn resume(broker: &Broker, client_id: &str, session_present: bool) -> TcpStream {
+        let mut stream = TcpStream::connect(broker.local_addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut packet = ConnectPacket::new("MQTT", client_id);
+        packet.set_clean_session(false);
+        send(&mut stream, packet);
+        assert_eq!(receive(&mut stream),
+                   VariablePacket::new(ConnackPacket::new(session_present, ConnectReturnCode::ConnectionAccepted)));
+        stream
+    }
+
+    #[test]
+    fn test_broker_publish_qos() {
+        let broker = Broker::bind("127.0.0.1:0").unwrap();
+        let mut subscriber = connect(&broker, ConnectPacket::new("MQTT", "subscriber"));
+        let mut publisher = connect(&broker, ConnectPacket::new("MQTT", "publisher"));
+        subscribe(&mut subscriber, "a/+", QualityOfService::Level1);
+
+        send(&mut publisher, message("a/b", QoSWithPacketIdentifier::Level2(7), "two"));
+        assert_eq!(receive(&mut publisher), VariablePacket::new(PubrecPacket::new(7)));
+        send(&mut publisher, PubrelPacket::new(7));
+        assert_eq!(receive(&mut publisher), VariablePacket::new(PubcompPacket::new(7)));
+
+        // Downgraded to the QoS of the subscription
+        assert_eq!(receive(&mut subscriber),
+                   VariablePacket::new(message("a/b", QoSWithPacketIdentifier::Level1(1), "two")));
+        send(&mut subscriber, PubackPacket::new(1));
+
+        send(&mut publisher, message("a/b/c", QoSWithPacketIdentifier::Level1(8), "unmatched"));
+        assert_eq!(receive(&mut publisher), VariablePacket::new(PubackPacket::new(8)));
+        send(&mut publisher, message("a/c", QoSWithPacketIdentifier::Level0, "zero"));
+        assert_eq!(receive(&mut subscriber),
+                   VariablePacket::new(message("a/c", QoSWithPacketIdentifier::Level0, "zero")));
+
+        send(&mut subscriber, UnsubscribePacket::new(2, vec![TopicFilter::new("a/+").unwrap()]));
+        assert_eq!(receive(&mut subscriber), VariablePacket::new(UnsubackPacket::new(2)));
+
+        broker.shutdown();
+    }
+
+    // Whether the broker closed the connection, rather than the read timing out
+    fn closed(stream: &mut TcpStream) -> bool {
+        match stream.read(&mut [0u8; 1]) {
+            Ok(len) => len == 0,
+            Err(err) => err.kind() != io::ErrorKind::WouldBlock && err.kind() != io::ErrorKind::TimedOut,
+        }
+    }
+
+    #[test]
+    fn test_broker_options() {
+        let options = BrokerOptions {
+            limits: DecodeLimits { max_packet_size: 64, ..DecodeLimits::default() },
+            connect_timeout: Duration::from_millis(100),
+        };
+        let broker = Broker::with_options("127.0.0.1:0", MemorySessionStore::new(), options).unwrap();
+
+        let mut idle = TcpStream::connect(broker.local_addr()).unwrap();
+        idle.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert!(closed(&mut idle));
+
+        let mut subscriber = connect(&broker, ConnectPacket::new("MQTT", "subscriber"));
+        let mut publisher = connect(&broker, ConnectPacket::new("MQTT", "publisher"));
+        subscribe(&mut subscriber, "a/b", QualityOfService::Level0);
+        send(&mut publisher, message("a/b", QoSWithPacketIdentifier::Level0, &"x".repeat(100)));
+        assert!(closed(&mut publisher));
+
+        // The connect timeout no longer applies once connected
+        thread::sleep(Duration::from_millis(200));
+        send(&mut subscriber, PingreqPacket::new());
+        assert_eq!(receive(&mut subscriber), VariablePacket::new(PingrespPacket::new()));
+    }
+
+    #[test]
+    fn test_broker_slow_subscriber() {
+        let broker = Broker::bind("127.0.0.1:0").unwrap();
+        // Never reads, so its socket buffers fill up
+        let mut stalled = connect(&broker, ConnectPacket::new("MQTT", "stalled"));
+        subscribe(&mut stalled, "a", QualityOfService::Level0);
+        let mut subscriber = connect(&broker, ConnectPacket::new("MQTT", "subscriber"));
+        subscribe(&mut subscriber, "a", QualityOfService::Level0);
+        let mut publisher = connect(&broker, ConnectPacket::new("MQTT", "publisher"));
+
+        let payload = vec![0u8; 64 * 1024];
+        let message = PublishPacket::new(TopicName::new("a").unwrap(),
+                                         QoSWithPacketIdentifier::Level0,
+                                         payload.clone());
+        for _ in 0..256 {
+            send(&mut publisher, message.clone());
+        }
+        for _ in 0..256 {
+            match receive(&mut subscriber) {
+                VariablePacket::PublishPacket(ref publish) => assert_eq!(publish.payload_ref().len(), payload.len()),
+                packet => panic!("unexpected {:?}", packet),
+            }
+        }
+
+        broker.shutdown();
+    }
+
+    #[test]
+    fn test_broker_unacceptable_protocol_version() {
+        let broker = Broker::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(broker.local_addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        send(&mut stream, ConnectPacket::with_level("MQTT", "client", 3));
+        assert_eq!(receive(&mut stream),
+                   VariablePacket::new(ConnackPacket::new(false, ConnectReturnCode::UnacceptableProtocolVersion)));
+        assert!(VariablePacket::decode(&mut stream).is_err());
+
+        broker.shutdown();
+    }
+
+    #[test]
+    fn test_broker_retained() {
+        let broker = Broker::bind("127.0.0.1:0").unwrap();
+        let mut publisher = connect(&broker, ConnectPacket::new("MQTT", "publisher"));
+
+        let mut retained = message("status", QoSWithPacketIdentifier::Level1(1), "online");
+        retained.set_retain(true);
+        send(&mut publisher, retained);
+        assert_eq!(receive(&mut publisher), VariablePacket::new(PubackPacket::new(1)));
+
+        let mut subscriber = connect(&broker, ConnectPacket::new("MQTT", "subscriber"));
+        subscribe(&mut subscriber, "#", QualityOfService::Level0);
+        let mut expected = message("status", QoSWithPacketIdentifier::Level0, "online");
+        expected.set_retain(true);
+        assert_eq!(receive(&mut subscriber), VariablePacket::new(expected));
+    }
+
+    #[test]
+    fn test_broker_persistent_session() {
+        let broker = Broker::bind("127.0.0.1:0").unwrap();
+        let mut publisher = connect(&broker, ConnectPacket::new("MQTT", "publisher"));
+        let mut subscriber = resume(&broker, "subscriber", false);
+        subscribe(&mut subscriber, "a", QualityOfService::Level2);
+        let mut other = connect(&broker, ConnectPacket::new("MQTT", "other"));
+        subscribe(&mut other, "b", QualityOfService::Level0);
+
+        send(&mut publisher, message("a", QoSWithPacketIdentifier::Level2(1), "two"));
+        assert_eq!(receive(&mut publisher), VariablePacket::new(PubrecPacket::new(1)));
+        send(&mut publisher, PubrelPacket::new(1));
+        assert_eq!(receive(&mut publisher), VariablePacket::new(PubcompPacket::new(1)));
+        send(&mut publisher, message("a", QoSWithPacketIdentifier::Level1(2), "one"));
+        assert_eq!(receive(&mut publisher), VariablePacket::new(PubackPacket::new(2)));
+
+        assert_eq!(receive(&mut subscriber),
+                   VariablePacket::new(message("a", QoSWithPacketIdentifier::Level2(1), "two")));
+        assert_eq!(receive(&mut subscriber),
+                   VariablePacket::new(message("a", QoSWithPacketIdentifier::Level1(2), "one")));
+        send(&mut subscriber, PubrecPacket::new(1));
+        assert_eq!(receive(&mut subscriber), VariablePacket::new(PubrelPacket::new(1)));
+        // Received by the broker, which waits for the PUBREL
+        send(&mut subscriber, message("b", QoSWithPacketIdentifier::Level2(9), "incoming"));
+        assert_eq!(receive(&mut subscriber), VariablePacket::new(PubrecPacket::new(9)));
+        assert_eq!(receive(&mut other),
+                   VariablePacket::new(message("b", QoSWithPacketIdentifier::Level0, "incoming")));
+        subscriber.shutdown(Shutdown::Both).unwrap();
+
+        // The PUBREC'd message is released again rather than published
+        let mut subscriber = resume(&broker, "subscriber", true);
+        let mut expected = message("a", QoSWithPacketIdentifier::Level1(2), "one");
+        expected.set_dup(true);
+        assert_eq!(receive(&mut subscriber), VariablePacket::new(PubrelPacket::new(1)));
+        assert_eq!(receive(&mut subscriber), VariablePacket::new(expected));
+        send(&mut subscriber, PubcompPacket::new(1));
+        send(&mut subscriber, PubackPacket::new(2));
+
+        // Not routed a second time
+        let mut incoming = message("b", QoSWithPacketIdentifier::Level2(9), "incoming");
+        incoming.set_dup(true);
+        send(&mut subscriber, incoming);
+        assert_eq!(receive(&mut subscriber), VariablePacket::new(PubrecPacket::new(9)));
+        send(&mut subscriber, PubrelPacket::new(9));
+        assert_eq!(receive(&mut subscriber), VariablePacket::new(PubcompPacket::new(9)));
+        send(&mut publisher, message("b", QoSWithPacketIdentifier::Level0, "next"));
+        assert_eq!(receive(&mut other),
+                   VariablePacket::new(message("b", QoSWithPacketIdentifier::Level0, "next")));
+
+        // The subscription is kept
+        send(&mut publisher, message("a", QoSWithPacketIdentifier::Level0, "zero"));
+        assert_eq!(receive(&mut subscriber),
+                   VariablePacket::new(message("a", QoSWithPacketIdentifier::Level0, "zero")));
+        subscriber.shutdown(Shutdown::Both).unwrap();
+
+        // A clean session discards it
+        let mut packet = ConnectPacket::new("MQTT", "subscriber");
+        packet.set_clean_session(true);
+        connect(&broker, packet).shutdown(Shutdown::Both).unwrap();
+        resume(&broker, "subscriber", false);
+
+        broker.shutdown();
+    }
+
+    #[test]
+    fn test_broker_file_session_store() {
+        let path = env::temp_dir().join(format!("mqtt-broker-session-{}", process::id()));
+        let _ = fs::remove_file(&path);
+
+        let broker = Broker::with_store("127.0.0.1:0", FileSessionStore::open(&path).unwrap()).unwrap();
+        let mut subscriber = resume(&broker, "subscriber", false);
+        subscribe(&mut subscriber, "a", QualityOfService::Level1);
+        let mut publisher = connect(&broker, ConnectPacket::new("MQTT", "publisher"));
+        send(&mut publisher, message("a", QoSWithPacketIdentifier::Level1(1), "one"));
+        assert_eq!(receive(&mut publisher), VariablePacket::new(PubackPacket::new(1)));
+        assert_eq!(receive(&mut subscriber),
+                   VariablePacket::new(message("a", QoSWithPacketIdentifier::Level1(1), "one")));
+        broker.shutdown();
+
+        let broker = Broker::with_store("127.0.0.1:0", FileSessionStore::open(&path).unwrap()).unwrap();
+        let mut subscriber = resume(&broker, "subscriber", true);
+        let mut expected = message("a", QoSWithPacketIdentifier::Level1(1), "one");
+        expected.set_dup(true);
+        assert_eq!(receive(&mut subscriber), VariablePacket::new(expected));
+        send(&mut subscriber, PubackPacket::new(1));
+        send(&mut subscriber, PingreqPacket::new());
+        assert_eq!(receive(&mut subscriber), VariablePacket::new(PingrespPacket::new()));
+
+        let mut publisher = connect(&broker, ConnectPacket::new("MQTT", "publisher"));
+        send(&mut publisher, message("a", QoSWithPacketIdentifier::Level1(1), "again"));
+        assert_eq!(receive(&mut publisher), VariablePacket::new(PubackPacket::new(1)));
+        // Packet identifier 1 was acknowledged, so it is free again
+        assert_eq!(receive(&mut subscriber),
+                   VariablePacket::new(message("a", QoSWithPacketIdentifier::Level1(1), "again")));
+
+        broker.shutdown();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_broker_will() {
+        let broker = Broker::bind("127.0.0.1:0").unwrap();
+        let mut subscriber = connect(&broker, ConnectPacket::new("MQTT", "subscriber"));
+        subscribe(&mut subscriber, "will", QualityOfService::Level0);
+
+        let mut packet = ConnectPacket::new("MQTT", "client");
+        packet.set_will(Some(LastWill::new(TopicName::new("will").unwrap(), QualityOfService::Level0, "gone")));
+        let client = connect(&broker, packet);
+        client.shutdown(Shutdown::Both).unwrap();
+
+        assert_eq!(receive(&mut subscriber),
+                   VariablePacket::new(message("will", QoSWithPacketIdentifier::Level0, "gone")));
+    }
+}
@@ -0,0 +1,714 @@
+//! Client on the tokio runtime
+//!
+//! `AsyncClient::connect` returns a handle for requests and the `Messages` stream. The stream
+//! drives the connection, so it must be polled, usually by spawning it, for requests to complete.
+//!
+//! ```rust,no_run
+//! # extern crate futures_util;
+//! # extern crate mqtt;
+//! # extern crate tokio;
+//! use futures_util::{future, StreamExt};
+//! use tokio::runtime::Runtime;
+//!
+//! use mqtt::client::AsyncClient;
+//! use mqtt::packet::{ConnectPacket, Packet, PublishPacket, QoSWithPacketIdentifier};
+//! use mqtt::{QualityOfService, TopicFilter, TopicName};
+//!
+//! # fn main() {
+//! let mut connect = ConnectPacket::new("MQTT", "client");
+//! connect.set_keep_alive(30);
+//! let (client, messages) = AsyncClient::connect("127.0.0.1:1883".parse().unwrap(), connect);
+//!
+//! let runtime = Runtime::new().unwrap();
+//! runtime.spawn(messages.for_each(|message| {
+//!     match message {
+//!         Ok(message) => println!("{}: {:?}", message.topic_name(), message.payload_ref()),
+//!         Err(err) => eprintln!("{}", err),
+//!     }
+//!     future::ready(())
+//! }));
+//!
+//! let filters = vec![(TopicFilter::new("sensors/#").unwrap(), QualityOfService::Level1)];
+//! runtime.block_on(client.subscribe(filters)).unwrap();
+//! let message = PublishPacket::new(TopicName::new("sensors/1").unwrap(), QoSWithPacketIdentifier::Level1(0), "20.5");
+//! runtime.block_on(client.publish(message)).unwrap();
+//! # }
+//! ```
+
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::mem;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_util::sink::Sink;
+use futures_util::stream::Stream;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{self, Instant, Sleep};
+use tokio_util::codec::Framed;
+
+use client::ClientError;
+use codec::MqttCodec;
+use control::variable_header::ConnectReturnCode;
+use packet::{ConnectPacket, DisconnectPacket, Packet, PingreqPacket, PublishPacket, QoSWithPacketIdentifier,
+             SubscribePacket, UnsubscribePacket, VariablePacket};
+use packet::suback::SubscribeReturnCode;
+use qos::QualityOfService;
+use session::{ClientEvent, ClientSession, PacketIdAllocator, SessionError};
+use topic_filter::TopicFilter;
+
+/// Delays between reconnection attempts
+///
+/// The delay starts at `min_delay` and doubles after every failed attempt, up to `max_delay`.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct ReconnectPolicy {
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> ReconnectPolicy {
+        ReconnectPolicy {
+            min_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Handle to send requests to the server
+///
+/// Requests made before the server accepted the connection, or while disconnected, are sent once
+/// it does. Dropping every handle disconnects the client.
+#[derive(Debug, Clone)]
+pub struct AsyncClient {
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl AsyncClient {
+    /// Connects to `addr` with the default `ReconnectPolicy`
+    pub fn connect(addr: SocketAddr, connect: ConnectPacket) -> (AsyncClient, Messages) {
+        AsyncClient::with_reconnect(addr, connect, ReconnectPolicy::default())
+    }
+
+    /// Connects to `addr`, sending `connect` on every connection
+    pub fn with_reconnect(addr: SocketAddr, connect: ConnectPacket, reconnect: ReconnectPolicy)
+                          -> (AsyncClient, Messages) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let messages = Messages {
+            addr,
+            connect,
+            reconnect,
+            delay: reconnect.min_delay,
+            commands: rx,
+            queued: VecDeque::new(),
+            disconnecting: false,
+            closing: false,
+            state: State::Connecting(Box::pin(TcpStream::connect(addr))),
+            session: ClientSession::new(),
+            packet_ids: PacketIdAllocator::new(),
+            pending: HashMap::new(),
+            subscriptions: Vec::new(),
+            messages: VecDeque::new(),
+        };
+        (AsyncClient { commands: tx }, messages)
+    }
+
+    /// Publishes a message, completing once it is acknowledged as its QoS requires
+    ///
+    /// The packet identifier of a QoS 1 or 2 message is assigned by the client.
+    pub fn publish(&self, packet: PublishPacket) -> Response<()> {
+        self.request(|tx| Command::Publish(packet, tx))
+    }
+
+    /// Subscribes to topic filters, completing with the return codes of the `SUBACK`
+    ///
+    /// Accepted subscriptions are made again after reconnecting without a session.
+    pub fn subscribe(&self, filters: Vec<(TopicFilter, QualityOfService)>) -> Response<Vec<SubscribeReturnCode>> {
+        self.request(|tx| Command::Subscribe(filters, tx))
+    }
+
+    /// Unsubscribes from topic filters, completing with the `UNSUBACK`
+    pub fn unsubscribe(&self, filters: Vec<TopicFilter>) -> Response<()> {
+        self.request(|tx| Command::Unsubscribe(filters, tx))
+    }
+
+    /// Sends `DISCONNECT` after the requests made before, and ends the `Messages` stream
+    ///
+    /// While the connection is not accepted, the stream ends at once and those requests fail.
+    pub fn disconnect(&self) {
+        let _ = self.commands.send(Command::Disconnect);
+    }
+
+    fn request<T, F>(&self, command: F) -> Response<T>
+    where
+        F: FnOnce(oneshot::Sender<Result<T, ClientError>>) -> Command,
+    {
+        let (tx, rx) = oneshot::channel();
+        // If the stream is gone, dropping the command cancels the response
+        let _ = self.commands.send(command(tx));
+        Response { rx }
+    }
+}
+
+/// Result of a request, failing with `ClientError::Disconnected` if the client stops first
+#[derive(Debug)]
+pub struct Response<T> {
+    rx: oneshot::Receiver<Result<T, ClientError>>,
+}
+
+impl<T> Future for Response<T> {
+    type Output = Result<T, ClientError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, ClientError>> {
+        match Pin::new(&mut self.rx).poll(cx) {
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+            Poll::Ready(Err(..)) => Poll::Ready(Err(ClientError::Disconnected)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Command {
+    Publish(PublishPacket, oneshot::Sender<Result<(), ClientError>>),
+    Subscribe(Vec<(TopicFilter, QualityOfService)>, oneshot::Sender<Result<Vec<SubscribeReturnCode>, ClientError>>),
+    Unsubscribe(Vec<TopicFilter>, oneshot::Sender<Result<(), ClientError>>),
+    Disconnect,
+}
+
+/// Request waiting for its acknowledgement, by packet identifier
+#[derive(Debug)]
+enum Pending {
+    Publish(oneshot::Sender<Result<(), ClientError>>),
+    // Without a sender when subscribing again after reconnecting
+    Subscribe(SubscribePacket, Option<oneshot::Sender<Result<Vec<SubscribeReturnCode>, ClientError>>>),
+    Unsubscribe(UnsubscribePacket, oneshot::Sender<Result<(), ClientError>>),
+}
+
+type ConnectFuture = Pin<Box<dyn Future<Output = io::Result<TcpStream>> + Send>>;
+
+enum State {
+    /// Waiting before connecting again
+    Waiting(Pin<Box<Sleep>>),
+    Connecting(ConnectFuture),
+    Connected(Connection),
+    Closed,
+}
+
+impl fmt::Debug for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            State::Waiting(ref delay) => f.debug_tuple("Waiting").field(delay).finish(),
+            State::Connecting(..) => f.write_str("Connecting"),
+            State::Connected(ref connection) => f.debug_tuple("Connected").field(connection).finish(),
+            State::Closed => f.write_str("Closed"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    framed: Framed<TcpStream, MqttCodec>,
+    outgoing: VecDeque<VariablePacket>,
+    /// Packets given to `framed` and not flushed yet
+    unflushed: bool,
+    /// `CONNACK` received
+    accepted: bool,
+    keep_alive: Option<(Duration, Pin<Box<Sleep>>)>,
+    /// `PINGREQ` sent and nothing received since
+    ping_sent: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream, connect: &ConnectPacket) -> Connection {
+        let keep_alive = match connect.keep_alive() {
+            0 => None,
+            secs => {
+                let interval = Duration::from_secs(u64::from(secs));
+                Some((interval, Box::pin(time::sleep(interval))))
+            }
+        };
+
+        let mut connection = Connection {
+            framed: Framed::new(stream, MqttCodec::new()),
+            outgoing: VecDeque::new(),
+            unflushed: false,
+            accepted: false,
+            keep_alive,
+            ping_sent: false,
+        };
+        connection.send(connect.clone());
+        connection
+    }
+
+    fn send<P>(&mut self, packet: P)
+    where
+        VariablePacket: From<P>,
+    {
+        self.outgoing.push_back(VariablePacket::new(packet));
+    }
+}
+
+/// What driving a connection led to
+enum Flow {
+    Progress,
+    Idle,
+    Lost,
+    Closed,
+}
+
+/// Messages received from the server
+///
+/// Polling the stream drives the connection: it connects, reconnects after the connection is lost,
+/// sends the requests of the `AsyncClient` and keeps the connection alive. It ends after
+/// `AsyncClient::disconnect` or once every `AsyncClient` is dropped, and after yielding the error
+/// if the server refuses the connection.
+#[derive(Debug)]
+pub struct Messages {
+    addr: SocketAddr,
+    connect: ConnectPacket,
+    reconnect: ReconnectPolicy,
+    delay: Duration,
+    commands: mpsc::UnboundedReceiver<Command>,
+    // Requests received before the server accepted the connection
+    queued: VecDeque<Command>,
+    /// `AsyncClient::disconnect` called, or every `AsyncClient` dropped
+    disconnecting: bool,
+    /// `DISCONNECT` sent
+    closing: bool,
+    state: State,
+    session: ClientSession,
+    packet_ids: PacketIdAllocator,
+    pending: HashMap<u16, Pending>,
+    // Accepted subscriptions, to subscribe again after reconnecting without a session
+    subscriptions: Vec<(TopicFilter, QualityOfService)>,
+    messages: VecDeque<PublishPacket>,
+}
+
+impl Stream for Messages {
+    type Item = Result<PublishPacket, ClientError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Result<PublishPacket, ClientError>>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(message) = this.messages.pop_front() {
+                return Poll::Ready(Some(Ok(message)));
+            }
+            if let State::Closed = this.state {
+                return Poll::Ready(None);
+            }
+            match this.step(cx) {
+                Ok(true) => {}
+                Ok(false) => return Poll::Pending,
+                // The state is `Closed`, so the stream ends after the error
+                Err(err) => return Poll::Ready(Some(Err(err))),
+            }
+        }
+    }
+}
+
+impl Messages {
+    /// Advances the connection, returning whether anything changed
+    fn step(&mut self, cx: &mut Context) -> Result<bool, ClientError> {
+        // Requests are taken in every state, so that a disconnect is seen while not connected
+        let mut progress = false;
+        while !self.disconnecting {
+            match self.commands.poll_recv(cx) {
+                Poll::Ready(Some(Command::Disconnect)) | Poll::Ready(None) => {
+                    self.queued.push_back(Command::Disconnect);
+                    self.disconnecting = true;
+                }
+                Poll::Ready(Some(command)) => self.queued.push_back(command),
+                Poll::Pending => break,
+            }
+            progress = true;
+        }
+
+        match mem::replace(&mut self.state, State::Closed) {
+            // Nothing to send `DISCONNECT` on, the requests still waiting fail
+            State::Waiting(..) | State::Connecting(..) if self.disconnecting => {
+                self.queued.clear();
+                self.pending.clear();
+                Ok(true)
+            }
+            State::Waiting(mut delay) => match delay.as_mut().poll(cx) {
+                Poll::Pending => {
+                    self.state = State::Waiting(delay);
+                    Ok(progress)
+                }
+                Poll::Ready(()) => {
+                    self.state = State::Connecting(Box::pin(TcpStream::connect(self.addr)));
+                    Ok(true)
+                }
+            },
+            State::Connecting(mut connecting) => match connecting.as_mut().poll(cx) {
+                Poll::Ready(Ok(stream)) => {
+                    self.state = State::Connected(Connection::new(stream, &self.connect));
+                    Ok(true)
+                }
+                Poll::Pending => {
+                    self.state = State::Connecting(connecting);
+                    Ok(progress)
+                }
+                Poll::Ready(Err(..)) => {
+                    self.wait();
+                    Ok(true)
+                }
+            },
+            State::Connected(mut connection) => match self.drive(&mut connection, cx) {
+                Ok(Flow::Progress) => {
+                    self.state = State::Connected(connection);
+                    Ok(true)
+                }
+                Ok(Flow::Idle) => {
+                    self.state = State::Connected(connection);
+                    Ok(progress)
+                }
+                Ok(Flow::Lost) => {
+                    self.wait();
+                    Ok(true)
+                }
+                Ok(Flow::Closed) => Ok(true),
+                Err(err) => Err(err),
+            },
+            State::Closed => Ok(false),
+        }
+    }
+
+    /// Waits before the next connection attempt
+    fn wait(&mut self) {
+        self.state = State::Waiting(Box::pin(time::sleep(self.delay)));
+        self.delay = cmp::min(self.delay * 2, self.reconnect.max_delay);
+    }
+
+    fn drive(&mut self, connection: &mut Connection, cx: &mut Context) -> Result<Flow, ClientError> {
+        let mut progress = false;
+
+        // Requests wait in the queue until the server accepted the connection
+        if connection.accepted {
+            while let Some(command) = self.queued.pop_front() {
+                self.command(connection, command);
+                progress = true;
+            }
+        } else if self.disconnecting && !self.closing {
+            self.command(connection, Command::Disconnect);
+            progress = true;
+        }
+
+        loop {
+            match Pin::new(&mut connection.framed).poll_next(cx) {
+                Poll::Ready(Some(Ok(packet))) => {
+                    connection.ping_sent = false;
+                    if !self.receive(connection, packet)? {
+                        return Ok(Flow::Lost);
+                    }
+                    progress = true;
+                }
+                Poll::Ready(Some(Err(..))) | Poll::Ready(None) => return Ok(Flow::Lost),
+                Poll::Pending => break,
+            }
+        }
+
+        let ping = match connection.keep_alive {
+            Some((interval, ref mut delay)) => match delay.as_mut().poll(cx) {
+                Poll::Pending => false,
+                Poll::Ready(()) => {
+                    delay.as_mut().reset(Instant::now() + interval);
+                    true
+                }
+            },
+            None => false,
+        };
+        if ping {
+            // Nothing received for a whole keep alive interval after the last PINGREQ
+            if connection.ping_sent {
+                return Ok(Flow::Lost);
+            }
+            connection.send(PingreqPacket::new());
+            connection.ping_sent = true;
+            progress = true;
+        }
+
+        while !connection.outgoing.is_empty() {
+            match Pin::new(&mut connection.framed).poll_ready(cx) {
+                Poll::Ready(Ok(())) => {
+                    let packet = connection.outgoing.pop_front().unwrap();
+                    if Pin::new(&mut connection.framed).start_send(packet).is_err() {
+                        return Ok(Flow::Lost);
+                    }
+                    connection.unflushed = true;
+                    progress = true;
+                }
+                Poll::Ready(Err(..)) => return Ok(Flow::Lost),
+                Poll::Pending => break,
+            }
+        }
+        if connection.unflushed {
+            match Pin::new(&mut connection.framed).poll_flush(cx) {
+                Poll::Ready(Ok(())) => {
+                    connection.unflushed = false;
+                    if let Some((interval, ref mut delay)) = connection.keep_alive {
+                        delay.as_mut().reset(Instant::now() + interval);
+                    }
+                    progress = true;
+                }
+                Poll::Ready(Err(..)) => return Ok(Flow::Lost),
+                Poll::Pending => {}
+            }
+        }
+
+        if self.closing && connection.outgoing.is_empty() && !connection.unflushed {
+            return Ok(Flow::Closed);
+        }
+        Ok(if progress { Flow::Progress } else { Flow::Idle })
+    }
+
+    fn command(&mut self, connection: &mut Connection, command: Command) {
+        match command {
+            Command::Publish(mut packet, tx) => {
+                let qos = QualityOfService::from(packet.qos());
+                if qos == QualityOfService::Level0 {
+                    let _ = self.session.publish(packet);
+                    self.flush_session(connection);
+                    let _ = tx.send(Ok(()));
+                    return;
+                }
+
+                let pkid = match self.packet_ids.allocate() {
+                    Some(pkid) => pkid,
+                    None => {
+                        let _ = tx.send(Err(SessionError::PacketIdentifiersExhausted.into()));
+                        return;
+                    }
+                };
+                packet.set_qos(QoSWithPacketIdentifier::new(qos, pkid));
+                match self.session.publish(packet) {
+                    Ok(()) => {
+                        self.pending.insert(pkid, Pending::Publish(tx));
+                        self.flush_session(connection);
+                    }
+                    Err(err) => {
+                        self.packet_ids.release(pkid);
+                        let _ = tx.send(Err(err.into()));
+                    }
+                }
+            }
+            Command::Subscribe(filters, tx) => match self.packet_ids.allocate() {
+                Some(pkid) => {
+                    let packet = SubscribePacket::new(pkid, filters);
+                    connection.send(packet.clone());
+                    self.pending.insert(pkid, Pending::Subscribe(packet, Some(tx)));
+                }
+                None => {
+                    let _ = tx.send(Err(SessionError::PacketIdentifiersExhausted.into()));
+                }
+            },
+            Command::Unsubscribe(filters, tx) => match self.packet_ids.allocate() {
+                Some(pkid) => {
+                    let packet = UnsubscribePacket::new(pkid, filters);
+                    connection.send(packet.clone());
+                    self.pending.insert(pkid, Pending::Unsubscribe(packet, tx));
+                }
+                None => {
+                    let _ = tx.send(Err(SessionError::PacketIdentifiersExhausted.into()));
+                }
+            },
+            Command::Disconnect => {
+                connection.send(DisconnectPacket::new());
+                self.closing = true;
+                // Fails the requests still waiting
+                self.queued.clear();
+                self.pending.clear();
+            }
+        }
+    }
+
+    /// Handles a packet from the server, returning `false` if the connection should be dropped
+    fn receive(&mut self, connection: &mut Connection, packet: VariablePacket) -> Result<bool, ClientError> {
+        match packet {
+            VariablePacket::ConnackPacket(ref connack) => {
+                let code = connack.connect_return_code();
+                if code != ConnectReturnCode::ConnectionAccepted {
+                    return Err(ClientError::ConnectionRefused(code));
+                }
+                self.accepted(connection, connack.connack_flags().session_present);
+                return Ok(true);
+            }
+            VariablePacket::SubackPacket(ref suback) => {
+                let pkid = suback.packet_identifier();
+                if let Some(Pending::Subscribe(subscribe, tx)) = self.pending.remove(&pkid) {
+                    self.packet_ids.release(pkid);
+                    let codes = suback.payload_ref().subscribes();
+                    for (&(ref filter, qos), code) in subscribe.payload_ref().subscribes().iter().zip(codes) {
+                        if *code != SubscribeReturnCode::Failure {
+                            self.subscriptions.retain(|(subscribed, _)| subscribed != filter);
+                            self.subscriptions.push((filter.clone(), qos));
+                        }
+                    }
+                    if let Some(tx) = tx {
+                        let _ = tx.send(Ok(codes.to_vec()));
+                    }
+                }
+                return Ok(true);
+            }
+            VariablePacket::UnsubackPacket(ref unsuback) => {
+                let pkid = unsuback.packet_identifier();
+                if let Some(Pending::Unsubscribe(unsubscribe, tx)) = self.pending.remove(&pkid) {
+                    self.packet_ids.release(pkid);
+                    let filters = unsubscribe.payload_ref().subscribes();
+                    self.subscriptions.retain(|(subscribed, _)| !filters.contains(subscribed));
+                    let _ = tx.send(Ok(()));
+                }
+                return Ok(true);
+            }
+            _ => {}
+        }
+
+        if self.session.handle(packet).is_err() {
+            return Ok(false);
+        }
+        self.flush_session(connection);
+        Ok(true)
+    }
+
+    fn accepted(&mut self, connection: &mut Connection, session_present: bool) {
+        connection.accepted = true;
+        self.delay = self.reconnect.min_delay;
+
+        // Requests sent on the lost connection
+        for pending in self.pending.values() {
+            match *pending {
+                Pending::Subscribe(ref packet, _) => connection.send(packet.clone()),
+                Pending::Unsubscribe(ref packet, _) => connection.send(packet.clone()),
+                Pending::Publish(..) => {}
+            }
+        }
+
+        if !session_present && !self.subscriptions.is_empty() {
+            if let Some(pkid) = self.packet_ids.allocate() {
+                let packet = SubscribePacket::new(pkid, self.subscriptions.clone());
+                connection.send(packet.clone());
+                self.pending.insert(pkid, Pending::Subscribe(packet, None));
+            }
+        }
+
+        self.session.resend();
+        self.flush_session(connection);
+    }
+
+    fn flush_session(&mut self, connection: &mut Connection) {
+        while let Some(event) = self.session.poll_event() {
+            match event {
+                ClientEvent::Send(packet) => connection.send(packet),
+                ClientEvent::Message(message) => self.messages.push_back(message),
+                ClientEvent::Published(pkid) => {
+                    self.packet_ids.release(pkid);
+                    if let Some(Pending::Publish(tx)) = self.pending.remove(&pkid) {
+                        let _ = tx.send(Ok(()));
+                    }
+                }
+                // The session is kept in memory across reconnections
+                ClientEvent::Released(..) | ClientEvent::Received(..) | ClientEvent::ReceiveComplete(..) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::mpsc as std_mpsc;
+
+    use futures_util::{future, StreamExt};
+    use tokio::runtime::Runtime;
+
+    use broker::Broker;
+    use topic_name::TopicName;
+
+    fn client(runtime: &Runtime, addr: SocketAddr) -> (AsyncClient, std_mpsc::Receiver<PublishPacket>) {
+        let reconnect = ReconnectPolicy {
+            min_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(100),
+        };
+        let (client, messages) = AsyncClient::with_reconnect(addr, ConnectPacket::new("MQTT", "client"), reconnect);
+        let (tx, rx) = std_mpsc::channel();
+        runtime.spawn(messages.for_each(move |message| {
+                                           let _ = tx.send(message.unwrap());
+                                           future::ready(())
+                                       }));
+        (client, rx)
+    }
+
+    fn message(qos: QoSWithPacketIdentifier, payload: &str) -> PublishPacket {
+        PublishPacket::new(TopicName::new("a/b").unwrap(), qos, payload)
+    }
+
+    #[test]
+    fn test_async_client_publish_subscribe() {
+        let broker = Broker::bind("127.0.0.1:0").unwrap();
+        let runtime = Runtime::new().unwrap();
+        let (client, messages) = client(&runtime, broker.local_addr());
+
+        let filters = vec![(TopicFilter::new("a/#").unwrap(), QualityOfService::Level2)];
+        assert_eq!(runtime.block_on(client.subscribe(filters)).unwrap(),
+                   vec![SubscribeReturnCode::MaximumQoSLevel2]);
+
+        runtime.block_on(client.publish(message(QoSWithPacketIdentifier::Level2(0), "two"))).unwrap();
+        runtime.block_on(client.publish(message(QoSWithPacketIdentifier::Level1(0), "one"))).unwrap();
+        let received = messages.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!((received.payload_ref().as_slice(), received.qos()), (&b"two"[..], QoSWithPacketIdentifier::Level2(1)));
+        let received = messages.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(received.payload_ref().as_slice(), b"one");
+
+        runtime.block_on(client.unsubscribe(vec![TopicFilter::new("a/#").unwrap()])).unwrap();
+        client.disconnect();
+        match runtime.block_on(client.publish(message(QoSWithPacketIdentifier::Level0, "late"))) {
+            Err(ClientError::Disconnected) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_async_client_resubscribes() {
+        let broker = Broker::bind("127.0.0.1:0").unwrap();
+        let addr = broker.local_addr();
+        let runtime = Runtime::new().unwrap();
+        let (client, messages) = client(&runtime, addr);
+
+        let filters = vec![(TopicFilter::new("a/b").unwrap(), QualityOfService::Level1)];
+        runtime.block_on(client.subscribe(filters)).unwrap();
+        broker.shutdown();
+
+        // The retained message is only delivered once the client subscribed again
+        let broker = Broker::bind(addr).unwrap();
+        let mut retained = message(QoSWithPacketIdentifier::Level1(0), "again");
+        retained.set_retain(true);
+        runtime.block_on(client.publish(retained)).unwrap();
+
+        let received = messages.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(received.payload_ref().as_slice(), b"again");
+        drop(broker);
+    }
+    #[test]
+    fn test_async_client_disconnect_while_connecting() {
+        // Nothing listens on the port once the listener is dropped
+        let addr = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let runtime = Runtime::new().unwrap();
+        let (client, mut messages) = AsyncClient::connect(addr, ConnectPacket::new("MQTT", "client"));
+
+        let subscribed = client.subscribe(vec![(TopicFilter::new("a/b").unwrap(), QualityOfService::Level0)]);
+        client.disconnect();
+        assert!(runtime.block_on(messages.next()).is_none());
+        match runtime.block_on(subscribed) {
+            Err(ClientError::Disconnected) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+}
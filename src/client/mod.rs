@@ -0,0 +1,70 @@
+//! High level clients
+//!
+//! `SyncClient` blocks on a `std::net::TcpStream`, while `AsyncClient`, enabled by the
+//! `async-client` feature, runs on tokio over `MqttCodec` and reconnects by itself. Both keep the
+//! QoS 1 and 2 flows in a `ClientSession` and send `PINGREQ` within the keep alive interval of the
+//! `CONNECT` packet.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use control::variable_header::ConnectReturnCode;
+use session::SessionError;
+
+#[cfg(feature = "async-client")]
+pub use self::async_client::{AsyncClient, Messages, ReconnectPolicy, Response};
+pub use self::sync_client::SyncClient;
+
+#[cfg(feature = "async-client")]
+mod async_client;
+mod sync_client;
+
+/// Errors of a client request
+#[derive(Debug)]
+pub enum ClientError {
+    /// Connecting to or writing to the server failed
+    Io(io::Error),
+    /// The client was disconnected, or stopped before the request completed
+    Disconnected,
+    /// The server did not answer in time
+    Timeout,
+    /// The server refused the connection
+    ConnectionRefused(ConnectReturnCode),
+    /// The request could not be sent in the current session
+    Session(SessionError),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClientError::Io(ref err) => err.fmt(f),
+            ClientError::Disconnected => write!(f, "Client disconnected"),
+            ClientError::Timeout => write!(f, "Request timed out"),
+            ClientError::ConnectionRefused(code) => write!(f, "Connection refused with {:?}", code),
+            ClientError::Session(ref err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ClientError::Io(ref err) => Some(err),
+            ClientError::Session(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> ClientError {
+        ClientError::Io(err)
+    }
+}
+
+impl From<SessionError> for ClientError {
+    fn from(err: SessionError) -> ClientError {
+        ClientError::Session(err)
+    }
+}
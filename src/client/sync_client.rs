@@ -0,0 +1,604 @@
+//! Blocking client on `std::net::TcpStream`
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use mqtt::broker::Broker;
+//! use mqtt::client::SyncClient;
+//! use mqtt::packet::{ConnectPacket, PublishPacket, QoSWithPacketIdentifier};
+//! use mqtt::{QualityOfService, TopicFilter, TopicName};
+//!
+//! # let broker = Broker::bind("127.0.0.1:0").unwrap();
+//! # let addr = broker.local_addr();
+//! let mut connect = ConnectPacket::new("MQTT", "client");
+//! connect.set_keep_alive(30);
+//! let client = SyncClient::connect(addr, connect).unwrap();
+//!
+//! client.subscribe(vec![(TopicFilter::new("sensors/#").unwrap(), QualityOfService::Level1)]).unwrap();
+//! client.publish(PublishPacket::new(TopicName::new("sensors/1").unwrap(), QoSWithPacketIdentifier::Level1(0), "20.5"))
+//!       .unwrap();
+//!
+//! let message = client.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+//! assert_eq!(message.topic_name(), "sensors/1");
+//! client.disconnect();
+//! ```
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use client::ClientError;
+use control::fixed_header::FixedHeaderError;
+use control::variable_header::ConnectReturnCode;
+use decoder::{Decoded, PacketDecoder};
+use encodable::{Decodable, Encodable};
+use packet::{ConnectPacket, DisconnectPacket, PingreqPacket, PublishPacket, QoSWithPacketIdentifier, SubackPacket,
+             SubscribePacket, UnsubscribePacket, VariablePacket, VariablePacketError};
+use qos::QualityOfService;
+use session::{ClientEvent, ClientSession, PacketIdAllocator, SessionError, SessionStore, StoredSession};
+use topic_filter::TopicFilter;
+
+/// Acknowledgement a caller is blocked on
+#[derive(Debug)]
+enum Ack {
+    Published,
+    Subscribed(SubackPacket),
+    Unsubscribed,
+}
+
+#[derive(Debug)]
+struct Inner {
+    // Only shut down here, reading and writing happen on the client threads
+    stream: TcpStream,
+    // Encoded packets for the writer thread, until the connection is closed
+    outgoing: Option<Sender<Vec<u8>>>,
+    session: ClientSession,
+    packet_ids: PacketIdAllocator,
+    pending: HashMap<u16, Sender<Ack>>,
+    messages: Option<Sender<PublishPacket>>,
+    // Store and client identifier the session is saved under, unless it is clean
+    store: Option<(Box<dyn SessionStore + Send>, String)>,
+    last_write: Instant,
+    /// `PINGREQ` sent and nothing received since
+    ping_sent: Option<Instant>,
+}
+
+impl Inner {
+    fn send<P>(&mut self, packet: P) -> io::Result<()>
+    where
+        VariablePacket: From<P>,
+    {
+        let packet = VariablePacket::new(packet);
+        let mut buf = Vec::with_capacity(packet.encoded_length() as usize);
+        packet.encode(&mut buf).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        match self.outgoing {
+            Some(ref outgoing) if outgoing.send(buf).is_ok() => {}
+            _ => return Err(io::ErrorKind::NotConnected.into()),
+        }
+        self.last_write = Instant::now();
+        Ok(())
+    }
+
+    fn flush_session(&mut self) -> io::Result<()> {
+        while let Some(event) = self.session.poll_event() {
+            match event {
+                ClientEvent::Send(packet) => self.send(packet)?,
+                ClientEvent::Message(message) => {
+                    if let Some(ref messages) = self.messages {
+                        // Fails only while the client is being dropped
+                        let _ = messages.send(message);
+                    }
+                }
+                ClientEvent::Published(pkid) => {
+                    self.save(|store, client_id| store.remove_in_flight(client_id, pkid));
+                    self.acknowledge(pkid, Ack::Published)
+                }
+                ClientEvent::Released(pkid) => self.save(|store, client_id| store.save_pubrel(client_id, pkid)),
+                ClientEvent::Received(pkid) => self.save(|store, client_id| store.save_received(client_id, pkid)),
+                ClientEvent::ReceiveComplete(pkid) => {
+                    self.save(|store, client_id| store.remove_received(client_id, pkid))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves a change of the session, unless it is clean
+    fn save<F>(&mut self, change: F)
+    where
+        F: FnOnce(&mut dyn SessionStore, &str) -> io::Result<()>,
+    {
+        if let Some((ref mut store, ref client_id)) = self.store {
+            // A failure only loses the session if the process stops
+            let _ = change(&mut **store, client_id);
+        }
+    }
+
+    fn acknowledge(&mut self, pkid: u16, ack: Ack) {
+        // Released even if the request timed out, since the identifier was in use until now
+        self.packet_ids.release(pkid);
+        if let Some(waiter) = self.pending.remove(&pkid) {
+            let _ = waiter.send(ack);
+        }
+    }
+
+    /// Handles a packet from the server, returning `false` if the connection should be closed
+    fn receive(&mut self, packet: VariablePacket) -> bool {
+        self.ping_sent = None;
+        match packet {
+            VariablePacket::SubackPacket(suback) => self.acknowledge(suback.packet_identifier(), Ack::Subscribed(suback)),
+            VariablePacket::UnsubackPacket(unsuback) => {
+                self.acknowledge(unsuback.packet_identifier(), Ack::Unsubscribed)
+            }
+            packet => return self.session.handle(packet).is_ok() && self.flush_session().is_ok(),
+        }
+        true
+    }
+
+    /// Stops delivering messages and fails the requests still waiting
+    fn close(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+        self.outgoing = None;
+        self.messages = None;
+        self.pending.clear();
+    }
+}
+
+/// Client blocking until each request is acknowledged
+///
+/// A background thread reads from the connection and sends `PINGREQ` when nothing was written for
+/// half the keep alive interval, and another one writes the packets queued by the requests.
+/// Messages from the server are queued for `recv`. The client does not reconnect: once the
+/// connection is lost, requests fail with `ClientError::Disconnected`.
+///
+/// Requests not acknowledged within `timeout` fail with `ClientError::Timeout`.
+#[derive(Debug)]
+pub struct SyncClient {
+    inner: Arc<Mutex<Inner>>,
+    messages: Receiver<PublishPacket>,
+    reader: Option<JoinHandle<()>>,
+    writer: Option<JoinHandle<()>>,
+    session_present: bool,
+    timeout: Duration,
+}
+
+impl SyncClient {
+    /// Time a request waits for its acknowledgement unless changed by `set_timeout`
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Connects to `addr` and waits at most `DEFAULT_TIMEOUT` for the `CONNACK`
+    ///
+    /// The session is lost with the client; see `connect_with_store` to keep it.
+    pub fn connect<A: ToSocketAddrs>(addr: A, connect: ConnectPacket) -> Result<SyncClient, ClientError> {
+        SyncClient::start(addr, connect, None, None)
+    }
+
+    /// Connects to `addr` like `connect`, keeping the session in `store` unless `connect` asks for a
+    /// clean session
+    ///
+    /// The messages in flight of the session saved by an earlier client are sent again once
+    /// connected. Subscriptions are kept by the server.
+    pub fn connect_with_store<A, S>(addr: A, connect: ConnectPacket, mut store: S) -> Result<SyncClient, ClientError>
+    where
+        A: ToSocketAddrs,
+        S: SessionStore + Send + 'static,
+    {
+        let client_id = connect.client_identifier().to_owned();
+        if connect.clean_session() {
+            store.remove(&client_id)?;
+            return SyncClient::start(addr, connect, None, None);
+        }
+        let stored = store.load(&client_id)?;
+        SyncClient::start(addr, connect, Some((Box::new(store), client_id)), stored)
+    }
+
+    /// Connects and starts the threads, after restoring `stored` so that the reader never sees an
+    /// acknowledgement before its message is in flight
+    fn start<A: ToSocketAddrs>(addr: A,
+                               connect: ConnectPacket,
+                               store: Option<(Box<dyn SessionStore + Send>, String)>,
+                               stored: Option<StoredSession>)
+                               -> Result<SyncClient, ClientError> {
+        let mut stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(SyncClient::DEFAULT_TIMEOUT))?;
+        stream.set_write_timeout(Some(SyncClient::DEFAULT_TIMEOUT))?;
+        VariablePacket::new(connect.clone())
+            .encode(&mut stream)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+        let connack = match VariablePacket::decode(&mut stream) {
+            Ok(VariablePacket::ConnackPacket(connack)) => connack,
+            Ok(packet) => {
+                return Err(SessionError::UnexpectedPacket(packet.fixed_header().packet_type.control_type).into())
+            }
+            Err(VariablePacketError::FixedHeaderError(FixedHeaderError::IoError(ref err)))
+                if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut => {
+                return Err(ClientError::Timeout)
+            }
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err).into()),
+        };
+        if connack.connect_return_code() != ConnectReturnCode::ConnectionAccepted {
+            return Err(ClientError::ConnectionRefused(connack.connect_return_code()));
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let (sender, outgoing) = mpsc::channel();
+        let mut inner = Inner {
+            stream: stream.try_clone()?,
+            outgoing: Some(sender),
+            session: ClientSession::new(),
+            packet_ids: PacketIdAllocator::new(),
+            pending: HashMap::new(),
+            messages: Some(tx),
+            store,
+            last_write: Instant::now(),
+            ping_sent: None,
+        };
+        if let Some(stored) = stored {
+            for message in &stored.in_flight {
+                inner.packet_ids.reserve(message.packet_identifier());
+            }
+            inner.session.restore(stored.in_flight)?;
+            inner.session.restore_received(stored.received);
+            inner.session.resend();
+            inner.flush_session()?;
+        }
+        let inner = Arc::new(Mutex::new(inner));
+
+        let keep_alive = match connect.keep_alive() {
+            0 => None,
+            secs => Some(Duration::from_secs(u64::from(secs))),
+        };
+        let writer = {
+            let stream = stream.try_clone()?;
+            thread::Builder::new()
+                .name("mqtt-client-writer".to_owned())
+                .spawn(move || write(stream, &outgoing))?
+        };
+        let reader = {
+            let inner = inner.clone();
+            thread::Builder::new()
+                .name("mqtt-client".to_owned())
+                .spawn(move || read(&inner, stream, keep_alive))?
+        };
+
+        Ok(SyncClient {
+               inner,
+               messages: rx,
+               reader: Some(reader),
+               writer: Some(writer),
+               session_present: connack.connack_flags().session_present,
+               timeout: SyncClient::DEFAULT_TIMEOUT,
+           })
+    }
+
+    /// Time a request waits for its acknowledgement
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Sets the time a request waits for its acknowledgement
+    ///
+    /// The connection is closed if a write blocks for as long. A zero `timeout` is an error.
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<(), ClientError> {
+        self.inner.lock().unwrap().stream.set_write_timeout(Some(timeout))?;
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    /// Whether the server had a session for this client
+    pub fn session_present(&self) -> bool {
+        self.session_present
+    }
+
+    /// Publishes a message, returning once it is acknowledged as its QoS requires
+    ///
+    /// The packet identifier of a QoS 1 or 2 message is assigned by the client.
+    pub fn publish(&self, mut packet: PublishPacket) -> Result<(), ClientError> {
+        let qos = QualityOfService::from(packet.qos());
+        if qos == QualityOfService::Level0 {
+            let mut inner = self.inner.lock().unwrap();
+            inner.session.publish(packet)?;
+            return inner.flush_session().map_err(From::from);
+        }
+
+        self.request(|inner, pkid| {
+                         packet.set_qos(QoSWithPacketIdentifier::new(qos, pkid));
+                         inner.session.publish(packet.clone())?;
+                         inner.save(|store, client_id| store.save_publish(client_id, &packet));
+                         inner.flush_session().map_err(From::from)
+                     })
+            .map(|_| ())
+    }
+
+    /// Subscribes to topic filters, returning the `SUBACK` with the granted QoS of each
+    pub fn subscribe(&self, filters: Vec<(TopicFilter, QualityOfService)>) -> Result<SubackPacket, ClientError> {
+        match self.request(|inner, pkid| inner.send(SubscribePacket::new(pkid, filters)).map_err(From::from))? {
+            Ack::Subscribed(suback) => Ok(suback),
+            _ => Err(ClientError::Disconnected),
+        }
+    }
+
+    /// Unsubscribes from topic filters, returning once the server acknowledged it
+    pub fn unsubscribe(&self, filters: Vec<TopicFilter>) -> Result<(), ClientError> {
+        self.request(|inner, pkid| inner.send(UnsubscribePacket::new(pkid, filters)).map_err(From::from))
+            .map(|_| ())
+    }
+
+    /// Waits for the next message from the server
+    pub fn recv(&self) -> Result<PublishPacket, ClientError> {
+        self.messages.recv().map_err(|_| ClientError::Disconnected)
+    }
+
+    /// Waits at most `timeout` for the next message, returning `None` if none came
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<PublishPacket>, ClientError> {
+        match self.messages.recv_timeout(timeout) {
+            Ok(message) => Ok(Some(message)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(ClientError::Disconnected),
+        }
+    }
+
+    /// Sends `DISCONNECT` and closes the connection
+    pub fn disconnect(mut self) {
+        self.close();
+    }
+
+    /// Sends the packet built by `send` with a new packet identifier and waits for its acknowledgement
+    fn request<F>(&self, send: F) -> Result<Ack, ClientError>
+    where
+        F: FnOnce(&mut Inner, u16) -> Result<(), ClientError>,
+    {
+        let (tx, rx) = mpsc::channel();
+        let pkid = {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.messages.is_none() {
+                return Err(ClientError::Disconnected);
+            }
+            let pkid = inner.packet_ids.allocate().ok_or(SessionError::PacketIdentifiersExhausted)?;
+            inner.pending.insert(pkid, tx);
+            if let Err(err) = send(&mut inner, pkid) {
+                inner.pending.remove(&pkid);
+                inner.packet_ids.release(pkid);
+                return Err(err);
+            }
+            pkid
+        };
+
+        match rx.recv_timeout(self.timeout) {
+            Ok(ack) => Ok(ack),
+            Err(RecvTimeoutError::Timeout) => {
+                // The packet identifier stays in use until the acknowledgement arrives
+                self.inner.lock().unwrap().pending.remove(&pkid);
+                Err(ClientError::Timeout)
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(ClientError::Disconnected),
+        }
+    }
+
+    fn close(&mut self) {
+        let reader = match self.reader.take() {
+            Some(reader) => reader,
+            None => return,
+        };
+
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.messages.is_some() {
+                let _ = inner.send(DisconnectPacket::new());
+            }
+            // The writer stops once it has written the DISCONNECT
+            inner.outgoing = None;
+        }
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+        self.inner.lock().unwrap().close();
+        let _ = reader.join();
+    }
+}
+
+impl Drop for SyncClient {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn write(mut stream: TcpStream, outgoing: &Receiver<Vec<u8>>) {
+    for buf in outgoing {
+        if stream.write_all(&buf).is_err() {
+            // Ends the reader as well, which closes the client
+            let _ = stream.shutdown(Shutdown::Both);
+            break;
+        }
+    }
+}
+
+fn read(inner: &Mutex<Inner>, mut stream: TcpStream, keep_alive: Option<Duration>) {
+    let mut decoder: PacketDecoder = PacketDecoder::new();
+    let mut buf = [0; 4096];
+
+    // Woken up twice per keep alive interval to send PINGREQ in time
+    if stream.set_read_timeout(keep_alive.map(|keep_alive| keep_alive / 2)).is_err() {
+        inner.lock().unwrap().close();
+        return;
+    }
+
+    loop {
+        let received = match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut => 0,
+            Err(..) => break,
+        };
+        decoder.feed(&buf[..received]);
+
+        let mut inner = inner.lock().unwrap();
+        let mut open = true;
+        while open {
+            match decoder.decode() {
+                Ok(Decoded::Packet(packet)) => open = inner.receive(packet),
+                Ok(Decoded::NeedMore(..)) => break,
+                Err(..) => open = false,
+            }
+        }
+
+        if let Some(keep_alive) = keep_alive {
+            // Nothing received for a whole keep alive interval after the last PINGREQ
+            if inner.ping_sent.is_some_and(|sent| sent.elapsed() >= keep_alive) {
+                open = false;
+            } else if inner.last_write.elapsed() >= keep_alive / 2 {
+                open = open && inner.send(PingreqPacket::new()).is_ok();
+                if inner.ping_sent.is_none() {
+                    inner.ping_sent = Some(Instant::now());
+                }
+            }
+        }
+
+        if !open {
+            inner.close();
+            return;
+        }
+    }
+
+    inner.lock().unwrap().close();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::net::TcpListener;
+    use std::process;
+    use std::thread;
+
+    use broker::Broker;
+    use packet::{ConnackPacket, Packet};
+    use packet::suback::SubscribeReturnCode;
+    use session::FileSessionStore;
+    use topic_name::TopicName;
+
+    fn message(qos: QoSWithPacketIdentifier, payload: &str) -> PublishPacket {
+        PublishPacket::new(TopicName::new("a/b").unwrap(), qos, payload)
+    }
+
+    #[test]
+    fn test_sync_client_publish_subscribe() {
+        let broker = Broker::bind("127.0.0.1:0").unwrap();
+        let client = SyncClient::connect(broker.local_addr(), ConnectPacket::new("MQTT", "client")).unwrap();
+        assert!(!client.session_present());
+
+        let suback = client.subscribe(vec![(TopicFilter::new("a/+").unwrap(), QualityOfService::Level2)]).unwrap();
+        assert_eq!(suback.payload_ref().subscribes(), &[SubscribeReturnCode::MaximumQoSLevel2]);
+
+        client.publish(message(QoSWithPacketIdentifier::Level2(0), "two")).unwrap();
+        client.publish(message(QoSWithPacketIdentifier::Level0, "zero")).unwrap();
+        assert_eq!(client.recv().unwrap(), message(QoSWithPacketIdentifier::Level2(1), "two"));
+        assert_eq!(client.recv().unwrap(), message(QoSWithPacketIdentifier::Level0, "zero"));
+
+        client.unsubscribe(vec![TopicFilter::new("a/+").unwrap()]).unwrap();
+        client.publish(message(QoSWithPacketIdentifier::Level1(0), "unsubscribed")).unwrap();
+        assert!(client.recv_timeout(Duration::from_millis(100)).unwrap().is_none());
+        client.disconnect();
+    }
+
+    #[test]
+    fn test_sync_client_timeout() {
+        // Accepts the connection but never acknowledges anything
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut stream = listener.accept().unwrap().0;
+            VariablePacket::decode(&mut stream).unwrap();
+            ConnackPacket::new(false, ConnectReturnCode::ConnectionAccepted).encode(&mut stream).unwrap();
+            while VariablePacket::decode(&mut stream).is_ok() {}
+        });
+
+        let mut client = SyncClient::connect(addr, ConnectPacket::new("MQTT", "client")).unwrap();
+        assert_eq!(client.timeout(), SyncClient::DEFAULT_TIMEOUT);
+        client.set_timeout(Duration::from_millis(100)).unwrap();
+        match client.subscribe(vec![(TopicFilter::new("a/+").unwrap(), QualityOfService::Level0)]) {
+            Err(ClientError::Timeout) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+        match client.publish(message(QoSWithPacketIdentifier::Level1(0), "lost")) {
+            Err(ClientError::Timeout) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+
+        client.disconnect();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_sync_client_store() {
+        let path = env::temp_dir().join(format!("mqtt-client-session-{}", process::id()));
+        let _ = fs::remove_file(&path);
+
+        // Never acknowledges the message, then receives it again from the next client
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut received = Vec::new();
+            for _ in 0..2 {
+                let mut stream = listener.accept().unwrap().0;
+                VariablePacket::decode(&mut stream).unwrap();
+                ConnackPacket::new(true, ConnectReturnCode::ConnectionAccepted).encode(&mut stream).unwrap();
+                while let Ok(packet) = VariablePacket::decode(&mut stream) {
+                    received.push(packet);
+                }
+            }
+            received
+        });
+
+        let mut client = SyncClient::connect_with_store(addr,
+                                                        ConnectPacket::new("MQTT", "client"),
+                                                        FileSessionStore::open(&path).unwrap())
+            .unwrap();
+        client.set_timeout(Duration::from_millis(100)).unwrap();
+        match client.publish(message(QoSWithPacketIdentifier::Level1(0), "kept")) {
+            Err(ClientError::Timeout) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+        client.disconnect();
+
+        let client = SyncClient::connect_with_store(addr,
+                                                    ConnectPacket::new("MQTT", "client"),
+                                                    FileSessionStore::open(&path).unwrap())
+            .unwrap();
+        client.disconnect();
+
+        let mut resent = message(QoSWithPacketIdentifier::Level1(1), "kept");
+        resent.set_dup(true);
+        assert_eq!(server.join().unwrap(),
+                   vec![VariablePacket::new(message(QoSWithPacketIdentifier::Level1(1), "kept")),
+                        VariablePacket::new(DisconnectPacket::new()),
+                        VariablePacket::new(resent),
+                        VariablePacket::new(DisconnectPacket::new())]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_sync_client_keep_alive() {
+        let broker = Broker::bind("127.0.0.1:0").unwrap();
+        let mut connect = ConnectPacket::new("MQTT", "client");
+        connect.set_keep_alive(1);
+        let client = SyncClient::connect(broker.local_addr(), connect).unwrap();
+
+        // The broker closes connections idle for one and a half keep alive intervals
+        thread::sleep(Duration::from_millis(2500));
+        client.publish(message(QoSWithPacketIdentifier::Level1(0), "alive")).unwrap();
+
+        broker.shutdown();
+        match client.recv() {
+            Err(ClientError::Disconnected) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Codec for `tokio_util::codec::Framed`
+//!
+//! Enabled by the `tokio-codec` feature.
+//!
+//! Wrap a socket in `Framed::new(stream, MqttCodec::new())` to get a `Stream` and `Sink` of
+//! `VariablePacket`s. The codec can also be driven directly:
+//!
+//! ```rust
+//! # extern crate bytes;
+//! # extern crate mqtt;
+//! # extern crate tokio_util;
+//! use bytes::BytesMut;
+//! use tokio_util::codec::{Decoder, Encoder};
+//!
+//! use mqtt::codec::MqttCodec;
+//! use mqtt::packet::{PingreqPacket, VariablePacket};
+//!
+//! # fn main() {
+//! let mut codec = MqttCodec::new();
+//! let mut buf = BytesMut::new();
+//! codec.encode(VariablePacket::new(PingreqPacket::new()), &mut buf).unwrap();
+//!
+//! let mut partial = buf.split_to(1);
+//! assert!(codec.decode(&mut partial).unwrap().is_none());
+//!
+//! partial.unsplit(buf);
+//! assert_eq!(codec.decode(&mut partial).unwrap(), Some(VariablePacket::new(PingreqPacket::new())));
+//! # }
+//! ```
+
+use std::io::Cursor;
+
+use bytes::{BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use Encodable;
+use decoder::{frame_length, DecodeLimits, LimitedDecodable};
+use packet::{VariablePacket, VariablePacketError};
+
+/// Encodes and decodes `VariablePacket`s
+///
+/// A packet is decoded once all of its remaining length has been buffered. Frames exceeding the
+/// `DecodeLimits` are rejected before buffer space is reserved for them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MqttCodec {
+    limits: DecodeLimits,
+}
+
+impl MqttCodec {
+    pub fn new() -> MqttCodec {
+        MqttCodec::with_limits(DecodeLimits::default())
+    }
+
+    pub fn with_limits(limits: DecodeLimits) -> MqttCodec {
+        MqttCodec { limits }
+    }
+
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+}
+
+impl Decoder for MqttCodec {
+    type Item = VariablePacket;
+    type Error = VariablePacketError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<VariablePacket>, VariablePacketError> {
+        let frame_len = match frame_length(&src[..])? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        self.limits.check_packet_size(frame_len)?;
+        if src.len() < frame_len {
+            src.reserve(frame_len - src.len());
+            return Ok(None);
+        }
+
+        let frame = src.split_to(frame_len);
+        self.limits.check_fields(&frame)?;
+        VariablePacket::decode_limited(&mut Cursor::new(&frame[..]), None, &self.limits).map(Some)
+    }
+}
+
+impl Encoder<VariablePacket> for MqttCodec {
+    type Error = VariablePacketError;
+
+    fn encode(&mut self, packet: VariablePacket, dst: &mut BytesMut) -> Result<(), VariablePacketError> {
+        dst.reserve(packet.encoded_length() as usize);
+        packet.encode(&mut dst.writer())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use decoder::LimitError;
+    use packet::{PingreqPacket, PublishPacket, QoSWithPacketIdentifier};
+    use topic_name::TopicName;
+
+    #[test]
+    fn test_codec_round_trip() {
+        let packet = VariablePacket::new(PublishPacket::new(TopicName::new("a/b").unwrap(),
+                                                            QoSWithPacketIdentifier::Level1(10),
+                                                            b"Hello".to_vec()));
+
+        let mut codec = MqttCodec::new();
+        let mut buf = BytesMut::new();
+        codec.encode(packet.clone(), &mut buf).unwrap();
+        codec.encode(VariablePacket::new(PingreqPacket::new()), &mut buf).unwrap();
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(packet));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(VariablePacket::new(PingreqPacket::new())));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_codec_partial_frame() {
+        let mut encoded = BytesMut::new();
+        let packet = VariablePacket::new(PublishPacket::new(TopicName::new("a/b").unwrap(),
+                                                            QoSWithPacketIdentifier::Level0,
+                                                            vec![0u8; 200]));
+        MqttCodec::new().encode(packet.clone(), &mut encoded).unwrap();
+
+        let mut codec = MqttCodec::new();
+        let mut buf = BytesMut::new();
+        for (idx, byte) in encoded.iter().enumerate() {
+            buf.put_u8(*byte);
+            let decoded = codec.decode(&mut buf).unwrap();
+            if idx + 1 < encoded.len() {
+                assert_eq!(decoded, None);
+            } else {
+                assert_eq!(decoded, Some(packet.clone()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_codec_limits() {
+        let mut codec = MqttCodec::with_limits(DecodeLimits { max_packet_size: 16, ..DecodeLimits::default() });
+        let mut buf = BytesMut::from(&b"\x30\xff\xff\xff\x7f"[..]);
+
+        match codec.decode(&mut buf) {
+            Err(VariablePacketError::LimitExceeded(LimitError::PacketTooLarge(..))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+        assert!(buf.capacity() < 1024);
+    }
+}
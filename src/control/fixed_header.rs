@@ -1,13 +1,13 @@
 //! Fixed header in MQTT
 
-use std::convert::From;
-use std::error::Error;
-use std::fmt;
-use std::io::{self, Read, Write};
-
-use byteorder::{ReadBytesExt, WriteBytesExt};
+use core::convert::From;
+use core::error::Error;
+use core::fmt;
+use io::{self, Read, ReadBytesExt, Write, WriteBytesExt};
 
+#[cfg(feature = "std")]
 use futures::{future, Future};
+#[cfg(feature = "std")]
 use tokio_io::{io as async_io, AsyncRead};
 
 use {Decodable, Encodable};
@@ -47,6 +47,7 @@ impl FixedHeader {
 
     /// Asynchronously parse a single fixed header from an AsyncRead type, such as a network
     /// socket.
+    #[cfg(feature = "std")]
     pub fn parse<A: AsyncRead>(rdr: A) -> impl Future<Item = (A, Self, Vec<u8>), Error = FixedHeaderError> {
         async_io::read_exact(rdr, [0u8])
             .from_err()
@@ -1,7 +1,7 @@
 //! Packet types
 
-use std::error::Error;
-use std::fmt;
+use core::error::Error;
+use core::fmt;
 
 /// Packet type
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
@@ -56,6 +56,9 @@ pub enum ControlType {
 
     /// Client is disconnecting
     Disconnect                      = value::DISCONNECT,
+
+    /// Authentication exchange (MQTT 5 only)
+    Authentication                  = value::AUTH,
 }
 
 impl PacketType {
@@ -93,6 +96,8 @@ impl PacketType {
             ControlType::PingResponse => PacketType::new(t, 0),
 
             ControlType::Disconnect => PacketType::new(t, 0),
+
+            ControlType::Authentication => PacketType::new(t, 0),
         }
     }
 
@@ -140,7 +145,9 @@ impl PacketType {
 
             value::DISCONNECT   => vconst!(0x00, ControlType::Disconnect),
 
-            0 | 15              => Err(PacketTypeError::ReservedType(type_val, flag)),
+            value::AUTH         => vconst!(0x00, ControlType::Authentication),
+
+            0                   => Err(PacketTypeError::ReservedType(type_val, flag)),
             _                   => Err(PacketTypeError::UndefinedType(type_val, flag)),
         }
     }
@@ -190,4 +197,5 @@ mod value {
     pub const PINGREQ:     u8 = 12;
     pub const PINGRESP:    u8 = 13;
     pub const DISCONNECT:  u8 = 14;
+    pub const AUTH:        u8 = 15;
 }
@@ -1,10 +1,8 @@
-use std::convert::From;
-use std::io::{Read, Write};
-
-use byteorder::{ReadBytesExt, WriteBytesExt};
+use core::convert::From;
+use io::{Read, ReadBytesExt, Write, WriteBytesExt};
 
 use {Decodable, Encodable};
-use control::variable_header::VariableHeaderError;
+use control::variable_header::{ProtocolVersion, VariableHeaderError};
 
 /// Flags in `CONNACK` packet
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
@@ -33,10 +31,16 @@ impl Encodable for ConnackFlags {
 
 impl Decodable for ConnackFlags {
     type Err = VariableHeaderError;
-    type Cond = ();
+    type Cond = ProtocolVersion;
 
-    fn decode_with<R: Read>(reader: &mut R, _rest: Option<()>) -> Result<ConnackFlags, VariableHeaderError> {
+    /// Decodes the flags, or skips the reserved byte of a MQTT 3.1 `CONNACK`
+    fn decode_with<R: Read>(reader: &mut R,
+                            version: Option<ProtocolVersion>)
+                            -> Result<ConnackFlags, VariableHeaderError> {
         let code = reader.read_u8()?;
+        if let Some(false) = version.map(ProtocolVersion::has_session_present) {
+            return Ok(ConnackFlags::empty());
+        }
         if code & !1 != 0 {
             return Err(VariableHeaderError::InvalidReservedFlag);
         }
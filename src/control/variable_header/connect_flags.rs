@@ -1,10 +1,9 @@
-use std::convert::From;
-use std::io::{Read, Write};
-
-use byteorder::{ReadBytesExt, WriteBytesExt};
+use core::convert::From;
+use io::{Read, ReadBytesExt, Write, WriteBytesExt};
 
 use {Decodable, Encodable};
 use control::variable_header::VariableHeaderError;
+use qos::QualityOfService;
 
 /// Flags for `CONNECT` packet
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
@@ -12,7 +11,7 @@ pub struct ConnectFlags {
     pub user_name: bool,
     pub password: bool,
     pub will_retain: bool,
-    pub will_qos: u8,
+    pub will_qos: QualityOfService,
     pub will_flag: bool,
     pub clean_session: bool,
 }
@@ -23,7 +22,7 @@ impl ConnectFlags {
             user_name: false,
             password: false,
             will_retain: false,
-            will_qos: 0,
+            will_qos: QualityOfService::Level0,
             will_flag: false,
             clean_session: false,
         }
@@ -38,7 +37,7 @@ impl Encodable for ConnectFlags {
         let code = ((self.user_name as u8) << 7)
             | ((self.password as u8) << 6)
             | ((self.will_retain as u8) << 5)
-            | ((self.will_qos) << 3)
+            | ((self.will_qos as u8) << 3)
             | ((self.will_flag as u8) << 2)
             | ((self.clean_session as u8) << 1);
 
@@ -60,11 +59,18 @@ impl Decodable for ConnectFlags {
             return Err(VariableHeaderError::InvalidReservedFlag);
         }
 
+        let will_qos = match (code & 0b0001_1000) >> 3 {
+            0 => QualityOfService::Level0,
+            1 => QualityOfService::Level1,
+            2 => QualityOfService::Level2,
+            qos => return Err(VariableHeaderError::InvalidQualityOfService(qos)),
+        };
+
         Ok(ConnectFlags {
                user_name: (code & 0b1000_0000) != 0,
                password: (code & 0b0100_0000) != 0,
                will_retain: (code & 0b0010_0000) != 0,
-               will_qos: (code & 0b0001_1000) >> 3,
+               will_qos,
                will_flag: (code & 0b0000_0100) != 0,
                clean_session: (code & 0b0000_0010) != 0,
            })
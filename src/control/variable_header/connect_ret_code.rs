@@ -1,7 +1,5 @@
-use std::convert::From;
-use std::io::{Read, Write};
-
-use byteorder::{ReadBytesExt, WriteBytesExt};
+use core::convert::From;
+use io::{Read, ReadBytesExt, Write, WriteBytesExt};
 
 use {Decodable, Encodable};
 use control::variable_header::VariableHeaderError;
@@ -1,21 +1,25 @@
 //! Variable header in MQTT
 
-use std::convert::From;
-use std::error::Error;
-use std::fmt;
-use std::io;
-use std::string::FromUtf8Error;
+use alloc::string::{FromUtf8Error, String};
+use core::convert::From;
+use core::error::Error;
+use core::fmt;
+use io;
 
 use encodable::StringEncodeError;
 use topic_name::TopicNameError;
 
+use self::properties::PropertyError;
+
 pub use self::connect_ack_flags::ConnackFlags;
 pub use self::connect_flags::ConnectFlags;
 pub use self::connect_ret_code::ConnectReturnCode;
 pub use self::keep_alive::KeepAlive;
 pub use self::packet_identifier::PacketIdentifier;
-pub use self::protocol_level::ProtocolLevel;
+pub use self::properties::{Properties, Property, PropertyIdentifier, PropertyLocation};
+pub use self::protocol_level::{ProtocolLevel, ProtocolVersion};
 pub use self::protocol_name::ProtocolName;
+pub use self::reason_code::ReasonCode;
 pub use self::topic_name::TopicNameHeader;
 
 mod packet_identifier;
@@ -26,6 +30,8 @@ mod keep_alive;
 mod connect_ack_flags;
 mod connect_ret_code;
 mod topic_name;
+pub mod properties;
+mod reason_code;
 
 /// Errors while decoding variable header
 #[derive(Debug)]
@@ -35,6 +41,11 @@ pub enum VariableHeaderError {
     InvalidReservedFlag,
     FromUtf8Error(FromUtf8Error),
     TopicNameError(TopicNameError),
+    PropertyError(PropertyError),
+    InvalidReasonCode(u8),
+    InvalidQualityOfService(u8),
+    UnsupportedProtocolLevel(u8),
+    InvalidProtocolName(String),
 }
 
 impl From<io::Error> for VariableHeaderError {
@@ -61,36 +72,42 @@ impl From<TopicNameError> for VariableHeaderError {
     }
 }
 
+impl From<PropertyError> for VariableHeaderError {
+    fn from(err: PropertyError) -> VariableHeaderError {
+        VariableHeaderError::PropertyError(err)
+    }
+}
+
 impl fmt::Display for VariableHeaderError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            &VariableHeaderError::IoError(ref err) => write!(f, "{}", err),
-            &VariableHeaderError::StringEncodeError(ref err) => write!(f, "{}", err),
-            &VariableHeaderError::InvalidReservedFlag => write!(f, "Invalid reserved flags"),
-            &VariableHeaderError::FromUtf8Error(ref err) => write!(f, "{}", err),
-            &VariableHeaderError::TopicNameError(ref err) => write!(f, "{}", err),
+        match *self {
+            VariableHeaderError::IoError(ref err) => write!(f, "{}", err),
+            VariableHeaderError::StringEncodeError(ref err) => write!(f, "{}", err),
+            VariableHeaderError::InvalidReservedFlag => write!(f, "Invalid reserved flags"),
+            VariableHeaderError::FromUtf8Error(ref err) => write!(f, "{}", err),
+            VariableHeaderError::TopicNameError(ref err) => write!(f, "{}", err),
+            VariableHeaderError::PropertyError(ref err) => write!(f, "{}", err),
+            VariableHeaderError::InvalidReasonCode(code) => write!(f, "Invalid reason code ({:#X})", code),
+            VariableHeaderError::InvalidQualityOfService(qos) => write!(f, "Invalid QoS ({})", qos),
+            VariableHeaderError::UnsupportedProtocolLevel(level) => write!(f, "Unsupported protocol level ({})", level),
+            VariableHeaderError::InvalidProtocolName(ref name) => write!(f, "Invalid protocol name ({:?})", name),
         }
     }
 }
 
 impl Error for VariableHeaderError {
-    fn description(&self) -> &str {
-        match self {
-            &VariableHeaderError::IoError(ref err) => err.description(),
-            &VariableHeaderError::StringEncodeError(ref err) => err.description(),
-            &VariableHeaderError::InvalidReservedFlag => "Invalid reserved flags",
-            &VariableHeaderError::FromUtf8Error(ref err) => err.description(),
-            &VariableHeaderError::TopicNameError(ref err) => err.description(),
-        }
-    }
-
     fn source(&self) -> Option<&(dyn Error + 'static)> {
-        match self {
-            &VariableHeaderError::IoError(ref err) => Some(err),
-            &VariableHeaderError::StringEncodeError(ref err) => Some(err),
-            &VariableHeaderError::InvalidReservedFlag => None,
-            &VariableHeaderError::FromUtf8Error(ref err) => Some(err),
-            &VariableHeaderError::TopicNameError(ref err) => Some(err),
+        match *self {
+            VariableHeaderError::IoError(ref err) => Some(err),
+            VariableHeaderError::StringEncodeError(ref err) => Some(err),
+            VariableHeaderError::InvalidReservedFlag => None,
+            VariableHeaderError::FromUtf8Error(ref err) => Some(err),
+            VariableHeaderError::TopicNameError(ref err) => Some(err),
+            VariableHeaderError::PropertyError(ref err) => Some(err),
+            VariableHeaderError::InvalidReasonCode(..) => None,
+            VariableHeaderError::InvalidQualityOfService(..) => None,
+            VariableHeaderError::UnsupportedProtocolLevel(..) => None,
+            VariableHeaderError::InvalidProtocolName(..) => None,
         }
     }
 }
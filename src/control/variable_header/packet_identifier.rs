@@ -1,7 +1,7 @@
-use std::convert::From;
-use std::io::{Read, Write};
+use core::convert::From;
+use io::{Read, ReadBytesExt, Write, WriteBytesExt};
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::BigEndian;
 
 use {Decodable, Encodable};
 use control::variable_header::VariableHeaderError;
@@ -0,0 +1,864 @@
+//! Properties in MQTT 5 variable headers
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::From;
+use core::error::Error;
+use core::fmt;
+use io::{self, Read, ReadBytesExt, Write, WriteBytesExt};
+
+use byteorder::BigEndian;
+
+use {Decodable, Encodable, QualityOfService};
+use control::ControlType;
+use decoder::{DecodeLimits, LimitedDecodable};
+use encodable::{StringEncodeError, StringField, VarBytes, VarInt, VarIntError};
+
+/// Property identifiers
+#[rustfmt::skip]
+#[repr(u8)]
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+pub enum PropertyIdentifier {
+    PayloadFormatIndicator          = 0x01,
+    MessageExpiryInterval           = 0x02,
+    ContentType                     = 0x03,
+    ResponseTopic                   = 0x08,
+    CorrelationData                 = 0x09,
+    SubscriptionIdentifier          = 0x0B,
+    SessionExpiryInterval           = 0x11,
+    AssignedClientIdentifier        = 0x12,
+    ServerKeepAlive                 = 0x13,
+    AuthenticationMethod            = 0x15,
+    AuthenticationData              = 0x16,
+    RequestProblemInformation       = 0x17,
+    WillDelayInterval               = 0x18,
+    RequestResponseInformation      = 0x19,
+    ResponseInformation             = 0x1A,
+    ServerReference                 = 0x1C,
+    ReasonString                    = 0x1F,
+    ReceiveMaximum                  = 0x21,
+    TopicAliasMaximum               = 0x22,
+    TopicAlias                      = 0x23,
+    MaximumQoS                      = 0x24,
+    RetainAvailable                 = 0x25,
+    UserProperty                    = 0x26,
+    MaximumPacketSize               = 0x27,
+    WildcardSubscriptionAvailable   = 0x28,
+    SubscriptionIdentifierAvailable = 0x29,
+    SharedSubscriptionAvailable     = 0x2A,
+}
+
+impl PropertyIdentifier {
+    /// Create `PropertyIdentifier` from code
+    pub fn from_u8(code: u8) -> Result<PropertyIdentifier, PropertyError> {
+        use self::PropertyIdentifier::*;
+
+        let id = match code {
+            0x01 => PayloadFormatIndicator,
+            0x02 => MessageExpiryInterval,
+            0x03 => ContentType,
+            0x08 => ResponseTopic,
+            0x09 => CorrelationData,
+            0x0B => SubscriptionIdentifier,
+            0x11 => SessionExpiryInterval,
+            0x12 => AssignedClientIdentifier,
+            0x13 => ServerKeepAlive,
+            0x15 => AuthenticationMethod,
+            0x16 => AuthenticationData,
+            0x17 => RequestProblemInformation,
+            0x18 => WillDelayInterval,
+            0x19 => RequestResponseInformation,
+            0x1A => ResponseInformation,
+            0x1C => ServerReference,
+            0x1F => ReasonString,
+            0x21 => ReceiveMaximum,
+            0x22 => TopicAliasMaximum,
+            0x23 => TopicAlias,
+            0x24 => MaximumQoS,
+            0x25 => RetainAvailable,
+            0x26 => UserProperty,
+            0x27 => MaximumPacketSize,
+            0x28 => WildcardSubscriptionAvailable,
+            0x29 => SubscriptionIdentifierAvailable,
+            0x2A => SharedSubscriptionAvailable,
+            _ => return Err(PropertyError::InvalidIdentifier(code)),
+        };
+
+        Ok(id)
+    }
+
+    /// Whether this property may appear in `location`
+    pub fn is_allowed_in(self, location: PropertyLocation) -> bool {
+        use self::PropertyIdentifier::*;
+        use control::ControlType as Ty;
+
+        let ty = match location {
+            PropertyLocation::Packet(ty) => ty,
+            PropertyLocation::Will => {
+                return matches!(self,
+                                PayloadFormatIndicator | MessageExpiryInterval | ContentType | ResponseTopic |
+                                CorrelationData | WillDelayInterval | UserProperty);
+            }
+        };
+
+        match self {
+            PayloadFormatIndicator | MessageExpiryInterval | ContentType | ResponseTopic | CorrelationData |
+            TopicAlias => ty == Ty::Publish,
+            SubscriptionIdentifier => ty == Ty::Publish || ty == Ty::Subscribe,
+            SessionExpiryInterval => {
+                matches!(ty, Ty::Connect | Ty::ConnectAcknowledgement | Ty::Disconnect)
+            }
+            AuthenticationMethod | AuthenticationData => {
+                matches!(ty, Ty::Connect | Ty::ConnectAcknowledgement | Ty::Authentication)
+            }
+            RequestProblemInformation | RequestResponseInformation => ty == Ty::Connect,
+            ReceiveMaximum | TopicAliasMaximum | MaximumPacketSize => {
+                ty == Ty::Connect || ty == Ty::ConnectAcknowledgement
+            }
+            AssignedClientIdentifier | ServerKeepAlive | ResponseInformation | MaximumQoS | RetainAvailable |
+            WildcardSubscriptionAvailable | SubscriptionIdentifierAvailable | SharedSubscriptionAvailable => {
+                ty == Ty::ConnectAcknowledgement
+            }
+            ServerReference => ty == Ty::ConnectAcknowledgement || ty == Ty::Disconnect,
+            WillDelayInterval => false,
+            ReasonString => {
+                !matches!(ty,
+                          Ty::Connect | Ty::Publish | Ty::Subscribe | Ty::Unsubscribe | Ty::PingRequest |
+                          Ty::PingResponse)
+            }
+            UserProperty => ty != Ty::PingRequest && ty != Ty::PingResponse,
+        }
+    }
+
+    /// Whether this property may appear more than once in `location`
+    pub fn allows_multiple(self, location: PropertyLocation) -> bool {
+        match self {
+            PropertyIdentifier::UserProperty => true,
+            PropertyIdentifier::SubscriptionIdentifier => {
+                location == PropertyLocation::Packet(ControlType::Publish)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Where a set of properties is carried
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum PropertyLocation {
+    /// Variable header of a packet
+    Packet(ControlType),
+    /// Will properties in the `CONNECT` payload
+    Will,
+}
+
+impl From<ControlType> for PropertyLocation {
+    fn from(ty: ControlType) -> PropertyLocation {
+        PropertyLocation::Packet(ty)
+    }
+}
+
+impl fmt::Display for PropertyLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PropertyLocation::Packet(ty) => write!(f, "{:?} packet", ty),
+            PropertyLocation::Will => write!(f, "will properties"),
+        }
+    }
+}
+
+/// A single property
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Property {
+    PayloadFormatIndicator(u8),
+    MessageExpiryInterval(u32),
+    ContentType(String),
+    ResponseTopic(String),
+    CorrelationData(Vec<u8>),
+    SubscriptionIdentifier(u32),
+    SessionExpiryInterval(u32),
+    AssignedClientIdentifier(String),
+    ServerKeepAlive(u16),
+    AuthenticationMethod(String),
+    AuthenticationData(Vec<u8>),
+    RequestProblemInformation(u8),
+    WillDelayInterval(u32),
+    RequestResponseInformation(u8),
+    ResponseInformation(String),
+    ServerReference(String),
+    ReasonString(String),
+    ReceiveMaximum(u16),
+    TopicAliasMaximum(u16),
+    TopicAlias(u16),
+    MaximumQoS(u8),
+    RetainAvailable(u8),
+    UserProperty(String, String),
+    MaximumPacketSize(u32),
+    WildcardSubscriptionAvailable(u8),
+    SubscriptionIdentifierAvailable(u8),
+    SharedSubscriptionAvailable(u8),
+}
+
+impl Property {
+    /// Identifier of this property
+    pub fn identifier(&self) -> PropertyIdentifier {
+        match *self {
+            Property::PayloadFormatIndicator(..) => PropertyIdentifier::PayloadFormatIndicator,
+            Property::MessageExpiryInterval(..) => PropertyIdentifier::MessageExpiryInterval,
+            Property::ContentType(..) => PropertyIdentifier::ContentType,
+            Property::ResponseTopic(..) => PropertyIdentifier::ResponseTopic,
+            Property::CorrelationData(..) => PropertyIdentifier::CorrelationData,
+            Property::SubscriptionIdentifier(..) => PropertyIdentifier::SubscriptionIdentifier,
+            Property::SessionExpiryInterval(..) => PropertyIdentifier::SessionExpiryInterval,
+            Property::AssignedClientIdentifier(..) => PropertyIdentifier::AssignedClientIdentifier,
+            Property::ServerKeepAlive(..) => PropertyIdentifier::ServerKeepAlive,
+            Property::AuthenticationMethod(..) => PropertyIdentifier::AuthenticationMethod,
+            Property::AuthenticationData(..) => PropertyIdentifier::AuthenticationData,
+            Property::RequestProblemInformation(..) => PropertyIdentifier::RequestProblemInformation,
+            Property::WillDelayInterval(..) => PropertyIdentifier::WillDelayInterval,
+            Property::RequestResponseInformation(..) => PropertyIdentifier::RequestResponseInformation,
+            Property::ResponseInformation(..) => PropertyIdentifier::ResponseInformation,
+            Property::ServerReference(..) => PropertyIdentifier::ServerReference,
+            Property::ReasonString(..) => PropertyIdentifier::ReasonString,
+            Property::ReceiveMaximum(..) => PropertyIdentifier::ReceiveMaximum,
+            Property::TopicAliasMaximum(..) => PropertyIdentifier::TopicAliasMaximum,
+            Property::TopicAlias(..) => PropertyIdentifier::TopicAlias,
+            Property::MaximumQoS(..) => PropertyIdentifier::MaximumQoS,
+            Property::RetainAvailable(..) => PropertyIdentifier::RetainAvailable,
+            Property::UserProperty(..) => PropertyIdentifier::UserProperty,
+            Property::MaximumPacketSize(..) => PropertyIdentifier::MaximumPacketSize,
+            Property::WildcardSubscriptionAvailable(..) => PropertyIdentifier::WildcardSubscriptionAvailable,
+            Property::SubscriptionIdentifierAvailable(..) => PropertyIdentifier::SubscriptionIdentifierAvailable,
+            Property::SharedSubscriptionAvailable(..) => PropertyIdentifier::SharedSubscriptionAvailable,
+        }
+    }
+
+    /// Checks the value against the range allowed by the specification
+    fn has_valid_value(&self) -> bool {
+        match *self {
+            Property::PayloadFormatIndicator(v)
+            | Property::RequestProblemInformation(v)
+            | Property::RequestResponseInformation(v)
+            | Property::MaximumQoS(v)
+            | Property::RetainAvailable(v)
+            | Property::WildcardSubscriptionAvailable(v)
+            | Property::SubscriptionIdentifierAvailable(v)
+            | Property::SharedSubscriptionAvailable(v) => v <= 1,
+
+            Property::ReceiveMaximum(v) | Property::TopicAlias(v) => v != 0,
+            Property::MaximumPacketSize(v) => v != 0,
+            Property::SubscriptionIdentifier(v) => v != 0 && v <= VarInt::MAX,
+
+            _ => true,
+        }
+    }
+}
+
+impl Encodable for Property {
+    type Err = PropertyError;
+
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), PropertyError> {
+        writer.write_u8(self.identifier() as u8)?;
+
+        match *self {
+            Property::PayloadFormatIndicator(v)
+            | Property::RequestProblemInformation(v)
+            | Property::RequestResponseInformation(v)
+            | Property::MaximumQoS(v)
+            | Property::RetainAvailable(v)
+            | Property::WildcardSubscriptionAvailable(v)
+            | Property::SubscriptionIdentifierAvailable(v)
+            | Property::SharedSubscriptionAvailable(v) => writer.write_u8(v)?,
+
+            Property::ServerKeepAlive(v)
+            | Property::ReceiveMaximum(v)
+            | Property::TopicAliasMaximum(v)
+            | Property::TopicAlias(v) => writer.write_u16::<BigEndian>(v)?,
+
+            Property::MessageExpiryInterval(v)
+            | Property::SessionExpiryInterval(v)
+            | Property::WillDelayInterval(v)
+            | Property::MaximumPacketSize(v) => writer.write_u32::<BigEndian>(v)?,
+
+            Property::SubscriptionIdentifier(v) => VarInt(v).encode(writer)?,
+
+            Property::ContentType(ref v)
+            | Property::ResponseTopic(ref v)
+            | Property::AssignedClientIdentifier(ref v)
+            | Property::AuthenticationMethod(ref v)
+            | Property::ResponseInformation(ref v)
+            | Property::ServerReference(ref v)
+            | Property::ReasonString(ref v) => v.encode(writer)?,
+
+            Property::CorrelationData(ref v) | Property::AuthenticationData(ref v) => {
+                assert!(v.len() <= u16::MAX as usize);
+                writer.write_u16::<BigEndian>(v.len() as u16)?;
+                writer.write_all(v)?;
+            }
+
+            Property::UserProperty(ref k, ref v) => {
+                k.encode(writer)?;
+                v.encode(writer)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn encoded_length(&self) -> u32 {
+        let value_len = match *self {
+            Property::PayloadFormatIndicator(..)
+            | Property::RequestProblemInformation(..)
+            | Property::RequestResponseInformation(..)
+            | Property::MaximumQoS(..)
+            | Property::RetainAvailable(..)
+            | Property::WildcardSubscriptionAvailable(..)
+            | Property::SubscriptionIdentifierAvailable(..)
+            | Property::SharedSubscriptionAvailable(..) => 1,
+
+            Property::ServerKeepAlive(..)
+            | Property::ReceiveMaximum(..)
+            | Property::TopicAliasMaximum(..)
+            | Property::TopicAlias(..) => 2,
+
+            Property::MessageExpiryInterval(..)
+            | Property::SessionExpiryInterval(..)
+            | Property::WillDelayInterval(..)
+            | Property::MaximumPacketSize(..) => 4,
+
+            Property::SubscriptionIdentifier(v) => VarInt(v).encoded_length(),
+
+            Property::ContentType(ref v)
+            | Property::ResponseTopic(ref v)
+            | Property::AssignedClientIdentifier(ref v)
+            | Property::AuthenticationMethod(ref v)
+            | Property::ResponseInformation(ref v)
+            | Property::ServerReference(ref v)
+            | Property::ReasonString(ref v) => v.encoded_length(),
+
+            Property::CorrelationData(ref v) | Property::AuthenticationData(ref v) => 2 + v.len() as u32,
+
+            Property::UserProperty(ref k, ref v) => k.encoded_length() + v.encoded_length(),
+        };
+
+        1 + value_len
+    }
+}
+
+fn decode_string<R: Read>(reader: &mut R, id: PropertyIdentifier, limits: &DecodeLimits)
+                          -> Result<String, StringEncodeError> {
+    String::decode_with(reader, Some(limits.string_cond(StringField::Property(id))))
+}
+
+impl Decodable for Property {
+    type Err = PropertyError;
+    type Cond = ();
+
+    fn decode_with<R: Read>(reader: &mut R, rest: Option<()>) -> Result<Property, PropertyError> {
+        Property::decode_limited(reader, rest, &DecodeLimits::default())
+    }
+}
+
+impl LimitedDecodable for Property {
+    fn decode_limited<R: Read>(reader: &mut R, _rest: Option<()>, limits: &DecodeLimits)
+                               -> Result<Property, PropertyError> {
+        use self::PropertyIdentifier as Id;
+
+
+        let id = PropertyIdentifier::from_u8(reader.read_u8()?)?;
+        let prop = match id {
+            Id::PayloadFormatIndicator => Property::PayloadFormatIndicator(reader.read_u8()?),
+            Id::MessageExpiryInterval => Property::MessageExpiryInterval(reader.read_u32::<BigEndian>()?),
+            Id::ContentType => Property::ContentType(decode_string(reader, id, limits)?),
+            Id::ResponseTopic => Property::ResponseTopic(decode_string(reader, id, limits)?),
+            Id::CorrelationData => Property::CorrelationData(VarBytes::decode(reader)?.0),
+            Id::SubscriptionIdentifier => Property::SubscriptionIdentifier(VarInt::decode(reader)?.0),
+            Id::SessionExpiryInterval => Property::SessionExpiryInterval(reader.read_u32::<BigEndian>()?),
+            Id::AssignedClientIdentifier => Property::AssignedClientIdentifier(decode_string(reader, id, limits)?),
+            Id::ServerKeepAlive => Property::ServerKeepAlive(reader.read_u16::<BigEndian>()?),
+            Id::AuthenticationMethod => Property::AuthenticationMethod(decode_string(reader, id, limits)?),
+            Id::AuthenticationData => Property::AuthenticationData(VarBytes::decode(reader)?.0),
+            Id::RequestProblemInformation => Property::RequestProblemInformation(reader.read_u8()?),
+            Id::WillDelayInterval => Property::WillDelayInterval(reader.read_u32::<BigEndian>()?),
+            Id::RequestResponseInformation => Property::RequestResponseInformation(reader.read_u8()?),
+            Id::ResponseInformation => Property::ResponseInformation(decode_string(reader, id, limits)?),
+            Id::ServerReference => Property::ServerReference(decode_string(reader, id, limits)?),
+            Id::ReasonString => Property::ReasonString(decode_string(reader, id, limits)?),
+            Id::ReceiveMaximum => Property::ReceiveMaximum(reader.read_u16::<BigEndian>()?),
+            Id::TopicAliasMaximum => Property::TopicAliasMaximum(reader.read_u16::<BigEndian>()?),
+            Id::TopicAlias => Property::TopicAlias(reader.read_u16::<BigEndian>()?),
+            Id::MaximumQoS => Property::MaximumQoS(reader.read_u8()?),
+            Id::RetainAvailable => Property::RetainAvailable(reader.read_u8()?),
+            Id::UserProperty => {
+                Property::UserProperty(decode_string(reader, id, limits)?, decode_string(reader, id, limits)?)
+            }
+            Id::MaximumPacketSize => Property::MaximumPacketSize(reader.read_u32::<BigEndian>()?),
+            Id::WildcardSubscriptionAvailable => Property::WildcardSubscriptionAvailable(reader.read_u8()?),
+            Id::SubscriptionIdentifierAvailable => Property::SubscriptionIdentifierAvailable(reader.read_u8()?),
+            Id::SharedSubscriptionAvailable => Property::SharedSubscriptionAvailable(reader.read_u8()?),
+        };
+
+        Ok(prop)
+    }
+}
+
+macro_rules! property_accessors {
+    () => {};
+    ($(#[$attr:meta])* value $getter:ident, $setter:ident: $variant:ident($ty:ty); $($rest:tt)*) => {
+        $(#[$attr])*
+        pub fn $getter(&self) -> Option<$ty> {
+            self.properties.iter().find_map(|p| match *p {
+                Property::$variant(v) => Some(v),
+                _ => None,
+            })
+        }
+
+        pub fn $setter(&mut self, value: Option<$ty>) {
+            self.replace(PropertyIdentifier::$variant, value.map(Property::$variant));
+        }
+
+        property_accessors! { $($rest)* }
+    };
+    ($(#[$attr:meta])* flag $getter:ident, $setter:ident: $variant:ident; $($rest:tt)*) => {
+        $(#[$attr])*
+        pub fn $getter(&self) -> Option<bool> {
+            self.properties.iter().find_map(|p| match *p {
+                Property::$variant(v) => Some(v != 0),
+                _ => None,
+            })
+        }
+
+        pub fn $setter(&mut self, value: Option<bool>) {
+            self.replace(PropertyIdentifier::$variant, value.map(|v| Property::$variant(v as u8)));
+        }
+
+        property_accessors! { $($rest)* }
+    };
+    ($(#[$attr:meta])* string $getter:ident, $setter:ident: $variant:ident; $($rest:tt)*) => {
+        $(#[$attr])*
+        pub fn $getter(&self) -> Option<&str> {
+            self.properties.iter().find_map(|p| match *p {
+                Property::$variant(ref v) => Some(&v[..]),
+                _ => None,
+            })
+        }
+
+        pub fn $setter(&mut self, value: Option<String>) {
+            self.replace(PropertyIdentifier::$variant, value.map(Property::$variant));
+        }
+
+        property_accessors! { $($rest)* }
+    };
+    ($(#[$attr:meta])* binary $getter:ident, $setter:ident: $variant:ident; $($rest:tt)*) => {
+        $(#[$attr])*
+        pub fn $getter(&self) -> Option<&[u8]> {
+            self.properties.iter().find_map(|p| match *p {
+                Property::$variant(ref v) => Some(&v[..]),
+                _ => None,
+            })
+        }
+
+        pub fn $setter(&mut self, value: Option<Vec<u8>>) {
+            self.replace(PropertyIdentifier::$variant, value.map(Property::$variant));
+        }
+
+        property_accessors! { $($rest)* }
+    };
+}
+
+/// Properties of a packet, prefixed by their length in a variable byte integer
+///
+/// The typed setters replace any existing property with the same identifier, while `push` appends
+/// unconditionally. Use `validate` to check a collection against the packet it will be attached to.
+///
+/// ```rust
+/// use mqtt::control::ControlType;
+/// use mqtt::control::variable_header::{Properties, Property, PropertyIdentifier};
+/// use mqtt::control::variable_header::properties::PropertyError;
+///
+/// let mut props = Properties::new();
+/// props.set_message_expiry_interval(Some(30));
+/// props.set_content_type(Some("text/plain".to_owned()));
+/// props.add_user_property("trace-id", "42");
+/// assert_eq!(props.message_expiry_interval(), Some(30));
+/// assert!(props.validate(ControlType::Publish.into()).is_ok());
+///
+/// props.push(Property::MessageExpiryInterval(60));
+/// match props.validate(ControlType::Publish.into()) {
+///     Err(PropertyError::Duplicate(PropertyIdentifier::MessageExpiryInterval)) => {}
+///     other => panic!("Unexpected result {:?}", other),
+/// }
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct Properties {
+    properties: Vec<Property>,
+}
+
+impl Properties {
+    pub fn new() -> Properties {
+        Properties { properties: Vec::new() }
+    }
+
+    pub fn push(&mut self, prop: Property) {
+        self.properties.push(prop);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.properties.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.properties.len()
+    }
+
+    pub fn iter(&self) -> ::core::slice::Iter<'_, Property> {
+        self.properties.iter()
+    }
+
+    /// First property with the identifier
+    pub fn get(&self, id: PropertyIdentifier) -> Option<&Property> {
+        self.properties.iter().find(|p| p.identifier() == id)
+    }
+
+    /// Removes all properties with the identifier
+    pub fn remove(&mut self, id: PropertyIdentifier) {
+        self.properties.retain(|p| p.identifier() != id);
+    }
+
+    fn replace(&mut self, id: PropertyIdentifier, prop: Option<Property>) {
+        self.remove(id);
+        if let Some(prop) = prop {
+            self.properties.push(prop);
+        }
+    }
+
+    /// Checks values, duplicates and whether every property may appear in `location`
+    pub fn validate(&self, location: PropertyLocation) -> Result<(), PropertyError> {
+        for (idx, prop) in self.properties.iter().enumerate() {
+            let id = prop.identifier();
+            if !prop.has_valid_value() {
+                return Err(PropertyError::InvalidValue(id));
+            }
+            if !id.is_allowed_in(location) {
+                return Err(PropertyError::NotAllowed(id, location));
+            }
+            if !id.allows_multiple(location) && self.properties[..idx].iter().any(|p| p.identifier() == id) {
+                return Err(PropertyError::Duplicate(id));
+            }
+        }
+
+        Ok(())
+    }
+
+    property_accessors! {
+        /// Whether the payload is UTF-8 encoded character data
+        flag payload_format_indicator, set_payload_format_indicator: PayloadFormatIndicator;
+        /// Lifetime of the message in seconds
+        value message_expiry_interval, set_message_expiry_interval: MessageExpiryInterval(u32);
+        string content_type, set_content_type: ContentType;
+        string response_topic, set_response_topic: ResponseTopic;
+        binary correlation_data, set_correlation_data: CorrelationData;
+        /// Seconds the session is kept after the network connection is closed
+        value session_expiry_interval, set_session_expiry_interval: SessionExpiryInterval(u32);
+        string assigned_client_identifier, set_assigned_client_identifier: AssignedClientIdentifier;
+        /// Keep alive imposed by the server, in seconds
+        value server_keep_alive, set_server_keep_alive: ServerKeepAlive(u16);
+        string authentication_method, set_authentication_method: AuthenticationMethod;
+        binary authentication_data, set_authentication_data: AuthenticationData;
+        flag request_problem_information, set_request_problem_information: RequestProblemInformation;
+        /// Seconds to wait before publishing the will message
+        value will_delay_interval, set_will_delay_interval: WillDelayInterval(u32);
+        flag request_response_information, set_request_response_information: RequestResponseInformation;
+        string response_information, set_response_information: ResponseInformation;
+        string server_reference, set_server_reference: ServerReference;
+        string reason_string, set_reason_string: ReasonString;
+        /// Number of unacknowledged QoS 1 and QoS 2 publishes the sender is willing to process
+        value receive_maximum, set_receive_maximum: ReceiveMaximum(u16);
+        value topic_alias_maximum, set_topic_alias_maximum: TopicAliasMaximum(u16);
+        value topic_alias, set_topic_alias: TopicAlias(u16);
+        flag retain_available, set_retain_available: RetainAvailable;
+        value maximum_packet_size, set_maximum_packet_size: MaximumPacketSize(u32);
+        flag wildcard_subscription_available, set_wildcard_subscription_available: WildcardSubscriptionAvailable;
+        flag subscription_identifier_available, set_subscription_identifier_available: SubscriptionIdentifierAvailable;
+        flag shared_subscription_available, set_shared_subscription_available: SharedSubscriptionAvailable;
+    }
+
+    /// Maximum QoS supported by the server, `None` if it is absent and QoS 2 is supported
+    pub fn maximum_qos(&self) -> Option<QualityOfService> {
+        self.properties.iter().find_map(|p| match *p {
+            Property::MaximumQoS(0) => Some(QualityOfService::Level0),
+            Property::MaximumQoS(1) => Some(QualityOfService::Level1),
+            _ => None,
+        })
+    }
+
+    /// Sets the maximum QoS; `Level2` removes the property like `None`, as only 0 and 1 may be sent
+    pub fn set_maximum_qos(&mut self, qos: Option<QualityOfService>) {
+        let property = match qos {
+            Some(QualityOfService::Level0) => Some(Property::MaximumQoS(0)),
+            Some(QualityOfService::Level1) => Some(Property::MaximumQoS(1)),
+            Some(QualityOfService::Level2) | None => None,
+        };
+        self.replace(PropertyIdentifier::MaximumQoS, property);
+    }
+
+    /// Subscription identifiers, a `PUBLISH` may carry several
+    pub fn subscription_identifiers(&self) -> impl Iterator<Item = u32> + '_ {
+        self.properties.iter().filter_map(|p| match *p {
+            Property::SubscriptionIdentifier(id) => Some(id),
+            _ => None,
+        })
+    }
+
+    pub fn add_subscription_identifier(&mut self, id: u32) {
+        self.properties.push(Property::SubscriptionIdentifier(id));
+    }
+
+    /// User properties as name and value pairs, in order
+    pub fn user_properties(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.properties.iter().filter_map(|p| match *p {
+            Property::UserProperty(ref k, ref v) => Some((&k[..], &v[..])),
+            _ => None,
+        })
+    }
+
+    pub fn add_user_property<K: Into<String>, V: Into<String>>(&mut self, name: K, value: V) {
+        self.properties.push(Property::UserProperty(name.into(), value.into()));
+    }
+
+    /// Length of all properties, without the length prefix
+    fn properties_length(&self) -> u32 {
+        self.properties.iter().fold(0, |b, p| b + p.encoded_length())
+    }
+}
+
+impl From<Vec<Property>> for Properties {
+    fn from(properties: Vec<Property>) -> Properties {
+        Properties { properties }
+    }
+}
+
+impl Encodable for Properties {
+    type Err = PropertyError;
+
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), PropertyError> {
+        VarInt(self.properties_length()).encode(writer)?;
+        for prop in &self.properties {
+            prop.encode(writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn encoded_length(&self) -> u32 {
+        let len = self.properties_length();
+        VarInt(len).encoded_length() + len
+    }
+}
+
+impl Decodable for Properties {
+    type Err = PropertyError;
+    type Cond = PropertyLocation;
+
+    /// Decodes properties, validating them for `location` if it is provided
+    fn decode_with<R: Read>(reader: &mut R, location: Option<PropertyLocation>) -> Result<Properties, PropertyError> {
+        Properties::decode_limited(reader, location, &DecodeLimits::default())
+    }
+}
+
+impl LimitedDecodable for Properties {
+    fn decode_limited<R: Read>(reader: &mut R,
+                               location: Option<PropertyLocation>,
+                               limits: &DecodeLimits)
+                               -> Result<Properties, PropertyError> {
+        let VarInt(mut remaining) = VarInt::decode(reader)?;
+        let mut properties = Vec::new();
+
+        while remaining > 0 {
+            let prop = Property::decode_limited(reader, None, limits)?;
+            remaining = remaining
+                .checked_sub(prop.encoded_length())
+                .ok_or(PropertyError::LengthMismatch)?;
+            properties.push(prop);
+        }
+
+        let properties = Properties { properties };
+        if let Some(location) = location {
+            properties.validate(location)?;
+        }
+
+        Ok(properties)
+    }
+}
+
+/// Errors while parsing properties
+#[derive(Debug)]
+pub enum PropertyError {
+    IoError(io::Error),
+    StringEncodeError(StringEncodeError),
+    VarIntError(VarIntError),
+    InvalidIdentifier(u8),
+    LengthMismatch,
+    InvalidValue(PropertyIdentifier),
+    Duplicate(PropertyIdentifier),
+    NotAllowed(PropertyIdentifier, PropertyLocation),
+}
+
+impl fmt::Display for PropertyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PropertyError::IoError(ref err) => err.fmt(f),
+            PropertyError::StringEncodeError(ref err) => err.fmt(f),
+            PropertyError::VarIntError(ref err) => err.fmt(f),
+            PropertyError::InvalidIdentifier(id) => write!(f, "Invalid property identifier ({:#X})", id),
+            PropertyError::LengthMismatch => write!(f, "Properties do not match the property length"),
+            PropertyError::InvalidValue(id) => write!(f, "Invalid value for property {:?}", id),
+            PropertyError::Duplicate(id) => write!(f, "Duplicate property {:?}", id),
+            PropertyError::NotAllowed(id, location) => write!(f, "Property {:?} is not allowed in {}", id, location),
+        }
+    }
+}
+
+impl Error for PropertyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            PropertyError::IoError(ref err) => Some(err),
+            PropertyError::StringEncodeError(ref err) => Some(err),
+            PropertyError::VarIntError(ref err) => Some(err),
+            PropertyError::InvalidIdentifier(..) => None,
+            PropertyError::LengthMismatch => None,
+            PropertyError::InvalidValue(..) => None,
+            PropertyError::Duplicate(..) => None,
+            PropertyError::NotAllowed(..) => None,
+        }
+    }
+}
+
+impl From<io::Error> for PropertyError {
+    fn from(err: io::Error) -> PropertyError {
+        PropertyError::IoError(err)
+    }
+}
+
+impl From<StringEncodeError> for PropertyError {
+    fn from(err: StringEncodeError) -> PropertyError {
+        PropertyError::StringEncodeError(err)
+    }
+}
+
+impl From<VarIntError> for PropertyError {
+    fn from(err: VarIntError) -> PropertyError {
+        PropertyError::VarIntError(err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn test_properties_encode_decode() {
+        let props = Properties::from(vec![Property::SessionExpiryInterval(3600),
+                                          Property::ReceiveMaximum(20),
+                                          Property::ContentType("text/plain".to_owned()),
+                                          Property::CorrelationData(vec![1, 2, 3]),
+                                          Property::SubscriptionIdentifier(268_435_455),
+                                          Property::UserProperty("key".to_owned(), "value".to_owned())]);
+
+        let mut buf = Vec::new();
+        props.encode(&mut buf).unwrap();
+        assert_eq!(buf.len() as u32, props.encoded_length());
+
+        let decoded = Properties::decode(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(props, decoded);
+    }
+
+    #[test]
+    fn test_properties_empty() {
+        let mut buf = Vec::new();
+        Properties::new().encode(&mut buf).unwrap();
+        assert_eq!(&buf, &[0]);
+    }
+
+    #[test]
+    fn test_properties_invalid_identifier() {
+        let mut buf = Cursor::new(vec![2, 0x7F, 0]);
+        match Properties::decode(&mut buf) {
+            Err(PropertyError::InvalidIdentifier(0x7F)) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_properties_typed_accessors() {
+        let mut props = Properties::new();
+        props.set_session_expiry_interval(Some(3600));
+        props.set_session_expiry_interval(Some(60));
+        props.set_request_problem_information(Some(false));
+        props.set_authentication_data(Some(vec![1, 2]));
+        props.add_user_property("a", "1");
+        props.add_user_property("a", "2");
+
+        assert_eq!(props.len(), 5);
+        assert_eq!(props.session_expiry_interval(), Some(60));
+        assert_eq!(props.request_problem_information(), Some(false));
+        assert_eq!(props.authentication_data(), Some(&[1, 2][..]));
+        assert_eq!(props.user_properties().collect::<Vec<_>>(), vec![("a", "1"), ("a", "2")]);
+        assert_eq!(props.receive_maximum(), None);
+        assert!(props.validate(ControlType::Connect.into()).is_ok());
+
+        props.set_session_expiry_interval(None);
+        assert_eq!(props.get(PropertyIdentifier::SessionExpiryInterval), None);
+
+        let mut props = Properties::new();
+        props.set_maximum_qos(Some(QualityOfService::Level1));
+        assert_eq!(props.maximum_qos(), Some(QualityOfService::Level1));
+        props.set_maximum_qos(Some(QualityOfService::Level2));
+        assert_eq!(props.get(PropertyIdentifier::MaximumQoS), None);
+        assert_eq!(props.maximum_qos(), None);
+        assert!(props.validate(ControlType::ConnectAcknowledgement.into()).is_ok());
+    }
+
+    #[test]
+    fn test_properties_reject_duplicate() {
+        // Two Content Type properties
+        let buf = vec![8, 0x03, 0, 1, b'a', 0x03, 0, 1, b'b'];
+        assert!(Properties::decode(&mut Cursor::new(&buf[..])).is_ok());
+        match Properties::decode_with(&mut Cursor::new(&buf[..]), Some(ControlType::Publish.into())) {
+            Err(PropertyError::Duplicate(PropertyIdentifier::ContentType)) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        let props = Properties::from(vec![Property::SubscriptionIdentifier(1), Property::SubscriptionIdentifier(2)]);
+        assert!(props.validate(ControlType::Publish.into()).is_ok());
+        match props.validate(ControlType::Subscribe.into()) {
+            Err(PropertyError::Duplicate(PropertyIdentifier::SubscriptionIdentifier)) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_properties_reject_wrong_packet() {
+        let props = Properties::from(vec![Property::WillDelayInterval(10)]);
+        assert!(props.validate(PropertyLocation::Will).is_ok());
+        match props.validate(ControlType::Connect.into()) {
+            Err(PropertyError::NotAllowed(PropertyIdentifier::WillDelayInterval,
+                                          PropertyLocation::Packet(ControlType::Connect))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        let props = Properties::from(vec![Property::ReasonString("oops".to_owned())]);
+        assert!(props.validate(ControlType::PublishAcknowledgement.into()).is_ok());
+        assert!(props.validate(ControlType::Publish.into()).is_err());
+    }
+
+    #[test]
+    fn test_properties_reject_invalid_value() {
+        let props = Properties::from(vec![Property::ReceiveMaximum(0)]);
+        match props.validate(ControlType::Connect.into()) {
+            Err(PropertyError::InvalidValue(PropertyIdentifier::ReceiveMaximum)) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+}
@@ -1,16 +1,18 @@
 //! Protocol level header
 
-use std::convert::From;
-use std::io::{Read, Write};
-
-use byteorder::{ReadBytesExt, WriteBytesExt};
+use alloc::borrow::ToOwned;
+use core::convert::From;
+use core::fmt;
+use io::{Read, ReadBytesExt, Write, WriteBytesExt};
 
 use {Decodable, Encodable};
 use control::variable_header::VariableHeaderError;
 
+pub const SPEC_3_1: u8 = 0x03;
 pub const SPEC_3_1_1: u8 = 0x04;
+pub const SPEC_5_0: u8 = 0x05;
 
-/// Protocol level in MQTT (`0x04` in v3.1.1)
+/// Protocol level in MQTT (`0x03` in v3.1, `0x04` in v3.1.1, `0x05` in v5)
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub struct ProtocolLevel(pub u8);
 
@@ -34,3 +36,123 @@ impl Decodable for ProtocolLevel {
         reader.read_u8().map(ProtocolLevel).map_err(From::from)
     }
 }
+
+/// Supported versions of the protocol
+///
+/// Each version is identified by the protocol name and level sent in `CONNECT`.
+///
+/// ```rust
+/// use mqtt::control::variable_header::ProtocolVersion;
+///
+/// let version = ProtocolVersion::from_name_and_level("MQIsdp", 3).unwrap();
+/// assert_eq!(version, ProtocolVersion::V31);
+/// assert!(!version.is_valid_client_identifier("a-client-identifier-over-23"));
+/// assert!(ProtocolVersion::from_name_and_level("MQTT", 3).is_err());
+/// ```
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash)]
+pub enum ProtocolVersion {
+    /// MQTT 3.1, protocol name `MQIsdp`
+    V31,
+    /// MQTT 3.1.1
+    V311,
+    /// MQTT 5
+    V5,
+}
+
+impl ProtocolVersion {
+    /// Version of a protocol level
+    pub fn from_level(level: u8) -> Result<ProtocolVersion, VariableHeaderError> {
+        match level {
+            SPEC_3_1 => Ok(ProtocolVersion::V31),
+            SPEC_3_1_1 => Ok(ProtocolVersion::V311),
+            SPEC_5_0 => Ok(ProtocolVersion::V5),
+            _ => Err(VariableHeaderError::UnsupportedProtocolLevel(level)),
+        }
+    }
+
+    /// Version of a protocol level, checking that the protocol name matches it
+    pub fn from_name_and_level(name: &str, level: u8) -> Result<ProtocolVersion, VariableHeaderError> {
+        let version = ProtocolVersion::from_level(level)?;
+        if name != version.protocol_name() {
+            return Err(VariableHeaderError::InvalidProtocolName(name.to_owned()));
+        }
+
+        Ok(version)
+    }
+
+    /// Protocol name sent in `CONNECT`
+    pub fn protocol_name(self) -> &'static str {
+        match self {
+            ProtocolVersion::V31 => "MQIsdp",
+            ProtocolVersion::V311 | ProtocolVersion::V5 => "MQTT",
+        }
+    }
+
+    pub fn protocol_level(self) -> ProtocolLevel {
+        match self {
+            ProtocolVersion::V31 => ProtocolLevel(SPEC_3_1),
+            ProtocolVersion::V311 => ProtocolLevel(SPEC_3_1_1),
+            ProtocolVersion::V5 => ProtocolLevel(SPEC_5_0),
+        }
+    }
+
+    /// Whether a server must accept the client identifier
+    ///
+    /// MQTT 3.1 requires 1 to 23 characters. Later versions allow any identifier that fits in a
+    /// string; whether an empty one is acceptable depends on the session flags.
+    pub fn is_valid_client_identifier(self, ident: &str) -> bool {
+        match self {
+            ProtocolVersion::V31 => {
+                let len = ident.chars().count();
+                (1..=23).contains(&len)
+            }
+            ProtocolVersion::V311 | ProtocolVersion::V5 => true,
+        }
+    }
+
+    /// Whether `CONNACK` carries the session present flag, which is a reserved byte in MQTT 3.1
+    pub fn has_session_present(self) -> bool {
+        self != ProtocolVersion::V31
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProtocolVersion::V31 => write!(f, "MQTT 3.1"),
+            ProtocolVersion::V311 => write!(f, "MQTT 3.1.1"),
+            ProtocolVersion::V5 => write!(f, "MQTT 5"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_protocol_version_name_and_level() {
+        for &version in &[ProtocolVersion::V31, ProtocolVersion::V311, ProtocolVersion::V5] {
+            let level = version.protocol_level().0;
+            assert_eq!(ProtocolVersion::from_name_and_level(version.protocol_name(), level).unwrap(),
+                       version);
+        }
+
+        match ProtocolVersion::from_name_and_level("MQTT", 6) {
+            Err(VariableHeaderError::UnsupportedProtocolLevel(6)) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+        match ProtocolVersion::from_name_and_level("MQIsdp", 4) {
+            Err(VariableHeaderError::InvalidProtocolName(ref name)) if name == "MQIsdp" => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_protocol_version_client_identifier() {
+        assert!(!ProtocolVersion::V31.is_valid_client_identifier(""));
+        assert!(ProtocolVersion::V31.is_valid_client_identifier("12345678901234567890123"));
+        assert!(!ProtocolVersion::V31.is_valid_client_identifier("123456789012345678901234"));
+        assert!(ProtocolVersion::V311.is_valid_client_identifier("123456789012345678901234"));
+    }
+}
@@ -1,8 +1,10 @@
-use std::convert::From;
-use std::io::{Read, Write};
+use alloc::string::String;
+use core::convert::From;
+use io::{Read, Write};
 
 use {Decodable, Encodable};
 use control::variable_header::VariableHeaderError;
+use encodable::StringField;
 
 /// Protocol name in variable header
 ///
@@ -39,6 +41,6 @@ impl Decodable for ProtocolName {
     type Cond = ();
 
     fn decode_with<R: Read>(reader: &mut R, _rest: Option<()>) -> Result<ProtocolName, VariableHeaderError> {
-        Ok(ProtocolName(Decodable::decode(reader)?))
+        Ok(ProtocolName(String::decode_with(reader, Some(StringField::ProtocolName.into()))?))
     }
 }
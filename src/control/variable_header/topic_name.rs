@@ -1,5 +1,6 @@
-use std::convert::{From, Into};
-use std::io::{Read, Write};
+use alloc::string::String;
+use core::convert::{From, Into};
+use io::{Read, Write};
 
 use {Decodable, Encodable};
 use control::variable_header::VariableHeaderError;
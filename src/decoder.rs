@@ -0,0 +1,479 @@
+//! Push-style decoder for partial buffers
+//!
+//! `PacketDecoder` buffers chunks of bytes as they arrive from a non-blocking source and decodes
+//! a packet once its fixed header and remaining length are complete.
+//!
+//! ```rust
+//! use mqtt::Encodable;
+//! use mqtt::decoder::{Decoded, PacketDecoder};
+//! use mqtt::packet::{PingreqPacket, VariablePacket};
+//!
+//! let mut buf = Vec::new();
+//! PingreqPacket::new().encode(&mut buf).unwrap();
+//!
+//! let mut decoder: PacketDecoder = PacketDecoder::new();
+//! decoder.feed(&buf[..1]);
+//! assert_eq!(decoder.decode().unwrap(), Decoded::NeedMore(1));
+//!
+//! decoder.feed(&buf[1..]);
+//! assert_eq!(decoder.decode().unwrap(), Decoded::Packet(VariablePacket::new(PingreqPacket::new())));
+//! ```
+//!
+//! `DecodeLimits` bounds what a peer may send. An oversize frame is rejected as soon as its fixed
+//! header is complete, before any of it is buffered or allocated:
+//!
+//! ```rust
+//! use mqtt::decoder::{DecodeLimits, LimitError, PacketDecoder};
+//! use mqtt::packet::VariablePacketError;
+//!
+//! let limits = DecodeLimits { max_packet_size: 1024, ..DecodeLimits::default() };
+//! let mut decoder: PacketDecoder = PacketDecoder::with_limits(limits);
+//!
+//! // PUBLISH header announcing a remaining length of 268,435,455 bytes
+//! decoder.feed(b"\x30\xff\xff\xff\x7f");
+//! match decoder.decode() {
+//!     Err(VariablePacketError::LimitExceeded(LimitError::PacketTooLarge(..))) => {}
+//!     other => panic!("Unexpected result {:?}", other),
+//! }
+//! ```
+
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+use core::marker::PhantomData;
+use io::{self, Cursor, Read};
+
+use Decodable;
+use control::{ControlType, FixedHeader};
+use control::fixed_header::FixedHeaderError;
+use control::variable_header::protocol_level::SPEC_5_0;
+use encodable::{StringCond, StringField, VarInt};
+use packet::VariablePacket;
+
+/// Result of decoding from a partial buffer
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Decoded<T> {
+    /// A complete packet
+    Packet(T),
+    /// At least this many more bytes are needed to complete the frame
+    NeedMore(usize),
+}
+
+/// Length of the frame (fixed header and remaining length) at the start of `buf`
+///
+/// Returns `None` if the fixed header itself is not complete yet.
+pub fn frame_length(buf: &[u8]) -> Result<Option<usize>, FixedHeaderError> {
+    let mut rdr = Cursor::new(buf);
+    let remaining_length = match FixedHeader::decode(&mut rdr) {
+        Ok(fixed_header) => fixed_header.remaining_length,
+        // The packet type is reported once the whole frame is decoded
+        Err(FixedHeaderError::Unrecognized(_, len)) | Err(FixedHeaderError::ReservedType(_, len)) => len,
+        Err(FixedHeaderError::IoError(ref err)) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    Ok(Some(rdr.position() as usize + remaining_length as usize))
+}
+
+/// Limits applied to received packets
+///
+/// The defaults are the largest values the protocol can express, so nothing is rejected unless a
+/// limit is lowered.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct DecodeLimits {
+    /// Maximum size of a whole packet, including its fixed header
+    pub max_packet_size: usize,
+    /// Maximum length in bytes of the topic name of a `PUBLISH` packet
+    pub max_topic_length: usize,
+    /// Maximum length in bytes of the client identifier of a `CONNECT` packet
+    pub max_client_id_length: usize,
+    /// Whether to refuse the control characters U+0001 to U+001F and U+007F to U+009F in every
+    /// string of a packet
+    pub reject_control_characters: bool,
+}
+
+impl DecodeLimits {
+    /// Largest packet size allowed by the protocol, a 5 byte fixed header and its remaining length
+    pub const MAX_PACKET_SIZE: usize = 5 + VarInt::MAX as usize;
+
+    /// Checks the size of a frame given by `frame_length`
+    pub fn check_packet_size(&self, size: usize) -> Result<(), LimitError> {
+        if size > self.max_packet_size {
+            Err(LimitError::PacketTooLarge(size, self.max_packet_size))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks the length of the fields in a complete frame without decoding it
+    ///
+    /// Frames that are too short to locate a field pass, and are reported when decoded.
+    pub fn check_fields(&self, frame: &[u8]) -> Result<(), LimitError> {
+        let mut rdr = Cursor::new(frame);
+        let control_type = match FixedHeader::decode(&mut rdr) {
+            Ok(fixed_header) => fixed_header.packet_type.control_type,
+            Err(..) => return Ok(()),
+        };
+        let body = &frame[rdr.position() as usize..];
+
+        match control_type {
+            ControlType::Publish => match read_length(body, 0) {
+                Some(len) if len > self.max_topic_length => Err(LimitError::TopicTooLong(len, self.max_topic_length)),
+                _ => Ok(()),
+            },
+            ControlType::Connect => match client_id_offset(body).and_then(|pos| read_length(body, pos)) {
+                Some(len) if len > self.max_client_id_length => {
+                    Err(LimitError::ClientIdTooLong(len, self.max_client_id_length))
+                }
+                _ => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Condition for decoding the string in `field` under these limits
+    pub fn string_cond(&self, field: StringField) -> StringCond {
+        StringCond {
+            field,
+            reject_control: self.reject_control_characters,
+        }
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> DecodeLimits {
+        DecodeLimits {
+            max_packet_size: DecodeLimits::MAX_PACKET_SIZE,
+            max_topic_length: u16::MAX as usize,
+            max_client_id_length: u16::MAX as usize,
+            reject_control_characters: false,
+        }
+    }
+}
+
+// Big endian length prefix at `pos`
+fn read_length(buf: &[u8], pos: usize) -> Option<usize> {
+    let bytes = buf.get(pos..pos + 2)?;
+    Some((bytes[0] as usize) << 8 | bytes[1] as usize)
+}
+
+// Offset of the client identifier in the body of a CONNECT packet
+fn client_id_offset(body: &[u8]) -> Option<usize> {
+    let level_pos = 2 + read_length(body, 0)?;
+    let level = *body.get(level_pos)?;
+    // Protocol level, connect flags and keep alive
+    let mut pos = level_pos + 4;
+
+    if level == SPEC_5_0 {
+        let mut rdr = Cursor::new(body.get(pos..)?);
+        let VarInt(properties_len) = VarInt::decode(&mut rdr).ok()?;
+        pos += rdr.position() as usize + properties_len as usize;
+    }
+
+    Some(pos)
+}
+
+/// Decoding under `DecodeLimits`
+///
+/// Applies the string rules of the limits; sizes and lengths are checked on the whole frame by
+/// `DecodeLimits::check_packet_size` and `DecodeLimits::check_fields`. `Decodable::decode_with`
+/// decodes with the default limits.
+pub trait LimitedDecodable: Decodable {
+    /// Decodes like `decode_with`, refusing the strings that `limits` rejects
+    fn decode_limited<R: Read>(reader: &mut R, cond: Option<Self::Cond>, limits: &DecodeLimits)
+                               -> Result<Self, Self::Err>;
+}
+
+/// A received packet exceeds one of the `DecodeLimits`
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum LimitError {
+    /// Packet size and the limit
+    PacketTooLarge(usize, usize),
+    /// Topic name length and the limit
+    TopicTooLong(usize, usize),
+    /// Client identifier length and the limit
+    ClientIdTooLong(usize, usize),
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LimitError::PacketTooLarge(size, max) => write!(f, "Packet of {} bytes exceeds the limit of {}", size, max),
+            LimitError::TopicTooLong(len, max) => write!(f, "Topic name of {} bytes exceeds the limit of {}", len, max),
+            LimitError::ClientIdTooLong(len, max) => {
+                write!(f, "Client identifier of {} bytes exceeds the limit of {}", len, max)
+            }
+        }
+    }
+}
+
+impl Error for LimitError {}
+
+/// Incremental decoder of `VariablePacket` or `v5::VariablePacket`
+///
+/// A frame that fails to decode is dropped, so decoding may continue with the next one. Errors in
+/// the fixed header, and frames larger than `max_packet_size`, leave the buffer as it is; the
+/// connection should be closed.
+#[derive(Debug, Clone)]
+pub struct PacketDecoder<P = VariablePacket> {
+    buf: Vec<u8>,
+    limits: DecodeLimits,
+    _packet: PhantomData<P>,
+}
+
+impl<P> PacketDecoder<P>
+where
+    P: LimitedDecodable<Cond = FixedHeader>,
+    P::Err: From<FixedHeaderError> + From<LimitError>,
+{
+    pub fn new() -> PacketDecoder<P> {
+        PacketDecoder::with_limits(DecodeLimits::default())
+    }
+
+    pub fn with_limits(limits: DecodeLimits) -> PacketDecoder<P> {
+        PacketDecoder {
+            buf: Vec::new(),
+            limits,
+            _packet: PhantomData,
+        }
+    }
+
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    /// Appends received bytes
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Number of buffered bytes that have not been decoded yet
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Discards all buffered bytes
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+
+    /// Decodes the next packet from the buffered bytes
+    pub fn decode(&mut self) -> Result<Decoded<P>, P::Err> {
+        let frame_len = match frame_length(&self.buf)? {
+            Some(len) => len,
+            None => return Ok(Decoded::NeedMore(1)),
+        };
+        self.limits.check_packet_size(frame_len)?;
+
+        if self.buf.len() < frame_len {
+            return Ok(Decoded::NeedMore(frame_len - self.buf.len()));
+        }
+
+        let result = match self.limits.check_fields(&self.buf[..frame_len]) {
+            Ok(()) => P::decode_limited(&mut Cursor::new(&self.buf[..frame_len]), None, &self.limits),
+            Err(err) => Err(err.into()),
+        };
+        self.buf.drain(..frame_len);
+        result.map(Decoded::Packet)
+    }
+}
+
+impl<P> Default for PacketDecoder<P>
+where
+    P: LimitedDecodable<Cond = FixedHeader>,
+    P::Err: From<FixedHeaderError> + From<LimitError>,
+{
+    fn default() -> PacketDecoder<P> {
+        PacketDecoder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use {Encodable, QualityOfService};
+    use control::variable_header::{Properties, Property, PropertyIdentifier, ReasonCode};
+    use encodable::InvalidCodePoint;
+    use packet::{v5, ConnectPacket, LastWill, PingreqPacket, PublishPacket, QoSWithPacketIdentifier, SubscribePacket,
+                 UnsubscribePacket, VariablePacketError};
+    use topic_filter::TopicFilter;
+    use topic_name::TopicName;
+
+    #[test]
+    fn test_packet_decoder_chunks() {
+        let packet = VariablePacket::new(PublishPacket::new(TopicName::new("a/b").unwrap(),
+                                                            QoSWithPacketIdentifier::Level1(10),
+                                                            vec![0u8; 300]));
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+        packet.encode(&mut buf).unwrap();
+
+        let mut decoder: PacketDecoder = PacketDecoder::new();
+        assert_eq!(decoder.decode().unwrap(), Decoded::NeedMore(1));
+
+        decoder.feed(&buf[..2]);
+        assert_eq!(decoder.decode().unwrap(), Decoded::NeedMore(1));
+
+        // Fixed header is complete, 3 bytes and 307 remaining
+        decoder.feed(&buf[2..10]);
+        assert_eq!(decoder.decode().unwrap(), Decoded::NeedMore(300));
+
+        decoder.feed(&buf[10..]);
+        assert_eq!(decoder.decode().unwrap(), Decoded::Packet(packet.clone()));
+        assert_eq!(decoder.decode().unwrap(), Decoded::Packet(packet));
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn test_packet_decoder_skips_invalid_frame() {
+        let mut decoder: PacketDecoder = PacketDecoder::new();
+        // Reserved packet type 0, then a PINGREQ
+        decoder.feed(b"\x00\x01\xff\xc0\x00");
+
+        match decoder.decode() {
+            Err(VariablePacketError::ReservedPacket(0, _)) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+        assert_eq!(decoder.decode().unwrap(),
+                   Decoded::Packet(VariablePacket::new(PingreqPacket::new())));
+    }
+
+    #[test]
+    fn test_packet_decoder_v5() {
+        let packet = v5::VariablePacket::new(v5::DisconnectPacket::new(ReasonCode::ServerShuttingDown));
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+
+        let mut decoder = PacketDecoder::<v5::VariablePacket>::new();
+        decoder.feed(&buf);
+        assert_eq!(decoder.decode().unwrap(), Decoded::Packet(packet));
+    }
+
+    #[test]
+    fn test_packet_decoder_rejects_oversize_frame() {
+        let limits = DecodeLimits { max_packet_size: 64, ..DecodeLimits::default() };
+        let mut decoder: PacketDecoder = PacketDecoder::with_limits(limits);
+
+        // Fixed header of a 3 + 300 byte PUBLISH, rejected before the rest arrives
+        decoder.feed(b"\x32\xac\x02");
+        match decoder.decode() {
+            Err(VariablePacketError::LimitExceeded(LimitError::PacketTooLarge(303, 64))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_packet_decoder_field_limits() {
+        let limits = DecodeLimits {
+            max_topic_length: 3,
+            max_client_id_length: 4,
+            ..DecodeLimits::default()
+        };
+
+        let mut buf = Vec::new();
+        PublishPacket::new(TopicName::new("a/b/c").unwrap(), QoSWithPacketIdentifier::Level0, "x")
+            .encode(&mut buf)
+            .unwrap();
+        ConnectPacket::new("MQTT", "client").encode(&mut buf).unwrap();
+        PublishPacket::new(TopicName::new("a/b").unwrap(), QoSWithPacketIdentifier::Level0, "x")
+            .encode(&mut buf)
+            .unwrap();
+
+        let mut decoder: PacketDecoder = PacketDecoder::with_limits(limits);
+        decoder.feed(&buf);
+        match decoder.decode() {
+            Err(VariablePacketError::LimitExceeded(LimitError::TopicTooLong(5, 3))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+        match decoder.decode() {
+            Err(VariablePacketError::LimitExceeded(LimitError::ClientIdTooLong(6, 4))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+        assert!(match decoder.decode().unwrap() {
+                    Decoded::Packet(VariablePacket::PublishPacket(..)) => true,
+                    _ => false,
+                });
+
+        // The client identifier follows the properties in MQTT 5
+        let mut buf = Vec::new();
+        let mut connect = v5::ConnectPacket::new("client");
+        connect.set_properties(Properties::from(vec![Property::ReceiveMaximum(10)])).unwrap();
+        connect.encode(&mut buf).unwrap();
+        assert_eq!(limits.check_fields(&buf), Err(LimitError::ClientIdTooLong(6, 4)));
+    }
+
+    // The `InvalidCodePoint` that failed decoding, found through the error sources
+    fn invalid_code_point(err: &(dyn Error + 'static)) -> Option<InvalidCodePoint> {
+        let mut source = Some(err);
+        while let Some(err) = source {
+            if let Some(err) = err.downcast_ref::<InvalidCodePoint>() {
+                return Some(*err);
+            }
+            source = err.source();
+        }
+        None
+    }
+
+    #[test]
+    fn test_packet_decoder_control_characters() {
+        let mut buf = Vec::new();
+        PublishPacket::new(TopicName::new("a/\u{85}").unwrap(), QoSWithPacketIdentifier::Level0, "x")
+            .encode(&mut buf)
+            .unwrap();
+
+        let mut decoder: PacketDecoder = PacketDecoder::new();
+        decoder.feed(&buf);
+        assert!(match decoder.decode().unwrap() {
+                    Decoded::Packet(VariablePacket::PublishPacket(..)) => true,
+                    _ => false,
+                });
+
+        let limits = DecodeLimits { reject_control_characters: true, ..DecodeLimits::default() };
+        let mut decoder: PacketDecoder = PacketDecoder::with_limits(limits);
+        decoder.feed(&buf);
+        let err = invalid_code_point(&decoder.decode().unwrap_err()).unwrap();
+        assert_eq!(err.code_point, '\u{85}');
+        assert_eq!(err.position, 2);
+        assert_eq!(err.field, Some(StringField::TopicName));
+
+        let will = LastWill::new(TopicName::new("will\t").unwrap(), QualityOfService::Level0, "x");
+        let mut with_will = ConnectPacket::new("MQTT", "client");
+        with_will.set_will(Some(will));
+        let mut with_user_name = ConnectPacket::new("MQTT", "client");
+        with_user_name.set_user_name(Some("user\n".to_owned()));
+        let filter = TopicFilter::new("a/\u{1b}").unwrap();
+        let subscribe = SubscribePacket::new(1, vec![(filter, QualityOfService::Level0)]);
+        let unsubscribe = UnsubscribePacket::new(1, vec![TopicFilter::new("\u{9f}").unwrap()]);
+        let client_id = ConnectPacket::new("MQTT", "client\t1");
+        let packets = vec![(VariablePacket::new(client_id), StringField::ClientIdentifier),
+                           (VariablePacket::new(with_will), StringField::WillTopic),
+                           (VariablePacket::new(with_user_name), StringField::UserName),
+                           (VariablePacket::new(subscribe), StringField::TopicFilter),
+                           (VariablePacket::new(unsubscribe), StringField::TopicFilter)];
+        for (packet, field) in packets {
+            let mut buf = Vec::new();
+            packet.encode(&mut buf).unwrap();
+            decoder.feed(&buf);
+            assert_eq!(invalid_code_point(&decoder.decode().unwrap_err()).unwrap().field, Some(field));
+        }
+
+        // The password is binary data, not subject to the string rules
+        let mut connect = ConnectPacket::new("MQTT", "client");
+        connect.set_user_name(Some("user".to_owned()));
+        connect.set_password(Some(b"\x00\x01".to_vec()));
+        let mut buf = Vec::new();
+        connect.encode(&mut buf).unwrap();
+        decoder.feed(&buf);
+        assert_eq!(decoder.decode().unwrap(), Decoded::Packet(VariablePacket::new(connect)));
+
+        let mut publish = v5::PublishPacket::new(TopicName::new("a/b").unwrap(), QoSWithPacketIdentifier::Level0, "x");
+        publish.set_properties(Properties::from(vec![Property::ContentType("text\u{7f}".to_owned())])).unwrap();
+        let mut buf = Vec::new();
+        publish.encode(&mut buf).unwrap();
+        let mut decoder = PacketDecoder::<v5::VariablePacket>::with_limits(limits);
+        decoder.feed(&buf);
+        let err = invalid_code_point(&decoder.decode().unwrap_err()).unwrap();
+        assert_eq!(err.field, Some(StringField::Property(PropertyIdentifier::ContentType)));
+    }
+}
@@ -1,13 +1,17 @@
 //! Encodable traits
 
-use std::convert::From;
-use std::error::Error;
-use std::fmt;
-use std::io::{self, Read, Write};
-use std::marker::Sized;
-use std::string::FromUtf8Error;
+use alloc::string::{FromUtf8Error, String};
+use alloc::vec::Vec;
+use core::convert::From;
+use core::error::Error;
+use core::fmt;
+use core::marker::Sized;
+use core::str::{self, Utf8Error};
+use io::{self, Read, ReadBytesExt, Write, WriteBytesExt};
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::BigEndian;
+
+use control::variable_header::PropertyIdentifier;
 
 /// Methods for encoding an Object to bytes according to MQTT specification
 pub trait Encodable {
@@ -17,6 +21,32 @@ pub trait Encodable {
     fn encode<W: Write>(&self, writer: &mut W) -> Result<(), Self::Err>;
     /// Length of bytes after encoded
     fn encoded_length(&self) -> u32;
+
+    /// Encodes into the start of `buf` without allocating, returning the number of bytes written
+    ///
+    /// Fails with `SliceEncodeError::BufferTooSmall` before writing anything if `buf` is shorter
+    /// than `encoded_length()`. Any error of `encode` itself is returned as `SliceEncodeError::Encode`.
+    ///
+    /// ```rust
+    /// use mqtt::Encodable;
+    /// use mqtt::packet::PingreqPacket;
+    ///
+    /// let mut buf = [0u8; 8];
+    /// let len = PingreqPacket::new().encode_to_slice(&mut buf).unwrap();
+    /// assert_eq!(&buf[..len], b"\xc0\x00");
+    /// assert!(PingreqPacket::new().encode_to_slice(&mut buf[..1]).is_err());
+    /// ```
+    fn encode_to_slice(&self, buf: &mut [u8]) -> Result<usize, SliceEncodeError<Self::Err>> {
+        let required = self.encoded_length() as usize;
+        let available = buf.len();
+        if available < required {
+            return Err(SliceEncodeError::BufferTooSmall(BufferTooSmall { required, available }));
+        }
+
+        let mut writer = &mut buf[..];
+        self.encode(&mut writer).map_err(SliceEncodeError::Encode)?;
+        Ok(available - writer.len())
+    }
 }
 
 /// Methods for decoding bytes to an Object according to MQTT specification
@@ -74,11 +104,15 @@ impl Encodable for String {
     }
 }
 
+/// Decodes a UTF-8 encoded string, rejecting the code points MQTT forbids
+///
+/// The field is reported in `InvalidCodePoint` errors. Control characters are only rejected with
+/// `StringCond::reject_control`.
 impl Decodable for String {
     type Err = StringEncodeError;
-    type Cond = ();
+    type Cond = StringCond;
 
-    fn decode_with<R: Read>(reader: &mut R, _rest: Option<()>) -> Result<String, StringEncodeError> {
+    fn decode_with<R: Read>(reader: &mut R, cond: Option<StringCond>) -> Result<String, StringEncodeError> {
         let len = reader.read_u16::<BigEndian>()? as usize;
         let mut buf = Vec::with_capacity(len);
         unsafe {
@@ -86,10 +120,129 @@ impl Decodable for String {
         }
         reader.read_exact(&mut buf)?;
 
-        String::from_utf8(buf).map_err(StringEncodeError::FromUtf8Error)
+        let s = String::from_utf8(buf).map_err(StringEncodeError::FromUtf8Error)?;
+        match cond {
+            Some(cond) => check_str(&s, Some(cond.field), cond.reject_control)?,
+            None => check_str(&s, None, false)?,
+        }
+        Ok(s)
     }
 }
 
+/// Decodes a length-prefixed string borrowed from the start of `buf`
+///
+/// Returns the string and the number of bytes consumed, including the length prefix.
+pub fn decode_str(buf: &[u8], field: Option<StringField>) -> Result<(&str, usize), StringEncodeError> {
+    if buf.len() < 2 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    let len = ((buf[0] as usize) << 8) | buf[1] as usize;
+    let bytes = buf.get(2..2 + len)
+                   .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+
+    let s = str::from_utf8(bytes).map_err(StringEncodeError::Utf8Error)?;
+    check_str(s, field, false)?;
+    Ok((s, 2 + len))
+}
+
+/// Checks the rules for UTF-8 encoded strings in MQTT
+///
+/// U+0000 is always rejected [MQTT-1.5.3-2]. Surrogates cannot occur in a `str`, since they are
+/// rejected as ill-formed UTF-8 when decoding [MQTT-1.5.3-1]. The control characters U+0001 to
+/// U+001F and U+007F to U+009F are allowed by the specification but a receiver may refuse them, so
+/// they are only rejected with `reject_control`. Decoding allows them, unless refused with
+/// `DecodeLimits::reject_control_characters`.
+///
+/// ```rust
+/// use mqtt::encodable::{check_str, StringField};
+///
+/// assert!(check_str("a/b", None, true).is_ok());
+/// assert!(check_str("a\tb", None, false).is_ok());
+///
+/// let err = check_str("a\tb", Some(StringField::TopicName), true).unwrap_err();
+/// assert_eq!(err.code_point, '\t');
+/// assert_eq!(err.field, Some(StringField::TopicName));
+/// ```
+pub fn check_str(s: &str, field: Option<StringField>, reject_control: bool) -> Result<(), InvalidCodePoint> {
+    let invalid = s.char_indices().find(|&(_, c)| {
+        c == '\u{0}' || (reject_control && (('\u{1}'..='\u{1F}').contains(&c) || ('\u{7F}'..='\u{9F}').contains(&c)))
+    });
+
+    match invalid {
+        Some((position, code_point)) => Err(InvalidCodePoint {
+            code_point,
+            position,
+            field,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Fields of a packet holding a UTF-8 encoded string
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum StringField {
+    ProtocolName,
+    ClientIdentifier,
+    WillTopic,
+    UserName,
+    TopicName,
+    TopicFilter,
+    Property(PropertyIdentifier),
+}
+
+/// Condition for decoding a `String`: its field and whether to refuse control characters
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct StringCond {
+    pub field: StringField,
+    /// Whether to refuse U+0001 to U+001F and U+007F to U+009F, see `check_str`
+    pub reject_control: bool,
+}
+
+impl From<StringField> for StringCond {
+    fn from(field: StringField) -> StringCond {
+        StringCond {
+            field,
+            reject_control: false,
+        }
+    }
+}
+
+impl fmt::Display for StringField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StringField::ProtocolName => write!(f, "protocol name"),
+            StringField::ClientIdentifier => write!(f, "client identifier"),
+            StringField::WillTopic => write!(f, "will topic"),
+            StringField::UserName => write!(f, "user name"),
+            StringField::TopicName => write!(f, "topic name"),
+            StringField::TopicFilter => write!(f, "topic filter"),
+            StringField::Property(id) => write!(f, "{:?} property", id),
+        }
+    }
+}
+
+/// A code point that is not allowed in an MQTT string
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct InvalidCodePoint {
+    pub code_point: char,
+    /// Byte offset in the string
+    pub position: usize,
+    /// Field of the string, if known
+    pub field: Option<StringField>,
+}
+
+impl fmt::Display for InvalidCodePoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid code point U+{:04X} at byte {}", self.code_point as u32, self.position)?;
+        match self.field {
+            Some(field) => write!(f, " of {}", field),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Error for InvalidCodePoint {}
+
 impl Encodable for Vec<u8> {
     type Err = io::Error;
 
@@ -109,11 +262,12 @@ impl Decodable for Vec<u8> {
     fn decode_with<R: Read>(reader: &mut R, length: Option<u32>) -> Result<Vec<u8>, io::Error> {
         match length {
             Some(length) => {
-                let mut buf = Vec::with_capacity(length as usize);
-                unsafe {
-                    buf.set_len(length as usize);
+                // The length comes from the wire, so the buffer only grows as bytes actually arrive
+                let mut buf = Vec::new();
+                reader.take(length as u64).read_to_end(&mut buf)?;
+                if buf.len() != length as usize {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
                 }
-                reader.read_exact(&mut buf)?;
                 Ok(buf)
             }
             None => {
@@ -179,6 +333,70 @@ impl Decodable for VarBytes {
     }
 }
 
+/// Variable byte integer
+///
+/// Encodes 7 bits per byte with the most significant bit as the continuation flag, for values up to
+/// 268,435,455. Used for the remaining length and by MQTT 5 for property lengths.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct VarInt(pub u32);
+
+impl VarInt {
+    /// Maximum value that can be represented in four bytes
+    pub const MAX: u32 = 0x0FFF_FFFF;
+}
+
+impl Encodable for VarInt {
+    type Err = io::Error;
+
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        assert!(self.0 <= VarInt::MAX);
+
+        let mut cur = self.0;
+        loop {
+            let mut byte = (cur & 0x7F) as u8;
+            cur >>= 7;
+
+            if cur > 0 {
+                byte |= 0x80;
+            }
+
+            writer.write_u8(byte)?;
+
+            if cur == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn encoded_length(&self) -> u32 {
+        match self.0 {
+            0..=127 => 1,
+            128..=16_383 => 2,
+            16_384..=2_097_151 => 3,
+            _ => 4,
+        }
+    }
+}
+
+impl Decodable for VarInt {
+    type Err = VarIntError;
+    type Cond = ();
+
+    fn decode_with<R: Read>(reader: &mut R, _rest: Option<()>) -> Result<VarInt, VarIntError> {
+        let mut cur = 0u32;
+        for i in 0..4 {
+            let byte = reader.read_u8()?;
+            cur |= (u32::from(byte) & 0x7F) << (7 * i);
+
+            if byte & 0x80 == 0 {
+                return Ok(VarInt(cur));
+            }
+        }
+
+        Err(VarIntError::Malformed)
+    }
+}
+
 /// Error that indicates we won't have any errors
 #[derive(Debug)]
 pub struct NoError;
@@ -195,38 +413,85 @@ impl Error for NoError {
     }
 }
 
+/// Error of `Encodable::encode_to_slice` when the buffer cannot hold the encoded bytes
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct BufferTooSmall {
+    /// Length of the encoded bytes
+    pub required: usize,
+    /// Length of the buffer
+    pub available: usize,
+}
+
+impl fmt::Display for BufferTooSmall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Buffer too small, {} bytes required but {} available", self.required, self.available)
+    }
+}
+
+impl Error for BufferTooSmall {}
+
+/// Error of `Encodable::encode_to_slice`
+#[derive(Debug)]
+pub enum SliceEncodeError<E> {
+    BufferTooSmall(BufferTooSmall),
+    /// Error returned by `Encodable::encode` itself
+    Encode(E),
+}
+
+impl<E: fmt::Display> fmt::Display for SliceEncodeError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SliceEncodeError::BufferTooSmall(ref err) => err.fmt(f),
+            SliceEncodeError::Encode(ref err) => err.fmt(f),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for SliceEncodeError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            SliceEncodeError::BufferTooSmall(ref err) => Some(err),
+            SliceEncodeError::Encode(ref err) => Some(err),
+        }
+    }
+}
+
+impl<E> From<BufferTooSmall> for SliceEncodeError<E> {
+    fn from(err: BufferTooSmall) -> SliceEncodeError<E> {
+        SliceEncodeError::BufferTooSmall(err)
+    }
+}
+
 /// Errors while parsing to a string
 #[derive(Debug)]
 pub enum StringEncodeError {
     IoError(io::Error),
     FromUtf8Error(FromUtf8Error),
+    Utf8Error(Utf8Error),
+    InvalidCodePoint(InvalidCodePoint),
     MalformedData,
 }
 
 impl fmt::Display for StringEncodeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            &StringEncodeError::IoError(ref err) => err.fmt(f),
-            &StringEncodeError::FromUtf8Error(ref err) => err.fmt(f),
-            &StringEncodeError::MalformedData => write!(f, "Malformed data"),
+        match *self {
+            StringEncodeError::IoError(ref err) => err.fmt(f),
+            StringEncodeError::FromUtf8Error(ref err) => err.fmt(f),
+            StringEncodeError::Utf8Error(ref err) => err.fmt(f),
+            StringEncodeError::InvalidCodePoint(ref err) => err.fmt(f),
+            StringEncodeError::MalformedData => write!(f, "Malformed data"),
         }
     }
 }
 
 impl Error for StringEncodeError {
-    fn description(&self) -> &str {
-        match self {
-            &StringEncodeError::IoError(ref err) => err.description(),
-            &StringEncodeError::FromUtf8Error(ref err) => err.description(),
-            &StringEncodeError::MalformedData => "Malformed data",
-        }
-    }
-
     fn source(&self) -> Option<&(dyn Error + 'static)> {
-        match self {
-            &StringEncodeError::IoError(ref err) => Some(err),
-            &StringEncodeError::FromUtf8Error(ref err) => Some(err),
-            &StringEncodeError::MalformedData => None,
+        match *self {
+            StringEncodeError::IoError(ref err) => Some(err),
+            StringEncodeError::FromUtf8Error(ref err) => Some(err),
+            StringEncodeError::Utf8Error(ref err) => Some(err),
+            StringEncodeError::InvalidCodePoint(ref err) => Some(err),
+            StringEncodeError::MalformedData => None,
         }
     }
 }
@@ -243,6 +508,43 @@ impl From<FromUtf8Error> for StringEncodeError {
     }
 }
 
+impl From<InvalidCodePoint> for StringEncodeError {
+    fn from(err: InvalidCodePoint) -> StringEncodeError {
+        StringEncodeError::InvalidCodePoint(err)
+    }
+}
+
+/// Errors while decoding a variable byte integer
+#[derive(Debug)]
+pub enum VarIntError {
+    IoError(io::Error),
+    Malformed,
+}
+
+impl fmt::Display for VarIntError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VarIntError::IoError(ref err) => err.fmt(f),
+            VarIntError::Malformed => write!(f, "Malformed variable byte integer"),
+        }
+    }
+}
+
+impl Error for VarIntError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            VarIntError::IoError(ref err) => Some(err),
+            VarIntError::Malformed => None,
+        }
+    }
+}
+
+impl From<io::Error> for VarIntError {
+    fn from(err: io::Error) -> VarIntError {
+        VarIntError::IoError(err)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -266,4 +568,103 @@ mod test {
 
         assert_eq!(decoded, bytes);
     }
+
+    #[test]
+    fn encode_to_slice_errors() {
+        struct Malformed;
+
+        impl Encodable for Malformed {
+            type Err = StringEncodeError;
+
+            fn encode<W: Write>(&self, _: &mut W) -> Result<(), StringEncodeError> {
+                Err(StringEncodeError::MalformedData)
+            }
+
+            fn encoded_length(&self) -> u32 {
+                1
+            }
+        }
+
+        let mut buf = [0u8; 4];
+        match Malformed.encode_to_slice(&mut buf[..0]) {
+            Err(SliceEncodeError::BufferTooSmall(BufferTooSmall { required: 1, available: 0 })) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+        // Not mistaken for a full buffer
+        match Malformed.encode_to_slice(&mut buf) {
+            Err(SliceEncodeError::Encode(StringEncodeError::MalformedData)) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn str_decode_borrowed() {
+        let buf = b"\x00\x03a/bxyz";
+        assert_eq!(decode_str(&buf[..], None).unwrap(), ("a/b", 5));
+
+        match decode_str(&buf[..4], None) {
+            Err(StringEncodeError::IoError(ref err)) if err.kind() == io::ErrorKind::UnexpectedEof => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+        match decode_str(b"\x00\x01\xff", None) {
+            Err(StringEncodeError::Utf8Error(..)) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn string_decode_rules() {
+        let decoded = String::decode(&mut Cursor::new(&b"\x00\x03a\x7fb"[..])).unwrap();
+        assert_eq!(decoded, "a\u{7f}b");
+
+        match String::decode_with(&mut Cursor::new(&b"\x00\x03a\x00b"[..]), Some(StringField::UserName.into())) {
+            Err(StringEncodeError::InvalidCodePoint(err)) => {
+                assert_eq!(err, InvalidCodePoint { code_point: '\u{0}', position: 1, field: Some(StringField::UserName) });
+                assert_eq!(err.to_string(), "Invalid code point U+0000 at byte 1 of user name");
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        let cond = StringCond { field: StringField::UserName, reject_control: true };
+        match String::decode_with(&mut Cursor::new(&b"\x00\x03a\x7fb"[..]), Some(cond)) {
+            Err(StringEncodeError::InvalidCodePoint(err)) => assert_eq!(err.code_point, '\u{7f}'),
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        // Encoded surrogate U+D800
+        match String::decode(&mut Cursor::new(&b"\x00\x03\xed\xa0\x80"[..])) {
+            Err(StringEncodeError::FromUtf8Error(..)) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        assert_eq!(check_str("a\u{85}", None, true).unwrap_err().code_point, '\u{85}');
+        assert!(check_str("a\u{a0}", None, true).is_ok());
+    }
+
+    #[test]
+    fn varint_encode() {
+        for &(value, ref expected) in &[(0, vec![0x00]),
+                                        (127, vec![0x7F]),
+                                        (128, vec![0x80, 0x01]),
+                                        (16_383, vec![0xFF, 0x7F]),
+                                        (2_097_152, vec![0x80, 0x80, 0x80, 0x01]),
+                                        (VarInt::MAX, vec![0xFF, 0xFF, 0xFF, 0x7F])] {
+            let var = VarInt(value);
+            let mut buf = Vec::new();
+            var.encode(&mut buf).unwrap();
+
+            assert_eq!(&buf, expected);
+            assert_eq!(var.encoded_length() as usize, expected.len());
+            assert_eq!(VarInt::decode(&mut Cursor::new(buf)).unwrap(), var);
+        }
+    }
+
+    #[test]
+    fn varint_decode_malformed() {
+        let mut reader = Cursor::new(vec![0xFF, 0xFF, 0xFF, 0xFF, 0x01]);
+        match VarInt::decode(&mut reader) {
+            Err(VarIntError::Malformed) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
 }
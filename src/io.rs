@@ -0,0 +1,293 @@
+//! Readers and writers that packets are encoded to and decoded from
+//!
+//! With the `std` feature (on by default) these are the `std::io` traits and types themselves, so
+//! sockets, files and buffers work as before. Without it the crate is `no_std` + `alloc` and this
+//! module provides a minimal stand-in: `Read` is implemented for byte slices, `Cursor` and `Take`,
+//! `Write` for `Vec<u8>`, `&mut [u8]` and `Cursor<&mut [u8]>`, and an `Error` carries only its
+//! `ErrorKind`.
+
+#[cfg(feature = "std")]
+pub use std::io::{Cursor, Error, ErrorKind, IoSlice, Read, Result, Take, Write};
+
+#[cfg(feature = "std")]
+pub use byteorder::{ReadBytesExt, WriteBytesExt};
+
+#[cfg(not(feature = "std"))]
+pub use self::bare::*;
+
+#[cfg(not(feature = "std"))]
+mod bare {
+    use alloc::vec::Vec;
+    use core::cmp;
+    use core::error;
+    use core::fmt;
+    use core::mem;
+    use core::result;
+
+    use byteorder::ByteOrder;
+
+    pub type Result<T> = result::Result<T, Error>;
+
+    /// Category of an I/O error, mirroring the subset of `std::io::ErrorKind` used by the codec
+    #[derive(Debug, Eq, PartialEq, Copy, Clone)]
+    pub enum ErrorKind {
+        InvalidInput,
+        InvalidData,
+        WriteZero,
+        UnexpectedEof,
+        Other,
+    }
+
+    /// I/O error
+    #[derive(Debug)]
+    pub struct Error {
+        kind: ErrorKind,
+    }
+
+    impl Error {
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Error {
+            Error { kind }
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let msg = match self.kind {
+                ErrorKind::InvalidInput => "invalid input parameter",
+                ErrorKind::InvalidData => "invalid data",
+                ErrorKind::WriteZero => "write zero",
+                ErrorKind::UnexpectedEof => "unexpected end of file",
+                ErrorKind::Other => "other error",
+            };
+            f.write_str(msg)
+        }
+    }
+
+    impl error::Error for Error {}
+
+    /// Source of bytes
+    pub trait Read {
+        /// Reads into `buf`, returning how many bytes were read; `0` means end of input
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+        fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.read(buf)? {
+                    0 => return Err(ErrorKind::UnexpectedEof.into()),
+                    n => buf = &mut mem::take(&mut buf)[n..],
+                }
+            }
+            Ok(())
+        }
+
+        fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+            let start = buf.len();
+            let mut chunk = [0u8; 64];
+            loop {
+                match self.read(&mut chunk)? {
+                    0 => return Ok(buf.len() - start),
+                    n => buf.extend_from_slice(&chunk[..n]),
+                }
+            }
+        }
+
+        /// Adapts this reader to yield at most `limit` bytes
+        fn take(self, limit: u64) -> Take<Self>
+            where Self: Sized
+        {
+            Take { inner: self, limit }
+        }
+    }
+
+    /// Sink of bytes
+    pub trait Write {
+        /// Writes from `buf`, returning how many bytes were accepted; `0` means the sink is full
+        fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.write(buf)? {
+                    0 => return Err(ErrorKind::WriteZero.into()),
+                    n => buf = &buf[n..],
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl<R: Read + ?Sized> Read for &mut R {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            (**self).read(buf)
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            (**self).write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+
+    impl Read for &[u8] {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let n = cmp::min(buf.len(), self.len());
+            let (head, tail) = self.split_at(n);
+            buf[..n].copy_from_slice(head);
+            *self = tail;
+            Ok(n)
+        }
+    }
+
+    impl Write for Vec<u8> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    impl Write for &mut [u8] {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            let n = cmp::min(buf.len(), self.len());
+            let (head, tail) = mem::take(self).split_at_mut(n);
+            head.copy_from_slice(&buf[..n]);
+            *self = tail;
+            Ok(n)
+        }
+    }
+
+    /// Reader adapter returned by `Read::take`
+    #[derive(Debug)]
+    pub struct Take<R> {
+        inner: R,
+        limit: u64,
+    }
+
+    impl<R> Take<R> {
+        /// Number of bytes that can still be read
+        pub fn limit(&self) -> u64 {
+            self.limit
+        }
+
+        pub fn into_inner(self) -> R {
+            self.inner
+        }
+    }
+
+    impl<R: Read> Read for Take<R> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let max = cmp::min(buf.len() as u64, self.limit) as usize;
+            let n = self.inner.read(&mut buf[..max])?;
+            self.limit -= n as u64;
+            Ok(n)
+        }
+    }
+
+    /// In-memory buffer with a read or write position
+    #[derive(Debug, Default, Eq, PartialEq, Clone)]
+    pub struct Cursor<T> {
+        inner: T,
+        pos: u64,
+    }
+
+    impl<T> Cursor<T> {
+        pub fn new(inner: T) -> Cursor<T> {
+            Cursor { inner, pos: 0 }
+        }
+
+        pub fn into_inner(self) -> T {
+            self.inner
+        }
+
+        pub fn get_ref(&self) -> &T {
+            &self.inner
+        }
+
+        pub fn get_mut(&mut self) -> &mut T {
+            &mut self.inner
+        }
+
+        pub fn position(&self) -> u64 {
+            self.pos
+        }
+
+        pub fn set_position(&mut self, pos: u64) {
+            self.pos = pos;
+        }
+    }
+
+    impl<T: AsRef<[u8]>> Read for Cursor<T> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let data = self.inner.as_ref();
+            let start = cmp::min(self.pos, data.len() as u64) as usize;
+            let n = (&data[start..]).read(buf)?;
+            self.pos += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Write for Cursor<&mut [u8]> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            let start = cmp::min(self.pos, self.inner.len() as u64) as usize;
+            let n = (&mut self.inner[start..]).write(buf)?;
+            self.pos += n as u64;
+            Ok(n)
+        }
+    }
+
+    /// Big- and little-endian integer reads, as in `byteorder::ReadBytesExt`
+    pub trait ReadBytesExt: Read {
+        fn read_u8(&mut self) -> Result<u8> {
+            let mut buf = [0u8; 1];
+            self.read_exact(&mut buf)?;
+            Ok(buf[0])
+        }
+
+        fn read_u16<T: ByteOrder>(&mut self) -> Result<u16> {
+            let mut buf = [0u8; 2];
+            self.read_exact(&mut buf)?;
+            Ok(T::read_u16(&buf))
+        }
+
+        fn read_u32<T: ByteOrder>(&mut self) -> Result<u32> {
+            let mut buf = [0u8; 4];
+            self.read_exact(&mut buf)?;
+            Ok(T::read_u32(&buf))
+        }
+    }
+
+    impl<R: Read + ?Sized> ReadBytesExt for R {}
+
+    /// Big- and little-endian integer writes, as in `byteorder::WriteBytesExt`
+    pub trait WriteBytesExt: Write {
+        fn write_u8(&mut self, n: u8) -> Result<()> {
+            self.write_all(&[n])
+        }
+
+        fn write_u16<T: ByteOrder>(&mut self, n: u16) -> Result<()> {
+            let mut buf = [0u8; 2];
+            T::write_u16(&mut buf, n);
+            self.write_all(&buf)
+        }
+
+        fn write_u32<T: ByteOrder>(&mut self, n: u32) -> Result<()> {
+            let mut buf = [0u8; 4];
+            T::write_u32(&mut buf, n);
+            self.write_all(&buf)
+        }
+    }
+
+    impl<W: Write + ?Sized> WriteBytesExt for W {}
+}
@@ -1,6 +1,7 @@
 //! MQTT protocol utilities library
 //!
 //! Strictly implements protocol of [MQTT v3.1.1](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html)
+//! and [MQTT v5.0](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html) (see `packet::v5`)
 //!
 //! ## Usage
 //!
@@ -33,23 +34,71 @@
 //! println!("Variable packet decode: {:?}", auto_decode);
 //! assert_eq!(VariablePacket::PublishPacket(packet), auto_decode);
 //! ```
+//!
+//! ## `no_std`
+//!
+//! Building without the default `std` feature gives a `no_std` + `alloc` crate with the packets,
+//! topics and `decoder`, encoding to and decoding from the minimal traits in `mqtt::io`. The clients,
+//! broker, sessions, retained store and futures-based `parse` helpers need `std`.
+//!
+//! `Encodable::encode_to_slice` encodes into a caller-provided buffer, and `packet::StackPublishPacket`
+//! keeps a small `PUBLISH` in a fixed-size array, for firmware that does not allocate at all.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[macro_use]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate core;
 
 extern crate log;
 extern crate byteorder;
-extern crate regex;
-#[macro_use]
-extern crate lazy_static;
+#[cfg(feature = "std")]
 extern crate futures;
+#[cfg(feature = "std")]
 extern crate tokio_io;
+#[cfg(feature = "tokio-codec")]
+extern crate bytes;
+#[cfg(feature = "tokio-codec")]
+extern crate tokio_util;
+#[cfg(feature = "async-client")]
+extern crate futures_util;
+#[cfg(feature = "async-client")]
+extern crate tokio;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 
 pub use self::encodable::{Decodable, Encodable};
 pub use self::qos::QualityOfService;
+#[cfg(feature = "std")]
+pub use self::retained::RetainedStore;
 pub use self::topic_filter::{TopicFilter, TopicFilterRef};
 pub use self::topic_name::{TopicName, TopicNameRef};
+#[cfg(feature = "std")]
+pub use self::topic_trie::TopicTrie;
 
+#[cfg(feature = "std")]
+pub mod broker;
+#[cfg(feature = "std")]
+pub mod client;
+#[cfg(feature = "tokio-codec")]
+pub mod codec;
 pub mod control;
+pub mod decoder;
 pub mod packet;
 pub mod encodable;
+pub mod io;
 pub mod qos;
+#[cfg(feature = "std")]
+pub mod retained;
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "std")]
+pub mod session;
 pub mod topic_filter;
 pub mod topic_name;
+#[cfg(feature = "std")]
+pub mod topic_trie;
@@ -0,0 +1,626 @@
+//! Builders for MQTT 3.1.1 packets
+//!
+//! Each packet has a `builder()` returning a builder with fluent setters. `build()` checks the
+//! combination of fields against the specification and returns a `BuildError` instead of a packet
+//! that would be rejected by the other end, or that could not be encoded.
+//!
+//! ```rust
+//! use mqtt::{QualityOfService, TopicName};
+//! use mqtt::packet::{BuildError, ConnectPacket, LastWill, ProtocolViolation, PublishPacket};
+//!
+//! let will = LastWill::new(TopicName::new("devices/1/status").unwrap(), QualityOfService::Level1, "offline");
+//! let connect = ConnectPacket::builder()
+//!     .client_id("device-1")
+//!     .keep_alive(30)
+//!     .will(will)
+//!     .build()
+//!     .unwrap();
+//! assert_eq!(connect.keep_alive(), 30);
+//!
+//! let err = ConnectPacket::builder().client_id("device-1").password("secret").build().unwrap_err();
+//! assert_eq!(err, BuildError::Violation(ProtocolViolation::PasswordWithoutUserName));
+//!
+//! let err = PublishPacket::builder()
+//!     .topic_name(TopicName::new("devices/1/temperature").unwrap())
+//!     .qos(QualityOfService::Level1)
+//!     .payload("21.5")
+//!     .build()
+//!     .unwrap_err();
+//! assert_eq!(err, BuildError::MissingField("packet_identifier"));
+//! ```
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+
+use control::variable_header::{ConnectReturnCode, ProtocolVersion};
+use encodable::VarInt;
+use packet::{ConnackPacket, ConnectPacket, DisconnectPacket, LastWill, PingreqPacket, PingrespPacket, PubackPacket,
+             PubcompPacket, PublishPacket, PubrecPacket, PubrelPacket, QoSWithPacketIdentifier, SubackPacket,
+             SubscribePacket, UnsubackPacket, UnsubscribePacket};
+use packet::suback::SubscribeReturnCode;
+use packet::validate::ProtocolViolation;
+use qos::QualityOfService;
+use topic_filter::TopicFilter;
+use topic_name::TopicName;
+
+/// Errors of `build()`
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum BuildError {
+    /// A required field was not set
+    MissingField(&'static str),
+    /// A field was set that the packet cannot carry, such as the packet identifier of a QoS 0 message
+    UnexpectedField(&'static str),
+    /// A string or binary field is longer than 65,535 bytes
+    FieldTooLong(&'static str),
+    /// The client identifier is not allowed by the protocol version
+    InvalidClientIdentifier(String),
+    /// The protocol version has its own packets in `packet::v5`
+    UnsupportedProtocolVersion(ProtocolVersion),
+    /// The packet is longer than the maximum remaining length
+    PacketTooLarge(usize),
+    /// The packet would violate the specification
+    Violation(ProtocolViolation),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::MissingField(field) => write!(f, "Missing {}", field),
+            BuildError::UnexpectedField(field) => write!(f, "Unexpected {}", field),
+            BuildError::FieldTooLong(field) => write!(f, "{} is longer than 65535 bytes", field),
+            BuildError::InvalidClientIdentifier(ref ident) => write!(f, "Invalid client identifier ({:?})", ident),
+            BuildError::UnsupportedProtocolVersion(version) => write!(f, "Unsupported protocol version {:?}", version),
+            BuildError::PacketTooLarge(len) => write!(f, "Remaining length {} is too large", len),
+            BuildError::Violation(ref err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for BuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            BuildError::Violation(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ProtocolViolation> for BuildError {
+    fn from(err: ProtocolViolation) -> BuildError {
+        BuildError::Violation(err)
+    }
+}
+
+/// Fails with the first of `violations`
+fn check(violations: Vec<ProtocolViolation>) -> Result<(), BuildError> {
+    match violations.into_iter().next() {
+        Some(violation) => Err(violation.into()),
+        None => Ok(()),
+    }
+}
+
+fn check_length(field: &'static str, bytes: &[u8]) -> Result<(), BuildError> {
+    if bytes.len() > u16::MAX as usize {
+        return Err(BuildError::FieldTooLong(field));
+    }
+    Ok(())
+}
+
+fn required_packet_identifier(pkid: Option<u16>) -> Result<u16, BuildError> {
+    match pkid {
+        None => Err(BuildError::MissingField("packet_identifier")),
+        Some(0) => Err(ProtocolViolation::ZeroPacketIdentifier.into()),
+        Some(pkid) => Ok(pkid),
+    }
+}
+
+/// Builder of `ConnectPacket`
+///
+/// Defaults to MQTT 3.1.1, an empty client identifier, no keep alive and no clean session, as
+/// `ConnectPacket::new`.
+#[derive(Debug, Clone)]
+pub struct ConnectPacketBuilder {
+    version: ProtocolVersion,
+    client_identifier: String,
+    keep_alive: u16,
+    clean_session: bool,
+    will: Option<LastWill>,
+    user_name: Option<String>,
+    password: Option<Vec<u8>>,
+}
+
+impl ConnectPacket {
+    pub fn builder() -> ConnectPacketBuilder {
+        ConnectPacketBuilder::default()
+    }
+}
+
+impl Default for ConnectPacketBuilder {
+    fn default() -> ConnectPacketBuilder {
+        ConnectPacketBuilder {
+            version: ProtocolVersion::V311,
+            client_identifier: String::new(),
+            keep_alive: 0,
+            clean_session: false,
+            will: None,
+            user_name: None,
+            password: None,
+        }
+    }
+}
+
+impl ConnectPacketBuilder {
+    /// Protocol name and level, MQTT 3.1 or 3.1.1
+    pub fn version(mut self, version: ProtocolVersion) -> ConnectPacketBuilder {
+        self.version = version;
+        self
+    }
+
+    pub fn client_id<S: Into<String>>(mut self, client_identifier: S) -> ConnectPacketBuilder {
+        self.client_identifier = client_identifier.into();
+        self
+    }
+
+    /// Keep alive interval in seconds, 0 to disable
+    pub fn keep_alive(mut self, keep_alive: u16) -> ConnectPacketBuilder {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    pub fn clean_session(mut self, clean_session: bool) -> ConnectPacketBuilder {
+        self.clean_session = clean_session;
+        self
+    }
+
+    pub fn will(mut self, will: LastWill) -> ConnectPacketBuilder {
+        self.will = Some(will);
+        self
+    }
+
+    pub fn user_name<S: Into<String>>(mut self, user_name: S) -> ConnectPacketBuilder {
+        self.user_name = Some(user_name.into());
+        self
+    }
+
+    pub fn password<P: Into<Vec<u8>>>(mut self, password: P) -> ConnectPacketBuilder {
+        self.password = Some(password.into());
+        self
+    }
+
+    pub fn build(self) -> Result<ConnectPacket, BuildError> {
+        let version = self.version;
+        if !version.is_valid_client_identifier(&self.client_identifier) {
+            return Err(BuildError::InvalidClientIdentifier(self.client_identifier));
+        }
+        check_length("client_id", self.client_identifier.as_bytes())?;
+        if let Some(ref will) = self.will {
+            check_length("will message", will.message())?;
+        }
+        if let Some(ref user_name) = self.user_name {
+            check_length("user_name", user_name.as_bytes())?;
+        }
+        if let Some(ref password) = self.password {
+            check_length("password", password)?;
+        }
+
+        let mut packet = ConnectPacket::with_version(version, self.client_identifier)
+            .map_err(|_| BuildError::UnsupportedProtocolVersion(version))?;
+        packet.set_keep_alive(self.keep_alive);
+        packet.set_clean_session(self.clean_session);
+        packet.set_will(self.will);
+        packet.set_user_name(self.user_name);
+        packet.set_password(self.password);
+        check(packet.violations())?;
+        Ok(packet)
+    }
+}
+
+/// Builder of `ConnackPacket`, accepting the connection without a session by default
+#[derive(Debug, Clone)]
+pub struct ConnackPacketBuilder {
+    session_present: bool,
+    return_code: ConnectReturnCode,
+}
+
+impl ConnackPacket {
+    pub fn builder() -> ConnackPacketBuilder {
+        ConnackPacketBuilder::default()
+    }
+}
+
+impl Default for ConnackPacketBuilder {
+    fn default() -> ConnackPacketBuilder {
+        ConnackPacketBuilder {
+            session_present: false,
+            return_code: ConnectReturnCode::ConnectionAccepted,
+        }
+    }
+}
+
+impl ConnackPacketBuilder {
+    pub fn session_present(mut self, session_present: bool) -> ConnackPacketBuilder {
+        self.session_present = session_present;
+        self
+    }
+
+    pub fn return_code(mut self, return_code: ConnectReturnCode) -> ConnackPacketBuilder {
+        self.return_code = return_code;
+        self
+    }
+
+    pub fn build(self) -> Result<ConnackPacket, BuildError> {
+        let packet = ConnackPacket::new(self.session_present, self.return_code);
+        check(packet.violations())?;
+        Ok(packet)
+    }
+}
+
+/// Builder of `PublishPacket`, a QoS 0 message with an empty payload by default
+#[derive(Debug, Clone)]
+pub struct PublishPacketBuilder {
+    topic_name: Option<TopicName>,
+    qos: QualityOfService,
+    packet_identifier: Option<u16>,
+    dup: bool,
+    retain: bool,
+    payload: Vec<u8>,
+}
+
+impl PublishPacket {
+    pub fn builder() -> PublishPacketBuilder {
+        PublishPacketBuilder::default()
+    }
+}
+
+impl Default for PublishPacketBuilder {
+    fn default() -> PublishPacketBuilder {
+        PublishPacketBuilder {
+            topic_name: None,
+            qos: QualityOfService::Level0,
+            packet_identifier: None,
+            dup: false,
+            retain: false,
+            payload: Vec::new(),
+        }
+    }
+}
+
+impl PublishPacketBuilder {
+    pub fn topic_name(mut self, topic_name: TopicName) -> PublishPacketBuilder {
+        self.topic_name = Some(topic_name);
+        self
+    }
+
+    pub fn qos(mut self, qos: QualityOfService) -> PublishPacketBuilder {
+        self.qos = qos;
+        self
+    }
+
+    /// Packet identifier, required for QoS 1 and 2 and not allowed for QoS 0
+    pub fn packet_identifier(mut self, pkid: u16) -> PublishPacketBuilder {
+        self.packet_identifier = Some(pkid);
+        self
+    }
+
+    pub fn dup(mut self, dup: bool) -> PublishPacketBuilder {
+        self.dup = dup;
+        self
+    }
+
+    pub fn retain(mut self, retain: bool) -> PublishPacketBuilder {
+        self.retain = retain;
+        self
+    }
+
+    pub fn payload<P: Into<Vec<u8>>>(mut self, payload: P) -> PublishPacketBuilder {
+        self.payload = payload.into();
+        self
+    }
+
+    pub fn build(self) -> Result<PublishPacket, BuildError> {
+        let topic_name = self.topic_name.ok_or(BuildError::MissingField("topic_name"))?;
+        let qos = match (self.qos, self.packet_identifier) {
+            (QualityOfService::Level0, None) => QoSWithPacketIdentifier::Level0,
+            // [MQTT-2.3.1-5]
+            (QualityOfService::Level0, Some(_)) => return Err(BuildError::UnexpectedField("packet_identifier")),
+            (qos, pkid) => {
+                let pkid = pkid.ok_or(BuildError::MissingField("packet_identifier"))?;
+                QoSWithPacketIdentifier::new(qos, pkid)
+            }
+        };
+
+        let pkid_length = if self.qos == QualityOfService::Level0 { 0 } else { 2 };
+        let remaining_length = 2 + topic_name.len() + pkid_length + self.payload.len();
+        if remaining_length > VarInt::MAX as usize {
+            return Err(BuildError::PacketTooLarge(remaining_length));
+        }
+
+        let mut packet = PublishPacket::new(topic_name, qos, self.payload);
+        packet.set_dup(self.dup);
+        packet.set_retain(self.retain);
+        check(packet.violations())?;
+        Ok(packet)
+    }
+}
+
+/// Builder of `SubscribePacket`
+#[derive(Debug, Clone, Default)]
+pub struct SubscribePacketBuilder {
+    packet_identifier: Option<u16>,
+    subscribes: Vec<(TopicFilter, QualityOfService)>,
+}
+
+impl SubscribePacket {
+    pub fn builder() -> SubscribePacketBuilder {
+        SubscribePacketBuilder::default()
+    }
+}
+
+impl SubscribePacketBuilder {
+    pub fn packet_identifier(mut self, pkid: u16) -> SubscribePacketBuilder {
+        self.packet_identifier = Some(pkid);
+        self
+    }
+
+    /// Adds a topic filter with its maximum QoS
+    pub fn subscribe(mut self, topic_filter: TopicFilter, qos: QualityOfService) -> SubscribePacketBuilder {
+        self.subscribes.push((topic_filter, qos));
+        self
+    }
+
+    pub fn build(self) -> Result<SubscribePacket, BuildError> {
+        let pkid = required_packet_identifier(self.packet_identifier)?;
+        let packet = SubscribePacket::new(pkid, self.subscribes);
+        check(packet.violations())?;
+        Ok(packet)
+    }
+}
+
+/// Builder of `SubackPacket`
+#[derive(Debug, Clone, Default)]
+pub struct SubackPacketBuilder {
+    packet_identifier: Option<u16>,
+    return_codes: Vec<SubscribeReturnCode>,
+}
+
+impl SubackPacket {
+    pub fn builder() -> SubackPacketBuilder {
+        SubackPacketBuilder::default()
+    }
+}
+
+impl SubackPacketBuilder {
+    pub fn packet_identifier(mut self, pkid: u16) -> SubackPacketBuilder {
+        self.packet_identifier = Some(pkid);
+        self
+    }
+
+    /// Adds the return code of the next topic filter of the `SUBSCRIBE`
+    pub fn return_code(mut self, return_code: SubscribeReturnCode) -> SubackPacketBuilder {
+        self.return_codes.push(return_code);
+        self
+    }
+
+    pub fn build(self) -> Result<SubackPacket, BuildError> {
+        let pkid = required_packet_identifier(self.packet_identifier)?;
+        if self.return_codes.is_empty() {
+            return Err(BuildError::MissingField("return_code"));
+        }
+        Ok(SubackPacket::new(pkid, self.return_codes))
+    }
+}
+
+/// Builder of `UnsubscribePacket`
+#[derive(Debug, Clone, Default)]
+pub struct UnsubscribePacketBuilder {
+    packet_identifier: Option<u16>,
+    topic_filters: Vec<TopicFilter>,
+}
+
+impl UnsubscribePacket {
+    pub fn builder() -> UnsubscribePacketBuilder {
+        UnsubscribePacketBuilder::default()
+    }
+}
+
+impl UnsubscribePacketBuilder {
+    pub fn packet_identifier(mut self, pkid: u16) -> UnsubscribePacketBuilder {
+        self.packet_identifier = Some(pkid);
+        self
+    }
+
+    pub fn topic_filter(mut self, topic_filter: TopicFilter) -> UnsubscribePacketBuilder {
+        self.topic_filters.push(topic_filter);
+        self
+    }
+
+    pub fn build(self) -> Result<UnsubscribePacket, BuildError> {
+        let pkid = required_packet_identifier(self.packet_identifier)?;
+        let packet = UnsubscribePacket::new(pkid, self.topic_filters);
+        check(packet.violations())?;
+        Ok(packet)
+    }
+}
+
+macro_rules! impl_ack_builder {
+    ($($name:ident => $builder:ident,)+) => {
+        $(
+            #[doc = concat!("Builder of `", stringify!($name), "`")]
+            #[derive(Debug, Clone, Default)]
+            pub struct $builder {
+                packet_identifier: Option<u16>,
+            }
+
+            impl $name {
+                pub fn builder() -> $builder {
+                    $builder::default()
+                }
+            }
+
+            impl $builder {
+                /// Packet identifier of the acknowledged packet
+                pub fn packet_identifier(mut self, pkid: u16) -> $builder {
+                    self.packet_identifier = Some(pkid);
+                    self
+                }
+
+                pub fn build(self) -> Result<$name, BuildError> {
+                    required_packet_identifier(self.packet_identifier).map($name::new)
+                }
+            }
+        )+
+    }
+}
+
+impl_ack_builder! {
+    PubackPacket => PubackPacketBuilder,
+    PubrecPacket => PubrecPacketBuilder,
+    PubrelPacket => PubrelPacketBuilder,
+    PubcompPacket => PubcompPacketBuilder,
+    UnsubackPacket => UnsubackPacketBuilder,
+}
+
+macro_rules! impl_empty_builder {
+    ($($name:ident => $builder:ident,)+) => {
+        $(
+            #[doc = concat!("Builder of `", stringify!($name), "`, which has no fields")]
+            #[derive(Debug, Clone, Default)]
+            pub struct $builder;
+
+            impl $name {
+                pub fn builder() -> $builder {
+                    $builder
+                }
+            }
+
+            impl $builder {
+                pub fn build(self) -> Result<$name, BuildError> {
+                    Ok($name::new())
+                }
+            }
+        )+
+    }
+}
+
+impl_empty_builder! {
+    PingreqPacket => PingreqPacketBuilder,
+    PingrespPacket => PingrespPacketBuilder,
+    DisconnectPacket => DisconnectPacketBuilder,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use packet::Packet;
+
+    #[test]
+    fn test_connect_packet_builder() {
+        let will = LastWill::new(TopicName::new("will").unwrap(), QualityOfService::Level2, "bye");
+        let packet = ConnectPacket::builder()
+            .version(ProtocolVersion::V31)
+            .client_id("client")
+            .keep_alive(10)
+            .clean_session(true)
+            .will(will.clone())
+            .user_name("user")
+            .password("secret")
+            .build()
+            .unwrap();
+
+        let mut expected = ConnectPacket::with_version(ProtocolVersion::V31, "client").unwrap();
+        expected.set_keep_alive(10);
+        expected.set_clean_session(true);
+        expected.set_will(Some(will));
+        expected.set_user_name(Some("user".to_owned()));
+        expected.set_password(Some(b"secret".to_vec()));
+        assert_eq!(packet, expected);
+
+        assert_eq!(ConnectPacket::builder().build(),
+                   Err(BuildError::Violation(ProtocolViolation::EmptyClientIdentifier)));
+        assert_eq!(ConnectPacket::builder().version(ProtocolVersion::V31).client_id("").build(),
+                   Err(BuildError::InvalidClientIdentifier(String::new())));
+        assert_eq!(ConnectPacket::builder().version(ProtocolVersion::V5).build(),
+                   Err(BuildError::UnsupportedProtocolVersion(ProtocolVersion::V5)));
+        assert_eq!(ConnectPacket::builder().client_id("a\0b").build(),
+                   Err(BuildError::Violation(ProtocolViolation::NullCharacter)));
+        assert_eq!(ConnectPacket::builder().client_id("a").user_name(vec!["x"; 65536].concat()).build(),
+                   Err(BuildError::FieldTooLong("user_name")));
+    }
+
+    #[test]
+    fn test_publish_packet_builder() {
+        let topic_name = TopicName::new("a/b").unwrap();
+        let packet = PublishPacket::builder()
+            .topic_name(topic_name.clone())
+            .qos(QualityOfService::Level2)
+            .packet_identifier(7)
+            .dup(true)
+            .retain(true)
+            .payload("x")
+            .build()
+            .unwrap();
+
+        let mut expected = PublishPacket::new(topic_name.clone(), QoSWithPacketIdentifier::Level2(7), "x");
+        expected.set_dup(true);
+        expected.set_retain(true);
+        assert_eq!(packet, expected);
+
+        let builder = PublishPacket::builder().topic_name(topic_name);
+        assert_eq!(PublishPacket::builder().build(), Err(BuildError::MissingField("topic_name")));
+        assert_eq!(builder.clone().packet_identifier(1).build(),
+                   Err(BuildError::UnexpectedField("packet_identifier")));
+        assert_eq!(builder.clone().dup(true).build(),
+                   Err(BuildError::Violation(ProtocolViolation::DupWithQos0)));
+        assert_eq!(builder.clone().qos(QualityOfService::Level1).packet_identifier(0).build(),
+                   Err(BuildError::Violation(ProtocolViolation::ZeroPacketIdentifier)));
+        assert_eq!(builder.build().unwrap().qos(), QoSWithPacketIdentifier::Level0);
+    }
+
+    #[test]
+    fn test_subscribe_packet_builders() {
+        let filter = TopicFilter::new("a/#").unwrap();
+        let packet = SubscribePacket::builder()
+            .packet_identifier(3)
+            .subscribe(filter.clone(), QualityOfService::Level1)
+            .build()
+            .unwrap();
+        assert_eq!(packet, SubscribePacket::new(3, vec![(filter.clone(), QualityOfService::Level1)]));
+        assert_eq!(SubscribePacket::builder().packet_identifier(3).build(),
+                   Err(BuildError::Violation(ProtocolViolation::EmptySubscribe)));
+        assert_eq!(SubscribePacket::builder().subscribe(filter.clone(), QualityOfService::Level0).build(),
+                   Err(BuildError::MissingField("packet_identifier")));
+
+        let packet = SubackPacket::builder()
+            .packet_identifier(3)
+            .return_code(SubscribeReturnCode::MaximumQoSLevel1)
+            .build()
+            .unwrap();
+        assert_eq!(packet.payload_ref().subscribes(), &[SubscribeReturnCode::MaximumQoSLevel1]);
+        assert_eq!(SubackPacket::builder().packet_identifier(3).build(),
+                   Err(BuildError::MissingField("return_code")));
+
+        let packet = UnsubscribePacket::builder().packet_identifier(4).topic_filter(filter.clone()).build().unwrap();
+        assert_eq!(packet, UnsubscribePacket::new(4, vec![filter]));
+        assert_eq!(UnsubscribePacket::builder().packet_identifier(4).build(),
+                   Err(BuildError::Violation(ProtocolViolation::EmptyUnsubscribe)));
+    }
+
+    #[test]
+    fn test_other_packet_builders() {
+        assert_eq!(ConnackPacket::builder().session_present(true).build(),
+                   Ok(ConnackPacket::new(true, ConnectReturnCode::ConnectionAccepted)));
+        assert_eq!(ConnackPacket::builder().session_present(true).return_code(ConnectReturnCode::NotAuthorized).build(),
+                   Err(BuildError::Violation(ProtocolViolation::SessionPresentWithError)));
+
+        assert_eq!(PubackPacket::builder().packet_identifier(1).build(), Ok(PubackPacket::new(1)));
+        assert_eq!(PubrelPacket::builder().packet_identifier(0).build(),
+                   Err(BuildError::Violation(ProtocolViolation::ZeroPacketIdentifier)));
+        assert_eq!(UnsubackPacket::builder().build(), Err(BuildError::MissingField("packet_identifier")));
+
+        assert_eq!(PingreqPacket::builder().build(), Ok(PingreqPacket::new()));
+        assert_eq!(DisconnectPacket::builder().build(), Ok(DisconnectPacket::new()));
+    }
+}
@@ -1,10 +1,10 @@
 //! CONNACK
 
-use std::io::{Read, Write};
+use io::{Read, Write};
 
 use {Decodable, Encodable};
 use control::{ControlType, FixedHeader, PacketType};
-use control::variable_header::{ConnackFlags, ConnectReturnCode};
+use control::variable_header::{ConnackFlags, ConnectReturnCode, ProtocolVersion};
 use packet::{Packet, PacketError};
 
 /// `CONNACK` packet
@@ -33,6 +33,24 @@ impl ConnackPacket {
     pub fn connect_return_code(&self) -> ConnectReturnCode {
         self.ret_code
     }
+
+    /// Decodes the packet body with the rules of `version`
+    ///
+    /// MQTT 3.1 has no session present flag, so the first byte is ignored and the flag is always `false`.
+    pub fn decode_packet_with_version<R: Read>(reader: &mut R,
+                                               fixed_header: FixedHeader,
+                                               version: ProtocolVersion)
+                                               -> Result<Self, PacketError<Self>> {
+        let flags = ConnackFlags::decode_with(reader, Some(version))?;
+        let code: ConnectReturnCode = Decodable::decode(reader)?;
+
+        Ok(ConnackPacket {
+               fixed_header,
+               flags,
+               ret_code: code,
+               payload: (),
+           })
+    }
 }
 
 impl Packet for ConnackPacket {
@@ -61,15 +79,7 @@ impl Packet for ConnackPacket {
     }
 
     fn decode_packet<R: Read>(reader: &mut R, fixed_header: FixedHeader) -> Result<Self, PacketError<Self>> {
-        let flags: ConnackFlags = Decodable::decode(reader)?;
-        let code: ConnectReturnCode = Decodable::decode(reader)?;
-
-        Ok(ConnackPacket {
-               fixed_header: fixed_header,
-               flags: flags,
-               ret_code: code,
-               payload: (),
-           })
+        ConnackPacket::decode_packet_with_version(reader, fixed_header, ProtocolVersion::V311)
     }
 }
 
@@ -94,4 +104,17 @@ mod test {
 
         assert_eq!(packet, decoded);
     }
+
+    #[test]
+    pub fn test_connack_packet_v31_reserved_byte() {
+        let encoded = b"\x20\x02\xff\x00";
+
+        assert!(ConnackPacket::decode(&mut Cursor::new(&encoded[..])).is_err());
+
+        let mut reader = Cursor::new(&encoded[..]);
+        let fixed_header = FixedHeader::decode(&mut reader).unwrap();
+        let decoded = ConnackPacket::decode_packet_with_version(&mut reader, fixed_header, ProtocolVersion::V31)
+            .unwrap();
+        assert_eq!(decoded, ConnackPacket::new(false, ConnectReturnCode::ConnectionAccepted));
+    }
 }
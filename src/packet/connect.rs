@@ -1,15 +1,20 @@
 //! CONNECT
 
-use std::error::Error;
-use std::fmt;
-use std::io::{self, Read, Write};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+use io::{self, Read, Write};
 
 use {Decodable, Encodable};
 use control::{ControlType, FixedHeader, PacketType};
-use control::variable_header::{ConnectFlags, KeepAlive, ProtocolLevel, ProtocolName};
+use control::variable_header::{ConnectFlags, KeepAlive, ProtocolLevel, ProtocolName, ProtocolVersion,
+                               VariableHeaderError};
 use control::variable_header::protocol_level::SPEC_3_1_1;
-use encodable::{StringEncodeError, VarBytes};
-use packet::{Packet, PacketError};
+use decoder::{DecodeLimits, LimitedDecodable};
+use encodable::{StringEncodeError, StringField, VarBytes};
+use packet::{Packet, PacketError, PublishPacket, QoSWithPacketIdentifier};
+use qos::QualityOfService;
 use topic_name::{TopicName, TopicNameError};
 
 /// `CONNECT` packet
@@ -53,6 +58,19 @@ impl ConnectPacket {
         pk
     }
 
+    /// Creates a `CONNECT` with the protocol name and level of `version`
+    ///
+    /// MQTT 3.1 servers only accept client identifiers of 1 to 23 characters. Fails with
+    /// `UnsupportedProtocolLevel` for `ProtocolVersion::V5`, which has its own `v5::ConnectPacket`.
+    pub fn with_version<C: Into<String>>(version: ProtocolVersion, client_identifier: C)
+                                         -> Result<ConnectPacket, VariableHeaderError> {
+        let level = version.protocol_level().0;
+        if version == ProtocolVersion::V5 {
+            return Err(VariableHeaderError::UnsupportedProtocolLevel(level));
+        }
+        Ok(ConnectPacket::with_level(version.protocol_name(), client_identifier, level))
+    }
+
     #[inline]
     fn calculate_remaining_length(&self) -> u32 {
         self.encoded_variable_headers_length() + self.payload_ref().encoded_length()
@@ -68,26 +86,47 @@ impl ConnectPacket {
         self.fixed_header.remaining_length = self.calculate_remaining_length();
     }
 
-    pub fn set_will(&mut self, topic_message: Option<(TopicName, Vec<u8>)>) {
-        self.flags.will_flag = topic_message.is_some();
+    /// Sets the will, with its QoS and retain flags
+    pub fn set_will(&mut self, will: Option<LastWill>) {
+        self.flags.will_flag = will.is_some();
+        self.flags.will_qos = will.as_ref().map_or(QualityOfService::Level0, |will| will.qos);
+        self.flags.will_retain = will.as_ref().is_some_and(|will| will.retain);
+        self.payload.will = will;
+        self.fixed_header.remaining_length = self.calculate_remaining_length();
+    }
 
-        match topic_message {
-            Some((topic, msg)) => {
-                self.payload.will_topic = Some(topic);
-                self.payload.will_message = Some(VarBytes(msg));
-            }
-            None => {
-                self.payload.will_topic = None;
-                self.payload.will_message = None;
-            }
+    /// Sets the retain flag of the will, ignored if no will is set
+    #[deprecated(since = "0.8.0", note = "use `LastWill::set_retain` and pass the will to `set_will`")]
+    pub fn set_will_retain(&mut self, will_retain: bool) {
+        if let Some(ref mut will) = self.payload.will {
+            will.retain = will_retain;
+            self.flags.will_retain = will_retain;
         }
+    }
 
-        self.fixed_header.remaining_length = self.calculate_remaining_length();
+    /// Sets the QoS of the will, ignored if no will is set
+    ///
+    /// # Panics
+    ///
+    /// If `will_qos` is above 2.
+    #[deprecated(since = "0.8.0", note = "give the QoS to `LastWill::new` and pass the will to `set_will`")]
+    pub fn set_will_qos(&mut self, will_qos: u8) {
+        let qos = match will_qos {
+            0 => QualityOfService::Level0,
+            1 => QualityOfService::Level1,
+            2 => QualityOfService::Level2,
+            _ => panic!("Invalid will QoS {}", will_qos),
+        };
+        if let Some(ref mut will) = self.payload.will {
+            will.qos = qos;
+            self.flags.will_qos = qos;
+        }
     }
 
-    pub fn set_password(&mut self, password: Option<String>) {
+    /// Sets the password, which is binary data rather than a UTF-8 string
+    pub fn set_password(&mut self, password: Option<Vec<u8>>) {
         self.flags.password = password.is_some();
-        self.payload.password = password;
+        self.payload.password = password.map(VarBytes);
         self.fixed_header.remaining_length = self.calculate_remaining_length();
     }
 
@@ -96,45 +135,32 @@ impl ConnectPacket {
         self.fixed_header.remaining_length = self.calculate_remaining_length();
     }
 
-    pub fn set_will_retain(&mut self, will_retain: bool) {
-        self.flags.will_retain = will_retain;
-    }
-
-    pub fn set_will_qos(&mut self, will_qos: u8) {
-        assert!(will_qos <= 2);
-        self.flags.will_qos = will_qos;
-    }
-
     pub fn set_clean_session(&mut self, clean_session: bool) {
         self.flags.clean_session = clean_session;
     }
 
+    /// Keep alive interval in seconds, 0 if disabled
+    pub fn keep_alive(&self) -> u16 {
+        self.keep_alive.0
+    }
+
     pub fn user_name(&self) -> Option<&str> {
         self.payload.user_name.as_ref().map(|x| &x[..])
     }
 
-    pub fn password(&self) -> Option<&str> {
-        self.payload.password.as_ref().map(|x| &x[..])
+    pub fn password(&self) -> Option<&[u8]> {
+        self.payload.password.as_ref().map(|x| &x.0[..])
     }
 
-    pub fn will(&self) -> Option<(&str, &Vec<u8>)> {
-        self.payload
-            .will_topic
-            .as_ref()
-            .map(|x| &x[..])
-            .and_then(|topic| {
-                          self.payload
-                              .will_message
-                              .as_ref()
-                              .map(|msg| (topic, &msg.0))
-                      })
+    pub fn will(&self) -> Option<&LastWill> {
+        self.payload.will.as_ref()
     }
 
     pub fn will_retain(&self) -> bool {
         self.flags.will_retain
     }
 
-    pub fn will_qos(&self) -> u8 {
+    pub fn will_qos(&self) -> QualityOfService {
         self.flags.will_qos
     }
 
@@ -145,6 +171,19 @@ impl ConnectPacket {
     pub fn clean_session(&self) -> bool {
         self.flags.clean_session
     }
+
+    pub fn protocol_name(&self) -> &str {
+        &self.protocol_name.0[..]
+    }
+
+    pub fn protocol_level(&self) -> u8 {
+        self.protocol_level.0
+    }
+
+    /// Protocol version given by the protocol name and level, if they are a known pair
+    pub fn protocol_version(&self) -> Option<ProtocolVersion> {
+        ProtocolVersion::from_name_and_level(&self.protocol_name.0, self.protocol_level.0).ok()
+    }
 }
 
 impl Packet for ConnectPacket {
@@ -177,13 +216,33 @@ impl Packet for ConnectPacket {
     }
 
     fn decode_packet<R: Read>(reader: &mut R, fixed_header: FixedHeader) -> Result<Self, PacketError<Self>> {
+        ConnectPacket::decode_packet_limited(reader, fixed_header, &DecodeLimits::default())
+    }
+
+    fn decode_packet_limited<R: Read>(reader: &mut R,
+                                      fixed_header: FixedHeader,
+                                      limits: &DecodeLimits)
+                                      -> Result<Self, PacketError<Self>> {
         let protoname: ProtocolName = Decodable::decode(reader)?;
         let protocol_level: ProtocolLevel = Decodable::decode(reader)?;
+        // An unknown name and level pair still decodes with the 3.1.1 layout, so the server can answer
+        // with `CONNACK` 0x01 [MQTT-3.1.2-2]; `validate` reports it
+        let version = ProtocolVersion::from_name_and_level(&protoname.0, protocol_level.0).ok();
+        if version == Some(ProtocolVersion::V5) {
+            let err = VariableHeaderError::UnsupportedProtocolLevel(protocol_level.0);
+            return Err(PacketError::VariableHeaderError(err));
+        }
+
         let flags: ConnectFlags = Decodable::decode(reader)?;
         let keep_alive: KeepAlive = Decodable::decode(reader)?;
-        let payload: ConnectPacketPayload = Decodable::decode_with(reader, Some(flags))
+        let payload = ConnectPacketPayload::decode_limited(reader, Some(flags), limits)
             .map_err(PacketError::PayloadError)?;
 
+        if version.is_some_and(|v| !v.is_valid_client_identifier(&payload.client_identifier)) {
+            let err = ConnectPacketPayloadError::InvalidClientIdentifier(payload.client_identifier);
+            return Err(PacketError::PayloadError(err));
+        }
+
         Ok(ConnectPacket {
                fixed_header: fixed_header,
                protocol_name: protoname,
@@ -195,22 +254,105 @@ impl Packet for ConnectPacket {
     }
 }
 
+/// Will message of a `CONNECT`
+///
+/// Published by the server when the connection is closed without `DISCONNECT` [MQTT-3.1.2-8].
+///
+/// ```rust
+/// use mqtt::{QualityOfService, TopicName};
+/// use mqtt::packet::{ConnectPacket, LastWill, PublishPacket, QoSWithPacketIdentifier};
+///
+/// let mut will = LastWill::new(TopicName::new("devices/1/status").unwrap(), QualityOfService::Level1, "offline");
+/// will.set_retain(true);
+///
+/// let mut connect = ConnectPacket::new("MQTT", "device-1");
+/// connect.set_will(Some(will.clone()));
+/// assert_eq!(connect.will(), Some(&will));
+///
+/// let publish = PublishPacket::from(will);
+/// assert_eq!(publish.qos(), QoSWithPacketIdentifier::Level1(0));
+/// assert!(publish.retain());
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct LastWill {
+    topic_name: TopicName,
+    message: VarBytes,
+    qos: QualityOfService,
+    retain: bool,
+}
+
+impl LastWill {
+    pub fn new<M: Into<Vec<u8>>>(topic_name: TopicName, qos: QualityOfService, message: M) -> LastWill {
+        LastWill {
+            topic_name,
+            message: VarBytes(message.into()),
+            qos,
+            retain: false,
+        }
+    }
+
+    pub fn set_retain(&mut self, retain: bool) {
+        self.retain = retain;
+    }
+
+    pub fn topic_name(&self) -> &str {
+        &self.topic_name[..]
+    }
+
+    pub fn message(&self) -> &[u8] {
+        &self.message.0
+    }
+
+    pub fn qos(&self) -> QualityOfService {
+        self.qos
+    }
+
+    pub fn retain(&self) -> bool {
+        self.retain
+    }
+}
+
+impl Encodable for LastWill {
+    type Err = StringEncodeError;
+
+    /// Encodes the will topic and the will message, as they follow each other in the `CONNECT` payload
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), StringEncodeError> {
+        (&self.topic_name[..]).encode(writer)?;
+        self.message.encode(writer)?;
+        Ok(())
+    }
+
+    fn encoded_length(&self) -> u32 {
+        self.topic_name.encoded_length() + self.message.encoded_length()
+    }
+}
+
+impl From<LastWill> for PublishPacket {
+    /// `PUBLISH` of the will, with packet identifier 0 for QoS 1 and 2
+    ///
+    /// The server sets a packet identifier for each subscriber it is sent to.
+    fn from(will: LastWill) -> PublishPacket {
+        let qos = QoSWithPacketIdentifier::new(will.qos, 0);
+        let mut publish = PublishPacket::new(will.topic_name, qos, will.message.0);
+        publish.set_retain(will.retain);
+        publish
+    }
+}
+
 /// Payloads for connect packet
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct ConnectPacketPayload {
     client_identifier: String,
-    will_topic: Option<TopicName>,
-    will_message: Option<VarBytes>,
+    will: Option<LastWill>,
     user_name: Option<String>,
-    password: Option<String>,
+    password: Option<VarBytes>,
 }
 
 impl ConnectPacketPayload {
     pub fn new(client_identifier: String) -> ConnectPacketPayload {
         ConnectPacketPayload {
             client_identifier: client_identifier,
-            will_topic: None,
-            will_message: None,
+            will: None,
             user_name: None,
             password: None,
         }
@@ -223,12 +365,8 @@ impl Encodable for ConnectPacketPayload {
     fn encode<W: Write>(&self, writer: &mut W) -> Result<(), ConnectPacketPayloadError> {
         self.client_identifier.encode(writer)?;
 
-        if let Some(ref will_topic) = self.will_topic {
-            will_topic.encode(writer)?;
-        }
-
-        if let Some(ref will_message) = self.will_message {
-            will_message.encode(writer)?;
+        if let Some(ref will) = self.will {
+            will.encode(writer)?;
         }
 
         if let Some(ref user_name) = self.user_name {
@@ -244,13 +382,9 @@ impl Encodable for ConnectPacketPayload {
 
     fn encoded_length(&self) -> u32 {
         self.client_identifier.encoded_length() +
-            self.will_topic
+            self.will
                 .as_ref()
-                .map(|t| t.encoded_length())
-                .unwrap_or(0) +
-            self.will_message
-                .as_ref()
-                .map(|t| t.encoded_length())
+                .map(|w| w.encoded_length())
                 .unwrap_or(0) +
             self.user_name
                 .as_ref()
@@ -270,44 +404,57 @@ impl Decodable for ConnectPacketPayload {
     fn decode_with<R: Read>(reader: &mut R,
                             rest: Option<ConnectFlags>)
                             -> Result<ConnectPacketPayload, ConnectPacketPayloadError> {
-        let mut need_will_topic = false;
-        let mut need_will_message = false;
+        ConnectPacketPayload::decode_limited(reader, rest, &DecodeLimits::default())
+    }
+}
+
+impl LimitedDecodable for ConnectPacketPayload {
+    fn decode_limited<R: Read>(reader: &mut R,
+                               rest: Option<ConnectFlags>,
+                               limits: &DecodeLimits)
+                               -> Result<ConnectPacketPayload, ConnectPacketPayloadError> {
+        let mut will_qos = None;
+        let mut will_retain = false;
         let mut need_user_name = false;
         let mut need_password = false;
 
         if let Some(r) = rest {
-            need_will_topic = r.will_flag;
-            need_will_message = r.will_flag;
+            if r.will_flag {
+                will_qos = Some(r.will_qos);
+            }
+            will_retain = r.will_retain;
             need_user_name = r.user_name;
             need_password = r.password;
         }
 
-        let ident: String = Decodable::decode(reader)?;
-        let topic = if need_will_topic {
-            Some(Decodable::decode(reader)?)
-        } else {
-            None
-        };
-        let msg = if need_will_message {
-            Some(Decodable::decode(reader)?)
-        } else {
-            None
+        let ident = String::decode_with(reader, Some(limits.string_cond(StringField::ClientIdentifier)))?;
+        let will = match will_qos {
+            Some(qos) => {
+                let topic_name = String::decode_with(reader, Some(limits.string_cond(StringField::WillTopic)))?;
+                let topic_name = TopicName::new(topic_name)?;
+                Some(LastWill {
+                         topic_name,
+                         message: Decodable::decode(reader)?,
+                         qos,
+                         retain: will_retain,
+                     })
+            }
+            None => None,
         };
         let uname = if need_user_name {
-            Some(Decodable::decode(reader)?)
+            Some(String::decode_with(reader, Some(limits.string_cond(StringField::UserName)))?)
         } else {
             None
         };
         let pwd = if need_password {
-            Some(Decodable::decode(reader)?)
+            Some(VarBytes::decode(reader)?)
         } else {
             None
         };
 
         Ok(ConnectPacketPayload {
                client_identifier: ident,
-               will_topic: topic,
-               will_message: msg,
+               will,
                user_name: uname,
                password: pwd,
            })
@@ -319,32 +466,29 @@ pub enum ConnectPacketPayloadError {
     IoError(io::Error),
     StringEncodeError(StringEncodeError),
     TopicNameError(TopicNameError),
+    InvalidClientIdentifier(String),
 }
 
 impl fmt::Display for ConnectPacketPayloadError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            &ConnectPacketPayloadError::IoError(ref err) => err.fmt(f),
-            &ConnectPacketPayloadError::StringEncodeError(ref err) => err.fmt(f),
-            &ConnectPacketPayloadError::TopicNameError(ref err) => err.fmt(f),
+        match *self {
+            ConnectPacketPayloadError::IoError(ref err) => err.fmt(f),
+            ConnectPacketPayloadError::StringEncodeError(ref err) => err.fmt(f),
+            ConnectPacketPayloadError::TopicNameError(ref err) => err.fmt(f),
+            ConnectPacketPayloadError::InvalidClientIdentifier(ref ident) => {
+                write!(f, "Invalid client identifier ({:?})", ident)
+            }
         }
     }
 }
 
 impl Error for ConnectPacketPayloadError {
-    fn description(&self) -> &str {
-        match self {
-            &ConnectPacketPayloadError::IoError(ref err) => err.description(),
-            &ConnectPacketPayloadError::StringEncodeError(ref err) => err.description(),
-            &ConnectPacketPayloadError::TopicNameError(ref err) => err.description(),
-        }
-    }
-
     fn source(&self) -> Option<&(dyn Error + 'static)> {
-        match self {
-            &ConnectPacketPayloadError::IoError(ref err) => Some(err),
-            &ConnectPacketPayloadError::StringEncodeError(ref err) => Some(err),
-            &ConnectPacketPayloadError::TopicNameError(ref err) => Some(err),
+        match *self {
+            ConnectPacketPayloadError::IoError(ref err) => Some(err),
+            ConnectPacketPayloadError::StringEncodeError(ref err) => Some(err),
+            ConnectPacketPayloadError::TopicNameError(ref err) => Some(err),
+            ConnectPacketPayloadError::InvalidClientIdentifier(..) => None,
         }
     }
 }
@@ -373,6 +517,7 @@ mod test {
 
     use std::io::Cursor;
 
+    use packet::ProtocolViolation;
     use {Decodable, Encodable};
 
     #[test]
@@ -410,4 +555,101 @@ mod test {
 
         assert_eq!(packet, decoded_packet);
     }
+
+    #[test]
+    fn test_connect_packet_will() {
+        let mut will = LastWill::new(TopicName::new("a/b").unwrap(), QualityOfService::Level2, "bye");
+        will.set_retain(true);
+        let mut packet = ConnectPacket::new("MQTT", "12345");
+        packet.set_will(Some(will.clone()));
+        assert_eq!(packet.will_qos(), QualityOfService::Level2);
+        assert!(packet.will_retain());
+
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+        let decoded = ConnectPacket::decode(&mut Cursor::new(&buf[..])).unwrap();
+        assert_eq!(decoded.will(), Some(&will));
+        assert_eq!(packet, decoded);
+
+        packet.set_will(None);
+        assert_eq!(packet, ConnectPacket::new("MQTT", "12345"));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_connect_packet_deprecated_will_setters() {
+        let mut packet = ConnectPacket::new("MQTT", "12345");
+        packet.set_will_qos(1);
+        packet.set_will_retain(true);
+        assert_eq!((packet.will_qos(), packet.will_retain()), (QualityOfService::Level0, false));
+
+        packet.set_will(Some(LastWill::new(TopicName::new("a/b").unwrap(), QualityOfService::Level0, "bye")));
+        packet.set_will_qos(2);
+        packet.set_will_retain(true);
+        let mut expected = LastWill::new(TopicName::new("a/b").unwrap(), QualityOfService::Level2, "bye");
+        expected.set_retain(true);
+        assert_eq!(packet.will(), Some(&expected));
+        assert_eq!((packet.will_qos(), packet.will_retain()), (QualityOfService::Level2, true));
+    }
+
+    #[test]
+    fn test_connect_packet_will_invalid_qos() {
+        let encoded_data = b"\x10\x19\x00\x04MQTT\x04\x1c\x00\x00\x00\x0512345\x00\x01a\x00\x03bye";
+        match ConnectPacket::decode(&mut Cursor::new(&encoded_data[..])) {
+            Err(PacketError::VariableHeaderError(VariableHeaderError::InvalidQualityOfService(3))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_connect_packet_v31() {
+        let packet = ConnectPacket::with_version(ProtocolVersion::V31, "12345").unwrap();
+        let expected = b"\x10\x13\x00\x06MQIsdp\x03\x00\x00\x00\x00\x0512345";
+
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+        assert_eq!(&expected[..], &buf[..]);
+
+        let decoded = ConnectPacket::decode(&mut Cursor::new(&buf[..])).unwrap();
+        assert_eq!(decoded.protocol_version(), Some(ProtocolVersion::V31));
+        assert_eq!(packet, decoded);
+
+        match ConnectPacket::with_version(ProtocolVersion::V5, "12345") {
+            Err(VariableHeaderError::UnsupportedProtocolLevel(5)) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_connect_packet_v31_client_identifier_too_long() {
+        let packet = ConnectPacket::with_version(ProtocolVersion::V31, "123456789012345678901234").unwrap();
+
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+
+        match ConnectPacket::decode(&mut Cursor::new(&buf[..])) {
+            Err(PacketError::PayloadError(ConnectPacketPayloadError::InvalidClientIdentifier(..))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_connect_packet_mismatched_protocol_name() {
+        let encoded_data = b"\x10\x11\x00\x04MQTT\x03\x00\x00\x00\x00\x0512345";
+        let decoded = ConnectPacket::decode(&mut Cursor::new(&encoded_data[..])).unwrap();
+        assert_eq!(decoded.protocol_name(), "MQTT");
+        assert_eq!(decoded.protocol_level(), 3);
+        assert_eq!(decoded.protocol_version(), None);
+        assert_eq!(decoded.violations(), vec![ProtocolViolation::ProtocolLevel]);
+    }
+
+    #[test]
+    fn test_connect_packet_unknown_protocol() {
+        let encoded_data = b"\x10\x11\x00\x04MQTX\x07\x00\x00\x00\x00\x0512345";
+        let decoded = ConnectPacket::decode(&mut Cursor::new(&encoded_data[..])).unwrap();
+        assert_eq!(decoded.protocol_name(), "MQTX");
+        assert_eq!(decoded.protocol_level(), 7);
+        assert_eq!(decoded.client_identifier(), "12345");
+        assert_eq!(decoded.violations(), vec![ProtocolViolation::ProtocolName]);
+    }
 }
@@ -1,11 +1,15 @@
 //! Specific packets
 
-use std::convert::From;
-use std::error::Error;
-use std::fmt;
-use std::io::{self, Read, Write, Cursor};
-
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::From;
+use core::error::Error;
+use core::fmt;
+use io::{self, Read, Write, Cursor};
+
+#[cfg(feature = "std")]
 use futures::Future;
+#[cfg(feature = "std")]
 use tokio_io::{io as async_io, AsyncRead};
 
 use {Decodable, Encodable};
@@ -13,17 +17,20 @@ use control::ControlType;
 use control::FixedHeader;
 use control::fixed_header::FixedHeaderError;
 use control::variable_header::VariableHeaderError;
+use control::variable_header::properties::PropertyError;
+use decoder::{DecodeLimits, LimitError, LimitedDecodable};
 use encodable::StringEncodeError;
 use topic_name::TopicNameError;
 
+pub use self::builder::BuildError;
 pub use self::connack::ConnackPacket;
-pub use self::connect::ConnectPacket;
+pub use self::connect::{ConnectPacket, LastWill};
 pub use self::disconnect::DisconnectPacket;
 pub use self::pingreq::PingreqPacket;
 pub use self::pingresp::PingrespPacket;
 pub use self::puback::PubackPacket;
 pub use self::pubcomp::PubcompPacket;
-pub use self::publish::PublishPacket;
+pub use self::publish::{PublishPacket, PublishPacketRef, StackPublishPacket, VectoredPublish};
 pub use self::pubrec::PubrecPacket;
 pub use self::pubrel::PubrelPacket;
 pub use self::suback::SubackPacket;
@@ -32,7 +39,10 @@ pub use self::unsuback::UnsubackPacket;
 pub use self::unsubscribe::UnsubscribePacket;
 
 pub use self::publish::QoSWithPacketIdentifier;
+pub use self::validate::ProtocolViolation;
+pub use self::versioned::{VersionedPacket, VersionedPacketError};
 
+pub mod builder;
 pub mod connect;
 pub mod connack;
 pub mod publish;
@@ -47,6 +57,8 @@ pub mod subscribe;
 pub mod suback;
 pub mod unsuback;
 pub mod unsubscribe;
+pub mod validate;
+pub mod versioned;
 
 /// Methods for encoding and decoding a packet
 pub trait Packet: Sized {
@@ -65,6 +77,15 @@ pub trait Packet: Sized {
     fn encoded_variable_headers_length(&self) -> u32;
     /// Deocde packet with a `FixedHeader`
     fn decode_packet<R: Read>(reader: &mut R, fixed_header: FixedHeader) -> Result<Self, PacketError<Self>>;
+    /// Decode packet with a `FixedHeader`, refusing the strings that `limits` rejects
+    ///
+    /// Packets without strings are decoded with `decode_packet`.
+    fn decode_packet_limited<R: Read>(reader: &mut R,
+                                      fixed_header: FixedHeader,
+                                      _limits: &DecodeLimits)
+                                      -> Result<Self, PacketError<Self>> {
+        Self::decode_packet(reader, fixed_header)
+    }
 }
 
 impl<T: Packet + fmt::Debug + 'static> Encodable for T {
@@ -99,6 +120,19 @@ impl<T: Packet + fmt::Debug + 'static> Decodable for T {
     }
 }
 
+impl<T: Packet + fmt::Debug + 'static> LimitedDecodable for T {
+    fn decode_limited<R: Read>(reader: &mut R, fixed_header: Option<FixedHeader>, limits: &DecodeLimits)
+                               -> Result<Self, PacketError<Self>> {
+        let fixed_header: FixedHeader = if let Some(hdr) = fixed_header {
+            hdr
+        } else {
+            Decodable::decode(reader)?
+        };
+
+        <Self as Packet>::decode_packet_limited(reader, fixed_header, limits)
+    }
+}
+
 /// Parsing errors for packet
 #[derive(Debug)]
 pub enum PacketError<T: Packet + 'static> {
@@ -163,6 +197,12 @@ impl<T: Packet> From<VariableHeaderError> for PacketError<T> {
     }
 }
 
+impl<T: Packet> From<PropertyError> for PacketError<T> {
+    fn from(err: PropertyError) -> PacketError<T> {
+        PacketError::VariableHeaderError(From::from(err))
+    }
+}
+
 impl<T: Packet> From<io::Error> for PacketError<T> {
     fn from(err: io::Error) -> PacketError<T> {
         PacketError::IoError(err)
@@ -192,6 +232,42 @@ macro_rules! impl_variable_packet {
         }
 
         impl VariablePacket {
+            pub fn new<T>(t: T) -> VariablePacket
+            where
+                VariablePacket: From<T>,
+            {
+                From::from(t)
+            }
+
+            /// `FixedHeader` of the wrapped packet
+            pub fn fixed_header(&self) -> &FixedHeader {
+                match *self {
+                    $(
+                        VariablePacket::$name(ref pk) => pk.fixed_header(),
+                    )+
+                }
+            }
+
+            /// Decodes a packet from a blocking reader, rejecting it if it exceeds `limits`
+            ///
+            /// The packet size is checked before its remaining length is read.
+            pub fn decode_with_limits<R: Read>(reader: &mut R, limits: &DecodeLimits) -> Result<VariablePacket, VariablePacketError> {
+                let fixed_header = FixedHeader::decode(reader)?;
+                let size = fixed_header.encoded_length() as usize + fixed_header.remaining_length as usize;
+                limits.check_packet_size(size)?;
+
+                let mut frame = Vec::new();
+                fixed_header.encode(&mut frame)?;
+                reader.take(u64::from(fixed_header.remaining_length)).read_to_end(&mut frame)?;
+                if frame.len() != size {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                }
+                limits.check_fields(&frame)?;
+
+                VariablePacket::decode_limited(&mut Cursor::new(frame), None, limits)
+            }
+
+            #[cfg(feature = "std")]
             pub fn peek<A: AsyncRead>(rdr: A) -> impl Future<Item = (A, FixedHeader, Vec<u8>), Error = VariablePacketError> {
                 FixedHeader::parse(rdr).then(|result| {
                     let (rdr, fixed_header, data) = match result {
@@ -212,6 +288,7 @@ macro_rules! impl_variable_packet {
                     Ok((rdr, fixed_header, data))
                 })
             }
+            #[cfg(feature = "std")]
             pub fn peek_finalize<A: AsyncRead>(rdr: A) -> impl Future<Item = (A, Vec<u8>, Self), Error = VariablePacketError> {
                 Self::peek(rdr).and_then(|(rdr, fixed_header, header_buffer)| {
                     let packet = vec![0u8; fixed_header.remaining_length as usize];
@@ -226,6 +303,8 @@ macro_rules! impl_variable_packet {
                                         VariablePacket::$name(pk)
                                     }
                                 )+
+                                #[allow(unreachable_patterns)]
+                                ty => return Err(VariablePacketError::ReservedPacket(ty as u8, packet)),
                             };
                             let mut result = Vec::new();
                             result.extend(header_buffer);
@@ -234,6 +313,7 @@ macro_rules! impl_variable_packet {
                         })
                 })
             }
+            #[cfg(feature = "std")]
             pub fn parse<A: AsyncRead>(rdr: A) -> impl Future<Item = (A, Self), Error = VariablePacketError> {
                 Self::peek(rdr).and_then(|(rdr, fixed_header, _)| {
                     let buffer = vec![0u8; fixed_header.remaining_length as usize];
@@ -248,6 +328,8 @@ macro_rules! impl_variable_packet {
                                         VariablePacket::$name(pk)
                                     }
                                 )+
+                                #[allow(unreachable_patterns)]
+                                ty => return Err(VariablePacketError::ReservedPacket(ty as u8, buff_rdr.into_inner())),
                             };
 
                             Ok((rdr, output))
@@ -290,6 +372,13 @@ macro_rules! impl_variable_packet {
 
             fn decode_with<R: Read>(reader: &mut R, fixed_header: Option<FixedHeader>)
                     -> Result<VariablePacket, Self::Err> {
+                VariablePacket::decode_limited(reader, fixed_header, &DecodeLimits::default())
+            }
+        }
+
+        impl LimitedDecodable for VariablePacket {
+            fn decode_limited<R: Read>(reader: &mut R, fixed_header: Option<FixedHeader>, limits: &DecodeLimits)
+                    -> Result<VariablePacket, Self::Err> {
                 let fixed_header = match fixed_header {
                     Some(fh) => fh,
                     None => {
@@ -297,14 +386,14 @@ macro_rules! impl_variable_packet {
                             Ok(header) => header,
                             Err(FixedHeaderError::Unrecognized(code, length)) => {
                                 let reader = &mut reader.take(length as u64);
-                                let mut buf = Vec::with_capacity(length as usize);
-                                try!(reader.read_to_end(&mut buf));
+                                let mut buf = Vec::new();
+                                reader.read_to_end(&mut buf)?;
                                 return Err(VariablePacketError::UnrecognizedPacket(code, buf));
                             },
                             Err(FixedHeaderError::ReservedType(code, length)) => {
                                 let reader = &mut reader.take(length as u64);
-                                let mut buf = Vec::with_capacity(length as usize);
-                                try!(reader.read_to_end(&mut buf));
+                                let mut buf = Vec::new();
+                                reader.read_to_end(&mut buf)?;
                                 return Err(VariablePacketError::ReservedPacket(code, buf));
                             },
                             Err(err) => return Err(From::from(err))
@@ -316,10 +405,17 @@ macro_rules! impl_variable_packet {
                 match fixed_header.packet_type.control_type {
                     $(
                         ControlType::$hdr => {
-                            let pk = try!(<$name as Packet>::decode_packet(reader, fixed_header));
+                            let pk = <$name as Packet>::decode_packet_limited(reader, fixed_header, limits)?;
                             Ok(VariablePacket::$name(pk))
                         }
                     )+
+                    // Control types that are not defined in this protocol version
+                    #[allow(unreachable_patterns)]
+                    ty => {
+                        let mut buf = Vec::new();
+                        reader.take(u64::from(fixed_header.remaining_length)).read_to_end(&mut buf)?;
+                        Err(VariablePacketError::ReservedPacket(ty as u8, buf))
+                    }
                 }
             }
         }
@@ -331,6 +427,7 @@ macro_rules! impl_variable_packet {
             UnrecognizedPacket(u8, Vec<u8>),
             ReservedPacket(u8, Vec<u8>),
             IoError(io::Error),
+            LimitExceeded(LimitError),
             $(
                 $errname(PacketError<$name>),
             )+
@@ -348,6 +445,12 @@ macro_rules! impl_variable_packet {
             }
         }
 
+        impl From<LimitError> for VariablePacketError {
+            fn from(err: LimitError) -> VariablePacketError {
+                VariablePacketError::LimitExceeded(err)
+            }
+        }
+
         $(
             impl From<PacketError<$name>> for VariablePacketError {
                 fn from(err: PacketError<$name>) -> VariablePacketError {
@@ -358,41 +461,31 @@ macro_rules! impl_variable_packet {
 
         impl fmt::Display for VariablePacketError {
             fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                match self {
-                    &VariablePacketError::FixedHeaderError(ref err) => err.fmt(f),
-                    &VariablePacketError::UnrecognizedPacket(ref code, ref v) =>
+                match *self {
+                    VariablePacketError::FixedHeaderError(ref err) => err.fmt(f),
+                    VariablePacketError::UnrecognizedPacket(code, ref v) =>
                         write!(f, "Unrecognized type ({}), [u8, ..{}]", code, v.len()),
-                    &VariablePacketError::ReservedPacket(ref code, ref v) =>
+                    VariablePacketError::ReservedPacket(code, ref v) =>
                         write!(f, "Reserved type ({}), [u8, ..{}]", code, v.len()),
-                    &VariablePacketError::IoError(ref err) => err.fmt(f),
+                    VariablePacketError::IoError(ref err) => err.fmt(f),
+                    VariablePacketError::LimitExceeded(ref err) => err.fmt(f),
                     $(
-                        &VariablePacketError::$errname(ref err) => err.fmt(f),
+                        VariablePacketError::$errname(ref err) => err.fmt(f),
                     )+
                 }
             }
         }
 
         impl Error for VariablePacketError {
-            fn description(&self) -> &str {
-                match self {
-                    &VariablePacketError::FixedHeaderError(ref err) => err.description(),
-                    &VariablePacketError::UnrecognizedPacket(..) => "Unrecognized packet",
-                    &VariablePacketError::ReservedPacket(..) => "Reserved packet",
-                    &VariablePacketError::IoError(ref err) => err.description(),
-                    $(
-                        &VariablePacketError::$errname(ref err) => err.description(),
-                    )+
-                }
-            }
-
             fn source(&self) -> Option<&(dyn Error + 'static)> {
-                match self {
-                    &VariablePacketError::FixedHeaderError(ref err) => Some(err),
-                    &VariablePacketError::UnrecognizedPacket(..) => None,
-                    &VariablePacketError::ReservedPacket(..) => None,
-                    &VariablePacketError::IoError(ref err) => Some(err),
+                match *self {
+                    VariablePacketError::FixedHeaderError(ref err) => Some(err),
+                    VariablePacketError::UnrecognizedPacket(..) => None,
+                    VariablePacketError::ReservedPacket(..) => None,
+                    VariablePacketError::IoError(ref err) => Some(err),
+                    VariablePacketError::LimitExceeded(ref err) => Some(err),
                     $(
-                        &VariablePacketError::$errname(ref err) => Some(err),
+                        VariablePacketError::$errname(ref err) => Some(err),
                     )+
                 }
             }
@@ -422,14 +515,8 @@ impl_variable_packet! {
     DisconnectPacket    & DisconnectPacketError     => Disconnect,
 }
 
-impl VariablePacket {
-    pub fn new<T>(t: T) -> VariablePacket
-    where
-        VariablePacket: From<T>,
-    {
-        From::from(t)
-    }
-}
+// Declared after `impl_variable_packet!` so that the MQTT 5 packets can reuse it
+pub mod v5;
 
 #[cfg(test)]
 mod test {
@@ -437,6 +524,7 @@ mod test {
 
     use std::io::Cursor;
 
+    use topic_name::TopicName;
     use {Decodable, Encodable};
 
     #[test]
@@ -457,6 +545,42 @@ mod test {
         assert_eq!(var_packet, decoded_packet);
     }
 
+    #[test]
+    fn test_variable_packet_decode_with_limits() {
+        let var_packet = VariablePacket::new(ConnectPacket::new("MQTT".to_owned(), "1234".to_owned()));
+        let mut buf = Vec::new();
+        var_packet.encode(&mut buf).unwrap();
+
+        let limits = DecodeLimits::default();
+        let decoded = VariablePacket::decode_with_limits(&mut Cursor::new(&buf[..]), &limits).unwrap();
+        assert_eq!(var_packet, decoded);
+
+        let limits = DecodeLimits { max_client_id_length: 3, ..DecodeLimits::default() };
+        match VariablePacket::decode_with_limits(&mut Cursor::new(&buf[..]), &limits) {
+            Err(VariablePacketError::LimitExceeded(LimitError::ClientIdTooLong(4, 3))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        let limits = DecodeLimits { max_packet_size: 8, ..DecodeLimits::default() };
+        match VariablePacket::decode_with_limits(&mut Cursor::new(&buf[..2]), &limits) {
+            Err(VariablePacketError::LimitExceeded(LimitError::PacketTooLarge(18, 8))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        let var_packet = VariablePacket::new(PublishPacket::new(TopicName::new("a\u{85}").unwrap(),
+                                                                QoSWithPacketIdentifier::Level0,
+                                                                "x"));
+        let mut buf = Vec::new();
+        var_packet.encode(&mut buf).unwrap();
+        let limits = DecodeLimits { reject_control_characters: true, ..DecodeLimits::default() };
+        match VariablePacket::decode_with_limits(&mut Cursor::new(&buf[..]), &limits) {
+            Err(VariablePacketError::PublishPacketError(PacketError::TopicNameError(TopicNameError::StringEncodeError(
+                StringEncodeError::InvalidCodePoint(err),
+            )))) => assert_eq!(err.code_point, '\u{85}'),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
     #[test]
     fn test_variable_packet_async_parse() {
         use std::io::Cursor;
@@ -1,6 +1,6 @@
 //! PINGRESP
 
-use std::io::{Read, Write};
+use io::{Read, Write};
 
 use control::{ControlType, FixedHeader, PacketType};
 use packet::{Packet, PacketError};
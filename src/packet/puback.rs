@@ -1,6 +1,6 @@
 //! PUBACK
 
-use std::io::{Read, Write};
+use io::{Read, Write};
 
 use {Decodable, Encodable};
 use control::{ControlType, FixedHeader, PacketType};
@@ -1,13 +1,21 @@
 //! PUBLISH
 
-use std::io::{Read, Write};
+use alloc::borrow::ToOwned;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str;
+use io::{self, Cursor, Read, Write};
+#[cfg(feature = "std")]
+use io::IoSlice;
 
 use {Decodable, Encodable};
 use control::{ControlType, FixedHeader, PacketType};
-use control::variable_header::PacketIdentifier;
+use control::variable_header::{PacketIdentifier, VariableHeaderError};
+use decoder::{DecodeLimits, LimitedDecodable};
+use encodable::{decode_str, BufferTooSmall, StringField};
 use packet::{Packet, PacketError};
 use qos::QualityOfService;
-use topic_name::TopicName;
+use topic_name::{TopicName, TopicNameRef};
 
 /// QoS with identifier pairs
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
@@ -61,11 +69,11 @@ impl PublishPacket {
     }
 
     pub fn set_dup(&mut self, dup: bool) {
-        self.fixed_header.packet_type.flags |= (dup as u8) << 3;
+        self.fixed_header.packet_type.flags = (self.fixed_header.packet_type.flags & !0x08) | ((dup as u8) << 3);
     }
 
     pub fn dup(&self) -> bool {
-        self.fixed_header.packet_type.flags & 0x80 != 0
+        self.fixed_header.packet_type.flags & 0x08 != 0
     }
 
     pub fn set_qos(&mut self, qos: QoSWithPacketIdentifier) {
@@ -74,8 +82,9 @@ impl PublishPacket {
             QoSWithPacketIdentifier::Level1(pkid) => (1, Some(PacketIdentifier(pkid))),
             QoSWithPacketIdentifier::Level2(pkid) => (2, Some(PacketIdentifier(pkid))),
         };
-        self.fixed_header.packet_type.flags |= qos << 1;
+        self.fixed_header.packet_type.flags = (self.fixed_header.packet_type.flags & !0x06) | (qos << 1);
         self.packet_identifier = pkid;
+        self.fixed_header.remaining_length = self.calculate_remaining_length();
     }
 
     pub fn qos(&self) -> QoSWithPacketIdentifier {
@@ -93,13 +102,17 @@ impl PublishPacket {
     }
 
     pub fn set_retain(&mut self, ret: bool) {
-        self.fixed_header.packet_type.flags |= ret as u8;
+        self.fixed_header.packet_type.flags = (self.fixed_header.packet_type.flags & !0x01) | ret as u8;
     }
 
     pub fn retain(&self) -> bool {
         self.fixed_header.packet_type.flags & 0x01 != 0
     }
 
+    pub fn packet_identifier(&self) -> Option<u16> {
+        self.packet_identifier.map(|pkid| pkid.0)
+    }
+
     pub fn set_topic_name(&mut self, topic_name: TopicName) {
         self.topic_name = topic_name;
         self.fixed_header.remaining_length = self.calculate_remaining_length();
@@ -108,6 +121,11 @@ impl PublishPacket {
     pub fn topic_name(&self) -> &str {
         &self.topic_name[..]
     }
+
+    /// Encodes the header for writing ahead of the payload without copying it, see `VectoredPublish`
+    pub fn encode_vectored(&self) -> Result<VectoredPublish<'_>, PacketError<PublishPacket>> {
+        PublishPacketRef::from(self).encode_vectored()
+    }
 }
 
 impl Packet for PublishPacket {
@@ -144,7 +162,18 @@ impl Packet for PublishPacket {
     }
 
     fn decode_packet<R: Read>(reader: &mut R, fixed_header: FixedHeader) -> Result<Self, PacketError<Self>> {
-        let topic_name: TopicName = TopicName::decode(reader)?;
+        PublishPacket::decode_packet_limited(reader, fixed_header, &DecodeLimits::default())
+    }
+
+    fn decode_packet_limited<R: Read>(reader: &mut R,
+                                      fixed_header: FixedHeader,
+                                      limits: &DecodeLimits)
+                                      -> Result<Self, PacketError<Self>> {
+        if fixed_header.packet_type.flags & 0x06 == 0x06 {
+            return Err(VariableHeaderError::InvalidQualityOfService(3).into());
+        }
+
+        let topic_name = TopicName::decode_limited(reader, None, limits)?;
 
         let packet_identifier = if fixed_header.packet_type.flags & 0x06 != 0 {
             Some(PacketIdentifier::decode(reader)?)
@@ -156,7 +185,10 @@ impl Packet for PublishPacket {
             packet_identifier.as_ref()
                              .map(|x| x.encoded_length())
                              .unwrap_or(0);
-        let payload_len = fixed_header.remaining_length - vhead_len;
+        let payload_len = fixed_header
+            .remaining_length
+            .checked_sub(vhead_len)
+            .ok_or_else(|| PacketError::MalformedPacket("Remaining length too short".to_owned()))?;
 
         let payload: Vec<u8> = Decodable::decode_with(reader, Some(payload_len))?;
 
@@ -169,6 +201,361 @@ impl Packet for PublishPacket {
     }
 }
 
+/// `PUBLISH` packet borrowing its topic name and payload from a buffer
+///
+/// Decoding with `decode_from` does not allocate, so the packet can be inspected in place and
+/// converted with `into_owned` only when it has to outlive the receive buffer.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct PublishPacketRef<'a> {
+    fixed_header: FixedHeader,
+    topic_name: &'a TopicNameRef,
+    packet_identifier: Option<PacketIdentifier>,
+    payload: &'a [u8],
+}
+
+impl<'a> PublishPacketRef<'a> {
+    pub fn new(topic_name: &'a TopicNameRef, qos: QoSWithPacketIdentifier, payload: &'a [u8]) -> PublishPacketRef<'a> {
+        let (qos, pkid) = match qos {
+            QoSWithPacketIdentifier::Level0 => (0, None),
+            QoSWithPacketIdentifier::Level1(pkid) => (1, Some(PacketIdentifier(pkid))),
+            QoSWithPacketIdentifier::Level2(pkid) => (2, Some(PacketIdentifier(pkid))),
+        };
+
+        let mut pk = PublishPacketRef {
+            fixed_header: FixedHeader::new(PacketType::with_default(ControlType::Publish), 0),
+            topic_name,
+            packet_identifier: pkid,
+            payload,
+        };
+        pk.fixed_header.packet_type.flags |= qos << 1;
+        pk.fixed_header.remaining_length = pk.encoded_variable_headers_length() + payload.len() as u32;
+        pk
+    }
+
+    /// Decodes a `PUBLISH` packet from the start of `buf`
+    ///
+    /// Returns the packet and the number of bytes it occupies in `buf`.
+    pub fn decode_from(buf: &'a [u8]) -> Result<(PublishPacketRef<'a>, usize), PacketError<PublishPacket>> {
+        let mut rdr = Cursor::new(buf);
+        let fixed_header = FixedHeader::decode(&mut rdr)?;
+        if fixed_header.packet_type.control_type != ControlType::Publish {
+            return Err(PacketError::MalformedPacket(format!("Expected PUBLISH, got {:?}",
+                                                            fixed_header.packet_type.control_type)));
+        }
+        if fixed_header.packet_type.flags & 0x06 == 0x06 {
+            return Err(VariableHeaderError::InvalidQualityOfService(3).into());
+        }
+
+        let header_len = rdr.position() as usize;
+        let frame_len = header_len + fixed_header.remaining_length as usize;
+        let body = buf.get(header_len..frame_len)
+                      .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+
+        let (topic_name, mut pos) = decode_str(body, Some(StringField::TopicName))?;
+        let topic_name = TopicNameRef::new(topic_name)?;
+
+        let packet_identifier = if fixed_header.packet_type.flags & 0x06 != 0 {
+            let pkid = PacketIdentifier::decode(&mut Cursor::new(&body[pos..]))?;
+            pos += 2;
+            Some(pkid)
+        } else {
+            None
+        };
+
+        let pk = PublishPacketRef {
+            fixed_header,
+            topic_name,
+            packet_identifier,
+            payload: &body[pos..],
+        };
+        Ok((pk, frame_len))
+    }
+
+    pub fn dup(&self) -> bool {
+        self.fixed_header.packet_type.flags & 0x08 != 0
+    }
+
+    pub fn qos(&self) -> QoSWithPacketIdentifier {
+        match self.packet_identifier {
+            None => QoSWithPacketIdentifier::Level0,
+            Some(pkid) => {
+                let qos_val = (self.fixed_header.packet_type.flags & 0x06) >> 1;
+                match qos_val {
+                    1 => QoSWithPacketIdentifier::Level1(pkid.0),
+                    2 => QoSWithPacketIdentifier::Level2(pkid.0),
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+
+    pub fn retain(&self) -> bool {
+        self.fixed_header.packet_type.flags & 0x01 != 0
+    }
+
+    pub fn topic_name(&self) -> &'a TopicNameRef {
+        self.topic_name
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    /// Copies the topic name and payload into an owned `PublishPacket`
+    pub fn into_owned(self) -> PublishPacket {
+        PublishPacket {
+            fixed_header: self.fixed_header,
+            topic_name: unsafe { TopicName::new_unchecked(self.topic_name[..].to_owned()) },
+            packet_identifier: self.packet_identifier,
+            payload: self.payload.to_vec(),
+        }
+    }
+
+    /// Encodes the header for writing ahead of the borrowed payload, see `VectoredPublish`
+    pub fn encode_vectored(&self) -> Result<VectoredPublish<'a>, PacketError<PublishPacket>> {
+        let mut header = Vec::with_capacity((self.encoded_length() - self.payload.len() as u32) as usize);
+        self.fixed_header.encode(&mut header)?;
+        (&self.topic_name[..]).encode(&mut header)?;
+        if let Some(pkid) = self.packet_identifier.as_ref() {
+            pkid.encode(&mut header)?;
+        }
+
+        Ok(VectoredPublish {
+            header,
+            payload: self.payload,
+        })
+    }
+
+    fn encoded_variable_headers_length(&self) -> u32 {
+        (&self.topic_name[..]).encoded_length() +
+            self.packet_identifier
+                .as_ref()
+                .map(|x| x.encoded_length())
+                .unwrap_or(0)
+    }
+}
+
+impl<'a> From<&'a PublishPacket> for PublishPacketRef<'a> {
+    fn from(packet: &'a PublishPacket) -> PublishPacketRef<'a> {
+        PublishPacketRef {
+            fixed_header: packet.fixed_header,
+            topic_name: &packet.topic_name,
+            packet_identifier: packet.packet_identifier,
+            payload: &packet.payload,
+        }
+    }
+}
+
+impl<'a> Encodable for PublishPacketRef<'a> {
+    type Err = PacketError<PublishPacket>;
+
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), PacketError<PublishPacket>> {
+        self.fixed_header.encode(writer)?;
+        (&self.topic_name[..]).encode(writer)?;
+
+        if let Some(pkid) = self.packet_identifier.as_ref() {
+            pkid.encode(writer)?;
+        }
+
+        self.payload.encode(writer).map_err(PacketError::PayloadError)
+    }
+
+    fn encoded_length(&self) -> u32 {
+        self.fixed_header.encoded_length() + self.encoded_variable_headers_length() + self.payload.len() as u32
+    }
+}
+
+/// `PUBLISH` packet storing its topic name and payload inline in an `N`-byte array
+///
+/// Like the `heapless` collections it never allocates, so small messages can be built, queued and
+/// encoded with `encode_to_slice` on targets without a heap.
+///
+/// ```rust
+/// use mqtt::{Encodable, TopicNameRef};
+/// use mqtt::packet::{QoSWithPacketIdentifier, StackPublishPacket};
+///
+/// let topic_name = TopicNameRef::new("sensors/temp").unwrap();
+/// let packet = StackPublishPacket::<32>::new(topic_name, QoSWithPacketIdentifier::Level1(1), b"21.5").unwrap();
+///
+/// let mut buf = [0u8; 64];
+/// let len = packet.encode_to_slice(&mut buf).unwrap();
+/// assert_eq!(len, packet.encoded_length() as usize);
+///
+/// // The topic name and payload need 16 bytes
+/// assert!(StackPublishPacket::<8>::new(topic_name, QoSWithPacketIdentifier::Level0, b"21.5").is_err());
+/// ```
+#[derive(Eq, PartialEq, Clone, Copy)]
+pub struct StackPublishPacket<const N: usize> {
+    fixed_header: FixedHeader,
+    packet_identifier: Option<PacketIdentifier>,
+    topic_name_len: usize,
+    len: usize,
+    data: [u8; N],
+}
+
+impl<const N: usize> StackPublishPacket<N> {
+    /// Copies the topic name and payload in, failing if together they take more than `N` bytes
+    pub fn new(topic_name: &TopicNameRef,
+               qos: QoSWithPacketIdentifier,
+               payload: &[u8])
+               -> Result<StackPublishPacket<N>, BufferTooSmall> {
+        let topic_name = topic_name[..].as_bytes();
+        let len = topic_name.len() + payload.len();
+        if len > N {
+            return Err(BufferTooSmall { required: len, available: N });
+        }
+
+        let (qos, pkid) = match qos {
+            QoSWithPacketIdentifier::Level0 => (0, None),
+            QoSWithPacketIdentifier::Level1(pkid) => (1, Some(PacketIdentifier(pkid))),
+            QoSWithPacketIdentifier::Level2(pkid) => (2, Some(PacketIdentifier(pkid))),
+        };
+
+        let mut pk = StackPublishPacket {
+            fixed_header: FixedHeader::new(PacketType::with_default(ControlType::Publish), 0),
+            packet_identifier: pkid,
+            topic_name_len: topic_name.len(),
+            len,
+            data: [0u8; N],
+        };
+        pk.data[..topic_name.len()].copy_from_slice(topic_name);
+        pk.data[topic_name.len()..len].copy_from_slice(payload);
+        pk.fixed_header.packet_type.flags |= qos << 1;
+        pk.fixed_header.remaining_length = PublishPacketRef::from(&pk).encoded_variable_headers_length() +
+                                           payload.len() as u32;
+        Ok(pk)
+    }
+
+    pub fn set_dup(&mut self, dup: bool) {
+        self.fixed_header.packet_type.flags = (self.fixed_header.packet_type.flags & !0x08) | ((dup as u8) << 3);
+    }
+
+    pub fn dup(&self) -> bool {
+        self.fixed_header.packet_type.flags & 0x08 != 0
+    }
+
+    pub fn qos(&self) -> QoSWithPacketIdentifier {
+        PublishPacketRef::from(self).qos()
+    }
+
+    pub fn set_retain(&mut self, ret: bool) {
+        self.fixed_header.packet_type.flags = (self.fixed_header.packet_type.flags & !0x01) | ret as u8;
+    }
+
+    pub fn retain(&self) -> bool {
+        self.fixed_header.packet_type.flags & 0x01 != 0
+    }
+
+    pub fn topic_name(&self) -> &TopicNameRef {
+        PublishPacketRef::from(self).topic_name()
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data[self.topic_name_len..self.len]
+    }
+}
+
+impl<'a, const N: usize> From<&'a StackPublishPacket<N>> for PublishPacketRef<'a> {
+    fn from(packet: &'a StackPublishPacket<N>) -> PublishPacketRef<'a> {
+        // The bytes were copied from a `TopicNameRef` in `new`
+        let topic_name = unsafe {
+            TopicNameRef::new_unchecked(str::from_utf8_unchecked(&packet.data[..packet.topic_name_len]))
+        };
+        PublishPacketRef {
+            fixed_header: packet.fixed_header,
+            topic_name,
+            packet_identifier: packet.packet_identifier,
+            payload: packet.payload(),
+        }
+    }
+}
+
+impl<const N: usize> fmt::Debug for StackPublishPacket<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StackPublishPacket")
+         .field("fixed_header", &self.fixed_header)
+         .field("topic_name", &self.topic_name())
+         .field("packet_identifier", &self.packet_identifier)
+         .field("payload", &self.payload())
+         .finish()
+    }
+}
+
+impl<const N: usize> Encodable for StackPublishPacket<N> {
+    type Err = PacketError<PublishPacket>;
+
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), PacketError<PublishPacket>> {
+        PublishPacketRef::from(self).encode(writer)
+    }
+
+    fn encoded_length(&self) -> u32 {
+        PublishPacketRef::from(self).encoded_length()
+    }
+}
+
+/// `PUBLISH` packet encoded as its header bytes followed by its still borrowed payload
+///
+/// Created by `encode_vectored`, so a multi-megabyte payload can be written with `write_vectored`
+/// instead of being copied into an output buffer first.
+///
+/// ```rust
+/// use mqtt::{Encodable, TopicName};
+/// use mqtt::packet::{Packet, PublishPacket, QoSWithPacketIdentifier};
+///
+/// let packet = PublishPacket::new(TopicName::new("files/upload").unwrap(),
+///                                 QoSWithPacketIdentifier::Level1(3),
+///                                 vec![0u8; 1 << 20]);
+/// let vectored = packet.encode_vectored().unwrap();
+/// assert_eq!(vectored.payload().as_ptr(), packet.payload_ref().as_ptr());
+///
+/// let mut buf = Vec::new();
+/// vectored.write_to(&mut buf).unwrap();
+/// assert_eq!(buf.len(), packet.encoded_length() as usize);
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct VectoredPublish<'a> {
+    header: Vec<u8>,
+    payload: &'a [u8],
+}
+
+impl<'a> VectoredPublish<'a> {
+    /// Fixed header, topic name and packet identifier
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    /// Header and payload as the buffers for `Write::write_vectored`
+    #[cfg(feature = "std")]
+    pub fn as_io_slices(&self) -> [IoSlice<'_>; 2] {
+        [IoSlice::new(&self.header), IoSlice::new(self.payload)]
+    }
+
+    /// Writes the whole packet with `write_vectored`, continuing after partial writes
+    #[cfg(feature = "std")]
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut written = 0;
+        while written < self.header.len() + self.payload.len() {
+            let result = if written < self.header.len() {
+                writer.write_vectored(&[IoSlice::new(&self.header[written..]), IoSlice::new(self.payload)])
+            } else {
+                writer.write(&self.payload[written - self.header.len()..])
+            };
+
+            match result {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                Ok(n) => written += n,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -176,6 +563,7 @@ mod test {
     use std::io::Cursor;
 
     use {Decodable, Encodable};
+    use encodable::SliceEncodeError;
     use topic_name::TopicName;
 
     #[test]
@@ -192,4 +580,139 @@ mod test {
 
         assert_eq!(packet, decoded);
     }
+
+    #[test]
+    fn test_publish_packet_ref_decode() {
+        let packet = PublishPacket::new(TopicName::new("a/b").unwrap(),
+                                        QoSWithPacketIdentifier::Level1(10),
+                                        b"Hello world!".to_vec());
+
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+        let frame_len = buf.len();
+        buf.extend_from_slice(b"\xc0\x00");
+
+        let (decoded, len) = PublishPacketRef::decode_from(&buf).unwrap();
+        assert_eq!(len, frame_len);
+        assert_eq!(&decoded.topic_name()[..], "a/b");
+        assert_eq!(decoded.payload(), b"Hello world!");
+        assert_eq!(decoded.qos(), QoSWithPacketIdentifier::Level1(10));
+        assert_eq!(decoded, PublishPacketRef::from(&packet));
+        assert_eq!(decoded.into_owned(), packet);
+
+        let mut encoded = Vec::new();
+        decoded.encode(&mut encoded).unwrap();
+        assert_eq!(&encoded[..], &buf[..frame_len]);
+    }
+
+    #[test]
+    fn test_publish_packet_invalid_qos() {
+        // QoS bits 0b11, topic "a", packet identifier 1
+        let buf = b"\x36\x05\x00\x01a\x00\x01";
+        match PublishPacket::decode(&mut Cursor::new(&buf[..])) {
+            Err(PacketError::VariableHeaderError(VariableHeaderError::InvalidQualityOfService(3))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+        match PublishPacketRef::decode_from(buf) {
+            Err(PacketError::VariableHeaderError(VariableHeaderError::InvalidQualityOfService(3))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        // Remaining length shorter than the topic name and packet identifier
+        let buf = b"\x32\x02\x00\x01a\x00\x01";
+        match PublishPacket::decode(&mut Cursor::new(&buf[..])) {
+            Err(PacketError::MalformedPacket(..)) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_stack_publish_packet() {
+        let topic_name = TopicNameRef::new("a/b").unwrap();
+        let mut packet: StackPublishPacket<16> =
+            StackPublishPacket::new(topic_name, QoSWithPacketIdentifier::Level2(7), b"Hello").unwrap();
+        packet.set_retain(true);
+        assert_eq!(&packet.topic_name()[..], "a/b");
+        assert_eq!(packet.payload(), b"Hello");
+        assert_eq!(packet.qos(), QoSWithPacketIdentifier::Level2(7));
+
+        let mut owned = PublishPacket::new(TopicName::new("a/b").unwrap(), QoSWithPacketIdentifier::Level2(7), "Hello");
+        owned.set_retain(true);
+        let mut expected = Vec::new();
+        owned.encode(&mut expected).unwrap();
+
+        let mut buf = [0u8; 32];
+        let len = packet.encode_to_slice(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &expected[..]);
+        match owned.encode_to_slice(&mut buf[..len - 1]) {
+            Err(SliceEncodeError::BufferTooSmall(err)) => {
+                assert_eq!(err, BufferTooSmall { required: len, available: len - 1 })
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        match StackPublishPacket::<7>::new(topic_name, QoSWithPacketIdentifier::Level0, b"Hello") {
+            Err(BufferTooSmall { required: 8, available: 7 }) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_publish_packet_encode_vectored() {
+        use std::io::{self, IoSlice, Write};
+
+        // Accepts at most three bytes per call to exercise partial vectored writes
+        struct Trickle(Vec<u8>);
+
+        impl Write for Trickle {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let n = buf.len().min(3);
+                self.0.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+
+            fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+                let buf = bufs.iter().find(|buf| !buf.is_empty()).map_or(&[][..], |buf| &buf[..]);
+                self.write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let packet = PublishPacket::new(TopicName::new("a/b").unwrap(),
+                                        QoSWithPacketIdentifier::Level1(10),
+                                        b"Hello world!".to_vec());
+        let mut expected = Vec::new();
+        packet.encode(&mut expected).unwrap();
+
+        let vectored = packet.encode_vectored().unwrap();
+        assert_eq!(vectored.header(), &expected[..expected.len() - 12]);
+        assert_eq!(vectored.payload(), b"Hello world!");
+        assert_eq!(vectored.as_io_slices().iter().map(|buf| buf.len()).sum::<usize>(), expected.len());
+
+        let mut writer = Trickle(Vec::new());
+        vectored.write_to(&mut writer).unwrap();
+        assert_eq!(writer.0, expected);
+    }
+
+    #[test]
+    fn test_publish_packet_ref_incomplete() {
+        let topic_name = TopicNameRef::new("a/b").unwrap();
+        let packet = PublishPacketRef::new(topic_name, QoSWithPacketIdentifier::Level0, b"xyz");
+
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+        assert_eq!(packet.encoded_length() as usize, buf.len());
+
+        match PublishPacketRef::decode_from(&buf[..buf.len() - 1]) {
+            Err(PacketError::IoError(ref err)) if err.kind() == io::ErrorKind::UnexpectedEof => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+        match PublishPacketRef::decode_from(b"\xc0\x00") {
+            Err(PacketError::MalformedPacket(..)) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
 }
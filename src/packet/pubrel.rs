@@ -1,6 +1,6 @@
 //! PUBREL
 
-use std::io::{Read, Write};
+use io::{Read, Write};
 
 use {Decodable, Encodable};
 use control::{ControlType, FixedHeader, PacketType};
@@ -1,12 +1,11 @@
 //! SUBACK
 
-use std::cmp::Ordering;
-use std::convert::From;
-use std::error::Error;
-use std::fmt;
-use std::io::{self, Read, Write};
-
-use byteorder::{ReadBytesExt, WriteBytesExt};
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::convert::From;
+use core::error::Error;
+use core::fmt;
+use io::{self, Read, ReadBytesExt, Write, WriteBytesExt};
 
 use {Decodable, Encodable};
 use control::{ControlType, FixedHeader, PacketType};
@@ -1,14 +1,16 @@
 //! UNSUBSCRIBE
 
-use std::convert::From;
-use std::error::Error;
-use std::fmt;
-use std::io::{self, Read, Write};
-use std::string::FromUtf8Error;
+use alloc::string::FromUtf8Error;
+use alloc::vec::Vec;
+use core::convert::From;
+use core::error::Error;
+use core::fmt;
+use io::{self, Read, Write};
 
 use {Decodable, Encodable};
 use control::{ControlType, FixedHeader, PacketType};
 use control::variable_header::PacketIdentifier;
+use decoder::{DecodeLimits, LimitedDecodable};
 use encodable::StringEncodeError;
 use packet::{Packet, PacketError};
 use topic_filter::{TopicFilter, TopicFilterError};
@@ -67,11 +69,16 @@ impl Packet for UnsubscribePacket {
     }
 
     fn decode_packet<R: Read>(reader: &mut R, fixed_header: FixedHeader) -> Result<Self, PacketError<Self>> {
+        UnsubscribePacket::decode_packet_limited(reader, fixed_header, &DecodeLimits::default())
+    }
+
+    fn decode_packet_limited<R: Read>(reader: &mut R,
+                                      fixed_header: FixedHeader,
+                                      limits: &DecodeLimits)
+                                      -> Result<Self, PacketError<Self>> {
         let packet_identifier: PacketIdentifier = PacketIdentifier::decode(reader)?;
-        let payload: UnsubscribePacketPayload =
-            UnsubscribePacketPayload::decode_with(reader,
-                                                  Some(fixed_header.remaining_length -
-                                                           packet_identifier.encoded_length()))
+        let payload_len = fixed_header.remaining_length - packet_identifier.encoded_length();
+        let payload = UnsubscribePacketPayload::decode_limited(reader, Some(payload_len), limits)
             .map_err(PacketError::PayloadError)?;
         Ok(UnsubscribePacket {
                fixed_header: fixed_header,
@@ -121,11 +128,20 @@ impl Decodable for UnsubscribePacketPayload {
     fn decode_with<R: Read>(reader: &mut R,
                             payload_len: Option<u32>)
                             -> Result<UnsubscribePacketPayload, UnsubscribePacketPayloadError> {
+        UnsubscribePacketPayload::decode_limited(reader, payload_len, &DecodeLimits::default())
+    }
+}
+
+impl LimitedDecodable for UnsubscribePacketPayload {
+    fn decode_limited<R: Read>(reader: &mut R,
+                               payload_len: Option<u32>,
+                               limits: &DecodeLimits)
+                               -> Result<UnsubscribePacketPayload, UnsubscribePacketPayloadError> {
         let mut payload_len = payload_len.expect("Must provide payload length");
         let mut subs = Vec::new();
 
         while payload_len > 0 {
-            let filter = TopicFilter::decode(reader)?;
+            let filter = TopicFilter::decode_limited(reader, None, limits)?;
             payload_len -= filter.encoded_length();
             subs.push(filter);
         }
@@ -0,0 +1,134 @@
+//! AUTH (MQTT 5)
+
+use io::{Read, Write};
+
+use {Decodable, Encodable};
+use control::{ControlType, FixedHeader, PacketType};
+use control::variable_header::{Properties, ReasonCode};
+use control::variable_header::properties::PropertyError;
+use decoder::{DecodeLimits, LimitedDecodable};
+use packet::{Packet, PacketError};
+
+/// `AUTH` packet
+///
+/// The reason code and properties are omitted on the wire when they are `Success` and empty.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct AuthPacket {
+    fixed_header: FixedHeader,
+    reason_code: ReasonCode,
+    properties: Properties,
+    payload: (),
+}
+
+impl AuthPacket {
+    pub fn new(reason_code: ReasonCode) -> AuthPacket {
+        let mut pk = AuthPacket {
+            fixed_header: FixedHeader::new(PacketType::with_default(ControlType::Authentication), 0),
+            reason_code,
+            properties: Properties::new(),
+            payload: (),
+        };
+        pk.fixed_header.remaining_length = pk.encoded_variable_headers_length();
+        pk
+    }
+
+    pub fn reason_code(&self) -> ReasonCode {
+        self.reason_code
+    }
+
+    pub fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    /// Sets the properties, rejecting any that are not valid in this packet
+    pub fn set_properties(&mut self, properties: Properties) -> Result<(), PropertyError> {
+        properties.validate(self.fixed_header.packet_type.control_type.into())?;
+        self.properties = properties;
+        self.fixed_header.remaining_length = self.encoded_variable_headers_length();
+        Ok(())
+    }
+}
+
+impl Packet for AuthPacket {
+    type Payload = ();
+
+    fn fixed_header(&self) -> &FixedHeader {
+        &self.fixed_header
+    }
+
+    fn payload(self) -> Self::Payload {
+        self.payload
+    }
+
+    fn payload_ref(&self) -> &Self::Payload {
+        &self.payload
+    }
+
+    fn encode_variable_headers<W: Write>(&self, writer: &mut W) -> Result<(), PacketError<Self>> {
+        if self.reason_code != ReasonCode::Success || !self.properties.is_empty() {
+            self.reason_code.encode(writer)?;
+            self.properties.encode(writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn encoded_variable_headers_length(&self) -> u32 {
+        if self.reason_code != ReasonCode::Success || !self.properties.is_empty() {
+            self.reason_code.encoded_length() + self.properties.encoded_length()
+        } else {
+            0
+        }
+    }
+
+    fn decode_packet<R: Read>(reader: &mut R, fixed_header: FixedHeader) -> Result<Self, PacketError<Self>> {
+        AuthPacket::decode_packet_limited(reader, fixed_header, &DecodeLimits::default())
+    }
+
+    fn decode_packet_limited<R: Read>(reader: &mut R,
+                                      fixed_header: FixedHeader,
+                                      limits: &DecodeLimits)
+                                      -> Result<Self, PacketError<Self>> {
+        let reason_code = if fixed_header.remaining_length > 0 {
+            ReasonCode::decode(reader)?
+        } else {
+            ReasonCode::Success
+        };
+        let properties = if fixed_header.remaining_length > 1 {
+            Properties::decode_limited(reader, Some(fixed_header.packet_type.control_type.into()), limits)?
+        } else {
+            Properties::new()
+        };
+
+        Ok(AuthPacket {
+               fixed_header,
+               reason_code,
+               properties,
+               payload: (),
+           })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use {Decodable, Encodable};
+    use control::variable_header::Property;
+
+    #[test]
+    fn test_auth_packet_basic() {
+        let mut packet = AuthPacket::new(ReasonCode::ContinueAuthentication);
+        packet.set_properties(Properties::from(vec![Property::AuthenticationMethod("SCRAM-SHA-1".to_owned()),
+                                                    Property::AuthenticationData(b"client-first".to_vec())])).unwrap();
+
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+        assert_eq!(buf[0], 0xF0);
+
+        let decoded = AuthPacket::decode(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(packet, decoded);
+    }
+}
@@ -0,0 +1,127 @@
+//! CONNACK (MQTT 5)
+
+use io::{Read, Write};
+
+use {Decodable, Encodable};
+use control::{ControlType, FixedHeader, PacketType};
+use control::variable_header::{ConnackFlags, Properties, ReasonCode};
+use control::variable_header::properties::PropertyError;
+use decoder::{DecodeLimits, LimitedDecodable};
+use packet::{Packet, PacketError};
+
+/// `CONNACK` packet
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ConnackPacket {
+    fixed_header: FixedHeader,
+    flags: ConnackFlags,
+    reason_code: ReasonCode,
+    properties: Properties,
+    payload: (),
+}
+
+impl ConnackPacket {
+    pub fn new(session_present: bool, reason_code: ReasonCode) -> ConnackPacket {
+        let mut pk = ConnackPacket {
+            fixed_header: FixedHeader::new(PacketType::with_default(ControlType::ConnectAcknowledgement), 0),
+            flags: ConnackFlags { session_present },
+            reason_code,
+            properties: Properties::new(),
+            payload: (),
+        };
+        pk.fixed_header.remaining_length = pk.encoded_variable_headers_length();
+        pk
+    }
+
+    pub fn connack_flags(&self) -> ConnackFlags {
+        self.flags
+    }
+
+    pub fn reason_code(&self) -> ReasonCode {
+        self.reason_code
+    }
+
+    pub fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    /// Sets the properties, rejecting any that are not valid in this packet
+    pub fn set_properties(&mut self, properties: Properties) -> Result<(), PropertyError> {
+        properties.validate(self.fixed_header.packet_type.control_type.into())?;
+        self.properties = properties;
+        self.fixed_header.remaining_length = self.encoded_variable_headers_length();
+        Ok(())
+    }
+}
+
+impl Packet for ConnackPacket {
+    type Payload = ();
+
+    fn fixed_header(&self) -> &FixedHeader {
+        &self.fixed_header
+    }
+
+    fn payload(self) -> Self::Payload {
+        self.payload
+    }
+
+    fn payload_ref(&self) -> &Self::Payload {
+        &self.payload
+    }
+
+    fn encode_variable_headers<W: Write>(&self, writer: &mut W) -> Result<(), PacketError<Self>> {
+        self.flags.encode(writer)?;
+        self.reason_code.encode(writer)?;
+        self.properties.encode(writer)?;
+        Ok(())
+    }
+
+    fn encoded_variable_headers_length(&self) -> u32 {
+        self.flags.encoded_length() + self.reason_code.encoded_length() + self.properties.encoded_length()
+    }
+
+    fn decode_packet<R: Read>(reader: &mut R, fixed_header: FixedHeader) -> Result<Self, PacketError<Self>> {
+        ConnackPacket::decode_packet_limited(reader, fixed_header, &DecodeLimits::default())
+    }
+
+    fn decode_packet_limited<R: Read>(reader: &mut R,
+                                      fixed_header: FixedHeader,
+                                      limits: &DecodeLimits)
+                                      -> Result<Self, PacketError<Self>> {
+        let flags: ConnackFlags = Decodable::decode(reader)?;
+        let reason_code: ReasonCode = Decodable::decode(reader)?;
+        let location = fixed_header.packet_type.control_type.into();
+        let properties = Properties::decode_limited(reader, Some(location), limits)?;
+
+        Ok(ConnackPacket {
+               fixed_header,
+               flags,
+               reason_code,
+               properties,
+               payload: (),
+           })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use {Decodable, Encodable};
+    use control::variable_header::Property;
+
+    #[test]
+    pub fn test_connack_packet_basic() {
+        let mut packet = ConnackPacket::new(true, ReasonCode::NotAuthorized);
+        packet.set_properties(Properties::from(vec![Property::ReasonString("denied".to_owned())])).unwrap();
+
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+
+        let mut decode_buf = Cursor::new(buf);
+        let decoded = ConnackPacket::decode(&mut decode_buf).unwrap();
+
+        assert_eq!(packet, decoded);
+    }
+}
@@ -0,0 +1,447 @@
+//! CONNECT (MQTT 5)
+
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+use io::{self, Read, Write};
+
+use {Decodable, Encodable};
+use control::{ControlType, FixedHeader, PacketType};
+use control::variable_header::{ConnectFlags, KeepAlive, Properties, ProtocolLevel, ProtocolName,
+                               VariableHeaderError};
+use control::variable_header::properties::{PropertyError, PropertyLocation};
+use control::variable_header::protocol_level::SPEC_5_0;
+use decoder::{DecodeLimits, LimitedDecodable};
+use encodable::{StringEncodeError, StringField, VarBytes};
+use packet::{LastWill, Packet, PacketError};
+use qos::QualityOfService;
+use topic_name::{TopicName, TopicNameError};
+
+/// `CONNECT` packet
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ConnectPacket {
+    fixed_header: FixedHeader,
+    protocol_name: ProtocolName,
+
+    protocol_level: ProtocolLevel,
+    flags: ConnectFlags,
+    keep_alive: KeepAlive,
+    properties: Properties,
+
+    payload: ConnectPacketPayload,
+}
+
+impl ConnectPacket {
+    pub fn new<C>(client_identifier: C) -> ConnectPacket
+    where
+        C: Into<String>,
+    {
+        let mut pk = ConnectPacket {
+            fixed_header: FixedHeader::new(PacketType::with_default(ControlType::Connect), 0),
+            protocol_name: ProtocolName("MQTT".to_owned()),
+            protocol_level: ProtocolLevel(SPEC_5_0),
+            flags: ConnectFlags::empty(),
+            keep_alive: KeepAlive(0),
+            properties: Properties::new(),
+            payload: ConnectPacketPayload::new(client_identifier.into()),
+        };
+
+        pk.fixed_header.remaining_length = pk.calculate_remaining_length();
+
+        pk
+    }
+
+    #[inline]
+    fn calculate_remaining_length(&self) -> u32 {
+        self.encoded_variable_headers_length() + self.payload_ref().encoded_length()
+    }
+
+    pub fn set_keep_alive(&mut self, keep_alive: u16) {
+        self.keep_alive = KeepAlive(keep_alive);
+    }
+
+    /// Sets the properties, rejecting any that are not valid in this packet
+    pub fn set_properties(&mut self, properties: Properties) -> Result<(), PropertyError> {
+        properties.validate(self.fixed_header.packet_type.control_type.into())?;
+        self.properties = properties;
+        self.fixed_header.remaining_length = self.calculate_remaining_length();
+        Ok(())
+    }
+
+    pub fn set_user_name(&mut self, name: Option<String>) {
+        self.flags.user_name = name.is_some();
+        self.payload.user_name = name;
+        self.fixed_header.remaining_length = self.calculate_remaining_length();
+    }
+
+    /// Sets the will, with its QoS and retain flags
+    ///
+    /// The will properties are kept while a will is set, and removed with it.
+    pub fn set_will(&mut self, will: Option<LastWill>) {
+        self.flags.will_flag = will.is_some();
+        self.flags.will_qos = will.as_ref().map_or(QualityOfService::Level0, |will| will.qos());
+        self.flags.will_retain = will.as_ref().is_some_and(|will| will.retain());
+        self.payload.will_properties = match will {
+            Some(..) => Some(self.payload.will_properties.take().unwrap_or_default()),
+            None => None,
+        };
+        self.payload.will = will;
+        self.fixed_header.remaining_length = self.calculate_remaining_length();
+    }
+
+    /// Set properties of the will message, ignored if no will is set
+    pub fn set_will_properties(&mut self, properties: Properties) -> Result<(), PropertyError> {
+        properties.validate(PropertyLocation::Will)?;
+        if self.payload.will.is_some() {
+            self.payload.will_properties = Some(properties);
+            self.fixed_header.remaining_length = self.calculate_remaining_length();
+        }
+        Ok(())
+    }
+
+    pub fn set_password(&mut self, password: Option<Vec<u8>>) {
+        self.flags.password = password.is_some();
+        self.payload.password = password.map(VarBytes);
+        self.fixed_header.remaining_length = self.calculate_remaining_length();
+    }
+
+    pub fn set_client_identifier<I: Into<String>>(&mut self, id: I) {
+        self.payload.client_identifier = id.into();
+        self.fixed_header.remaining_length = self.calculate_remaining_length();
+    }
+
+    /// Sets the Clean Start flag, which replaces Clean Session in MQTT 5
+    pub fn set_clean_start(&mut self, clean_start: bool) {
+        self.flags.clean_session = clean_start;
+    }
+
+    pub fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    pub fn keep_alive(&self) -> u16 {
+        self.keep_alive.0
+    }
+
+    pub fn user_name(&self) -> Option<&str> {
+        self.payload.user_name.as_ref().map(|x| &x[..])
+    }
+
+    pub fn password(&self) -> Option<&[u8]> {
+        self.payload.password.as_ref().map(|x| &x.0[..])
+    }
+
+    pub fn will(&self) -> Option<&LastWill> {
+        self.payload.will.as_ref()
+    }
+
+    pub fn will_properties(&self) -> Option<&Properties> {
+        self.payload.will_properties.as_ref()
+    }
+
+    pub fn will_retain(&self) -> bool {
+        self.flags.will_retain
+    }
+
+    pub fn will_qos(&self) -> QualityOfService {
+        self.flags.will_qos
+    }
+
+    pub fn client_identifier(&self) -> &str {
+        &self.payload.client_identifier[..]
+    }
+
+    pub fn clean_start(&self) -> bool {
+        self.flags.clean_session
+    }
+}
+
+impl Packet for ConnectPacket {
+    type Payload = ConnectPacketPayload;
+
+    fn fixed_header(&self) -> &FixedHeader {
+        &self.fixed_header
+    }
+
+    fn payload(self) -> ConnectPacketPayload {
+        self.payload
+    }
+
+    fn payload_ref(&self) -> &ConnectPacketPayload {
+        &self.payload
+    }
+
+    fn encode_variable_headers<W: Write>(&self, writer: &mut W) -> Result<(), PacketError<Self>> {
+        self.protocol_name.encode(writer)?;
+        self.protocol_level.encode(writer)?;
+        self.flags.encode(writer)?;
+        self.keep_alive.encode(writer)?;
+        self.properties.encode(writer)?;
+
+        Ok(())
+    }
+
+    fn encoded_variable_headers_length(&self) -> u32 {
+        self.protocol_name.encoded_length() + self.protocol_level.encoded_length() + self.flags.encoded_length() +
+            self.keep_alive.encoded_length() + self.properties.encoded_length()
+    }
+
+    fn decode_packet<R: Read>(reader: &mut R, fixed_header: FixedHeader) -> Result<Self, PacketError<Self>> {
+        ConnectPacket::decode_packet_limited(reader, fixed_header, &DecodeLimits::default())
+    }
+
+    fn decode_packet_limited<R: Read>(reader: &mut R,
+                                      fixed_header: FixedHeader,
+                                      limits: &DecodeLimits)
+                                      -> Result<Self, PacketError<Self>> {
+        let protoname: ProtocolName = Decodable::decode(reader)?;
+        let protocol_level: ProtocolLevel = Decodable::decode(reader)?;
+        if protocol_level.0 != SPEC_5_0 {
+            return Err(PacketError::VariableHeaderError(VariableHeaderError::UnsupportedProtocolLevel(protocol_level.0)));
+        }
+        let flags: ConnectFlags = Decodable::decode(reader)?;
+        let keep_alive: KeepAlive = Decodable::decode(reader)?;
+        let location = fixed_header.packet_type.control_type.into();
+        let properties = Properties::decode_limited(reader, Some(location), limits)?;
+        let payload = ConnectPacketPayload::decode_limited(reader, Some(flags), limits)
+            .map_err(PacketError::PayloadError)?;
+
+        Ok(ConnectPacket {
+               fixed_header,
+               protocol_name: protoname,
+               protocol_level,
+               flags,
+               keep_alive,
+               properties,
+               payload,
+           })
+    }
+}
+
+/// Payloads for connect packet
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ConnectPacketPayload {
+    client_identifier: String,
+    will_properties: Option<Properties>,
+    will: Option<LastWill>,
+    user_name: Option<String>,
+    password: Option<VarBytes>,
+}
+
+impl ConnectPacketPayload {
+    pub fn new(client_identifier: String) -> ConnectPacketPayload {
+        ConnectPacketPayload {
+            client_identifier,
+            will_properties: None,
+            will: None,
+            user_name: None,
+            password: None,
+        }
+    }
+}
+
+impl Encodable for ConnectPacketPayload {
+    type Err = ConnectPacketPayloadError;
+
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), ConnectPacketPayloadError> {
+        self.client_identifier.encode(writer)?;
+
+        if let Some(ref will_properties) = self.will_properties {
+            will_properties.encode(writer)?;
+        }
+
+        if let Some(ref will) = self.will {
+            will.encode(writer)?;
+        }
+
+        if let Some(ref user_name) = self.user_name {
+            user_name.encode(writer)?;
+        }
+
+        if let Some(ref password) = self.password {
+            password.encode(writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn encoded_length(&self) -> u32 {
+        self.client_identifier.encoded_length() +
+            self.will_properties
+                .as_ref()
+                .map(|t| t.encoded_length())
+                .unwrap_or(0) +
+            self.will
+                .as_ref()
+                .map(|t| t.encoded_length())
+                .unwrap_or(0) +
+            self.user_name
+                .as_ref()
+                .map(|t| t.encoded_length())
+                .unwrap_or(0) +
+            self.password
+                .as_ref()
+                .map(|t| t.encoded_length())
+                .unwrap_or(0)
+    }
+}
+
+impl Decodable for ConnectPacketPayload {
+    type Err = ConnectPacketPayloadError;
+    type Cond = ConnectFlags;
+
+    fn decode_with<R: Read>(reader: &mut R,
+                            rest: Option<ConnectFlags>)
+                            -> Result<ConnectPacketPayload, ConnectPacketPayloadError> {
+        ConnectPacketPayload::decode_limited(reader, rest, &DecodeLimits::default())
+    }
+}
+
+impl LimitedDecodable for ConnectPacketPayload {
+    fn decode_limited<R: Read>(reader: &mut R,
+                               rest: Option<ConnectFlags>,
+                               limits: &DecodeLimits)
+                               -> Result<ConnectPacketPayload, ConnectPacketPayloadError> {
+        let flags = rest.unwrap_or_else(ConnectFlags::empty);
+
+        let ident = String::decode_with(reader, Some(limits.string_cond(StringField::ClientIdentifier)))?;
+        let (will_properties, will) = if flags.will_flag {
+            let will_properties = Properties::decode_limited(reader, Some(PropertyLocation::Will), limits)?;
+            let will_topic = String::decode_with(reader, Some(limits.string_cond(StringField::WillTopic)))?;
+            let message = VarBytes::decode(reader)?;
+            let mut will = LastWill::new(TopicName::new(will_topic)?, flags.will_qos, message.0);
+            will.set_retain(flags.will_retain);
+            (Some(will_properties), Some(will))
+        } else {
+            (None, None)
+        };
+        let uname = if flags.user_name {
+            Some(String::decode_with(reader, Some(limits.string_cond(StringField::UserName)))?)
+        } else {
+            None
+        };
+        let pwd = if flags.password {
+            Some(Decodable::decode(reader)?)
+        } else {
+            None
+        };
+
+        Ok(ConnectPacketPayload {
+               client_identifier: ident,
+               will_properties,
+               will,
+               user_name: uname,
+               password: pwd,
+           })
+    }
+}
+
+#[derive(Debug)]
+pub enum ConnectPacketPayloadError {
+    IoError(io::Error),
+    StringEncodeError(StringEncodeError),
+    TopicNameError(TopicNameError),
+    PropertyError(PropertyError),
+}
+
+impl fmt::Display for ConnectPacketPayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConnectPacketPayloadError::IoError(ref err) => err.fmt(f),
+            ConnectPacketPayloadError::StringEncodeError(ref err) => err.fmt(f),
+            ConnectPacketPayloadError::TopicNameError(ref err) => err.fmt(f),
+            ConnectPacketPayloadError::PropertyError(ref err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for ConnectPacketPayloadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ConnectPacketPayloadError::IoError(ref err) => Some(err),
+            ConnectPacketPayloadError::StringEncodeError(ref err) => Some(err),
+            ConnectPacketPayloadError::TopicNameError(ref err) => Some(err),
+            ConnectPacketPayloadError::PropertyError(ref err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for ConnectPacketPayloadError {
+    fn from(err: io::Error) -> ConnectPacketPayloadError {
+        ConnectPacketPayloadError::IoError(err)
+    }
+}
+
+impl From<StringEncodeError> for ConnectPacketPayloadError {
+    fn from(err: StringEncodeError) -> ConnectPacketPayloadError {
+        ConnectPacketPayloadError::StringEncodeError(err)
+    }
+}
+
+impl From<TopicNameError> for ConnectPacketPayloadError {
+    fn from(err: TopicNameError) -> ConnectPacketPayloadError {
+        ConnectPacketPayloadError::TopicNameError(err)
+    }
+}
+
+impl From<PropertyError> for ConnectPacketPayloadError {
+    fn from(err: PropertyError) -> ConnectPacketPayloadError {
+        ConnectPacketPayloadError::PropertyError(err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use {Decodable, Encodable};
+    use control::variable_header::Property;
+
+    #[test]
+    fn test_connect_packet_encode_basic() {
+        let packet = ConnectPacket::new("12345");
+        let expected = b"\x10\x12\x00\x04MQTT\x05\x00\x00\x00\x00\x00\x0512345";
+
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+
+        assert_eq!(&expected[..], &buf[..]);
+    }
+
+    #[test]
+    fn test_connect_packet_will_and_properties() {
+        let mut packet = ConnectPacket::new("12345");
+        packet.set_properties(Properties::from(vec![Property::SessionExpiryInterval(60)])).unwrap();
+        let mut will = LastWill::new(TopicName::new("a/b").unwrap(), QualityOfService::Level1, "bye");
+        will.set_retain(true);
+        packet.set_will(Some(will.clone()));
+        packet.set_will_properties(Properties::from(vec![Property::WillDelayInterval(5)])).unwrap();
+        packet.set_user_name(Some("user".to_owned()));
+        packet.set_password(Some(vec![0, 1, 2]));
+
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+
+        let decoded = ConnectPacket::decode(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(packet, decoded);
+        assert_eq!(decoded.will(), Some(&will));
+        assert_eq!((decoded.will_qos(), decoded.will_retain()), (QualityOfService::Level1, true));
+
+        packet.set_will(None);
+        assert_eq!((packet.will(), packet.will_properties()), (None, None));
+    }
+
+    #[test]
+    fn test_connect_packet_reject_v311() {
+        let encoded_data = b"\x10\x11\x00\x04MQTT\x04\x00\x00\x00\x00\x0512345";
+
+        match ConnectPacket::decode(&mut Cursor::new(&encoded_data[..])) {
+            Err(PacketError::VariableHeaderError(VariableHeaderError::UnsupportedProtocolLevel(4))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+}
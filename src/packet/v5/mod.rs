@@ -0,0 +1,143 @@
+//! MQTT 5 packets
+//!
+//! Implements the packets of [MQTT v5.0](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html),
+//! which add properties and reason codes to the 3.1.1 layouts, and the new `AUTH` packet.
+//! `PINGREQ` and `PINGRESP` are unchanged and shared with 3.1.1.
+//!
+//! ```rust
+//! use std::io::Cursor;
+//!
+//! use mqtt::{Decodable, Encodable, TopicName};
+//! use mqtt::control::variable_header::{Properties, Property};
+//! use mqtt::packet::QoSWithPacketIdentifier;
+//! use mqtt::packet::v5::{PublishPacket, VariablePacket};
+//!
+//! let mut packet = PublishPacket::new(TopicName::new("mqtt/learning").unwrap(),
+//!                                     QoSWithPacketIdentifier::Level1(10),
+//!                                     b"Hello MQTT 5!".to_vec());
+//! packet.set_properties(Properties::from(vec![Property::ContentType("text/plain".to_owned())])).unwrap();
+//!
+//! let mut buf = Vec::new();
+//! packet.encode(&mut buf).unwrap();
+//!
+//! let decoded = VariablePacket::decode(&mut Cursor::new(&buf[..])).unwrap();
+//! assert_eq!(VariablePacket::PublishPacket(packet), decoded);
+//! ```
+
+use alloc::vec::Vec;
+use core::convert::From;
+use core::error::Error;
+use core::fmt;
+use io::{self, Cursor, Read, Write};
+
+#[cfg(feature = "std")]
+use futures::Future;
+#[cfg(feature = "std")]
+use tokio_io::{io as async_io, AsyncRead};
+
+use {Decodable, Encodable};
+use control::ControlType;
+use control::FixedHeader;
+use control::fixed_header::FixedHeaderError;
+use decoder::{DecodeLimits, LimitError, LimitedDecodable};
+use packet::{Packet, PacketError};
+
+pub use packet::pingreq::PingreqPacket;
+pub use packet::pingresp::PingrespPacket;
+
+pub use self::auth::AuthPacket;
+pub use self::connack::ConnackPacket;
+pub use self::connect::ConnectPacket;
+pub use self::disconnect::DisconnectPacket;
+pub use self::puback::PubackPacket;
+pub use self::pubcomp::PubcompPacket;
+pub use self::publish::PublishPacket;
+pub use self::pubrec::PubrecPacket;
+pub use self::pubrel::PubrelPacket;
+pub use self::suback::SubackPacket;
+pub use self::subscribe::{RetainHandling, SubscribeOptions, SubscribePacket};
+pub use self::unsuback::UnsubackPacket;
+pub use self::unsubscribe::UnsubscribePacket;
+
+pub mod connect;
+pub mod connack;
+pub mod publish;
+pub mod puback;
+pub mod pubrec;
+pub mod pubrel;
+pub mod pubcomp;
+pub mod disconnect;
+pub mod subscribe;
+pub mod suback;
+pub mod unsuback;
+pub mod unsubscribe;
+pub mod auth;
+
+impl_variable_packet! {
+    ConnectPacket       & ConnectPacketError        => Connect,
+    ConnackPacket       & ConnackPacketError        => ConnectAcknowledgement,
+
+    PublishPacket       & PublishPacketError        => Publish,
+    PubackPacket        & PubackPacketError         => PublishAcknowledgement,
+    PubrecPacket        & PubrecPacketError         => PublishReceived,
+    PubrelPacket        & PubrelPacketError         => PublishRelease,
+    PubcompPacket       & PubcompPacketError        => PublishComplete,
+
+    PingreqPacket       & PingreqPacketError        => PingRequest,
+    PingrespPacket      & PingrespPacketError       => PingResponse,
+
+    SubscribePacket     & SubscribePacketError      => Subscribe,
+    SubackPacket        & SubackPacketError         => SubscribeAcknowledgement,
+
+    UnsubscribePacket   & UnsubscribePacketError    => Unsubscribe,
+    UnsubackPacket      & UnsubackPacketError       => UnsubscribeAcknowledgement,
+
+    DisconnectPacket    & DisconnectPacketError     => Disconnect,
+
+    AuthPacket          & AuthPacketError           => Authentication,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use {Decodable, Encodable};
+    use control::variable_header::ReasonCode;
+
+    #[test]
+    fn test_variable_packet_basic() {
+        let packets = vec![VariablePacket::new(ConnectPacket::new("1234")),
+                           VariablePacket::new(ConnackPacket::new(false, ReasonCode::Success)),
+                           VariablePacket::new(PubrelPacket::new(3)),
+                           VariablePacket::new(PingreqPacket::new()),
+                           VariablePacket::new(UnsubackPacket::new(4, vec![ReasonCode::NoSubscriptionExisted])),
+                           VariablePacket::new(DisconnectPacket::new(ReasonCode::ServerShuttingDown)),
+                           VariablePacket::new(AuthPacket::new(ReasonCode::ReAuthenticate))];
+
+        for var_packet in packets {
+            let mut buf = Vec::new();
+            var_packet.encode(&mut buf).unwrap();
+            assert_eq!(buf.len() as u32, var_packet.encoded_length());
+
+            let mut decode_buf = Cursor::new(buf);
+            let decoded_packet = VariablePacket::decode(&mut decode_buf).unwrap();
+
+            assert_eq!(var_packet, decoded_packet);
+        }
+    }
+
+    #[test]
+    fn test_variable_packet_async_parse() {
+        let var_packet = VariablePacket::new(AuthPacket::new(ReasonCode::ContinueAuthentication));
+
+        let mut buf = Vec::new();
+        var_packet.encode(&mut buf).unwrap();
+
+        match VariablePacket::parse(Cursor::new(buf)).wait() {
+            Err(err) => panic!("Failed to parse {:?}", err),
+            Ok((_, decoded_packet)) => assert_eq!(var_packet, decoded_packet),
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! PUBLISH (MQTT 5)
+
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec::Vec;
+use io::{Read, Write};
+
+use {Decodable, Encodable};
+use control::{ControlType, FixedHeader, PacketType};
+use control::variable_header::{PacketIdentifier, Properties, VariableHeaderError};
+use control::variable_header::properties::PropertyError;
+use decoder::{DecodeLimits, LimitedDecodable};
+use encodable::StringField;
+use packet::{Packet, PacketError, QoSWithPacketIdentifier};
+use topic_name::TopicName;
+
+/// `PUBLISH` packet
+///
+/// The topic name may be absent when the packet carries a Topic Alias property instead.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct PublishPacket {
+    fixed_header: FixedHeader,
+    topic_name: Option<TopicName>,
+    packet_identifier: Option<PacketIdentifier>,
+    properties: Properties,
+    payload: Vec<u8>,
+}
+
+impl PublishPacket {
+    pub fn new<P: Into<Vec<u8>>>(topic_name: TopicName, qos: QoSWithPacketIdentifier, payload: P) -> PublishPacket {
+        let mut pk = PublishPacket {
+            fixed_header: FixedHeader::new(PacketType::with_default(ControlType::Publish), 0),
+            topic_name: Some(topic_name),
+            packet_identifier: None,
+            properties: Properties::new(),
+            payload: payload.into(),
+        };
+        pk.set_qos(qos);
+        pk
+    }
+
+    #[inline]
+    fn calculate_remaining_length(&self) -> u32 {
+        self.encoded_variable_headers_length() + self.payload_ref().encoded_length()
+    }
+
+    pub fn set_dup(&mut self, dup: bool) {
+        self.fixed_header.packet_type.flags = (self.fixed_header.packet_type.flags & !0x08) | ((dup as u8) << 3);
+    }
+
+    pub fn dup(&self) -> bool {
+        self.fixed_header.packet_type.flags & 0x08 != 0
+    }
+
+    pub fn set_qos(&mut self, qos: QoSWithPacketIdentifier) {
+        let (qos, pkid) = match qos {
+            QoSWithPacketIdentifier::Level0 => (0, None),
+            QoSWithPacketIdentifier::Level1(pkid) => (1, Some(PacketIdentifier(pkid))),
+            QoSWithPacketIdentifier::Level2(pkid) => (2, Some(PacketIdentifier(pkid))),
+        };
+        self.fixed_header.packet_type.flags = (self.fixed_header.packet_type.flags & !0x06) | (qos << 1);
+        self.packet_identifier = pkid;
+        self.fixed_header.remaining_length = self.calculate_remaining_length();
+    }
+
+    pub fn qos(&self) -> QoSWithPacketIdentifier {
+        match self.packet_identifier {
+            None => QoSWithPacketIdentifier::Level0,
+            Some(pkid) => {
+                let qos_val = (self.fixed_header.packet_type.flags & 0x06) >> 1;
+                match qos_val {
+                    1 => QoSWithPacketIdentifier::Level1(pkid.0),
+                    2 => QoSWithPacketIdentifier::Level2(pkid.0),
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+
+    pub fn set_retain(&mut self, ret: bool) {
+        self.fixed_header.packet_type.flags = (self.fixed_header.packet_type.flags & !0x01) | ret as u8;
+    }
+
+    pub fn retain(&self) -> bool {
+        self.fixed_header.packet_type.flags & 0x01 != 0
+    }
+
+    /// Sets the topic name, or clears it when the topic is given by a Topic Alias property
+    pub fn set_topic_name(&mut self, topic_name: Option<TopicName>) {
+        self.topic_name = topic_name;
+        self.fixed_header.remaining_length = self.calculate_remaining_length();
+    }
+
+    pub fn topic_name(&self) -> Option<&str> {
+        self.topic_name.as_ref().map(|x| &x[..])
+    }
+
+    /// Sets the properties, rejecting any that are not valid in this packet
+    pub fn set_properties(&mut self, properties: Properties) -> Result<(), PropertyError> {
+        properties.validate(self.fixed_header.packet_type.control_type.into())?;
+        self.properties = properties;
+        self.fixed_header.remaining_length = self.calculate_remaining_length();
+        Ok(())
+    }
+
+    pub fn properties(&self) -> &Properties {
+        &self.properties
+    }
+}
+
+impl Packet for PublishPacket {
+    type Payload = Vec<u8>;
+
+    fn fixed_header(&self) -> &FixedHeader {
+        &self.fixed_header
+    }
+
+    fn payload(self) -> Self::Payload {
+        self.payload
+    }
+
+    fn payload_ref(&self) -> &Self::Payload {
+        &self.payload
+    }
+
+    fn encode_variable_headers<W: Write>(&self, writer: &mut W) -> Result<(), PacketError<Self>> {
+        match self.topic_name {
+            Some(ref topic_name) => topic_name.encode(writer)?,
+            None => "".encode(writer)?,
+        }
+
+        if let Some(pkid) = self.packet_identifier.as_ref() {
+            pkid.encode(writer)?;
+        }
+
+        self.properties.encode(writer)?;
+
+        Ok(())
+    }
+
+    fn encoded_variable_headers_length(&self) -> u32 {
+        self.topic_name
+            .as_ref()
+            .map(|x| x.encoded_length())
+            .unwrap_or(2) +
+            self.packet_identifier
+                .as_ref()
+                .map(|x| x.encoded_length())
+                .unwrap_or(0) + self.properties.encoded_length()
+    }
+
+    fn decode_packet<R: Read>(reader: &mut R, fixed_header: FixedHeader) -> Result<Self, PacketError<Self>> {
+        PublishPacket::decode_packet_limited(reader, fixed_header, &DecodeLimits::default())
+    }
+
+    fn decode_packet_limited<R: Read>(reader: &mut R,
+                                      fixed_header: FixedHeader,
+                                      limits: &DecodeLimits)
+                                      -> Result<Self, PacketError<Self>> {
+        if fixed_header.packet_type.flags & 0x06 == 0x06 {
+            return Err(VariableHeaderError::InvalidQualityOfService(3).into());
+        }
+
+        let topic_name = String::decode_with(reader, Some(limits.string_cond(StringField::TopicName)))?;
+        let topic_name = if topic_name.is_empty() {
+            None
+        } else {
+            Some(TopicName::new(topic_name)?)
+        };
+
+        let packet_identifier = if fixed_header.packet_type.flags & 0x06 != 0 {
+            Some(PacketIdentifier::decode(reader)?)
+        } else {
+            None
+        };
+
+        let location = fixed_header.packet_type.control_type.into();
+        let properties = Properties::decode_limited(reader, Some(location), limits)?;
+
+        let mut pk = PublishPacket {
+            fixed_header,
+            topic_name,
+            packet_identifier,
+            properties,
+            payload: Vec::new(),
+        };
+
+        let payload_len = fixed_header
+            .remaining_length
+            .checked_sub(pk.encoded_variable_headers_length())
+            .ok_or_else(|| PacketError::MalformedPacket("Remaining length too short".to_owned()))?;
+        pk.payload = Decodable::decode_with(reader, Some(payload_len))?;
+
+        Ok(pk)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use {Decodable, Encodable};
+    use control::variable_header::{Property, VariableHeaderError};
+    use topic_name::TopicName;
+
+    #[test]
+    fn test_publish_packet_basic() {
+        let mut packet = PublishPacket::new(TopicName::new("a/b".to_owned()).unwrap(),
+                                            QoSWithPacketIdentifier::Level2(10),
+                                            b"Hello world!".to_vec());
+        packet.set_properties(Properties::from(vec![Property::MessageExpiryInterval(30),
+                                                    Property::UserProperty("k".to_owned(), "v".to_owned())])).unwrap();
+
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+
+        let mut decode_buf = Cursor::new(buf);
+        let decoded = PublishPacket::decode(&mut decode_buf).unwrap();
+
+        assert_eq!(packet, decoded);
+    }
+
+    #[test]
+    fn test_publish_packet_topic_alias() {
+        let mut packet = PublishPacket::new(TopicName::new("a/b").unwrap(), QoSWithPacketIdentifier::Level0, "x");
+        packet.set_topic_name(None);
+        packet.set_properties(Properties::from(vec![Property::TopicAlias(3)])).unwrap();
+
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+        assert_eq!(&buf[..], b"\x30\x07\x00\x00\x03\x23\x00\x03x");
+
+        let decoded = PublishPacket::decode(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(decoded.topic_name(), None);
+        assert_eq!(packet, decoded);
+    }
+
+    #[test]
+    fn test_publish_packet_reject_connect_property() {
+        let mut packet = PublishPacket::new(TopicName::new("a/b").unwrap(), QoSWithPacketIdentifier::Level0, "x");
+        assert!(packet.set_properties(Properties::from(vec![Property::SessionExpiryInterval(60)])).is_err());
+
+        // Same packet with a Session Expiry Interval property crafted on the wire
+        let encoded = b"\x30\x0b\x00\x03a/b\x05\x11\x00\x00\x00\x3cx";
+        match PublishPacket::decode(&mut Cursor::new(&encoded[..])) {
+            Err(PacketError::VariableHeaderError(VariableHeaderError::PropertyError(PropertyError::NotAllowed(..)))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_publish_packet_invalid_qos() {
+        let encoded = b"\x36\x06\x00\x01a\x00\x01\x00";
+        match PublishPacket::decode(&mut Cursor::new(&encoded[..])) {
+            Err(PacketError::VariableHeaderError(VariableHeaderError::InvalidQualityOfService(3))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! PUBREC (MQTT 5)
+
+use io::{Read, Write};
+
+use {Decodable, Encodable};
+use control::{ControlType, FixedHeader, PacketType};
+use control::variable_header::{PacketIdentifier, Properties, ReasonCode};
+use control::variable_header::properties::PropertyError;
+use decoder::{DecodeLimits, LimitedDecodable};
+use packet::{Packet, PacketError};
+
+/// `PUBREC` packet
+///
+/// The reason code and properties are omitted on the wire when they are `Success` and empty.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct PubrecPacket {
+    fixed_header: FixedHeader,
+    packet_identifier: PacketIdentifier,
+    reason_code: ReasonCode,
+    properties: Properties,
+    payload: (),
+}
+
+impl PubrecPacket {
+    pub fn new(pkid: u16) -> PubrecPacket {
+        PubrecPacket::with_reason_code(pkid, ReasonCode::Success)
+    }
+
+    pub fn with_reason_code(pkid: u16, reason_code: ReasonCode) -> PubrecPacket {
+        let mut pk = PubrecPacket {
+            fixed_header: FixedHeader::new(PacketType::with_default(ControlType::PublishReceived), 0),
+            packet_identifier: PacketIdentifier(pkid),
+            reason_code,
+            properties: Properties::new(),
+            payload: (),
+        };
+        pk.fixed_header.remaining_length = pk.encoded_variable_headers_length();
+        pk
+    }
+
+    pub fn packet_identifier(&self) -> u16 {
+        self.packet_identifier.0
+    }
+
+    pub fn set_packet_identifier(&mut self, pkid: u16) {
+        self.packet_identifier.0 = pkid;
+    }
+
+    pub fn reason_code(&self) -> ReasonCode {
+        self.reason_code
+    }
+
+    pub fn set_reason_code(&mut self, reason_code: ReasonCode) {
+        self.reason_code = reason_code;
+        self.fixed_header.remaining_length = self.encoded_variable_headers_length();
+    }
+
+    pub fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    /// Sets the properties, rejecting any that are not valid in this packet
+    pub fn set_properties(&mut self, properties: Properties) -> Result<(), PropertyError> {
+        properties.validate(self.fixed_header.packet_type.control_type.into())?;
+        self.properties = properties;
+        self.fixed_header.remaining_length = self.encoded_variable_headers_length();
+        Ok(())
+    }
+}
+
+impl Packet for PubrecPacket {
+    type Payload = ();
+
+    fn fixed_header(&self) -> &FixedHeader {
+        &self.fixed_header
+    }
+
+    fn payload(self) -> Self::Payload {
+        self.payload
+    }
+
+    fn payload_ref(&self) -> &Self::Payload {
+        &self.payload
+    }
+
+    fn encode_variable_headers<W: Write>(&self, writer: &mut W) -> Result<(), PacketError<Self>> {
+        self.packet_identifier.encode(writer)?;
+
+        if !self.properties.is_empty() {
+            self.reason_code.encode(writer)?;
+            self.properties.encode(writer)?;
+        } else if self.reason_code != ReasonCode::Success {
+            self.reason_code.encode(writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn encoded_variable_headers_length(&self) -> u32 {
+        let len = self.packet_identifier.encoded_length();
+        if !self.properties.is_empty() {
+            len + self.reason_code.encoded_length() + self.properties.encoded_length()
+        } else if self.reason_code != ReasonCode::Success {
+            len + self.reason_code.encoded_length()
+        } else {
+            len
+        }
+    }
+
+    fn decode_packet<R: Read>(reader: &mut R, fixed_header: FixedHeader) -> Result<Self, PacketError<Self>> {
+        PubrecPacket::decode_packet_limited(reader, fixed_header, &DecodeLimits::default())
+    }
+
+    fn decode_packet_limited<R: Read>(reader: &mut R,
+                                      fixed_header: FixedHeader,
+                                      limits: &DecodeLimits)
+                                      -> Result<Self, PacketError<Self>> {
+        let packet_identifier: PacketIdentifier = PacketIdentifier::decode(reader)?;
+        let reason_code = if fixed_header.remaining_length > 2 {
+            ReasonCode::decode(reader)?
+        } else {
+            ReasonCode::Success
+        };
+        let properties = if fixed_header.remaining_length > 3 {
+            Properties::decode_limited(reader, Some(fixed_header.packet_type.control_type.into()), limits)?
+        } else {
+            Properties::new()
+        };
+
+        Ok(PubrecPacket {
+               fixed_header,
+               packet_identifier,
+               reason_code,
+               properties,
+               payload: (),
+           })
+    }
+}
@@ -0,0 +1,186 @@
+//! SUBACK (MQTT 5)
+
+use alloc::borrow::ToOwned;
+use alloc::vec::Vec;
+use io::{Read, Write};
+
+use {Decodable, Encodable};
+use control::{ControlType, FixedHeader, PacketType};
+use control::variable_header::{PacketIdentifier, Properties, ReasonCode, VariableHeaderError};
+use control::variable_header::properties::PropertyError;
+use decoder::{DecodeLimits, LimitedDecodable};
+use packet::{Packet, PacketError};
+
+/// `SUBACK` packet
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct SubackPacket {
+    fixed_header: FixedHeader,
+    packet_identifier: PacketIdentifier,
+    properties: Properties,
+    payload: SubackPacketPayload,
+}
+
+impl SubackPacket {
+    pub fn new(pkid: u16, reason_codes: Vec<ReasonCode>) -> SubackPacket {
+        let mut pk = SubackPacket {
+            fixed_header: FixedHeader::new(PacketType::with_default(ControlType::SubscribeAcknowledgement), 0),
+            packet_identifier: PacketIdentifier(pkid),
+            properties: Properties::new(),
+            payload: SubackPacketPayload::new(reason_codes),
+        };
+        pk.fixed_header.remaining_length = pk.encoded_variable_headers_length() + pk.payload.encoded_length();
+        pk
+    }
+
+    pub fn packet_identifier(&self) -> u16 {
+        self.packet_identifier.0
+    }
+
+    pub fn set_packet_identifier(&mut self, pkid: u16) {
+        self.packet_identifier.0 = pkid;
+    }
+
+    pub fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    /// Sets the properties, rejecting any that are not valid in this packet
+    pub fn set_properties(&mut self, properties: Properties) -> Result<(), PropertyError> {
+        properties.validate(self.fixed_header.packet_type.control_type.into())?;
+        self.properties = properties;
+        self.fixed_header.remaining_length = self.encoded_variable_headers_length() + self.payload.encoded_length();
+        Ok(())
+    }
+}
+
+impl Packet for SubackPacket {
+    type Payload = SubackPacketPayload;
+
+    fn fixed_header(&self) -> &FixedHeader {
+        &self.fixed_header
+    }
+
+    fn payload(self) -> Self::Payload {
+        self.payload
+    }
+
+    fn payload_ref(&self) -> &Self::Payload {
+        &self.payload
+    }
+
+    fn encode_variable_headers<W: Write>(&self, writer: &mut W) -> Result<(), PacketError<Self>> {
+        self.packet_identifier.encode(writer)?;
+        self.properties.encode(writer)?;
+
+        Ok(())
+    }
+
+    fn encoded_variable_headers_length(&self) -> u32 {
+        self.packet_identifier.encoded_length() + self.properties.encoded_length()
+    }
+
+    fn decode_packet<R: Read>(reader: &mut R, fixed_header: FixedHeader) -> Result<Self, PacketError<Self>> {
+        SubackPacket::decode_packet_limited(reader, fixed_header, &DecodeLimits::default())
+    }
+
+    fn decode_packet_limited<R: Read>(reader: &mut R,
+                                      fixed_header: FixedHeader,
+                                      limits: &DecodeLimits)
+                                      -> Result<Self, PacketError<Self>> {
+        let packet_identifier: PacketIdentifier = PacketIdentifier::decode(reader)?;
+        let location = fixed_header.packet_type.control_type.into();
+        let properties = Properties::decode_limited(reader, Some(location), limits)?;
+        let payload_len = fixed_header
+            .remaining_length
+            .checked_sub(packet_identifier.encoded_length() + properties.encoded_length())
+            .ok_or_else(|| PacketError::MalformedPacket("Remaining length too short".to_owned()))?;
+        let payload: SubackPacketPayload = SubackPacketPayload::decode_with(reader, Some(payload_len))
+            .map_err(PacketError::PayloadError)?;
+        Ok(SubackPacket {
+               fixed_header,
+               packet_identifier,
+               properties,
+               payload,
+           })
+    }
+}
+
+/// Payload of suback packet, one reason code per topic filter
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct SubackPacketPayload {
+    reason_codes: Vec<ReasonCode>,
+}
+
+impl SubackPacketPayload {
+    pub fn new(reason_codes: Vec<ReasonCode>) -> SubackPacketPayload {
+        SubackPacketPayload { reason_codes }
+    }
+
+    pub fn reason_codes(&self) -> &[ReasonCode] {
+        &self.reason_codes[..]
+    }
+}
+
+impl Encodable for SubackPacketPayload {
+    type Err = VariableHeaderError;
+
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), Self::Err> {
+        for code in &self.reason_codes {
+            code.encode(writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn encoded_length(&self) -> u32 {
+        self.reason_codes.len() as u32
+    }
+}
+
+impl Decodable for SubackPacketPayload {
+    type Err = VariableHeaderError;
+    type Cond = u32;
+
+    fn decode_with<R: Read>(reader: &mut R,
+                            payload_len: Option<u32>)
+                            -> Result<SubackPacketPayload, VariableHeaderError> {
+        let payload_len = payload_len.expect("Must provide payload length");
+        let mut reason_codes = Vec::new();
+
+        for _ in 0..payload_len {
+            reason_codes.push(ReasonCode::decode(reader)?);
+        }
+
+        Ok(SubackPacketPayload::new(reason_codes))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use {Decodable, Encodable};
+
+    #[test]
+    fn test_suback_packet_basic() {
+        let packet = SubackPacket::new(10, vec![ReasonCode::GrantedQoS1, ReasonCode::TopicFilterInvalid]);
+
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+        assert_eq!(&buf[..], b"\x90\x05\x00\x0a\x00\x01\x8f");
+
+        let decoded = SubackPacket::decode(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(packet, decoded);
+    }
+
+    #[test]
+    fn test_suback_packet_invalid_reason_code() {
+        let encoded = b"\x90\x04\x00\x0a\x00\x03";
+        match SubackPacket::decode(&mut Cursor::new(&encoded[..])) {
+            Err(PacketError::PayloadError(VariableHeaderError::InvalidReasonCode(3))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,335 @@
+//! SUBSCRIBE (MQTT 5)
+
+use alloc::borrow::ToOwned;
+use alloc::vec::Vec;
+use core::convert::From;
+use core::error::Error;
+use core::fmt;
+use io::{self, Read, ReadBytesExt, Write, WriteBytesExt};
+
+use {Decodable, Encodable, QualityOfService};
+use control::{ControlType, FixedHeader, PacketType};
+use control::variable_header::{PacketIdentifier, Properties};
+use control::variable_header::properties::PropertyError;
+use decoder::{DecodeLimits, LimitedDecodable};
+use encodable::StringEncodeError;
+use packet::{Packet, PacketError};
+use topic_filter::{TopicFilter, TopicFilterError};
+
+/// When the server sends retained messages for a new subscription
+#[repr(u8)]
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum RetainHandling {
+    /// Send retained messages at the time of the subscribe
+    SendAtSubscribe = 0,
+    /// Send retained messages only if the subscription does not currently exist
+    SendAtNewSubscribe = 1,
+    /// Do not send retained messages
+    DoNotSend = 2,
+}
+
+/// Subscription options of a topic filter
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct SubscribeOptions {
+    pub qos: QualityOfService,
+    pub no_local: bool,
+    pub retain_as_published: bool,
+    pub retain_handling: RetainHandling,
+}
+
+impl SubscribeOptions {
+    pub fn new(qos: QualityOfService) -> SubscribeOptions {
+        SubscribeOptions {
+            qos,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: RetainHandling::SendAtSubscribe,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        (self.retain_handling as u8) << 4 | (self.retain_as_published as u8) << 3 | (self.no_local as u8) << 2 |
+            self.qos as u8
+    }
+
+    fn from_u8(code: u8) -> Result<SubscribeOptions, SubscribePacketPayloadError> {
+        if code & 0b1100_0000 != 0 {
+            return Err(SubscribePacketPayloadError::InvalidSubscribeOptions(code));
+        }
+
+        let qos = match code & 0b0000_0011 {
+            0 => QualityOfService::Level0,
+            1 => QualityOfService::Level1,
+            2 => QualityOfService::Level2,
+            _ => return Err(SubscribePacketPayloadError::InvalidQualityOfService),
+        };
+        let retain_handling = match (code & 0b0011_0000) >> 4 {
+            0 => RetainHandling::SendAtSubscribe,
+            1 => RetainHandling::SendAtNewSubscribe,
+            2 => RetainHandling::DoNotSend,
+            _ => return Err(SubscribePacketPayloadError::InvalidSubscribeOptions(code)),
+        };
+
+        Ok(SubscribeOptions {
+               qos,
+               no_local: code & 0b0000_0100 != 0,
+               retain_as_published: code & 0b0000_1000 != 0,
+               retain_handling,
+           })
+    }
+}
+
+impl From<QualityOfService> for SubscribeOptions {
+    fn from(qos: QualityOfService) -> SubscribeOptions {
+        SubscribeOptions::new(qos)
+    }
+}
+
+/// `SUBSCRIBE` packet
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct SubscribePacket {
+    fixed_header: FixedHeader,
+    packet_identifier: PacketIdentifier,
+    properties: Properties,
+    payload: SubscribePacketPayload,
+}
+
+impl SubscribePacket {
+    pub fn new(pkid: u16, subscribes: Vec<(TopicFilter, SubscribeOptions)>) -> SubscribePacket {
+        let mut pk = SubscribePacket {
+            fixed_header: FixedHeader::new(PacketType::with_default(ControlType::Subscribe), 0),
+            packet_identifier: PacketIdentifier(pkid),
+            properties: Properties::new(),
+            payload: SubscribePacketPayload::new(subscribes),
+        };
+        pk.fixed_header.remaining_length = pk.encoded_variable_headers_length() + pk.payload.encoded_length();
+        pk
+    }
+
+    pub fn packet_identifier(&self) -> u16 {
+        self.packet_identifier.0
+    }
+
+    pub fn set_packet_identifier(&mut self, pkid: u16) {
+        self.packet_identifier.0 = pkid;
+    }
+
+    pub fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    /// Sets the properties, rejecting any that are not valid in this packet
+    pub fn set_properties(&mut self, properties: Properties) -> Result<(), PropertyError> {
+        properties.validate(self.fixed_header.packet_type.control_type.into())?;
+        self.properties = properties;
+        self.fixed_header.remaining_length = self.encoded_variable_headers_length() + self.payload.encoded_length();
+        Ok(())
+    }
+}
+
+impl Packet for SubscribePacket {
+    type Payload = SubscribePacketPayload;
+
+    fn fixed_header(&self) -> &FixedHeader {
+        &self.fixed_header
+    }
+
+    fn payload(self) -> Self::Payload {
+        self.payload
+    }
+
+    fn payload_ref(&self) -> &Self::Payload {
+        &self.payload
+    }
+
+    fn encode_variable_headers<W: Write>(&self, writer: &mut W) -> Result<(), PacketError<Self>> {
+        self.packet_identifier.encode(writer)?;
+        self.properties.encode(writer)?;
+
+        Ok(())
+    }
+
+    fn encoded_variable_headers_length(&self) -> u32 {
+        self.packet_identifier.encoded_length() + self.properties.encoded_length()
+    }
+
+    fn decode_packet<R: Read>(reader: &mut R, fixed_header: FixedHeader) -> Result<Self, PacketError<Self>> {
+        SubscribePacket::decode_packet_limited(reader, fixed_header, &DecodeLimits::default())
+    }
+
+    fn decode_packet_limited<R: Read>(reader: &mut R,
+                                      fixed_header: FixedHeader,
+                                      limits: &DecodeLimits)
+                                      -> Result<Self, PacketError<Self>> {
+        let packet_identifier: PacketIdentifier = PacketIdentifier::decode(reader)?;
+        let location = fixed_header.packet_type.control_type.into();
+        let properties = Properties::decode_limited(reader, Some(location), limits)?;
+        let payload_len = fixed_header
+            .remaining_length
+            .checked_sub(packet_identifier.encoded_length() + properties.encoded_length())
+            .ok_or_else(|| PacketError::MalformedPacket("Remaining length too short".to_owned()))?;
+        let payload = SubscribePacketPayload::decode_limited(reader, Some(payload_len), limits)
+            .map_err(PacketError::PayloadError)?;
+        Ok(SubscribePacket {
+               fixed_header,
+               packet_identifier,
+               properties,
+               payload,
+           })
+    }
+}
+
+/// Payload of subscribe packet
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct SubscribePacketPayload {
+    subscribes: Vec<(TopicFilter, SubscribeOptions)>,
+}
+
+impl SubscribePacketPayload {
+    pub fn new(subs: Vec<(TopicFilter, SubscribeOptions)>) -> SubscribePacketPayload {
+        SubscribePacketPayload { subscribes: subs }
+    }
+
+    pub fn subscribes(&self) -> &[(TopicFilter, SubscribeOptions)] {
+        &self.subscribes[..]
+    }
+}
+
+impl Encodable for SubscribePacketPayload {
+    type Err = SubscribePacketPayloadError;
+
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), Self::Err> {
+        for &(ref filter, options) in &self.subscribes {
+            filter.encode(writer)?;
+            writer.write_u8(options.to_u8())?;
+        }
+
+        Ok(())
+    }
+
+    fn encoded_length(&self) -> u32 {
+        self.subscribes
+            .iter()
+            .fold(0, |b, a| b + a.0.encoded_length() + 1)
+    }
+}
+
+impl Decodable for SubscribePacketPayload {
+    type Err = SubscribePacketPayloadError;
+    type Cond = u32;
+
+    fn decode_with<R: Read>(reader: &mut R,
+                            payload_len: Option<u32>)
+                            -> Result<SubscribePacketPayload, SubscribePacketPayloadError> {
+        SubscribePacketPayload::decode_limited(reader, payload_len, &DecodeLimits::default())
+    }
+}
+
+impl LimitedDecodable for SubscribePacketPayload {
+    fn decode_limited<R: Read>(reader: &mut R,
+                               payload_len: Option<u32>,
+                               limits: &DecodeLimits)
+                               -> Result<SubscribePacketPayload, SubscribePacketPayloadError> {
+        let mut payload_len = payload_len.expect("Must provide payload length");
+        let mut subs = Vec::new();
+
+        while payload_len > 0 {
+            let filter = TopicFilter::decode_limited(reader, None, limits)?;
+            let options = SubscribeOptions::from_u8(reader.read_u8()?)?;
+
+            payload_len = payload_len
+                .checked_sub(filter.encoded_length() + 1)
+                .ok_or(SubscribePacketPayloadError::LengthMismatch)?;
+            subs.push((filter, options));
+        }
+
+        Ok(SubscribePacketPayload::new(subs))
+    }
+}
+
+#[derive(Debug)]
+pub enum SubscribePacketPayloadError {
+    IoError(io::Error),
+    StringEncodeError(StringEncodeError),
+    InvalidQualityOfService,
+    InvalidSubscribeOptions(u8),
+    TopicFilterError(TopicFilterError),
+    LengthMismatch,
+}
+
+impl fmt::Display for SubscribePacketPayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SubscribePacketPayloadError::IoError(ref err) => err.fmt(f),
+            SubscribePacketPayloadError::StringEncodeError(ref err) => err.fmt(f),
+            SubscribePacketPayloadError::InvalidQualityOfService => write!(f, "Invalid quality of service"),
+            SubscribePacketPayloadError::InvalidSubscribeOptions(code) => {
+                write!(f, "Invalid subscribe options ({:#X})", code)
+            }
+            SubscribePacketPayloadError::TopicFilterError(ref err) => err.fmt(f),
+            SubscribePacketPayloadError::LengthMismatch => write!(f, "Payload does not match the remaining length"),
+        }
+    }
+}
+
+impl Error for SubscribePacketPayloadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            SubscribePacketPayloadError::IoError(ref err) => Some(err),
+            SubscribePacketPayloadError::StringEncodeError(ref err) => Some(err),
+            SubscribePacketPayloadError::InvalidQualityOfService => None,
+            SubscribePacketPayloadError::InvalidSubscribeOptions(..) => None,
+            SubscribePacketPayloadError::TopicFilterError(ref err) => Some(err),
+            SubscribePacketPayloadError::LengthMismatch => None,
+        }
+    }
+}
+
+impl From<TopicFilterError> for SubscribePacketPayloadError {
+    fn from(err: TopicFilterError) -> SubscribePacketPayloadError {
+        SubscribePacketPayloadError::TopicFilterError(err)
+    }
+}
+
+impl From<StringEncodeError> for SubscribePacketPayloadError {
+    fn from(err: StringEncodeError) -> SubscribePacketPayloadError {
+        SubscribePacketPayloadError::StringEncodeError(err)
+    }
+}
+
+impl From<io::Error> for SubscribePacketPayloadError {
+    fn from(err: io::Error) -> SubscribePacketPayloadError {
+        SubscribePacketPayloadError::IoError(err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use {Decodable, Encodable};
+
+    #[test]
+    fn test_subscribe_packet_options() {
+        let mut opts = SubscribeOptions::new(QualityOfService::Level1);
+        opts.no_local = true;
+        opts.retain_handling = RetainHandling::DoNotSend;
+
+        let packet = SubscribePacket::new(10, vec![(TopicFilter::new("a/+").unwrap(), opts)]);
+
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+        assert_eq!(&buf[..], b"\x82\x09\x00\x0a\x00\x00\x03a/+\x25");
+
+        let decoded = SubscribePacket::decode(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(packet, decoded);
+    }
+
+    #[test]
+    fn test_subscribe_packet_reserved_options() {
+        let encoded = b"\x82\x09\x00\x0a\x00\x00\x03a/+\x40";
+        assert!(SubscribePacket::decode(&mut Cursor::new(&encoded[..])).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+//! UNSUBSCRIBE (MQTT 5)
+
+use alloc::borrow::ToOwned;
+use alloc::vec::Vec;
+use io::{Read, Write};
+
+use {Decodable, Encodable};
+use control::{ControlType, FixedHeader, PacketType};
+use control::variable_header::{PacketIdentifier, Properties};
+use control::variable_header::properties::PropertyError;
+use decoder::{DecodeLimits, LimitedDecodable};
+use packet::{Packet, PacketError};
+use packet::unsubscribe::UnsubscribePacketPayload;
+use topic_filter::TopicFilter;
+
+/// `UNSUBSCRIBE` packet
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct UnsubscribePacket {
+    fixed_header: FixedHeader,
+    packet_identifier: PacketIdentifier,
+    properties: Properties,
+    payload: UnsubscribePacketPayload,
+}
+
+impl UnsubscribePacket {
+    pub fn new(pkid: u16, subscribes: Vec<TopicFilter>) -> UnsubscribePacket {
+        let mut pk = UnsubscribePacket {
+            fixed_header: FixedHeader::new(PacketType::with_default(ControlType::Unsubscribe), 0),
+            packet_identifier: PacketIdentifier(pkid),
+            properties: Properties::new(),
+            payload: UnsubscribePacketPayload::new(subscribes),
+        };
+        pk.fixed_header.remaining_length = pk.encoded_variable_headers_length() + pk.payload.encoded_length();
+        pk
+    }
+
+    pub fn packet_identifier(&self) -> u16 {
+        self.packet_identifier.0
+    }
+
+    pub fn set_packet_identifier(&mut self, pkid: u16) {
+        self.packet_identifier.0 = pkid;
+    }
+
+    pub fn properties(&self) -> &Properties {
+        &self.properties
+    }
+
+    /// Sets the properties, rejecting any that are not valid in this packet
+    pub fn set_properties(&mut self, properties: Properties) -> Result<(), PropertyError> {
+        properties.validate(self.fixed_header.packet_type.control_type.into())?;
+        self.properties = properties;
+        self.fixed_header.remaining_length = self.encoded_variable_headers_length() + self.payload.encoded_length();
+        Ok(())
+    }
+}
+
+impl Packet for UnsubscribePacket {
+    type Payload = UnsubscribePacketPayload;
+
+    fn fixed_header(&self) -> &FixedHeader {
+        &self.fixed_header
+    }
+
+    fn payload(self) -> Self::Payload {
+        self.payload
+    }
+
+    fn payload_ref(&self) -> &Self::Payload {
+        &self.payload
+    }
+
+    fn encode_variable_headers<W: Write>(&self, writer: &mut W) -> Result<(), PacketError<Self>> {
+        self.packet_identifier.encode(writer)?;
+        self.properties.encode(writer)?;
+
+        Ok(())
+    }
+
+    fn encoded_variable_headers_length(&self) -> u32 {
+        self.packet_identifier.encoded_length() + self.properties.encoded_length()
+    }
+
+    fn decode_packet<R: Read>(reader: &mut R, fixed_header: FixedHeader) -> Result<Self, PacketError<Self>> {
+        UnsubscribePacket::decode_packet_limited(reader, fixed_header, &DecodeLimits::default())
+    }
+
+    fn decode_packet_limited<R: Read>(reader: &mut R,
+                                      fixed_header: FixedHeader,
+                                      limits: &DecodeLimits)
+                                      -> Result<Self, PacketError<Self>> {
+        let packet_identifier: PacketIdentifier = PacketIdentifier::decode(reader)?;
+        let location = fixed_header.packet_type.control_type.into();
+        let properties = Properties::decode_limited(reader, Some(location), limits)?;
+        let payload_len = fixed_header
+            .remaining_length
+            .checked_sub(packet_identifier.encoded_length() + properties.encoded_length())
+            .ok_or_else(|| PacketError::MalformedPacket("Remaining length too short".to_owned()))?;
+        let payload = UnsubscribePacketPayload::decode_limited(reader, Some(payload_len), limits)
+            .map_err(PacketError::PayloadError)?;
+        Ok(UnsubscribePacket {
+               fixed_header,
+               packet_identifier,
+               properties,
+               payload,
+           })
+    }
+}
@@ -0,0 +1,296 @@
+//! Conformance checks for MQTT 3.1.1 packets
+//!
+//! Decoding only rejects what cannot be represented. `VariablePacket::validate` additionally checks
+//! the normative statements of the specification that a well-formed packet may still violate, so a
+//! server can close the connection of a misbehaving client as the specification requires.
+//!
+//! ```rust
+//! use mqtt::packet::{ConnectPacket, ProtocolViolation, VariablePacket};
+//!
+//! let mut packet = ConnectPacket::new("MQTT", "client");
+//! packet.set_password(Some(b"secret".to_vec()));
+//!
+//! let violation = VariablePacket::new(packet).validate().unwrap_err();
+//! assert_eq!(violation, ProtocolViolation::PasswordWithoutUserName);
+//! assert_eq!(violation.normative_id(), "MQTT-3.1.2-22");
+//! ```
+
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+
+use control::variable_header::{ConnectReturnCode, ProtocolVersion};
+use packet::{ConnackPacket, ConnectPacket, Packet, PublishPacket, SubscribePacket, UnsubscribePacket,
+             VariablePacket};
+use qos::QualityOfService;
+
+/// A normative statement of the MQTT 3.1.1 specification violated by a packet
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ProtocolViolation {
+    /// `SUBSCRIBE`, `UNSUBSCRIBE` or a QoS 1 or 2 `PUBLISH` with packet identifier 0
+    ZeroPacketIdentifier,
+    /// A string contains the null character U+0000
+    NullCharacter,
+    /// `CONNECT` protocol name is neither "MQTT" nor the "MQIsdp" of MQTT 3.1
+    ProtocolName,
+    /// `CONNECT` protocol level does not match the protocol name
+    ProtocolLevel,
+    /// `CONNECT` has a will QoS but no will
+    WillQosWithoutWill,
+    /// `CONNECT` has will retain set but no will
+    WillRetainWithoutWill,
+    /// `CONNECT` has a password but no user name
+    PasswordWithoutUserName,
+    /// `CONNECT` has an empty client identifier without clean session
+    EmptyClientIdentifier,
+    /// `CONNACK` has session present set with a non-zero return code
+    SessionPresentWithError,
+    /// `PUBLISH` has the DUP flag set with QoS 0
+    DupWithQos0,
+    /// A topic name or topic filter contains the null character U+0000
+    NullInTopic,
+    /// `SUBSCRIBE` has no topic filters
+    EmptySubscribe,
+    /// `UNSUBSCRIBE` has no topic filters
+    EmptyUnsubscribe,
+}
+
+impl ProtocolViolation {
+    /// Identifier of the violated normative statement, such as "MQTT-3.1.2-11"
+    pub fn normative_id(&self) -> &'static str {
+        match *self {
+            ProtocolViolation::ZeroPacketIdentifier => "MQTT-2.3.1-1",
+            ProtocolViolation::NullCharacter => "MQTT-1.5.3-2",
+            ProtocolViolation::ProtocolName => "MQTT-3.1.2-1",
+            ProtocolViolation::ProtocolLevel => "MQTT-3.1.2-2",
+            ProtocolViolation::WillQosWithoutWill => "MQTT-3.1.2-13",
+            ProtocolViolation::WillRetainWithoutWill => "MQTT-3.1.2-15",
+            ProtocolViolation::PasswordWithoutUserName => "MQTT-3.1.2-22",
+            ProtocolViolation::EmptyClientIdentifier => "MQTT-3.1.3-8",
+            ProtocolViolation::SessionPresentWithError => "MQTT-3.2.2-4",
+            ProtocolViolation::DupWithQos0 => "MQTT-3.3.1-2",
+            ProtocolViolation::NullInTopic => "MQTT-4.7.3-2",
+            ProtocolViolation::EmptySubscribe => "MQTT-3.8.3-3",
+            ProtocolViolation::EmptyUnsubscribe => "MQTT-3.10.3-2",
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match *self {
+            ProtocolViolation::ZeroPacketIdentifier => "Packet identifier must be non-zero",
+            ProtocolViolation::NullCharacter => "String must not contain U+0000",
+            ProtocolViolation::ProtocolName => "Unsupported protocol name",
+            ProtocolViolation::ProtocolLevel => "Unsupported protocol level",
+            ProtocolViolation::WillQosWithoutWill => "Will QoS must be 0 if the will flag is 0",
+            ProtocolViolation::WillRetainWithoutWill => "Will retain must be 0 if the will flag is 0",
+            ProtocolViolation::PasswordWithoutUserName => "Password flag must be 0 if the user name flag is 0",
+            ProtocolViolation::EmptyClientIdentifier => "Empty client identifier requires clean session",
+            ProtocolViolation::SessionPresentWithError => "Session present must be 0 with a non-zero return code",
+            ProtocolViolation::DupWithQos0 => "DUP flag must be 0 for QoS 0 messages",
+            ProtocolViolation::NullInTopic => "Topic must not contain U+0000",
+            ProtocolViolation::EmptySubscribe => "SUBSCRIBE must contain at least one topic filter",
+            ProtocolViolation::EmptyUnsubscribe => "UNSUBSCRIBE must contain at least one topic filter",
+        }
+    }
+}
+
+impl fmt::Display for ProtocolViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} [{}]", self.message(), self.normative_id())
+    }
+}
+
+impl Error for ProtocolViolation {}
+
+impl VariablePacket {
+    /// Checks the packet against the normative statements of the specification
+    ///
+    /// Returns the first violation found; see `violations` for all of them.
+    pub fn validate(&self) -> Result<(), ProtocolViolation> {
+        match self.violations().into_iter().next() {
+            Some(violation) => Err(violation),
+            None => Ok(()),
+        }
+    }
+
+    /// All violations of the normative statements of the specification
+    pub fn violations(&self) -> Vec<ProtocolViolation> {
+        match *self {
+            VariablePacket::ConnectPacket(ref pk) => pk.violations(),
+            VariablePacket::ConnackPacket(ref pk) => pk.violations(),
+            VariablePacket::PublishPacket(ref pk) => pk.violations(),
+            VariablePacket::SubscribePacket(ref pk) => pk.violations(),
+            VariablePacket::UnsubscribePacket(ref pk) => pk.violations(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl ConnectPacket {
+    /// All violations of the normative statements of the specification
+    pub fn violations(&self) -> Vec<ProtocolViolation> {
+        collect(|check| {
+            if self.protocol_version().is_none() {
+                let known_name = [ProtocolVersion::V31, ProtocolVersion::V311]
+                    .iter()
+                    .any(|v| v.protocol_name() == self.protocol_name());
+                check(!known_name, ProtocolViolation::ProtocolName);
+                check(known_name, ProtocolViolation::ProtocolLevel);
+            }
+
+            let has_will = self.will().is_some();
+            check(!has_will && self.will_qos() != QualityOfService::Level0,
+                  ProtocolViolation::WillQosWithoutWill);
+            check(!has_will && self.will_retain(), ProtocolViolation::WillRetainWithoutWill);
+            check(self.password().is_some() && self.user_name().is_none(),
+                  ProtocolViolation::PasswordWithoutUserName);
+            check(self.client_identifier().is_empty() && !self.clean_session(),
+                  ProtocolViolation::EmptyClientIdentifier);
+
+            check(self.will().is_some_and(|will| will.topic_name().contains('\0')),
+                  ProtocolViolation::NullInTopic);
+            let strings = [Some(self.client_identifier()), self.user_name()];
+            check(strings.iter().any(|s| s.is_some_and(|s| s.contains('\0'))),
+                  ProtocolViolation::NullCharacter);
+        })
+    }
+}
+
+impl ConnackPacket {
+    /// All violations of the normative statements of the specification
+    pub fn violations(&self) -> Vec<ProtocolViolation> {
+        collect(|check| {
+            check(self.connack_flags().session_present &&
+                      self.connect_return_code() != ConnectReturnCode::ConnectionAccepted,
+                  ProtocolViolation::SessionPresentWithError);
+        })
+    }
+}
+
+impl PublishPacket {
+    /// All violations of the normative statements of the specification
+    pub fn violations(&self) -> Vec<ProtocolViolation> {
+        collect(|check| {
+            let qos = (self.fixed_header().packet_type.flags & 0x06) >> 1;
+            check(qos == 0 && self.dup(), ProtocolViolation::DupWithQos0);
+            check(qos != 0 && self.packet_identifier() == Some(0),
+                  ProtocolViolation::ZeroPacketIdentifier);
+            check(self.topic_name().contains('\0'), ProtocolViolation::NullInTopic);
+        })
+    }
+}
+
+impl SubscribePacket {
+    /// All violations of the normative statements of the specification
+    pub fn violations(&self) -> Vec<ProtocolViolation> {
+        collect(|check| {
+            let subscribes = self.payload_ref().subscribes();
+            check(self.packet_identifier() == 0, ProtocolViolation::ZeroPacketIdentifier);
+            check(subscribes.is_empty(), ProtocolViolation::EmptySubscribe);
+            check(subscribes.iter().any(|(filter, _)| filter.contains('\0')),
+                  ProtocolViolation::NullInTopic);
+        })
+    }
+}
+
+impl UnsubscribePacket {
+    /// All violations of the normative statements of the specification
+    pub fn violations(&self) -> Vec<ProtocolViolation> {
+        collect(|check| {
+            let subscribes = self.payload_ref().subscribes();
+            check(self.packet_identifier() == 0, ProtocolViolation::ZeroPacketIdentifier);
+            check(subscribes.is_empty(), ProtocolViolation::EmptyUnsubscribe);
+            check(subscribes.iter().any(|filter| filter.contains('\0')),
+                  ProtocolViolation::NullInTopic);
+        })
+    }
+}
+
+/// Runs `checks`, keeping each violation found once
+fn collect<F>(checks: F) -> Vec<ProtocolViolation>
+where
+    F: FnOnce(&mut dyn FnMut(bool, ProtocolViolation)),
+{
+    let mut violations = Vec::new();
+    checks(&mut |violated, violation| if violated && !violations.contains(&violation) {
+                    violations.push(violation);
+                });
+    violations
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use Decodable;
+    use packet::{ConnackPacket, ConnectPacket, SubscribePacket};
+
+    fn decode_violations(encoded: &[u8]) -> Vec<ProtocolViolation> {
+        VariablePacket::decode(&mut Cursor::new(encoded)).unwrap().violations()
+    }
+
+    #[test]
+    fn test_validate_connect() {
+        let packet = VariablePacket::new(ConnectPacket::new("MQTT", "client"));
+        assert!(packet.validate().is_ok());
+
+        // Will QoS 1 and will retain without the will flag, password without user name
+        let encoded = b"\x10\x12\x00\x04MQTT\x04\x68\x00\x3c\x00\x01c\x00\x03pwd";
+        assert_eq!(decode_violations(encoded),
+                   vec![ProtocolViolation::WillQosWithoutWill,
+                        ProtocolViolation::WillRetainWithoutWill,
+                        ProtocolViolation::PasswordWithoutUserName]);
+
+        let mut packet = ConnectPacket::new("MQTT", "client");
+        packet.set_user_name(Some("user".to_owned()));
+        packet.set_password(Some(b"p\0ss".to_vec()));
+        assert_eq!(packet.violations(), vec![]);
+
+        // Empty client identifier without clean session
+        let encoded = b"\x10\x0c\x00\x04MQTT\x04\x00\x00\x3c\x00\x00";
+        assert_eq!(decode_violations(encoded), vec![ProtocolViolation::EmptyClientIdentifier]);
+
+        let packet = VariablePacket::new(ConnectPacket::with_level("MQTT", "client", 3));
+        assert_eq!(packet.validate(), Err(ProtocolViolation::ProtocolLevel));
+        let packet = VariablePacket::new(ConnectPacket::new("MQTX", "client"));
+        assert_eq!(packet.validate(), Err(ProtocolViolation::ProtocolName));
+        let encoded = b"\x10\x11\x00\x04MQTT\x06\x02\x00\x3c\x00\x05hello";
+        assert_eq!(decode_violations(encoded), vec![ProtocolViolation::ProtocolLevel]);
+    }
+
+    #[test]
+    fn test_validate_publish() {
+        // QoS 3 is already rejected when decoding
+        assert!(VariablePacket::decode(&mut Cursor::new(&b"\x36\x07\x00\x03a/b\x00\x01"[..])).is_err());
+        // DUP with QoS 0
+        assert_eq!(decode_violations(b"\x38\x05\x00\x03a/b"), vec![ProtocolViolation::DupWithQos0]);
+        // A null character in the topic name is already rejected when decoding
+        assert!(VariablePacket::decode(&mut Cursor::new(&b"\x30\x05\x00\x03a\x00b"[..])).is_err());
+        // QoS 1 with packet identifier 0
+        let violation = VariablePacket::decode(&mut Cursor::new(&b"\x32\x07\x00\x03a/b\x00\x00"[..]))
+            .unwrap()
+            .validate()
+            .unwrap_err();
+        assert_eq!(violation.normative_id(), "MQTT-2.3.1-1");
+    }
+
+    #[test]
+    fn test_validate_subscribe() {
+        assert_eq!(decode_violations(b"\x82\x02\x00\x01"), vec![ProtocolViolation::EmptySubscribe]);
+        assert_eq!(decode_violations(b"\xa2\x02\x00\x01"), vec![ProtocolViolation::EmptyUnsubscribe]);
+
+        let packet = VariablePacket::new(SubscribePacket::new(0, Vec::new()));
+        assert_eq!(packet.violations(),
+                   vec![ProtocolViolation::ZeroPacketIdentifier, ProtocolViolation::EmptySubscribe]);
+    }
+
+    #[test]
+    fn test_validate_connack() {
+        let packet = VariablePacket::new(ConnackPacket::new(true, ConnectReturnCode::NotAuthorized));
+        assert_eq!(packet.validate(), Err(ProtocolViolation::SessionPresentWithError));
+        assert_eq!(ProtocolViolation::SessionPresentWithError.to_string(),
+                   "Session present must be 0 with a non-zero return code [MQTT-3.2.2-4]");
+    }
+}
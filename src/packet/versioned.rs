@@ -0,0 +1,384 @@
+//! Packets of any supported protocol level
+
+use alloc::vec::Vec;
+use core::convert::From;
+use core::error::Error;
+use core::fmt;
+use io::{self, Cursor, Read, Write};
+
+use {Decodable, Encodable};
+use control::{ControlType, FixedHeader};
+use control::fixed_header::FixedHeaderError;
+use control::variable_header::{ProtocolLevel, ProtocolName, ProtocolVersion, VariableHeaderError};
+use decoder::{DecodeLimits, LimitError, LimitedDecodable};
+use packet::{v5, ConnackPacket, VariablePacket, VariablePacketError};
+
+/// A packet decoded with the rules of the protocol version negotiated in `CONNECT`
+///
+/// ```rust
+/// use std::io::Cursor;
+///
+/// use mqtt::{Decodable, Encodable};
+/// use mqtt::control::variable_header::ProtocolVersion;
+/// use mqtt::packet::{v5, VersionedPacket};
+///
+/// let mut buf = Vec::new();
+/// v5::ConnectPacket::new("client").encode(&mut buf).unwrap();
+///
+/// // Without a version, the packet must be the `CONNECT` that negotiates it
+/// let connect = VersionedPacket::decode(&mut Cursor::new(&buf[..])).unwrap();
+/// assert_eq!(connect.version(), ProtocolVersion::V5);
+///
+/// let mut buf = Vec::new();
+/// v5::PingreqPacket::new().encode(&mut buf).unwrap();
+/// let ping = VersionedPacket::decode_with(&mut Cursor::new(&buf[..]), Some(connect.version())).unwrap();
+/// assert_eq!(ping, VersionedPacket::V5(v5::VariablePacket::new(v5::PingreqPacket::new())));
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum VersionedPacket {
+    /// MQTT 3.1 packet
+    V31(VariablePacket),
+    /// MQTT 3.1.1 packet
+    V311(VariablePacket),
+    /// MQTT 5 packet
+    V5(v5::VariablePacket),
+}
+
+impl VersionedPacket {
+    /// Protocol version of this packet
+    pub fn version(&self) -> ProtocolVersion {
+        match *self {
+            VersionedPacket::V31(..) => ProtocolVersion::V31,
+            VersionedPacket::V311(..) => ProtocolVersion::V311,
+            VersionedPacket::V5(..) => ProtocolVersion::V5,
+        }
+    }
+
+    /// Protocol level of this packet
+    pub fn protocol_level(&self) -> ProtocolLevel {
+        self.version().protocol_level()
+    }
+
+    /// Decodes like `decode_with`, rejecting a packet that exceeds `limits`
+    ///
+    /// The packet size is checked before its remaining length is read.
+    pub fn decode_with_limits<R: Read>(reader: &mut R,
+                                       version: Option<ProtocolVersion>,
+                                       limits: &DecodeLimits)
+                                       -> Result<VersionedPacket, VersionedPacketError> {
+        let fixed_header = FixedHeader::decode(reader)?;
+        let size = fixed_header.encoded_length() as usize + fixed_header.remaining_length as usize;
+        limits.check_packet_size(size)?;
+
+        let mut frame = Vec::new();
+        fixed_header.encode(&mut frame)?;
+        reader.take(u64::from(fixed_header.remaining_length)).read_to_end(&mut frame)?;
+        if frame.len() != size {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        limits.check_fields(&frame)?;
+
+        VersionedPacket::decode_limited(&mut Cursor::new(frame), version, limits)
+    }
+
+    fn decode_connect<R: Read>(reader: &mut R, limits: &DecodeLimits) -> Result<VersionedPacket, VersionedPacketError> {
+        let fixed_header = FixedHeader::decode(reader)?;
+        if fixed_header.packet_type.control_type != ControlType::Connect {
+            return Err(VersionedPacketError::ExpectedConnect(fixed_header.packet_type.control_type));
+        }
+
+        let mut buf = Vec::new();
+        reader.take(u64::from(fixed_header.remaining_length)).read_to_end(&mut buf)?;
+
+        let version = {
+            let mut rdr = Cursor::new(&buf[..]);
+            let name = ProtocolName::decode(&mut rdr)?;
+            let level = ProtocolLevel::decode(&mut rdr)?;
+            ProtocolVersion::from_name_and_level(&name.0, level.0)?
+        };
+
+        VersionedPacket::decode_version(&mut Cursor::new(buf), Some(fixed_header), version, limits)
+    }
+
+    fn decode_version<R: Read>(reader: &mut R,
+                               fixed_header: Option<FixedHeader>,
+                               version: ProtocolVersion,
+                               limits: &DecodeLimits)
+                               -> Result<VersionedPacket, VersionedPacketError> {
+        match version {
+            ProtocolVersion::V31 => {
+                let pk = VersionedPacket::decode_v31(reader, fixed_header, limits)?;
+                Ok(VersionedPacket::V31(pk))
+            }
+            ProtocolVersion::V311 => {
+                let pk = VariablePacket::decode_limited(reader, fixed_header, limits)?;
+                Ok(VersionedPacket::V311(pk))
+            }
+            ProtocolVersion::V5 => {
+                let pk = v5::VariablePacket::decode_limited(reader, fixed_header, limits)?;
+                Ok(VersionedPacket::V5(pk))
+            }
+        }
+    }
+
+    /// Decodes with the 3.1.1 layouts, except for `CONNACK` which has no session present flag in 3.1
+    fn decode_v31<R: Read>(reader: &mut R,
+                           fixed_header: Option<FixedHeader>,
+                           limits: &DecodeLimits)
+                           -> Result<VariablePacket, VariablePacketError> {
+        let fixed_header = match fixed_header {
+            Some(fh) => fh,
+            None => {
+                match FixedHeader::decode(reader) {
+                    Ok(fh) => fh,
+                    Err(FixedHeaderError::Unrecognized(code, length)) => {
+                        let mut buf = Vec::new();
+                        reader.take(u64::from(length)).read_to_end(&mut buf)?;
+                        return Err(VariablePacketError::UnrecognizedPacket(code, buf));
+                    }
+                    Err(FixedHeaderError::ReservedType(code, length)) => {
+                        let mut buf = Vec::new();
+                        reader.take(u64::from(length)).read_to_end(&mut buf)?;
+                        return Err(VariablePacketError::ReservedPacket(code, buf));
+                    }
+                    Err(err) => return Err(From::from(err)),
+                }
+            }
+        };
+
+        if fixed_header.packet_type.control_type == ControlType::ConnectAcknowledgement {
+            let reader = &mut reader.take(u64::from(fixed_header.remaining_length));
+            let pk = ConnackPacket::decode_packet_with_version(reader, fixed_header, ProtocolVersion::V31)?;
+            return Ok(VariablePacket::ConnackPacket(pk));
+        }
+
+        VariablePacket::decode_limited(reader, Some(fixed_header), limits)
+    }
+}
+
+/// Packets of the 3.1 layouts are treated as MQTT 3.1.1
+impl From<VariablePacket> for VersionedPacket {
+    fn from(pk: VariablePacket) -> VersionedPacket {
+        VersionedPacket::V311(pk)
+    }
+}
+
+impl From<v5::VariablePacket> for VersionedPacket {
+    fn from(pk: v5::VariablePacket) -> VersionedPacket {
+        VersionedPacket::V5(pk)
+    }
+}
+
+impl Encodable for VersionedPacket {
+    type Err = VersionedPacketError;
+
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), VersionedPacketError> {
+        match *self {
+            VersionedPacket::V31(ref pk) | VersionedPacket::V311(ref pk) => pk.encode(writer).map_err(From::from),
+            VersionedPacket::V5(ref pk) => pk.encode(writer).map_err(From::from),
+        }
+    }
+
+    fn encoded_length(&self) -> u32 {
+        match *self {
+            VersionedPacket::V31(ref pk) | VersionedPacket::V311(ref pk) => pk.encoded_length(),
+            VersionedPacket::V5(ref pk) => pk.encoded_length(),
+        }
+    }
+}
+
+impl Decodable for VersionedPacket {
+    type Err = VersionedPacketError;
+    type Cond = ProtocolVersion;
+
+    /// Decodes a packet of the given protocol version, or a `CONNECT` of any version if it is `None`
+    fn decode_with<R: Read>(reader: &mut R,
+                            version: Option<ProtocolVersion>)
+                            -> Result<VersionedPacket, Self::Err> {
+        VersionedPacket::decode_limited(reader, version, &DecodeLimits::default())
+    }
+}
+
+impl LimitedDecodable for VersionedPacket {
+    fn decode_limited<R: Read>(reader: &mut R,
+                               version: Option<ProtocolVersion>,
+                               limits: &DecodeLimits)
+                               -> Result<VersionedPacket, Self::Err> {
+        match version {
+            Some(version) => VersionedPacket::decode_version(reader, None, version, limits),
+            None => VersionedPacket::decode_connect(reader, limits),
+        }
+    }
+}
+
+/// Parsing errors for versioned packet
+#[derive(Debug)]
+pub enum VersionedPacketError {
+    FixedHeaderError(FixedHeaderError),
+    VariableHeaderError(VariableHeaderError),
+    IoError(io::Error),
+    ExpectedConnect(ControlType),
+    LimitExceeded(LimitError),
+    V3(VariablePacketError),
+    V5(v5::VariablePacketError),
+}
+
+impl fmt::Display for VersionedPacketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VersionedPacketError::FixedHeaderError(ref err) => err.fmt(f),
+            VersionedPacketError::VariableHeaderError(ref err) => err.fmt(f),
+            VersionedPacketError::IoError(ref err) => err.fmt(f),
+            VersionedPacketError::ExpectedConnect(ty) => write!(f, "Expected CONNECT, got {:?}", ty),
+            VersionedPacketError::LimitExceeded(ref err) => err.fmt(f),
+            VersionedPacketError::V3(ref err) => err.fmt(f),
+            VersionedPacketError::V5(ref err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for VersionedPacketError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            VersionedPacketError::FixedHeaderError(ref err) => Some(err),
+            VersionedPacketError::VariableHeaderError(ref err) => Some(err),
+            VersionedPacketError::IoError(ref err) => Some(err),
+            VersionedPacketError::ExpectedConnect(..) => None,
+            VersionedPacketError::LimitExceeded(ref err) => Some(err),
+            VersionedPacketError::V3(ref err) => Some(err),
+            VersionedPacketError::V5(ref err) => Some(err),
+        }
+    }
+}
+
+impl From<FixedHeaderError> for VersionedPacketError {
+    fn from(err: FixedHeaderError) -> VersionedPacketError {
+        VersionedPacketError::FixedHeaderError(err)
+    }
+}
+
+impl From<VariableHeaderError> for VersionedPacketError {
+    fn from(err: VariableHeaderError) -> VersionedPacketError {
+        VersionedPacketError::VariableHeaderError(err)
+    }
+}
+
+impl From<io::Error> for VersionedPacketError {
+    fn from(err: io::Error) -> VersionedPacketError {
+        VersionedPacketError::IoError(err)
+    }
+}
+
+impl From<LimitError> for VersionedPacketError {
+    fn from(err: LimitError) -> VersionedPacketError {
+        VersionedPacketError::LimitExceeded(err)
+    }
+}
+
+impl From<VariablePacketError> for VersionedPacketError {
+    fn from(err: VariablePacketError) -> VersionedPacketError {
+        VersionedPacketError::V3(err)
+    }
+}
+
+impl From<v5::VariablePacketError> for VersionedPacketError {
+    fn from(err: v5::VariablePacketError) -> VersionedPacketError {
+        VersionedPacketError::V5(err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use {Decodable, Encodable};
+    use control::variable_header::ConnectReturnCode;
+    use packet::ConnectPacket;
+
+    #[test]
+    fn test_versioned_packet_connect() {
+        let packet = ConnectPacket::new("MQTT", "1234");
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+
+        let decoded = VersionedPacket::decode(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(decoded, VersionedPacket::V311(VariablePacket::new(packet)));
+
+        let packet = v5::ConnectPacket::new("1234");
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+
+        let decoded = VersionedPacket::decode(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(decoded, VersionedPacket::V5(v5::VariablePacket::new(packet)));
+    }
+
+    #[test]
+    fn test_versioned_packet_expects_connect() {
+        let buf = b"\xc0\x00";
+        match VersionedPacket::decode(&mut Cursor::new(&buf[..])) {
+            Err(VersionedPacketError::ExpectedConnect(ControlType::PingRequest)) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_versioned_packet_truncated_connect() {
+        // Remaining length of 268,435,455 bytes with only the protocol name following
+        let buf = b"\x10\xff\xff\xff\x7f\x00\x04MQTT";
+        assert!(VersionedPacket::decode(&mut Cursor::new(&buf[..])).is_err());
+    }
+
+    #[test]
+    fn test_versioned_packet_limits() {
+        let limits = DecodeLimits { max_packet_size: 64, max_client_id_length: 4, ..DecodeLimits::default() };
+
+        let buf = b"\x10\xff\xff\xff\x7f\x00\x04MQTT";
+        match VersionedPacket::decode_with_limits(&mut Cursor::new(&buf[..]), None, &limits) {
+            Err(VersionedPacketError::LimitExceeded(LimitError::PacketTooLarge(268_435_460, 64))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        let mut buf = Vec::new();
+        v5::ConnectPacket::new("client").encode(&mut buf).unwrap();
+        match VersionedPacket::decode_with_limits(&mut Cursor::new(&buf[..]), None, &limits) {
+            Err(VersionedPacketError::LimitExceeded(LimitError::ClientIdTooLong(6, 4))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        let mut buf = Vec::new();
+        v5::ConnectPacket::new("c").encode(&mut buf).unwrap();
+        let decoded = VersionedPacket::decode_with_limits(&mut Cursor::new(&buf[..]), None, &limits).unwrap();
+        assert_eq!(decoded.version(), ProtocolVersion::V5);
+    }
+
+    #[test]
+    fn test_versioned_packet_auth_by_version() {
+        let buf = b"\xf0\x00";
+
+        let decoded = VersionedPacket::decode_with(&mut Cursor::new(&buf[..]), Some(ProtocolVersion::V5)).unwrap();
+        assert_eq!(decoded.version(), ProtocolVersion::V5);
+
+        match VersionedPacket::decode_with(&mut Cursor::new(&buf[..]), Some(ProtocolVersion::V311)) {
+            Err(VersionedPacketError::V3(VariablePacketError::ReservedPacket(15, _))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_versioned_packet_v31() {
+        let packet = ConnectPacket::with_version(ProtocolVersion::V31, "1234").unwrap();
+        let mut buf = Vec::new();
+        packet.encode(&mut buf).unwrap();
+
+        let decoded = VersionedPacket::decode(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(decoded, VersionedPacket::V31(VariablePacket::new(packet)));
+
+        // The first byte of a 3.1 CONNACK is reserved
+        let buf = b"\x20\x02\x01\x02";
+        let decoded = VersionedPacket::decode_with(&mut Cursor::new(&buf[..]), Some(ProtocolVersion::V31)).unwrap();
+        let expected = ConnackPacket::new(false, ConnectReturnCode::IdentifierRejected);
+        assert_eq!(decoded, VersionedPacket::V31(VariablePacket::new(expected)));
+    }
+}
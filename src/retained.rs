@@ -0,0 +1,141 @@
+//! Last retained message of each topic
+//!
+//! ```rust
+//! use mqtt::{RetainedStore, TopicFilter, TopicName};
+//! use mqtt::packet::{PublishPacket, QoSWithPacketIdentifier};
+//!
+//! let mut store = RetainedStore::new();
+//! let mut status = PublishPacket::new(TopicName::new("devices/1/status").unwrap(), QoSWithPacketIdentifier::Level0, "on");
+//! status.set_retain(true);
+//! store.insert(status.clone());
+//!
+//! let matched: Vec<_> = store.matches(&TopicFilter::new("devices/+/status").unwrap()).collect();
+//! assert_eq!(matched, vec![&status]);
+//!
+//! // An empty payload deletes the retained message
+//! let mut delete = PublishPacket::new(TopicName::new("devices/1/status").unwrap(), QoSWithPacketIdentifier::Level0, "");
+//! delete.set_retain(true);
+//! assert_eq!(store.insert(delete), Some(status));
+//! assert!(store.is_empty());
+//! ```
+
+use std::collections::HashMap;
+
+use packet::{Packet, PublishPacket};
+use topic_filter::TopicFilterRef;
+use topic_name::TopicNameRef;
+
+/// Retained `PublishPacket`s by topic name
+#[derive(Debug, Clone, Default)]
+pub struct RetainedStore {
+    messages: HashMap<String, PublishPacket>,
+}
+
+impl RetainedStore {
+    pub fn new() -> RetainedStore {
+        RetainedStore::default()
+    }
+
+    /// Number of retained messages
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Keeps a retained message, replacing the one of the same topic, which is returned
+    ///
+    /// A message with an empty payload only removes the retained message of its topic
+    /// [MQTT-3.3.1-10]. The message is stored with its retain flag set, ready to send to new
+    /// subscribers [MQTT-3.3.1-8].
+    pub fn insert(&mut self, mut packet: PublishPacket) -> Option<PublishPacket> {
+        if packet.payload_ref().is_empty() {
+            return self.messages.remove(packet.topic_name());
+        }
+        packet.set_retain(true);
+        self.messages.insert(packet.topic_name().to_owned(), packet)
+    }
+
+    /// Retained message of `topic_name`
+    pub fn get(&self, topic_name: &TopicNameRef) -> Option<&PublishPacket> {
+        self.messages.get(&topic_name[..])
+    }
+
+    /// Removes the retained message of `topic_name`, returning it
+    pub fn remove(&mut self, topic_name: &TopicNameRef) -> Option<PublishPacket> {
+        self.messages.remove(&topic_name[..])
+    }
+
+    /// Retained messages whose topic name matches `topic_filter`, to send to a new subscription
+    ///
+    /// A message without a valid topic name matches no filter.
+    pub fn matches<'a>(&'a self, topic_filter: &TopicFilterRef) -> impl Iterator<Item = &'a PublishPacket> {
+        let matcher = topic_filter.get_matcher();
+        let matched: Vec<_> = self.messages
+            .values()
+            .filter(|packet| match TopicNameRef::new(packet.topic_name()) {
+                        Ok(topic_name) => matcher.is_match(topic_name),
+                        Err(..) => false,
+                    })
+            .collect();
+        matched.into_iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use packet::QoSWithPacketIdentifier;
+    use topic_filter::TopicFilter;
+    use topic_name::TopicName;
+
+    fn message(topic_name: &str, payload: &str) -> PublishPacket {
+        PublishPacket::new(TopicName::new(topic_name).unwrap(), QoSWithPacketIdentifier::Level1(1), payload)
+    }
+
+    fn matched(store: &RetainedStore, topic_filter: &str) -> Vec<String> {
+        let filter = TopicFilter::new(topic_filter).unwrap();
+        let mut matched: Vec<_> = store.matches(&filter).map(|packet| packet.topic_name().to_owned()).collect();
+        matched.sort();
+        matched
+    }
+
+    #[test]
+    fn test_retained_store_insert_replace_delete() {
+        let mut store = RetainedStore::new();
+        assert_eq!(store.insert(message("a/b", "first")), None);
+        let old = store.insert(message("a/b", "second")).unwrap();
+        assert_eq!(old.payload_ref(), b"first");
+        assert!(old.retain());
+
+        let topic_name = TopicName::new("a/b").unwrap();
+        assert_eq!(store.get(&topic_name).unwrap().payload_ref(), b"second");
+        assert_eq!(store.len(), 1);
+
+        // Deleting a topic without a retained message is a no-op
+        assert_eq!(store.insert(message("a/c", "")), None);
+        assert!(store.insert(message("a/b", "")).is_some());
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_retained_store_matches() {
+        let mut store = RetainedStore::new();
+        for topic_name in ["a", "a/b", "a/b/c", "b/b", "$SYS/uptime"].iter() {
+            store.insert(message(topic_name, "x"));
+        }
+
+        assert_eq!(matched(&store, "a/+"), vec!["a/b"]);
+        assert_eq!(matched(&store, "a/#"), vec!["a", "a/b", "a/b/c"]);
+        assert_eq!(matched(&store, "+/b"), vec!["a/b", "b/b"]);
+        assert_eq!(matched(&store, "#"), vec!["a", "a/b", "a/b/c", "b/b"]);
+        assert_eq!(matched(&store, "$SYS/#"), vec!["$SYS/uptime"]);
+    }
+}
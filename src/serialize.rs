@@ -0,0 +1,534 @@
+//! `Serialize` and `Deserialize` implementations, enabled by the `serde` feature
+//!
+//! Packets are serialized as structs of their fields rather than as their encoded bytes, so logged
+//! traffic stays readable. QoS and return codes are serialized as their numeric codes.
+//!
+//! Deserializing validates topic names and filters with `TopicName::new` and `TopicFilter::new`, and
+//! rejects QoS and return codes out of range. Other fields are taken as they are, like decoding does,
+//! so logged traffic violating the specification can be replayed; check such packets with
+//! `VariablePacket::validate`.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use serde::de::{self, Deserialize, Deserializer, Unexpected};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use control::variable_header::ConnectReturnCode;
+use packet::{ConnackPacket, ConnectPacket, DisconnectPacket, LastWill, Packet, PingreqPacket, PingrespPacket,
+             PubackPacket, PubcompPacket, PublishPacket, PubrecPacket, PubrelPacket, QoSWithPacketIdentifier,
+             SubackPacket, SubscribePacket, UnsubackPacket, UnsubscribePacket, VariablePacket};
+use packet::suback::SubscribeReturnCode;
+use qos::QualityOfService;
+use topic_filter::{TopicFilter, TopicFilterRef};
+use topic_name::{TopicName, TopicNameRef};
+
+impl Serialize for TopicNameRef {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
+    }
+}
+
+impl Serialize for TopicName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TopicName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<TopicName, D::Error> {
+        let topic_name = String::deserialize(deserializer)?;
+        TopicName::new(topic_name).map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for TopicFilterRef {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
+    }
+}
+
+impl Serialize for TopicFilter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TopicFilter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<TopicFilter, D::Error> {
+        let topic_filter = String::deserialize(deserializer)?;
+        TopicFilter::new(topic_filter).map_err(de::Error::custom)
+    }
+}
+
+/// Deserializes a `u8` code, converted by `from_code`
+fn deserialize_code<'de, D, T, F>(deserializer: D, expected: &'static str, from_code: F) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    F: FnOnce(u8) -> Option<T>,
+{
+    let code = u8::deserialize(deserializer)?;
+    from_code(code).ok_or_else(|| de::Error::invalid_value(Unexpected::Unsigned(u64::from(code)), &expected))
+}
+
+impl Serialize for QualityOfService {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(*self as u8)
+    }
+}
+
+impl<'de> Deserialize<'de> for QualityOfService {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<QualityOfService, D::Error> {
+        deserialize_code(deserializer, "a QoS of 0, 1 or 2", |code| match code {
+            0 => Some(QualityOfService::Level0),
+            1 => Some(QualityOfService::Level1),
+            2 => Some(QualityOfService::Level2),
+            _ => None,
+        })
+    }
+}
+
+impl Serialize for ConnectReturnCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(self.to_u8())
+    }
+}
+
+impl<'de> Deserialize<'de> for ConnectReturnCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ConnectReturnCode, D::Error> {
+        deserialize_code(deserializer, "a return code", |code| Some(ConnectReturnCode::from_u8(code)))
+    }
+}
+
+impl Serialize for SubscribeReturnCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(*self as u8)
+    }
+}
+
+impl<'de> Deserialize<'de> for SubscribeReturnCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<SubscribeReturnCode, D::Error> {
+        deserialize_code(deserializer, "a return code of 0, 1, 2 or 128", |code| match code {
+            0x00 => Some(SubscribeReturnCode::MaximumQoSLevel0),
+            0x01 => Some(SubscribeReturnCode::MaximumQoSLevel1),
+            0x02 => Some(SubscribeReturnCode::MaximumQoSLevel2),
+            0x80 => Some(SubscribeReturnCode::Failure),
+            _ => None,
+        })
+    }
+}
+
+impl Serialize for LastWill {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("LastWill", 4)?;
+        state.serialize_field("topic_name", self.topic_name())?;
+        state.serialize_field("message", self.message())?;
+        state.serialize_field("qos", &self.qos())?;
+        state.serialize_field("retain", &self.retain())?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "LastWill")]
+struct LastWillFields {
+    topic_name: TopicName,
+    message: Vec<u8>,
+    qos: QualityOfService,
+    retain: bool,
+}
+
+impl<'de> Deserialize<'de> for LastWill {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<LastWill, D::Error> {
+        let fields = LastWillFields::deserialize(deserializer)?;
+        let mut will = LastWill::new(fields.topic_name, fields.qos, fields.message);
+        will.set_retain(fields.retain);
+        Ok(will)
+    }
+}
+
+impl Serialize for ConnectPacket {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("ConnectPacket", 8)?;
+        state.serialize_field("protocol_name", self.protocol_name())?;
+        state.serialize_field("protocol_level", &self.protocol_level())?;
+        state.serialize_field("client_identifier", self.client_identifier())?;
+        state.serialize_field("keep_alive", &self.keep_alive())?;
+        state.serialize_field("clean_session", &self.clean_session())?;
+        state.serialize_field("will", &self.will())?;
+        state.serialize_field("user_name", &self.user_name())?;
+        state.serialize_field("password", &self.password())?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "ConnectPacket")]
+struct ConnectFields {
+    protocol_name: String,
+    protocol_level: u8,
+    client_identifier: String,
+    keep_alive: u16,
+    clean_session: bool,
+    will: Option<LastWill>,
+    user_name: Option<String>,
+    password: Option<Vec<u8>>,
+}
+
+impl<'de> Deserialize<'de> for ConnectPacket {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ConnectPacket, D::Error> {
+        let fields = ConnectFields::deserialize(deserializer)?;
+        let mut packet =
+            ConnectPacket::with_level(fields.protocol_name, fields.client_identifier, fields.protocol_level);
+        packet.set_keep_alive(fields.keep_alive);
+        packet.set_clean_session(fields.clean_session);
+        packet.set_will(fields.will);
+        packet.set_user_name(fields.user_name);
+        packet.set_password(fields.password);
+        Ok(packet)
+    }
+}
+
+impl Serialize for ConnackPacket {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("ConnackPacket", 2)?;
+        state.serialize_field("session_present", &self.connack_flags().session_present)?;
+        state.serialize_field("return_code", &self.connect_return_code())?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "ConnackPacket")]
+struct ConnackFields {
+    session_present: bool,
+    return_code: ConnectReturnCode,
+}
+
+impl<'de> Deserialize<'de> for ConnackPacket {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ConnackPacket, D::Error> {
+        let fields = ConnackFields::deserialize(deserializer)?;
+        Ok(ConnackPacket::new(fields.session_present, fields.return_code))
+    }
+}
+
+impl Serialize for PublishPacket {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // From the flags, since `qos()` panics if both QoS bits are set
+        let qos = (self.fixed_header().packet_type.flags & 0x06) >> 1;
+
+        let mut state = serializer.serialize_struct("PublishPacket", 6)?;
+        state.serialize_field("topic_name", self.topic_name())?;
+        state.serialize_field("qos", &qos)?;
+        state.serialize_field("packet_identifier", &self.packet_identifier())?;
+        state.serialize_field("dup", &self.dup())?;
+        state.serialize_field("retain", &self.retain())?;
+        state.serialize_field("payload", self.payload_ref())?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "PublishPacket")]
+struct PublishFields {
+    topic_name: TopicName,
+    qos: QualityOfService,
+    packet_identifier: Option<u16>,
+    dup: bool,
+    retain: bool,
+    payload: Vec<u8>,
+}
+
+impl<'de> Deserialize<'de> for PublishPacket {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<PublishPacket, D::Error> {
+        let fields = PublishFields::deserialize(deserializer)?;
+        let qos = match (fields.qos, fields.packet_identifier) {
+            (QualityOfService::Level0, None) => QoSWithPacketIdentifier::Level0,
+            (QualityOfService::Level0, Some(..)) | (_, None) => {
+                return Err(de::Error::custom("a packet identifier goes with QoS 1 and 2 only"))
+            }
+            (qos, Some(pkid)) => QoSWithPacketIdentifier::new(qos, pkid),
+        };
+        let mut packet = PublishPacket::new(fields.topic_name, qos, fields.payload);
+        packet.set_dup(fields.dup);
+        packet.set_retain(fields.retain);
+        Ok(packet)
+    }
+}
+
+impl Serialize for SubscribePacket {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("SubscribePacket", 2)?;
+        state.serialize_field("packet_identifier", &self.packet_identifier())?;
+        state.serialize_field("subscribes", self.payload_ref().subscribes())?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "SubscribePacket")]
+struct SubscribeFields {
+    packet_identifier: u16,
+    subscribes: Vec<(TopicFilter, QualityOfService)>,
+}
+
+impl<'de> Deserialize<'de> for SubscribePacket {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<SubscribePacket, D::Error> {
+        let fields = SubscribeFields::deserialize(deserializer)?;
+        Ok(SubscribePacket::new(fields.packet_identifier, fields.subscribes))
+    }
+}
+
+impl Serialize for SubackPacket {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("SubackPacket", 2)?;
+        state.serialize_field("packet_identifier", &self.packet_identifier())?;
+        state.serialize_field("return_codes", self.payload_ref().subscribes())?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "SubackPacket")]
+struct SubackFields {
+    packet_identifier: u16,
+    return_codes: Vec<SubscribeReturnCode>,
+}
+
+impl<'de> Deserialize<'de> for SubackPacket {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<SubackPacket, D::Error> {
+        let fields = SubackFields::deserialize(deserializer)?;
+        Ok(SubackPacket::new(fields.packet_identifier, fields.return_codes))
+    }
+}
+
+impl Serialize for UnsubscribePacket {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("UnsubscribePacket", 2)?;
+        state.serialize_field("packet_identifier", &self.packet_identifier())?;
+        state.serialize_field("topic_filters", self.payload_ref().subscribes())?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "UnsubscribePacket")]
+struct UnsubscribeFields {
+    packet_identifier: u16,
+    topic_filters: Vec<TopicFilter>,
+}
+
+impl<'de> Deserialize<'de> for UnsubscribePacket {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<UnsubscribePacket, D::Error> {
+        let fields = UnsubscribeFields::deserialize(deserializer)?;
+        Ok(UnsubscribePacket::new(fields.packet_identifier, fields.topic_filters))
+    }
+}
+
+#[derive(Deserialize)]
+struct AckFields {
+    packet_identifier: u16,
+}
+
+macro_rules! impl_ack_serde {
+    ($($name:ident,)+) => {
+        $(
+            impl Serialize for $name {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    let mut state = serializer.serialize_struct(stringify!($name), 1)?;
+                    state.serialize_field("packet_identifier", &self.packet_identifier())?;
+                    state.end()
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $name {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<$name, D::Error> {
+                    let fields = AckFields::deserialize(deserializer)?;
+                    Ok($name::new(fields.packet_identifier))
+                }
+            }
+        )+
+    }
+}
+
+impl_ack_serde! {
+    PubackPacket,
+    PubrecPacket,
+    PubrelPacket,
+    PubcompPacket,
+    UnsubackPacket,
+}
+
+#[derive(Deserialize)]
+struct EmptyFields {}
+
+macro_rules! impl_empty_serde {
+    ($($name:ident,)+) => {
+        $(
+            impl Serialize for $name {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    serializer.serialize_struct(stringify!($name), 0)?.end()
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $name {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<$name, D::Error> {
+                    EmptyFields::deserialize(deserializer)?;
+                    Ok($name::new())
+                }
+            }
+        )+
+    }
+}
+
+impl_empty_serde! {
+    PingreqPacket,
+    PingrespPacket,
+    DisconnectPacket,
+}
+
+macro_rules! impl_variable_packet_serde {
+    ($($name:ident,)+) => {
+        // Variants are named after `VariablePacket`'s so they double as the serialized tags
+        #[allow(clippy::enum_variant_names)]
+        #[derive(Serialize)]
+        #[serde(rename = "VariablePacket")]
+        enum VariablePacketRef<'a> {
+            $($name(&'a $name),)+
+        }
+
+        impl Serialize for VariablePacket {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                match *self {
+                    $(VariablePacket::$name(ref packet) => VariablePacketRef::$name(packet).serialize(serializer),)+
+                }
+            }
+        }
+
+        #[allow(clippy::enum_variant_names)]
+        #[derive(Deserialize)]
+        #[serde(rename = "VariablePacket")]
+        enum VariablePacketFields {
+            $($name($name),)+
+        }
+
+        impl<'de> Deserialize<'de> for VariablePacket {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<VariablePacket, D::Error> {
+                match VariablePacketFields::deserialize(deserializer)? {
+                    $(VariablePacketFields::$name(packet) => Ok(VariablePacket::$name(packet)),)+
+                }
+            }
+        }
+    }
+}
+
+impl_variable_packet_serde! {
+    ConnectPacket,
+    ConnackPacket,
+    PublishPacket,
+    PubackPacket,
+    PubrecPacket,
+    PubrelPacket,
+    PubcompPacket,
+    PingreqPacket,
+    PingrespPacket,
+    SubscribePacket,
+    SubackPacket,
+    UnsubscribePacket,
+    UnsubackPacket,
+    DisconnectPacket,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json;
+
+    use packet::ProtocolViolation;
+
+    fn round_trip(packet: VariablePacket) {
+        let json = serde_json::to_string(&packet).unwrap();
+        assert_eq!(serde_json::from_str::<VariablePacket>(&json).unwrap(), packet);
+    }
+
+    #[test]
+    fn test_serde_publish_packet() {
+        let mut packet = PublishPacket::new(TopicName::new("a/b").unwrap(), QoSWithPacketIdentifier::Level1(10), "hi");
+        packet.set_retain(true);
+        let json = serde_json::to_string(&VariablePacket::new(packet.clone())).unwrap();
+        let expected = concat!(r#"{"PublishPacket":{"topic_name":"a/b","qos":1,"packet_identifier":10,"#,
+                               r#""dup":false,"retain":true,"payload":[104,105]}}"#);
+        assert_eq!(json, expected);
+        assert_eq!(serde_json::from_str::<VariablePacket>(&json).unwrap(), VariablePacket::new(packet));
+
+        let invalid = [
+            // Wildcard in topic name
+            r#"{"topic_name":"a/+","qos":0,"packet_identifier":null,"dup":false,"retain":false,"payload":[]}"#,
+            r#"{"topic_name":"a","qos":3,"packet_identifier":1,"dup":false,"retain":false,"payload":[]}"#,
+            r#"{"topic_name":"a","qos":1,"packet_identifier":null,"dup":false,"retain":false,"payload":[]}"#,
+            r#"{"topic_name":"a","qos":0,"packet_identifier":1,"dup":false,"retain":false,"payload":[]}"#,
+        ];
+        for json in invalid.iter() {
+            assert!(serde_json::from_str::<PublishPacket>(json).is_err(), "{}", json);
+        }
+
+        // DUP set on a QoS 0 message is kept, as when decoding
+        let json = r#"{"topic_name":"a","qos":0,"packet_identifier":null,"dup":true,"retain":false,"payload":[]}"#;
+        let packet = serde_json::from_str::<PublishPacket>(json).unwrap();
+        assert!(packet.dup());
+        assert_eq!(packet.violations(), vec![ProtocolViolation::DupWithQos0]);
+    }
+
+    #[test]
+    fn test_serde_topics() {
+        let filter: TopicFilter = serde_json::from_str(r#""a/+/#""#).unwrap();
+        assert_eq!(serde_json::to_string(&filter).unwrap(), r#""a/+/#""#);
+        assert!(serde_json::from_str::<TopicFilter>(r#""a/#/b""#).is_err());
+        assert!(serde_json::from_str::<TopicName>(r#""a/#""#).is_err());
+        assert!(serde_json::from_str::<QualityOfService>("3").is_err());
+        assert_eq!(serde_json::from_str::<ConnectReturnCode>("5").unwrap(), ConnectReturnCode::NotAuthorized);
+    }
+
+    #[test]
+    fn test_serde_all_packets() {
+        let mut will = LastWill::new(TopicName::new("will").unwrap(), QualityOfService::Level2, "bye");
+        will.set_retain(true);
+        let connect = ConnectPacket::builder()
+            .client_id("client")
+            .keep_alive(30)
+            .will(will)
+            .user_name("user")
+            .password("secret")
+            .build()
+            .unwrap();
+        let filter = TopicFilter::new("a/#").unwrap();
+
+        round_trip(VariablePacket::new(connect));
+        round_trip(VariablePacket::new(ConnackPacket::new(true, ConnectReturnCode::ConnectionAccepted)));
+        round_trip(VariablePacket::new(PubackPacket::new(1)));
+        round_trip(VariablePacket::new(PubrecPacket::new(2)));
+        round_trip(VariablePacket::new(PubrelPacket::new(3)));
+        round_trip(VariablePacket::new(PubcompPacket::new(4)));
+        round_trip(VariablePacket::new(PingreqPacket::new()));
+        round_trip(VariablePacket::new(PingrespPacket::new()));
+        round_trip(VariablePacket::new(SubscribePacket::new(5, vec![(filter.clone(), QualityOfService::Level1)])));
+        round_trip(VariablePacket::new(SubackPacket::new(5, vec![SubscribeReturnCode::Failure])));
+        round_trip(VariablePacket::new(UnsubscribePacket::new(6, vec![filter])));
+        round_trip(VariablePacket::new(UnsubackPacket::new(6)));
+        round_trip(VariablePacket::new(DisconnectPacket::new()));
+
+        // Violations of the specification are kept, as when decoding
+        let subscribe = serde_json::from_str::<SubscribePacket>(r#"{"packet_identifier":1,"subscribes":[]}"#).unwrap();
+        assert_eq!(subscribe.violations(), vec![ProtocolViolation::EmptySubscribe]);
+        let puback = serde_json::from_str::<PubackPacket>(r#"{"packet_identifier":0}"#).unwrap();
+        assert_eq!(puback.packet_identifier(), 0);
+        let json = concat!(r#"{"protocol_name":"MQTT","protocol_level":4,"client_identifier":"","keep_alive":0,"#,
+                           r#""clean_session":false,"will":null,"user_name":null,"#,
+                           r#""password":[115,101,99,114,101,116]}"#);
+        let connect = serde_json::from_str::<ConnectPacket>(json).unwrap();
+        assert_eq!(connect.password(), Some(&b"secret"[..]));
+        assert_eq!(connect.violations(),
+                   vec![ProtocolViolation::PasswordWithoutUserName, ProtocolViolation::EmptyClientIdentifier]);
+    }
+}
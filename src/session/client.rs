@@ -0,0 +1,297 @@
+//! Client side of the QoS 1 and QoS 2 flows
+//!
+//! ```rust
+//! use mqtt::TopicName;
+//! use mqtt::packet::{PubcompPacket, PublishPacket, PubrecPacket, PubrelPacket, QoSWithPacketIdentifier,
+//!                    VariablePacket};
+//! use mqtt::session::{ClientEvent, ClientSession};
+//!
+//! let mut session = ClientSession::new();
+//! let publish = PublishPacket::new(TopicName::new("a/b").unwrap(), QoSWithPacketIdentifier::Level2(1), "x");
+//! session.publish(publish.clone()).unwrap();
+//! assert_eq!(session.poll_event(), Some(ClientEvent::Send(VariablePacket::new(publish))));
+//!
+//! // To be saved with `SessionStore::save_pubrel`, if the session is kept
+//! session.handle(VariablePacket::new(PubrecPacket::new(1))).unwrap();
+//! assert_eq!(session.poll_event(), Some(ClientEvent::Released(1)));
+//! assert_eq!(session.poll_event(), Some(ClientEvent::Send(VariablePacket::new(PubrelPacket::new(1)))));
+//!
+//! session.handle(VariablePacket::new(PubcompPacket::new(1))).unwrap();
+//! assert_eq!(session.poll_event(), Some(ClientEvent::Published(1)));
+//! assert!(!session.is_in_flight(1));
+//! ```
+
+use std::collections::VecDeque;
+
+use packet::{PublishPacket, QoSWithPacketIdentifier, VariablePacket};
+use session::{InFlightMessage, SessionError};
+use session::inflight::InFlight;
+
+/// Output of a `ClientSession`
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum ClientEvent {
+    /// Packet to send to the server
+    Send(VariablePacket),
+    /// Message from the server to deliver to the application
+    Message(PublishPacket),
+    /// The outgoing QoS 1 or 2 message with this packet identifier is complete
+    ///
+    /// Forget it with `SessionStore::remove_in_flight`.
+    Published(u16),
+    /// The outgoing QoS 2 message with this packet identifier was received by the server and is
+    /// released with the `PUBREL` that follows
+    ///
+    /// Replace it with `SessionStore::save_pubrel`, so that `PUBREL` is what is sent again.
+    Released(u16),
+    /// A QoS 2 message with this packet identifier was received and is not delivered again until
+    /// released by the server, answered by the `PUBREC` that follows
+    ///
+    /// Save it with `SessionStore::save_received`.
+    Received(u16),
+    /// The received QoS 2 message with this packet identifier was released by the server
+    ///
+    /// Forget it with `SessionStore::remove_received`.
+    ReceiveComplete(u16),
+}
+
+/// Client session state of the messages in flight
+///
+/// Outgoing messages stay in flight until acknowledged, and are sent again by `resend` after
+/// reconnecting to a session that was not cleaned.
+#[derive(Debug, Clone, Default)]
+pub struct ClientSession {
+    in_flight: InFlight,
+    events: VecDeque<ClientEvent>,
+}
+
+impl ClientSession {
+    pub fn new() -> ClientSession {
+        ClientSession::default()
+    }
+
+    /// Sends a message, keeping QoS 1 and 2 messages in flight until acknowledged
+    pub fn publish(&mut self, packet: PublishPacket) -> Result<(), SessionError> {
+        self.in_flight.publish(&packet)?;
+        self.send(packet);
+        Ok(())
+    }
+
+    /// Handles a packet received from the server
+    ///
+    /// Packets outside of the publish flows, such as `CONNACK` and `SUBACK`, are accepted and
+    /// left to the caller.
+    pub fn handle(&mut self, packet: VariablePacket) -> Result<(), SessionError> {
+        packet.validate()?;
+
+        match packet {
+            VariablePacket::PublishPacket(publish) => {
+                let (deliver, ack) = self.in_flight.receive(&publish);
+                if deliver {
+                    if let QoSWithPacketIdentifier::Level2(pkid) = publish.qos() {
+                        self.events.push_back(ClientEvent::Received(pkid));
+                    }
+                    self.events.push_back(ClientEvent::Message(publish));
+                }
+                self.events.extend(ack.map(ClientEvent::Send));
+                Ok(())
+            }
+            VariablePacket::PubackPacket(ref ack) => {
+                self.in_flight.puback(ack.packet_identifier())?;
+                self.events.push_back(ClientEvent::Published(ack.packet_identifier()));
+                Ok(())
+            }
+            VariablePacket::PubrecPacket(ref ack) => {
+                let (released, pubrel) = self.in_flight.pubrec(ack.packet_identifier())?;
+                if released {
+                    self.events.push_back(ClientEvent::Released(ack.packet_identifier()));
+                }
+                self.send(pubrel);
+                Ok(())
+            }
+            VariablePacket::PubcompPacket(ref ack) => {
+                self.in_flight.pubcomp(ack.packet_identifier())?;
+                self.events.push_back(ClientEvent::Published(ack.packet_identifier()));
+                Ok(())
+            }
+            VariablePacket::PubrelPacket(ref rel) => {
+                let (complete, pubcomp) = self.in_flight.pubrel(rel.packet_identifier());
+                if complete {
+                    self.events.push_back(ClientEvent::ReceiveComplete(rel.packet_identifier()));
+                }
+                self.send(pubcomp);
+                Ok(())
+            }
+            VariablePacket::ConnectPacket(..) |
+            VariablePacket::SubscribePacket(..) |
+            VariablePacket::UnsubscribePacket(..) |
+            VariablePacket::PingreqPacket(..) |
+            VariablePacket::DisconnectPacket(..) => {
+                Err(SessionError::UnexpectedPacket(packet.fixed_header().packet_type.control_type))
+            }
+            VariablePacket::ConnackPacket(..) |
+            VariablePacket::SubackPacket(..) |
+            VariablePacket::UnsubackPacket(..) |
+            VariablePacket::PingrespPacket(..) => Ok(()),
+        }
+    }
+
+    /// Sends the in-flight messages again, as required after reconnecting with a session
+    ///
+    /// Unacknowledged `PUBLISH`es are sent with the DUP flag, and `PUBREL`s for the released ones.
+    pub fn resend(&mut self) {
+        let packets = self.in_flight.resend();
+        self.events.extend(packets.into_iter().map(ClientEvent::Send));
+    }
+
+    /// Forgets all messages in flight, as for a clean session
+    pub fn clear(&mut self) {
+        self.in_flight.clear();
+    }
+
+    /// Restores the messages in flight of a session loaded from a `SessionStore`
+    ///
+    /// Call `resend` once connected to send them again.
+    pub fn restore<I>(&mut self, messages: I) -> Result<(), SessionError>
+    where
+        I: IntoIterator<Item = InFlightMessage>,
+    {
+        for message in messages {
+            self.in_flight.restore(message)?;
+        }
+        Ok(())
+    }
+
+    /// Restores the received QoS 2 messages of a session loaded from a `SessionStore`, so they are
+    /// not delivered again when the server resends them
+    pub fn restore_received<I>(&mut self, pkids: I)
+    where
+        I: IntoIterator<Item = u16>,
+    {
+        for pkid in pkids {
+            self.in_flight.restore_received(pkid);
+        }
+    }
+
+    /// Next packet to send or event for the application
+    pub fn poll_event(&mut self) -> Option<ClientEvent> {
+        self.events.pop_front()
+    }
+
+    /// Whether an outgoing message with this packet identifier is waiting for acknowledgement
+    pub fn is_in_flight(&self, pkid: u16) -> bool {
+        self.in_flight.is_in_flight(pkid)
+    }
+
+    /// Packet identifiers of the outgoing messages in flight, in the order they were sent
+    pub fn in_flight(&self) -> impl Iterator<Item = u16> + '_ {
+        self.in_flight.in_flight()
+    }
+
+    fn send<P>(&mut self, packet: P)
+    where
+        VariablePacket: From<P>,
+    {
+        self.events.push_back(ClientEvent::Send(VariablePacket::new(packet)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use control::ControlType;
+    use packet::{PingreqPacket, PubackPacket, PubcompPacket, PubrecPacket, PubrelPacket, QoSWithPacketIdentifier};
+    use packet::validate::ProtocolViolation;
+    use topic_name::TopicName;
+
+    fn publish(qos: QoSWithPacketIdentifier) -> PublishPacket {
+        PublishPacket::new(TopicName::new("a/b").unwrap(), qos, "x")
+    }
+
+    fn events(session: &mut ClientSession) -> Vec<ClientEvent> {
+        let mut events = Vec::new();
+        while let Some(event) = session.poll_event() {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn test_client_session_qos1_outgoing() {
+        let mut session = ClientSession::new();
+        session.publish(publish(QoSWithPacketIdentifier::Level1(5))).unwrap();
+        assert_eq!(session.publish(publish(QoSWithPacketIdentifier::Level2(5))),
+                   Err(SessionError::PacketIdentifierInUse(5)));
+        assert_eq!(session.in_flight().collect::<Vec<_>>(), vec![5]);
+
+        // PUBREC does not acknowledge a QoS 1 message
+        assert!(session.handle(VariablePacket::new(PubrecPacket::new(5))).is_err());
+
+        session.handle(VariablePacket::new(PubackPacket::new(5))).unwrap();
+        assert_eq!(events(&mut session),
+                   vec![ClientEvent::Send(VariablePacket::new(publish(QoSWithPacketIdentifier::Level1(5)))),
+                        ClientEvent::Published(5)]);
+        assert_eq!(session.handle(VariablePacket::new(PubackPacket::new(5))),
+                   Err(SessionError::UnexpectedAcknowledgement(ControlType::PublishAcknowledgement, 5)));
+    }
+
+    #[test]
+    fn test_client_session_resend() {
+        let mut session = ClientSession::new();
+        session.publish(publish(QoSWithPacketIdentifier::Level2(1))).unwrap();
+        session.publish(publish(QoSWithPacketIdentifier::Level1(2))).unwrap();
+        session.publish(publish(QoSWithPacketIdentifier::Level0)).unwrap();
+        session.handle(VariablePacket::new(PubrecPacket::new(1))).unwrap();
+        assert_eq!(events(&mut session)[3..],
+                   [ClientEvent::Released(1), ClientEvent::Send(VariablePacket::new(PubrelPacket::new(1)))]);
+        // PUBREC sent again by the server
+        session.handle(VariablePacket::new(PubrecPacket::new(1))).unwrap();
+        assert_eq!(events(&mut session), vec![ClientEvent::Send(VariablePacket::new(PubrelPacket::new(1)))]);
+
+        session.resend();
+        let mut dup = publish(QoSWithPacketIdentifier::Level1(2));
+        dup.set_dup(true);
+        assert_eq!(events(&mut session),
+                   vec![ClientEvent::Send(VariablePacket::new(PubrelPacket::new(1))),
+                        ClientEvent::Send(VariablePacket::new(dup))]);
+
+        session.clear();
+        assert_eq!(session.in_flight().count(), 0);
+    }
+
+    #[test]
+    fn test_client_session_qos2_incoming() {
+        let mut session = ClientSession::new();
+        let message = publish(QoSWithPacketIdentifier::Level2(7));
+
+        session.handle(VariablePacket::new(message.clone())).unwrap();
+        // Sent again by the server before it got our PUBREC
+        let mut dup = message.clone();
+        dup.set_dup(true);
+        session.handle(VariablePacket::new(dup)).unwrap();
+        session.handle(VariablePacket::new(PubrelPacket::new(7))).unwrap();
+
+        assert_eq!(events(&mut session),
+                   vec![ClientEvent::Received(7),
+                        ClientEvent::Message(message.clone()),
+                        ClientEvent::Send(VariablePacket::new(PubrecPacket::new(7))),
+                        ClientEvent::Send(VariablePacket::new(PubrecPacket::new(7))),
+                        ClientEvent::ReceiveComplete(7),
+                        ClientEvent::Send(VariablePacket::new(PubcompPacket::new(7)))]);
+
+        // Received before the session was saved and restored
+        let mut session = ClientSession::new();
+        session.restore_received(vec![7]);
+        session.handle(VariablePacket::new(message)).unwrap();
+        assert_eq!(events(&mut session), vec![ClientEvent::Send(VariablePacket::new(PubrecPacket::new(7)))]);
+    }
+
+    #[test]
+    fn test_client_session_unexpected_packet() {
+        let mut session = ClientSession::new();
+        assert_eq!(session.handle(VariablePacket::new(PingreqPacket::new())),
+                   Err(SessionError::UnexpectedPacket(ControlType::PingRequest)));
+        assert_eq!(session.handle(VariablePacket::new(publish(QoSWithPacketIdentifier::Level1(0)))),
+                   Err(SessionError::Violation(ProtocolViolation::ZeroPacketIdentifier)));
+    }
+}
@@ -0,0 +1,168 @@
+//! QoS 1 and QoS 2 message flows shared by both ends of a connection
+
+use std::collections::{HashSet, VecDeque};
+
+use control::ControlType;
+use packet::{PubackPacket, PubcompPacket, PublishPacket, PubrecPacket, PubrelPacket, QoSWithPacketIdentifier,
+             VariablePacket};
+use packet::validate::ProtocolViolation;
+use session::{InFlightMessage, SessionError};
+
+#[derive(Debug, Clone)]
+enum Outgoing {
+    /// Sent `PUBLISH`, waiting for `PUBACK` or `PUBREC`
+    Publish(PublishPacket),
+    /// Sent `PUBREL`, waiting for `PUBCOMP`
+    Release,
+}
+
+/// Messages in flight in both directions
+#[derive(Debug, Clone, Default)]
+pub struct InFlight {
+    // In the order they were first sent [MQTT-4.6.0-1]
+    outgoing: VecDeque<(u16, Outgoing)>,
+    // QoS 2 messages received and not yet released
+    incoming: HashSet<u16>,
+}
+
+impl InFlight {
+    /// Keeps an outgoing QoS 1 or 2 message until it is acknowledged
+    pub fn publish(&mut self, packet: &PublishPacket) -> Result<(), SessionError> {
+        match packet.qos() {
+            QoSWithPacketIdentifier::Level0 => Ok(()),
+            QoSWithPacketIdentifier::Level1(0) | QoSWithPacketIdentifier::Level2(0) => {
+                Err(ProtocolViolation::ZeroPacketIdentifier.into())
+            }
+            QoSWithPacketIdentifier::Level1(pkid) | QoSWithPacketIdentifier::Level2(pkid) => {
+                if self.is_in_flight(pkid) {
+                    return Err(SessionError::PacketIdentifierInUse(pkid));
+                }
+                self.outgoing.push_back((pkid, Outgoing::Publish(packet.clone())));
+                Ok(())
+            }
+        }
+    }
+
+    /// Completes a QoS 1 message
+    pub fn puback(&mut self, pkid: u16) -> Result<(), SessionError> {
+        match self.position(pkid) {
+            Some(idx) if self.awaits(idx, QoSWithPacketIdentifier::Level1(pkid)) => {
+                self.outgoing.remove(idx);
+                Ok(())
+            }
+            _ => Err(SessionError::UnexpectedAcknowledgement(ControlType::PublishAcknowledgement, pkid)),
+        }
+    }
+
+    /// Releases a QoS 2 message, returning whether it was waiting for `PUBREC` and the `PUBREL` to send
+    pub fn pubrec(&mut self, pkid: u16) -> Result<(bool, PubrelPacket), SessionError> {
+        match self.position(pkid) {
+            Some(idx) if self.awaits(idx, QoSWithPacketIdentifier::Level2(pkid)) => {
+                self.outgoing[idx].1 = Outgoing::Release;
+                Ok((true, PubrelPacket::new(pkid)))
+            }
+            // PUBREC sent again since our PUBREL was lost
+            Some(idx) if !self.awaits_publish(idx) => Ok((false, PubrelPacket::new(pkid))),
+            _ => Err(SessionError::UnexpectedAcknowledgement(ControlType::PublishReceived, pkid)),
+        }
+    }
+
+    /// Completes a QoS 2 message
+    pub fn pubcomp(&mut self, pkid: u16) -> Result<(), SessionError> {
+        match self.position(pkid) {
+            Some(idx) if !self.awaits_publish(idx) => {
+                self.outgoing.remove(idx);
+                Ok(())
+            }
+            _ => Err(SessionError::UnexpectedAcknowledgement(ControlType::PublishComplete, pkid)),
+        }
+    }
+
+    /// Receives a message, returning whether to deliver it and the acknowledgement to send
+    ///
+    /// A QoS 2 message received again before it is released is acknowledged but not delivered
+    /// twice.
+    pub fn receive(&mut self, packet: &PublishPacket) -> (bool, Option<VariablePacket>) {
+        match packet.qos() {
+            QoSWithPacketIdentifier::Level0 => (true, None),
+            QoSWithPacketIdentifier::Level1(pkid) => (true, Some(VariablePacket::new(PubackPacket::new(pkid)))),
+            QoSWithPacketIdentifier::Level2(pkid) => {
+                (self.incoming.insert(pkid), Some(VariablePacket::new(PubrecPacket::new(pkid))))
+            }
+        }
+    }
+
+    /// Releases a received QoS 2 message, returning whether it was waiting for `PUBREL` and the
+    /// `PUBCOMP` to send
+    ///
+    /// Unknown identifiers are answered too, since our earlier `PUBCOMP` may have been lost.
+    pub fn pubrel(&mut self, pkid: u16) -> (bool, PubcompPacket) {
+        (self.incoming.remove(&pkid), PubcompPacket::new(pkid))
+    }
+
+    /// Packets to send again after reconnecting with a session
+    ///
+    /// Unacknowledged `PUBLISH`es are sent with the DUP flag, and `PUBREL`s for the released ones.
+    pub fn resend(&self) -> Vec<VariablePacket> {
+        self.outgoing
+            .iter()
+            .map(|&(pkid, ref outgoing)| match *outgoing {
+                     Outgoing::Publish(ref publish) => {
+                         let mut publish = publish.clone();
+                         publish.set_dup(true);
+                         VariablePacket::new(publish)
+                     }
+                     Outgoing::Release => VariablePacket::new(PubrelPacket::new(pkid)),
+                 })
+            .collect()
+    }
+
+    /// Adds outgoing messages loaded from a `SessionStore`
+    pub fn restore(&mut self, message: InFlightMessage) -> Result<(), SessionError> {
+        match message {
+            InFlightMessage::Publish(ref publish) => self.publish(publish),
+            InFlightMessage::Release(0) => Err(ProtocolViolation::ZeroPacketIdentifier.into()),
+            InFlightMessage::Release(pkid) if self.is_in_flight(pkid) => Err(SessionError::PacketIdentifierInUse(pkid)),
+            InFlightMessage::Release(pkid) => {
+                self.outgoing.push_back((pkid, Outgoing::Release));
+                Ok(())
+            }
+        }
+    }
+
+    /// Adds a received QoS 2 message loaded from a `SessionStore`, so it is not delivered again
+    pub fn restore_received(&mut self, pkid: u16) {
+        self.incoming.insert(pkid);
+    }
+
+    pub fn clear(&mut self) {
+        self.outgoing.clear();
+        self.incoming.clear();
+    }
+
+    pub fn is_in_flight(&self, pkid: u16) -> bool {
+        self.position(pkid).is_some()
+    }
+
+    pub fn in_flight(&self) -> impl Iterator<Item = u16> + '_ {
+        self.outgoing.iter().map(|&(pkid, _)| pkid)
+    }
+
+    fn position(&self, pkid: u16) -> Option<usize> {
+        self.outgoing.iter().position(|&(id, _)| id == pkid)
+    }
+
+    fn awaits(&self, idx: usize, qos: QoSWithPacketIdentifier) -> bool {
+        match self.outgoing[idx].1 {
+            Outgoing::Publish(ref publish) => publish.qos() == qos,
+            Outgoing::Release => false,
+        }
+    }
+
+    fn awaits_publish(&self, idx: usize) -> bool {
+        match self.outgoing[idx].1 {
+            Outgoing::Publish(..) => true,
+            Outgoing::Release => false,
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Protocol state machines without I/O
+//!
+//! Sessions consume decoded packets and produce the packets to send and the events for the
+//! application. Reading from and writing to the network is left to the caller, so the same session
+//! works with blocking sockets, an event loop or a test harness.
+
+use std::error::Error;
+use std::fmt;
+
+use control::ControlType;
+use packet::ProtocolViolation;
+
+pub use self::client::{ClientEvent, ClientSession};
+pub use self::packet_id::PacketIdAllocator;
+pub use self::server::{CloseReason, ServerEvent, ServerSession};
+pub use self::store::{FileSessionStore, InFlightMessage, MemorySessionStore, SessionStore, StoredSession};
+
+pub mod client;
+mod inflight;
+pub mod packet_id;
+pub mod server;
+pub mod store;
+
+/// Errors of a session, after which the connection should be closed
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum SessionError {
+    /// The packet identifier is already used by an in-flight message
+    PacketIdentifierInUse(u16),
+    /// All packet identifiers are used by in-flight messages
+    PacketIdentifiersExhausted,
+    /// An acknowledgement for a packet identifier that is not waiting for it
+    UnexpectedAcknowledgement(ControlType, u16),
+    /// A packet that is not allowed in the current state
+    UnexpectedPacket(ControlType),
+    /// A packet violating the protocol
+    Violation(ProtocolViolation),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SessionError::PacketIdentifierInUse(pkid) => write!(f, "Packet identifier {} is in use", pkid),
+            SessionError::PacketIdentifiersExhausted => write!(f, "All packet identifiers are in use"),
+            SessionError::UnexpectedAcknowledgement(ty, pkid) => {
+                write!(f, "Unexpected {:?} for packet identifier {}", ty, pkid)
+            }
+            SessionError::UnexpectedPacket(ty) => write!(f, "Unexpected {:?} packet", ty),
+            SessionError::Violation(ref err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for SessionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            SessionError::Violation(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ProtocolViolation> for SessionError {
+    fn from(err: ProtocolViolation) -> SessionError {
+        SessionError::Violation(err)
+    }
+}
@@ -0,0 +1,145 @@
+//! Packet identifiers for outgoing packets
+//!
+//! ```rust
+//! use mqtt::TopicFilter;
+//! use mqtt::packet::{SubackPacket, SubscribePacket, VariablePacket};
+//! use mqtt::packet::suback::SubscribeReturnCode;
+//! use mqtt::QualityOfService;
+//! use mqtt::session::PacketIdAllocator;
+//!
+//! let mut ids = PacketIdAllocator::new();
+//! let pkid = ids.allocate().unwrap();
+//! let subscribe = SubscribePacket::new(pkid, vec![(TopicFilter::new("a/+").unwrap(), QualityOfService::Level1)]);
+//! assert!(ids.is_in_flight(pkid));
+//!
+//! let suback = SubackPacket::new(pkid, vec![SubscribeReturnCode::MaximumQoSLevel1]);
+//! assert_eq!(ids.acknowledge(&VariablePacket::new(suback)), Some(pkid));
+//! assert!(!ids.is_in_flight(pkid));
+//! ```
+
+use std::collections::HashSet;
+
+use packet::VariablePacket;
+
+/// Allocator of non-zero packet identifiers that are not in flight
+///
+/// Identifiers are handed out in increasing order, wrapping from 65535 to 1, so a released
+/// identifier is not reused right away.
+#[derive(Debug, Clone)]
+pub struct PacketIdAllocator {
+    next: u16,
+    in_flight: HashSet<u16>,
+}
+
+impl PacketIdAllocator {
+    pub fn new() -> PacketIdAllocator {
+        PacketIdAllocator {
+            next: 1,
+            in_flight: HashSet::new(),
+        }
+    }
+
+    /// Next free identifier, or `None` if all 65535 are in flight
+    pub fn allocate(&mut self) -> Option<u16> {
+        if self.in_flight.len() == u16::MAX as usize {
+            return None;
+        }
+
+        loop {
+            let pkid = self.next;
+            self.next = if pkid == u16::MAX { 1 } else { pkid + 1 };
+            if self.in_flight.insert(pkid) {
+                return Some(pkid);
+            }
+        }
+    }
+
+    /// Marks an identifier chosen elsewhere as in flight, such as one restored with a session
+    ///
+    /// Returns `false` if it is 0 or already in flight.
+    pub fn reserve(&mut self, pkid: u16) -> bool {
+        pkid != 0 && self.in_flight.insert(pkid)
+    }
+
+    /// Frees an identifier, returning whether it was in flight
+    pub fn release(&mut self, pkid: u16) -> bool {
+        self.in_flight.remove(&pkid)
+    }
+
+    /// Frees the identifier completed by a `PUBACK`, `PUBCOMP`, `SUBACK` or `UNSUBACK`
+    ///
+    /// Returns the released identifier. `PUBREC` does not complete a QoS 2 flow, so it is ignored
+    /// like any other packet.
+    pub fn acknowledge(&mut self, packet: &VariablePacket) -> Option<u16> {
+        let pkid = match *packet {
+            VariablePacket::PubackPacket(ref pk) => pk.packet_identifier(),
+            VariablePacket::PubcompPacket(ref pk) => pk.packet_identifier(),
+            VariablePacket::SubackPacket(ref pk) => pk.packet_identifier(),
+            VariablePacket::UnsubackPacket(ref pk) => pk.packet_identifier(),
+            _ => return None,
+        };
+
+        if self.release(pkid) { Some(pkid) } else { None }
+    }
+
+    pub fn is_in_flight(&self, pkid: u16) -> bool {
+        self.in_flight.contains(&pkid)
+    }
+
+    /// Number of identifiers in flight
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Frees all identifiers
+    pub fn clear(&mut self) {
+        self.in_flight.clear();
+    }
+}
+
+impl Default for PacketIdAllocator {
+    fn default() -> PacketIdAllocator {
+        PacketIdAllocator::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use packet::{PubackPacket, PubrecPacket, UnsubackPacket};
+
+    #[test]
+    fn test_packet_id_allocator_skips_in_flight() {
+        let mut ids = PacketIdAllocator::new();
+        assert!(ids.reserve(2));
+        assert!(!ids.reserve(2));
+        assert!(!ids.reserve(0));
+
+        assert_eq!(ids.allocate(), Some(1));
+        assert_eq!(ids.allocate(), Some(3));
+        assert_eq!(ids.in_flight(), 3);
+
+        assert_eq!(ids.acknowledge(&VariablePacket::new(PubrecPacket::new(1))), None);
+        assert_eq!(ids.acknowledge(&VariablePacket::new(PubackPacket::new(1))), Some(1));
+        assert_eq!(ids.acknowledge(&VariablePacket::new(UnsubackPacket::new(3))), Some(3));
+        assert_eq!(ids.acknowledge(&VariablePacket::new(UnsubackPacket::new(3))), None);
+        assert!(ids.release(2));
+        assert_eq!(ids.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_packet_id_allocator_wraps() {
+        let mut ids = PacketIdAllocator::new();
+        for expected in 1..=u16::MAX {
+            assert_eq!(ids.allocate(), Some(expected));
+        }
+        assert_eq!(ids.allocate(), None);
+
+        ids.release(7);
+        ids.release(3);
+        assert_eq!(ids.allocate(), Some(3));
+        assert_eq!(ids.allocate(), Some(7));
+        assert_eq!(ids.allocate(), None);
+    }
+}
@@ -0,0 +1,516 @@
+//! Server side of a client connection
+//!
+//! ```rust
+//! use std::time::{Duration, Instant};
+//!
+//! use mqtt::packet::{ConnackPacket, ConnectPacket, VariablePacket};
+//! use mqtt::control::ConnectReturnCode;
+//! use mqtt::session::{CloseReason, ServerEvent, ServerSession};
+//!
+//! let now = Instant::now();
+//! let mut connect = ConnectPacket::new("MQTT", "client");
+//! connect.set_keep_alive(10);
+//!
+//! let mut session = ServerSession::new();
+//! session.handle(VariablePacket::new(connect.clone()), now).unwrap();
+//! assert_eq!(session.poll_event(), Some(ServerEvent::Connect(connect)));
+//!
+//! session.accept(false);
+//! let connack = ConnackPacket::new(false, ConnectReturnCode::ConnectionAccepted);
+//! assert_eq!(session.poll_event(), Some(ServerEvent::Send(VariablePacket::new(connack))));
+//!
+//! // Nothing received for one and a half keep alive intervals
+//! assert_eq!(session.next_timeout(), Some(now + Duration::from_secs(15)));
+//! session.handle_timeout(now + Duration::from_secs(15));
+//! assert_eq!(session.poll_event(), Some(ServerEvent::Close(CloseReason::KeepAliveTimeout)));
+//! ```
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use control::variable_header::ConnectReturnCode;
+use packet::{ConnackPacket, ConnectPacket, PingrespPacket, PublishPacket, QoSWithPacketIdentifier, SubackPacket,
+             SubscribePacket, UnsubackPacket, UnsubscribePacket, VariablePacket};
+use packet::ProtocolViolation;
+use packet::suback::SubscribeReturnCode;
+use qos::QualityOfService;
+use session::{InFlightMessage, PacketIdAllocator, SessionError};
+use session::inflight::InFlight;
+
+/// Output of a `ServerSession`
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum ServerEvent {
+    /// Packet to send to the client
+    Send(VariablePacket),
+    /// The client asks to connect, answer with `ServerSession::accept` or `ServerSession::reject`
+    Connect(ConnectPacket),
+    /// Message from the client, or its will, to publish to the subscribers
+    ///
+    /// The packet identifier is the one of the client, or 0 for a will.
+    Publish(PublishPacket),
+    /// Subscriptions to add, answer with `ServerSession::suback`
+    Subscribe(SubscribePacket),
+    /// Subscriptions to remove, already answered with `UNSUBACK`
+    Unsubscribe(UnsubscribePacket),
+    /// The outgoing QoS 1 or 2 message with this packet identifier is complete
+    ///
+    /// Forget it with `SessionStore::remove_in_flight`.
+    Published(u16),
+    /// The outgoing QoS 2 message with this packet identifier was received by the client and is
+    /// released with the `PUBREL` that follows
+    ///
+    /// Replace it with `SessionStore::save_pubrel`, so that `PUBREL` is what is sent again.
+    Released(u16),
+    /// A QoS 2 message with this packet identifier was received and is not published again until
+    /// released by the client, answered by the `PUBREC` that follows
+    ///
+    /// Save it with `SessionStore::save_received`.
+    Received(u16),
+    /// The received QoS 2 message with this packet identifier was released by the client
+    ///
+    /// Forget it with `SessionStore::remove_received`.
+    ReceiveComplete(u16),
+    /// The network connection should be closed
+    Close(CloseReason),
+}
+
+/// Why a `ServerSession` closes the connection
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum CloseReason {
+    /// The client sent `DISCONNECT`
+    Disconnect,
+    /// Nothing was received within one and a half times the keep alive interval [MQTT-3.1.2-24]
+    KeepAliveTimeout,
+    /// The connection was refused with this `CONNACK` return code
+    Rejected(ConnectReturnCode),
+    /// The client sent a packet it should not have
+    Error(SessionError),
+}
+
+/// Server session state of one client
+///
+/// A session starts waiting for `CONNECT` and goes back to waiting when the connection is closed,
+/// so a session that is not cleaned can be kept for the next connection of the same client.
+#[derive(Debug, Clone, Default)]
+pub struct ServerSession {
+    connected: bool,
+    keep_alive: Option<Duration>,
+    deadline: Option<Instant>,
+    will: Option<PublishPacket>,
+    packet_ids: PacketIdAllocator,
+    in_flight: InFlight,
+    events: VecDeque<ServerEvent>,
+}
+
+impl ServerSession {
+    pub fn new() -> ServerSession {
+        ServerSession::default()
+    }
+
+    /// Handles a packet received from the client at `now`
+    ///
+    /// On error the connection is closed: the will is published and a `ServerEvent::Close` is
+    /// emitted.
+    pub fn handle(&mut self, packet: VariablePacket, now: Instant) -> Result<(), SessionError> {
+        match self.handle_packet(packet, now) {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                self.close(CloseReason::Error(err));
+                Err(err)
+            }
+        }
+    }
+
+    /// Accepts the connection requested by the last `ServerEvent::Connect`
+    ///
+    /// With `session_present`, call `resend` afterwards to send the messages still in flight.
+    /// Otherwise the messages in flight are forgotten.
+    pub fn accept(&mut self, session_present: bool) {
+        if !session_present {
+            self.clear();
+        }
+        self.send(ConnackPacket::new(session_present, ConnectReturnCode::ConnectionAccepted));
+    }
+
+    /// Refuses the connection requested by the last `ServerEvent::Connect` and closes it
+    ///
+    /// The will is discarded, since the connection was never established.
+    pub fn reject(&mut self, code: ConnectReturnCode) {
+        // If a server sends a CONNACK packet containing a non-zero return code it MUST set
+        // Session Present to 0 [MQTT-3.2.2-4].
+        self.send(ConnackPacket::new(false, code));
+        self.will = None;
+        self.close(CloseReason::Rejected(code));
+    }
+
+    /// Answers the `SUBSCRIBE` with packet identifier `pkid`
+    pub fn suback(&mut self, pkid: u16, return_codes: Vec<SubscribeReturnCode>) {
+        self.send(SubackPacket::new(pkid, return_codes));
+    }
+
+    /// Sends a message to the client, keeping QoS 1 and 2 messages in flight until acknowledged
+    ///
+    /// The packet identifier is replaced by a free one, which is returned.
+    pub fn publish(&mut self, mut packet: PublishPacket) -> Result<Option<u16>, SessionError> {
+        let qos = QualityOfService::from(packet.qos());
+        let pkid = match qos {
+            QualityOfService::Level0 => None,
+            _ => Some(self.packet_ids.allocate().ok_or(SessionError::PacketIdentifiersExhausted)?),
+        };
+
+        packet.set_qos(QoSWithPacketIdentifier::new(qos, pkid.unwrap_or(0)));
+        packet.set_dup(false);
+        if let Err(err) = self.in_flight.publish(&packet) {
+            if let Some(pkid) = pkid {
+                self.packet_ids.release(pkid);
+            }
+            return Err(err);
+        }
+        self.send(packet);
+        Ok(pkid)
+    }
+
+    /// Sends the in-flight messages again, after accepting a connection with a session present
+    pub fn resend(&mut self) {
+        let packets = self.in_flight.resend();
+        self.events.extend(packets.into_iter().map(ServerEvent::Send));
+    }
+
+    /// Forgets all messages in flight, as for a clean session
+    pub fn clear(&mut self) {
+        self.in_flight.clear();
+        self.packet_ids.clear();
+    }
+
+    /// Restores the messages in flight of a session loaded from a `SessionStore`
+    ///
+    /// Their packet identifiers are not allocated to new messages until acknowledged.
+    pub fn restore<I>(&mut self, messages: I) -> Result<(), SessionError>
+    where
+        I: IntoIterator<Item = InFlightMessage>,
+    {
+        for message in messages {
+            let pkid = message.packet_identifier();
+            self.in_flight.restore(message)?;
+            self.packet_ids.reserve(pkid);
+        }
+        Ok(())
+    }
+
+    /// Restores the received QoS 2 messages of a session loaded from a `SessionStore`, so they are
+    /// not published again when the client resends them
+    pub fn restore_received<I>(&mut self, pkids: I)
+    where
+        I: IntoIterator<Item = u16>,
+    {
+        for pkid in pkids {
+            self.in_flight.restore_received(pkid);
+        }
+    }
+
+    /// The network connection was lost without `DISCONNECT`
+    ///
+    /// Publishes the will, if any. No `ServerEvent::Close` is emitted.
+    pub fn connection_lost(&mut self) {
+        self.publish_will();
+        self.disconnected();
+    }
+
+    /// Time at which the connection times out if nothing is received
+    pub fn next_timeout(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Closes the connection if its keep alive deadline has passed at `now`
+    pub fn handle_timeout(&mut self, now: Instant) {
+        if self.deadline.is_some_and(|deadline| now >= deadline) {
+            self.close(CloseReason::KeepAliveTimeout);
+        }
+    }
+
+    /// Next packet to send or event for the broker
+    pub fn poll_event(&mut self) -> Option<ServerEvent> {
+        self.events.pop_front()
+    }
+
+    /// Whether the client has connected and the connection is not closed
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Whether an outgoing message with this packet identifier is waiting for acknowledgement
+    pub fn is_in_flight(&self, pkid: u16) -> bool {
+        self.in_flight.is_in_flight(pkid)
+    }
+
+    fn handle_packet(&mut self, packet: VariablePacket, now: Instant) -> Result<(), SessionError> {
+        let control_type = packet.fixed_header().packet_type.control_type;
+        if let Err(violation) = packet.validate() {
+            // The Server MUST respond to the CONNECT Packet with a CONNACK return code 0x01 if it does
+            // not support the level of the MQTT protocol requested by the Client [MQTT-3.1.2-2].
+            if violation == ProtocolViolation::ProtocolLevel && !self.connected {
+                self.send(ConnackPacket::new(false, ConnectReturnCode::UnacceptableProtocolVersion));
+            }
+            return Err(violation.into());
+        }
+
+        match packet {
+            VariablePacket::ConnectPacket(connect) => {
+                // A client can only send the CONNECT packet once over a network connection
+                // [MQTT-3.1.0-2].
+                if self.connected {
+                    return Err(SessionError::UnexpectedPacket(control_type));
+                }
+                self.connect(&connect, now);
+                self.events.push_back(ServerEvent::Connect(connect));
+                return Ok(());
+            }
+            // The first packet sent from the client to the server MUST be a CONNECT packet
+            // [MQTT-3.1.0-1].
+            _ if !self.connected => return Err(SessionError::UnexpectedPacket(control_type)),
+            _ => {}
+        }
+
+        self.deadline = self.keep_alive.map(|keep_alive| now + keep_alive);
+
+        match packet {
+            VariablePacket::PublishPacket(publish) => {
+                let (deliver, ack) = self.in_flight.receive(&publish);
+                if deliver {
+                    if let QoSWithPacketIdentifier::Level2(pkid) = publish.qos() {
+                        self.events.push_back(ServerEvent::Received(pkid));
+                    }
+                    self.events.push_back(ServerEvent::Publish(publish));
+                }
+                self.events.extend(ack.map(ServerEvent::Send));
+            }
+            VariablePacket::PubackPacket(ref ack) => {
+                self.in_flight.puback(ack.packet_identifier())?;
+                self.complete(ack.packet_identifier());
+            }
+            VariablePacket::PubrecPacket(ref ack) => {
+                let (released, pubrel) = self.in_flight.pubrec(ack.packet_identifier())?;
+                if released {
+                    self.events.push_back(ServerEvent::Released(ack.packet_identifier()));
+                }
+                self.send(pubrel);
+            }
+            VariablePacket::PubcompPacket(ref ack) => {
+                self.in_flight.pubcomp(ack.packet_identifier())?;
+                self.complete(ack.packet_identifier());
+            }
+            VariablePacket::PubrelPacket(ref rel) => {
+                let (complete, pubcomp) = self.in_flight.pubrel(rel.packet_identifier());
+                if complete {
+                    self.events.push_back(ServerEvent::ReceiveComplete(rel.packet_identifier()));
+                }
+                self.send(pubcomp);
+            }
+            VariablePacket::SubscribePacket(subscribe) => {
+                self.events.push_back(ServerEvent::Subscribe(subscribe));
+            }
+            VariablePacket::UnsubscribePacket(unsubscribe) => {
+                self.send(UnsubackPacket::new(unsubscribe.packet_identifier()));
+                self.events.push_back(ServerEvent::Unsubscribe(unsubscribe));
+            }
+            VariablePacket::PingreqPacket(..) => self.send(PingrespPacket::new()),
+            VariablePacket::DisconnectPacket(..) => {
+                // On receipt of DISCONNECT the Server MUST discard any Will Message associated
+                // with the current connection without publishing it [MQTT-3.14.4-3].
+                self.will = None;
+                self.close(CloseReason::Disconnect);
+            }
+            VariablePacket::ConnectPacket(..) |
+            VariablePacket::ConnackPacket(..) |
+            VariablePacket::SubackPacket(..) |
+            VariablePacket::UnsubackPacket(..) |
+            VariablePacket::PingrespPacket(..) => return Err(SessionError::UnexpectedPacket(control_type)),
+        }
+        Ok(())
+    }
+
+    fn connect(&mut self, connect: &ConnectPacket, now: Instant) {
+        self.connected = true;
+        self.keep_alive = match connect.keep_alive() {
+            0 => None,
+            secs => Some(Duration::from_millis(u64::from(secs) * 1500)),
+        };
+        self.deadline = self.keep_alive.map(|keep_alive| now + keep_alive);
+        self.will = connect.will().cloned().map(PublishPacket::from);
+    }
+
+    fn complete(&mut self, pkid: u16) {
+        self.packet_ids.release(pkid);
+        self.events.push_back(ServerEvent::Published(pkid));
+    }
+
+    fn close(&mut self, reason: CloseReason) {
+        self.publish_will();
+        self.disconnected();
+        self.events.push_back(ServerEvent::Close(reason));
+    }
+
+    fn publish_will(&mut self) {
+        if let Some(will) = self.will.take() {
+            self.events.push_back(ServerEvent::Publish(will));
+        }
+    }
+
+    fn disconnected(&mut self) {
+        self.connected = false;
+        self.keep_alive = None;
+        self.deadline = None;
+    }
+
+    fn send<P>(&mut self, packet: P)
+    where
+        VariablePacket: From<P>,
+    {
+        self.events.push_back(ServerEvent::Send(VariablePacket::new(packet)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use control::ControlType;
+    use packet::{DisconnectPacket, LastWill, PingreqPacket, PubackPacket, PubcompPacket, PubrecPacket, PubrelPacket};
+    use topic_name::TopicName;
+
+    fn events(session: &mut ServerSession) -> Vec<ServerEvent> {
+        let mut events = Vec::new();
+        while let Some(event) = session.poll_event() {
+            events.push(event);
+        }
+        events
+    }
+
+    fn connected(keep_alive: u16, now: Instant) -> ServerSession {
+        let mut connect = ConnectPacket::new("MQTT", "client");
+        connect.set_keep_alive(keep_alive);
+        let mut will = LastWill::new(TopicName::new("will").unwrap(), QualityOfService::Level1, "bye");
+        will.set_retain(true);
+        connect.set_will(Some(will));
+
+        let mut session = ServerSession::new();
+        session.handle(VariablePacket::new(connect), now).unwrap();
+        session.accept(false);
+        events(&mut session);
+        session
+    }
+
+    fn will() -> PublishPacket {
+        let mut will = PublishPacket::new(TopicName::new("will").unwrap(), QoSWithPacketIdentifier::Level1(0), "bye");
+        will.set_retain(true);
+        will
+    }
+
+    #[test]
+    fn test_server_session_connect_first() {
+        let now = Instant::now();
+        let mut session = ServerSession::new();
+        assert_eq!(session.handle(VariablePacket::new(PingreqPacket::new()), now),
+                   Err(SessionError::UnexpectedPacket(ControlType::PingRequest)));
+        assert_eq!(events(&mut session),
+                   vec![ServerEvent::Close(CloseReason::Error(SessionError::UnexpectedPacket(ControlType::PingRequest)))]);
+
+        let mut session = connected(0, now);
+        assert!(session.is_connected());
+        assert_eq!(session.next_timeout(), None);
+        assert_eq!(session.handle(VariablePacket::new(ConnectPacket::new("MQTT", "client")), now),
+                   Err(SessionError::UnexpectedPacket(ControlType::Connect)));
+        assert_eq!(events(&mut session),
+                   vec![ServerEvent::Publish(will()),
+                        ServerEvent::Close(CloseReason::Error(SessionError::UnexpectedPacket(ControlType::Connect)))]);
+        assert!(!session.is_connected());
+    }
+
+    #[test]
+    fn test_server_session_unacceptable_protocol_version() {
+        let now = Instant::now();
+        let mut session = ServerSession::new();
+        let err = SessionError::Violation(ProtocolViolation::ProtocolLevel);
+        assert_eq!(session.handle(VariablePacket::new(ConnectPacket::with_level("MQTT", "client", 3)), now),
+                   Err(err));
+        let connack = ConnackPacket::new(false, ConnectReturnCode::UnacceptableProtocolVersion);
+        assert_eq!(events(&mut session),
+                   vec![ServerEvent::Send(VariablePacket::new(connack)), ServerEvent::Close(CloseReason::Error(err))]);
+        assert!(!session.is_connected());
+    }
+
+    #[test]
+    fn test_server_session_keep_alive() {
+        let now = Instant::now();
+        let mut session = connected(2, now);
+        assert_eq!(session.next_timeout(), Some(now + Duration::from_secs(3)));
+
+        let later = now + Duration::from_secs(2);
+        session.handle(VariablePacket::new(PingreqPacket::new()), later).unwrap();
+        assert_eq!(events(&mut session),
+                   vec![ServerEvent::Send(VariablePacket::new(PingrespPacket::new()))]);
+        assert_eq!(session.next_timeout(), Some(later + Duration::from_secs(3)));
+
+        session.handle_timeout(now + Duration::from_secs(4));
+        assert!(events(&mut session).is_empty());
+        session.handle_timeout(now + Duration::from_secs(5));
+        assert_eq!(events(&mut session),
+                   vec![ServerEvent::Publish(will()), ServerEvent::Close(CloseReason::KeepAliveTimeout)]);
+        assert_eq!(session.next_timeout(), None);
+    }
+
+    #[test]
+    fn test_server_session_disconnect_discards_will() {
+        let now = Instant::now();
+        let mut session = connected(10, now);
+        session.handle(VariablePacket::new(DisconnectPacket::new()), now).unwrap();
+        assert_eq!(events(&mut session), vec![ServerEvent::Close(CloseReason::Disconnect)]);
+
+        session.connection_lost();
+        assert!(events(&mut session).is_empty());
+    }
+
+    #[test]
+    fn test_server_session_qos2_incoming() {
+        let now = Instant::now();
+        let mut session = connected(0, now);
+        let message = PublishPacket::new(TopicName::new("a/b").unwrap(), QoSWithPacketIdentifier::Level2(3), "x");
+
+        session.handle(VariablePacket::new(message.clone()), now).unwrap();
+        session.handle(VariablePacket::new(message.clone()), now).unwrap();
+        session.handle(VariablePacket::new(PubrelPacket::new(3)), now).unwrap();
+        assert_eq!(events(&mut session),
+                   vec![ServerEvent::Received(3),
+                        ServerEvent::Publish(message.clone()),
+                        ServerEvent::Send(VariablePacket::new(PubrecPacket::new(3))),
+                        ServerEvent::Send(VariablePacket::new(PubrecPacket::new(3))),
+                        ServerEvent::ReceiveComplete(3),
+                        ServerEvent::Send(VariablePacket::new(PubcompPacket::new(3)))]);
+
+        // Received before the session was saved and restored
+        let mut session = connected(0, now);
+        session.restore_received(vec![3]);
+        session.handle(VariablePacket::new(message), now).unwrap();
+        assert_eq!(events(&mut session), vec![ServerEvent::Send(VariablePacket::new(PubrecPacket::new(3)))]);
+    }
+
+    #[test]
+    fn test_server_session_outgoing() {
+        let now = Instant::now();
+        let mut session = connected(0, now);
+        let message = PublishPacket::new(TopicName::new("a/b").unwrap(), QoSWithPacketIdentifier::Level1(9), "x");
+
+        assert_eq!(session.publish(message.clone()), Ok(Some(1)));
+        assert_eq!(session.publish(will()), Ok(Some(2)));
+        session.handle(VariablePacket::new(PubackPacket::new(1)), now).unwrap();
+        assert!(!session.is_in_flight(1));
+        assert!(session.is_in_flight(2));
+
+        let mut sent = message;
+        sent.set_qos(QoSWithPacketIdentifier::Level1(1));
+        assert_eq!(events(&mut session)[0], ServerEvent::Send(VariablePacket::new(sent)));
+
+        assert_eq!(session.handle(VariablePacket::new(PubackPacket::new(1)), now),
+                   Err(SessionError::UnexpectedAcknowledgement(ControlType::PublishAcknowledgement, 1)));
+    }
+}
@@ -0,0 +1,603 @@
+//! Session state kept across connections
+//!
+//! A client connecting without clean session resumes its subscriptions and the QoS 1 and 2
+//! messages that were not acknowledged [MQTT-3.1.2-4]. A `SessionStore` keeps that state per client
+//! identifier, in memory with `MemorySessionStore`, or in a file surviving restarts with
+//! `FileSessionStore`. The `ClientEvent`s and `ServerEvent`s of a session tell which change to
+//! save, and `Broker::with_store` keeps the sessions of its clients in a store.
+//!
+//! ```rust
+//! use mqtt::{QualityOfService, TopicFilter, TopicName};
+//! use mqtt::packet::{PublishPacket, QoSWithPacketIdentifier};
+//! use mqtt::session::{ClientSession, InFlightMessage, MemorySessionStore, SessionStore};
+//!
+//! let mut store = MemorySessionStore::new();
+//! let publish = PublishPacket::new(TopicName::new("a/b").unwrap(), QoSWithPacketIdentifier::Level2(1), "x");
+//! store.save_publish("client", &publish).unwrap();
+//! store.save_pubrel("client", 1).unwrap();
+//! store.save_subscription("client", &TopicFilter::new("a/#").unwrap(), QualityOfService::Level1).unwrap();
+//!
+//! let stored = store.load("client").unwrap().unwrap();
+//! assert_eq!(stored.in_flight, vec![InFlightMessage::Release(1)]);
+//!
+//! let mut session = ClientSession::new();
+//! session.restore(stored.in_flight).unwrap();
+//! assert!(session.is_in_flight(1));
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use encodable::{Decodable, Encodable};
+use packet::PublishPacket;
+use qos::QualityOfService;
+use topic_filter::{TopicFilter, TopicFilterRef};
+
+/// Outgoing QoS 1 or 2 message waiting for acknowledgement
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum InFlightMessage {
+    /// `PUBLISH` waiting for `PUBACK` or `PUBREC`
+    Publish(PublishPacket),
+    /// `PUBREL` with this packet identifier waiting for `PUBCOMP`
+    Release(u16),
+}
+
+impl InFlightMessage {
+    pub fn packet_identifier(&self) -> u16 {
+        match *self {
+            InFlightMessage::Publish(ref publish) => publish.packet_identifier().unwrap_or(0),
+            InFlightMessage::Release(pkid) => pkid,
+        }
+    }
+}
+
+/// State of one client session
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct StoredSession {
+    /// Subscriptions with their maximum QoS
+    pub subscriptions: Vec<(TopicFilter, QualityOfService)>,
+    /// Messages in flight, in the order they were first sent
+    pub in_flight: Vec<InFlightMessage>,
+    /// Packet identifiers of the QoS 2 messages received and not released yet
+    pub received: Vec<u16>,
+}
+
+impl StoredSession {
+    fn save_publish(&mut self, packet: &PublishPacket) {
+        let pkid = packet.packet_identifier().unwrap_or(0);
+        match self.in_flight.iter().position(|message| message.packet_identifier() == pkid) {
+            Some(idx) => self.in_flight[idx] = InFlightMessage::Publish(packet.clone()),
+            None => self.in_flight.push(InFlightMessage::Publish(packet.clone())),
+        }
+    }
+
+    fn save_pubrel(&mut self, pkid: u16) {
+        match self.in_flight.iter().position(|message| message.packet_identifier() == pkid) {
+            Some(idx) => self.in_flight[idx] = InFlightMessage::Release(pkid),
+            None => self.in_flight.push(InFlightMessage::Release(pkid)),
+        }
+    }
+
+    fn remove_in_flight(&mut self, pkid: u16) {
+        self.in_flight.retain(|message| message.packet_identifier() != pkid);
+    }
+
+    fn save_received(&mut self, pkid: u16) {
+        if !self.received.contains(&pkid) {
+            self.received.push(pkid);
+        }
+    }
+
+    fn remove_received(&mut self, pkid: u16) {
+        self.received.retain(|&received| received != pkid);
+    }
+
+    fn save_subscription(&mut self, filter: &TopicFilterRef, qos: QualityOfService) {
+        match self.subscriptions.iter().position(|(subscribed, _)| **subscribed == *filter) {
+            Some(idx) => self.subscriptions[idx].1 = qos,
+            None => self.subscriptions.push((owned_filter(filter), qos)),
+        }
+    }
+
+    fn remove_subscription(&mut self, filter: &TopicFilterRef) {
+        self.subscriptions.retain(|(subscribed, _)| **subscribed != *filter);
+    }
+}
+
+fn owned_filter(filter: &TopicFilterRef) -> TopicFilter {
+    // Checked when the reference was created
+    unsafe { TopicFilter::new_unchecked(&filter[..]) }
+}
+
+/// Storage of sessions by client identifier
+///
+/// Every change is saved as it happens, so a session can be loaded again after the process stops
+/// at any point.
+pub trait SessionStore {
+    /// Session of `client_id`, if any was saved
+    fn load(&self, client_id: &str) -> io::Result<Option<StoredSession>>;
+
+    /// Saves a QoS 1 or 2 `PUBLISH` sent and waiting for acknowledgement
+    fn save_publish(&mut self, client_id: &str, packet: &PublishPacket) -> io::Result<()>;
+
+    /// Replaces the `PUBLISH` of a QoS 2 message by its `PUBREL`, after receiving `PUBREC`
+    fn save_pubrel(&mut self, client_id: &str, pkid: u16) -> io::Result<()>;
+
+    /// Forgets a message after its `PUBACK` or `PUBCOMP`
+    fn remove_in_flight(&mut self, client_id: &str, pkid: u16) -> io::Result<()>;
+
+    /// Saves a QoS 2 message received and acknowledged with `PUBREC`
+    fn save_received(&mut self, client_id: &str, pkid: u16) -> io::Result<()>;
+
+    /// Forgets a received QoS 2 message after its `PUBREL`
+    fn remove_received(&mut self, client_id: &str, pkid: u16) -> io::Result<()>;
+
+    /// Saves a subscription, replacing the QoS of an existing one
+    fn save_subscription(&mut self, client_id: &str, filter: &TopicFilterRef, qos: QualityOfService)
+                         -> io::Result<()>;
+
+    fn remove_subscription(&mut self, client_id: &str, filter: &TopicFilterRef) -> io::Result<()>;
+
+    /// Forgets the whole session, as when a client connects with clean session
+    fn remove(&mut self, client_id: &str) -> io::Result<()>;
+}
+
+impl fmt::Debug for dyn SessionStore + Send {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SessionStore")
+    }
+}
+
+/// Sessions kept in memory for the lifetime of the process
+#[derive(Debug, Clone, Default)]
+pub struct MemorySessionStore {
+    sessions: HashMap<String, StoredSession>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> MemorySessionStore {
+        MemorySessionStore::default()
+    }
+
+    fn session(&mut self, client_id: &str) -> &mut StoredSession {
+        self.sessions.entry(client_id.to_owned()).or_default()
+    }
+}
+
+impl SessionStore for MemorySessionStore {
+    fn load(&self, client_id: &str) -> io::Result<Option<StoredSession>> {
+        Ok(self.sessions.get(client_id).cloned())
+    }
+
+    fn save_publish(&mut self, client_id: &str, packet: &PublishPacket) -> io::Result<()> {
+        self.session(client_id).save_publish(packet);
+        Ok(())
+    }
+
+    fn save_pubrel(&mut self, client_id: &str, pkid: u16) -> io::Result<()> {
+        self.session(client_id).save_pubrel(pkid);
+        Ok(())
+    }
+
+    fn remove_in_flight(&mut self, client_id: &str, pkid: u16) -> io::Result<()> {
+        if let Some(session) = self.sessions.get_mut(client_id) {
+            session.remove_in_flight(pkid);
+        }
+        Ok(())
+    }
+
+    fn save_received(&mut self, client_id: &str, pkid: u16) -> io::Result<()> {
+        self.session(client_id).save_received(pkid);
+        Ok(())
+    }
+
+    fn remove_received(&mut self, client_id: &str, pkid: u16) -> io::Result<()> {
+        if let Some(session) = self.sessions.get_mut(client_id) {
+            session.remove_received(pkid);
+        }
+        Ok(())
+    }
+
+    fn save_subscription(&mut self, client_id: &str, filter: &TopicFilterRef, qos: QualityOfService)
+                         -> io::Result<()> {
+        self.session(client_id).save_subscription(filter, qos);
+        Ok(())
+    }
+
+    fn remove_subscription(&mut self, client_id: &str, filter: &TopicFilterRef) -> io::Result<()> {
+        if let Some(session) = self.sessions.get_mut(client_id) {
+            session.remove_subscription(filter);
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, client_id: &str) -> io::Result<()> {
+        self.sessions.remove(client_id);
+        Ok(())
+    }
+}
+
+const RECORD_PUBLISH: u8 = 1;
+const RECORD_PUBREL: u8 = 2;
+const RECORD_REMOVE_IN_FLIGHT: u8 = 3;
+const RECORD_SUBSCRIBE: u8 = 4;
+const RECORD_UNSUBSCRIBE: u8 = 5;
+const RECORD_REMOVE: u8 = 6;
+const RECORD_RECEIVED: u8 = 7;
+const RECORD_REMOVE_RECEIVED: u8 = 8;
+
+/// Sessions saved in an append-only file
+///
+/// Every change is appended to the file as a record: its length on four bytes, a type byte, the
+/// client identifier as an MQTT string, then the `PUBLISH` packet, the packet identifier, or the
+/// topic filter and QoS.
+/// Opening the file replays the records into memory. The file is cut at the first record that is
+/// incomplete, as after a crash, or cannot be read; the records after it are lost.
+/// `compact` rewrites the file with only the current sessions.
+#[derive(Debug)]
+pub struct FileSessionStore {
+    path: PathBuf,
+    file: File,
+    sessions: MemorySessionStore,
+}
+
+impl FileSessionStore {
+    /// Opens or creates the file at `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileSessionStore> {
+        let path = path.as_ref().to_owned();
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        let mut sessions = MemorySessionStore::new();
+        let mut valid = 0;
+        while buf.len() - valid >= 4 {
+            let len = (&buf[valid..]).read_u32::<BigEndian>()? as usize;
+            if buf.len() - valid - 4 < len {
+                break;
+            }
+            // A record is decoded entirely before it is applied
+            if replay(&mut &buf[valid + 4..valid + 4 + len], &mut sessions).is_err() {
+                break;
+            }
+            valid += 4 + len;
+        }
+
+        // Appends after the last valid record
+        file.set_len(valid as u64)?;
+        file.seek(SeekFrom::Start(valid as u64))?;
+        Ok(FileSessionStore { path, file, sessions })
+    }
+
+    /// Rewrites the file with one record per saved item
+    ///
+    /// The records are written to a new file next to it, which then replaces it, so the sessions
+    /// are not lost if the process stops meanwhile.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let mut buf = Vec::new();
+        for (client_id, session) in &self.sessions.sessions {
+            for &(ref filter, qos) in &session.subscriptions {
+                let mut rec = record(RECORD_SUBSCRIBE, client_id)?;
+                encode(filter, &mut rec)?;
+                rec.write_u8(qos as u8)?;
+                frame(&mut buf, &rec)?;
+            }
+            for message in &session.in_flight {
+                let rec = match *message {
+                    InFlightMessage::Publish(ref publish) => {
+                        let mut rec = record(RECORD_PUBLISH, client_id)?;
+                        encode(publish, &mut rec)?;
+                        rec
+                    }
+                    InFlightMessage::Release(pkid) => {
+                        let mut rec = record(RECORD_PUBREL, client_id)?;
+                        rec.write_u16::<BigEndian>(pkid)?;
+                        rec
+                    }
+                };
+                frame(&mut buf, &rec)?;
+            }
+            for &pkid in &session.received {
+                let mut rec = record(RECORD_RECEIVED, client_id)?;
+                rec.write_u16::<BigEndian>(pkid)?;
+                frame(&mut buf, &rec)?;
+            }
+        }
+
+        let mut compacted = self.path.clone().into_os_string();
+        compacted.push(".compact");
+        let mut file = File::create(&compacted)?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        fs::rename(&compacted, &self.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+
+    fn append(&mut self, rec: &[u8]) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(4 + rec.len());
+        frame(&mut buf, rec)?;
+        // A single write, so a crash leaves at most one partial record at the end
+        self.file.write_all(&buf)
+    }
+}
+
+impl SessionStore for FileSessionStore {
+    fn load(&self, client_id: &str) -> io::Result<Option<StoredSession>> {
+        self.sessions.load(client_id)
+    }
+
+    fn save_publish(&mut self, client_id: &str, packet: &PublishPacket) -> io::Result<()> {
+        let mut buf = record(RECORD_PUBLISH, client_id)?;
+        encode(packet, &mut buf)?;
+        self.append(&buf)?;
+        self.sessions.save_publish(client_id, packet)
+    }
+
+    fn save_pubrel(&mut self, client_id: &str, pkid: u16) -> io::Result<()> {
+        let mut buf = record(RECORD_PUBREL, client_id)?;
+        buf.write_u16::<BigEndian>(pkid)?;
+        self.append(&buf)?;
+        self.sessions.save_pubrel(client_id, pkid)
+    }
+
+    fn remove_in_flight(&mut self, client_id: &str, pkid: u16) -> io::Result<()> {
+        let mut buf = record(RECORD_REMOVE_IN_FLIGHT, client_id)?;
+        buf.write_u16::<BigEndian>(pkid)?;
+        self.append(&buf)?;
+        self.sessions.remove_in_flight(client_id, pkid)
+    }
+
+    fn save_received(&mut self, client_id: &str, pkid: u16) -> io::Result<()> {
+        let mut buf = record(RECORD_RECEIVED, client_id)?;
+        buf.write_u16::<BigEndian>(pkid)?;
+        self.append(&buf)?;
+        self.sessions.save_received(client_id, pkid)
+    }
+
+    fn remove_received(&mut self, client_id: &str, pkid: u16) -> io::Result<()> {
+        let mut buf = record(RECORD_REMOVE_RECEIVED, client_id)?;
+        buf.write_u16::<BigEndian>(pkid)?;
+        self.append(&buf)?;
+        self.sessions.remove_received(client_id, pkid)
+    }
+
+    fn save_subscription(&mut self, client_id: &str, filter: &TopicFilterRef, qos: QualityOfService)
+                         -> io::Result<()> {
+        let mut buf = record(RECORD_SUBSCRIBE, client_id)?;
+        encode(&&filter[..], &mut buf)?;
+        buf.write_u8(qos as u8)?;
+        self.append(&buf)?;
+        self.sessions.save_subscription(client_id, filter, qos)
+    }
+
+    fn remove_subscription(&mut self, client_id: &str, filter: &TopicFilterRef) -> io::Result<()> {
+        let mut buf = record(RECORD_UNSUBSCRIBE, client_id)?;
+        encode(&&filter[..], &mut buf)?;
+        self.append(&buf)?;
+        self.sessions.remove_subscription(client_id, filter)
+    }
+
+    fn remove(&mut self, client_id: &str) -> io::Result<()> {
+        let buf = record(RECORD_REMOVE, client_id)?;
+        self.append(&buf)?;
+        self.sessions.remove(client_id)
+    }
+}
+
+/// Starts a record of `kind` for `client_id`
+fn record(kind: u8, client_id: &str) -> io::Result<Vec<u8>> {
+    let mut buf = vec![kind];
+    encode(&client_id, &mut buf)?;
+    Ok(buf)
+}
+
+/// Writes `rec` to `buf` after its length
+fn frame(buf: &mut Vec<u8>, rec: &[u8]) -> io::Result<()> {
+    buf.write_u32::<BigEndian>(rec.len() as u32)?;
+    buf.extend_from_slice(rec);
+    Ok(())
+}
+
+fn encode<T: Encodable>(value: &T, buf: &mut Vec<u8>) -> io::Result<()> {
+    value.encode(buf).map_err(invalid_data)
+}
+
+fn decode<T: Decodable, R: Read>(reader: &mut R) -> io::Result<T> {
+    T::decode(reader).map_err(invalid_data)
+}
+
+fn invalid_data<E: ::std::error::Error>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+/// Applies a record read from the file
+fn replay<R: Read>(reader: &mut R, sessions: &mut MemorySessionStore) -> io::Result<()> {
+    let kind = reader.read_u8()?;
+    let client_id: String = decode(reader)?;
+    match kind {
+        RECORD_PUBLISH => {
+            let packet: PublishPacket = decode(reader)?;
+            sessions.save_publish(&client_id, &packet)
+        }
+        RECORD_PUBREL => sessions.save_pubrel(&client_id, reader.read_u16::<BigEndian>()?),
+        RECORD_REMOVE_IN_FLIGHT => sessions.remove_in_flight(&client_id, reader.read_u16::<BigEndian>()?),
+        RECORD_RECEIVED => sessions.save_received(&client_id, reader.read_u16::<BigEndian>()?),
+        RECORD_REMOVE_RECEIVED => sessions.remove_received(&client_id, reader.read_u16::<BigEndian>()?),
+        RECORD_SUBSCRIBE => {
+            let filter: TopicFilter = decode(reader)?;
+            let qos = match reader.read_u8()? {
+                0 => QualityOfService::Level0,
+                1 => QualityOfService::Level1,
+                2 => QualityOfService::Level2,
+                qos => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid QoS {}", qos))),
+            };
+            sessions.save_subscription(&client_id, &filter, qos)
+        }
+        RECORD_UNSUBSCRIBE => {
+            let filter: TopicFilter = decode(reader)?;
+            sessions.remove_subscription(&client_id, &filter)
+        }
+        RECORD_REMOVE => sessions.remove(&client_id),
+        kind => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid record type {}", kind))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::env;
+    use std::process;
+
+    use packet::QoSWithPacketIdentifier;
+    use session::{ServerSession, SessionError};
+    use topic_name::TopicName;
+
+    fn publish(pkid: u16) -> PublishPacket {
+        PublishPacket::new(TopicName::new("a/b").unwrap(), QoSWithPacketIdentifier::Level2(pkid), "x")
+    }
+
+    fn filter(filter: &str) -> TopicFilter {
+        TopicFilter::new(filter).unwrap()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("mqtt-session-{}-{}", name, process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn fill<S: SessionStore>(store: &mut S) {
+        store.save_publish("a", &publish(1)).unwrap();
+        store.save_publish("a", &publish(2)).unwrap();
+        store.save_publish("a", &publish(3)).unwrap();
+        store.save_pubrel("a", 2).unwrap();
+        store.remove_in_flight("a", 1).unwrap();
+        store.save_subscription("a", &filter("a/#"), QualityOfService::Level0).unwrap();
+        store.save_subscription("a", &filter("b"), QualityOfService::Level1).unwrap();
+        store.save_subscription("a", &filter("a/#"), QualityOfService::Level2).unwrap();
+        store.remove_subscription("a", &filter("b")).unwrap();
+        store.save_received("a", 4).unwrap();
+        store.save_received("a", 5).unwrap();
+        store.remove_received("a", 4).unwrap();
+        store.save_publish("b", &publish(1)).unwrap();
+        store.remove("b").unwrap();
+    }
+
+    fn expected() -> StoredSession {
+        StoredSession {
+            subscriptions: vec![(filter("a/#"), QualityOfService::Level2)],
+            in_flight: vec![InFlightMessage::Release(2), InFlightMessage::Publish(publish(3))],
+            received: vec![5],
+        }
+    }
+
+    #[test]
+    fn test_memory_session_store() {
+        let mut store = MemorySessionStore::new();
+        fill(&mut store);
+        assert_eq!(store.load("a").unwrap(), Some(expected()));
+        assert_eq!(store.load("b").unwrap(), None);
+    }
+
+    #[test]
+    fn test_file_session_store_survives_reopen() {
+        let path = temp_path("reopen");
+        {
+            let mut store = FileSessionStore::open(&path).unwrap();
+            fill(&mut store);
+            assert_eq!(store.load("a").unwrap(), Some(expected()));
+        }
+
+        let mut store = FileSessionStore::open(&path).unwrap();
+        assert_eq!(store.load("a").unwrap(), Some(expected()));
+        assert_eq!(store.load("b").unwrap(), None);
+
+        let len = fs::metadata(&path).unwrap().len();
+        store.compact().unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < len);
+        store.remove_in_flight("a", 2).unwrap();
+        drop(store);
+
+        let store = FileSessionStore::open(&path).unwrap();
+        let mut session = expected();
+        session.in_flight.remove(0);
+        assert_eq!(store.load("a").unwrap(), Some(session));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_file_session_store_discards_partial_record() {
+        let path = temp_path("partial");
+        {
+            let mut store = FileSessionStore::open(&path).unwrap();
+            store.save_publish("a", &publish(1)).unwrap();
+            store.save_publish("a", &publish(2)).unwrap();
+        }
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 2).unwrap();
+
+        {
+            let mut store = FileSessionStore::open(&path).unwrap();
+            assert_eq!(store.load("a").unwrap().unwrap().in_flight,
+                       vec![InFlightMessage::Publish(publish(1))]);
+            store.save_pubrel("a", 1).unwrap();
+        }
+
+        let store = FileSessionStore::open(&path).unwrap();
+        assert_eq!(store.load("a").unwrap().unwrap().in_flight, vec![InFlightMessage::Release(1)]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_file_session_store_discards_corrupt_record() {
+        let path = temp_path("corrupt");
+        {
+            let mut store = FileSessionStore::open(&path).unwrap();
+            store.save_publish("a", &publish(1)).unwrap();
+        }
+        let len = fs::metadata(&path).unwrap().len();
+        {
+            let mut store = FileSessionStore::open(&path).unwrap();
+            store.save_publish("a", &publish(2)).unwrap();
+            store.save_publish("a", &publish(3)).unwrap();
+        }
+        // Unknown record type in the second record, which is complete
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(len + 4)).unwrap();
+        file.write_all(&[0xff]).unwrap();
+        drop(file);
+
+        {
+            let mut store = FileSessionStore::open(&path).unwrap();
+            assert_eq!(store.load("a").unwrap().unwrap().in_flight,
+                       vec![InFlightMessage::Publish(publish(1))]);
+            assert_eq!(fs::metadata(&path).unwrap().len(), len);
+            store.save_pubrel("a", 1).unwrap();
+        }
+
+        let store = FileSessionStore::open(&path).unwrap();
+        assert_eq!(store.load("a").unwrap().unwrap().in_flight, vec![InFlightMessage::Release(1)]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_server_session_restore_reserves_packet_identifiers() {
+        let mut session = ServerSession::new();
+        session.restore(expected().in_flight).unwrap();
+        assert!(session.is_in_flight(2));
+        assert!(session.is_in_flight(3));
+
+        let mut packet = publish(0);
+        packet.set_qos(QoSWithPacketIdentifier::Level1(0));
+        assert_eq!(session.publish(packet).unwrap(), Some(1));
+        assert_eq!(session.restore(vec![InFlightMessage::Release(3)]),
+                   Err(SessionError::PacketIdentifierInUse(3)));
+    }
+}
@@ -1,27 +1,38 @@
 //! Topic filter
 
-use std::convert::Into;
-use std::error::Error;
-use std::fmt;
-use std::io::{Read, Write};
-use std::mem;
-use std::ops::Deref;
-
-use regex::Regex;
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use core::convert::Into;
+use core::error::Error;
+use core::fmt;
+use core::mem;
+use core::ops::Deref;
+use io::{Read, Write};
 
 use {Decodable, Encodable};
-use encodable::StringEncodeError;
+use decoder::{DecodeLimits, LimitedDecodable};
+use encodable::{StringEncodeError, StringField};
 use topic_name::TopicNameRef;
 
-const VALIDATE_TOPIC_FILTER_REGEX: &'static str = r"^(([^+#]*|\+)(/([^+#]*|\+))*(/#)?|#)$";
-
-lazy_static! {
-    static ref TOPIC_FILTER_VALIDATOR: Regex = Regex::new(VALIDATE_TOPIC_FILTER_REGEX).unwrap();
-}
-
 #[inline]
 fn is_invalid_topic_filter(topic: &str) -> bool {
-    topic.is_empty() || topic.as_bytes().len() > 65535 || !TOPIC_FILTER_VALIDATOR.is_match(&topic)
+    if topic.is_empty() || topic.len() > 65535 || topic.contains('\0') {
+        return true;
+    }
+
+    // `+` must fill a whole level and `#` must fill the last one
+    let mut levels = topic.split('/').peekable();
+    while let Some(level) = levels.next() {
+        let valid = match level {
+            "+" => true,
+            "#" => levels.peek().is_none(),
+            _ => !level.contains(&['#', '+'][..]),
+        };
+        if !valid {
+            return true;
+        }
+    }
+    false
 }
 
 /// Topic filter
@@ -74,8 +85,15 @@ impl Decodable for TopicFilter {
     type Err = TopicFilterError;
     type Cond = ();
 
-    fn decode_with<R: Read>(reader: &mut R, _rest: Option<()>) -> Result<TopicFilter, TopicFilterError> {
-        let topic_filter: String = Decodable::decode(reader)
+    fn decode_with<R: Read>(reader: &mut R, rest: Option<()>) -> Result<TopicFilter, TopicFilterError> {
+        TopicFilter::decode_limited(reader, rest, &DecodeLimits::default())
+    }
+}
+
+impl LimitedDecodable for TopicFilter {
+    fn decode_limited<R: Read>(reader: &mut R, _rest: Option<()>, limits: &DecodeLimits)
+                               -> Result<TopicFilter, TopicFilterError> {
+        let topic_filter = String::decode_with(reader, Some(limits.string_cond(StringField::TopicFilter)))
             .map_err(TopicFilterError::StringEncodeError)?;
         TopicFilter::new(topic_filter)
     }
@@ -274,6 +292,15 @@ mod test {
 
         let topic = "$SYS".to_owned();
         TopicFilter::new(topic).unwrap();
+
+        let topic = "/+//".to_owned();
+        TopicFilter::new(topic).unwrap();
+
+        let topic = "#/".to_owned();
+        assert!(TopicFilter::new(topic).is_err());
+
+        let topic = "++/a".to_owned();
+        assert!(TopicFilter::new(topic).is_err());
     }
 
     #[test]
@@ -1,26 +1,21 @@
 //! Topic name
 
-use std::convert::Into;
-use std::error::Error;
-use std::fmt;
-use std::io::{Read, Write};
-use std::mem;
-use std::ops::Deref;
-
-use regex::Regex;
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use core::convert::Into;
+use core::error::Error;
+use core::fmt;
+use core::mem;
+use core::ops::Deref;
+use io::{Read, Write};
 
 use {Decodable, Encodable};
-use encodable::StringEncodeError;
-
-const TOPIC_NAME_VALIDATE_REGEX: &'static str = r"^[^#+]+$";
-
-lazy_static! {
-    static ref TOPIC_NAME_VALIDATOR: Regex = Regex::new(TOPIC_NAME_VALIDATE_REGEX).unwrap();
-}
+use decoder::{DecodeLimits, LimitedDecodable};
+use encodable::{StringEncodeError, StringField};
 
 #[inline]
 fn is_invalid_topic_name(topic_name: &str) -> bool {
-    topic_name.is_empty() || topic_name.as_bytes().len() > 65535 || !TOPIC_NAME_VALIDATOR.is_match(&topic_name)
+    topic_name.is_empty() || topic_name.len() > 65535 || topic_name.contains(&['\0', '#', '+'][..])
 }
 
 /// Topic name
@@ -79,8 +74,15 @@ impl Decodable for TopicName {
     type Err = TopicNameError;
     type Cond = ();
 
-    fn decode_with<R: Read>(reader: &mut R, _rest: Option<()>) -> Result<TopicName, TopicNameError> {
-        let topic_name: String = Decodable::decode(reader)
+    fn decode_with<R: Read>(reader: &mut R, rest: Option<()>) -> Result<TopicName, TopicNameError> {
+        TopicName::decode_limited(reader, rest, &DecodeLimits::default())
+    }
+}
+
+impl LimitedDecodable for TopicName {
+    fn decode_limited<R: Read>(reader: &mut R, _rest: Option<()>, limits: &DecodeLimits)
+                               -> Result<TopicName, TopicNameError> {
+        let topic_name = String::decode_with(reader, Some(limits.string_cond(StringField::TopicName)))
             .map_err(TopicNameError::StringEncodeError)?;
         TopicName::new(topic_name)
     }
@@ -159,6 +161,8 @@ impl Deref for TopicNameRef {
 mod test {
     use super::*;
 
+    use std::io::Cursor;
+
     #[test]
     fn topic_name_sys() {
         let topic_name = "$SYS".to_owned();
@@ -178,4 +182,18 @@ mod test {
         TopicName::new("/finance").unwrap();
         TopicName::new("/finance//def").unwrap();
     }
+
+    #[test]
+    fn topic_name_null_character() {
+        assert!(TopicName::new("a/\u{0}").is_err());
+
+        let encoded = b"\x00\x03a\x00b";
+        match TopicName::decode(&mut Cursor::new(&encoded[..])) {
+            Err(TopicNameError::StringEncodeError(StringEncodeError::InvalidCodePoint(err))) => {
+                assert_eq!(err.field, Some(StringField::TopicName));
+                assert_eq!(err.position, 1);
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
 }
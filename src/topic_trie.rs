@@ -0,0 +1,230 @@
+//! Subscription matching by topic levels
+//!
+//! `TopicTrie` stores a value per topic filter, split into its levels, so finding the filters that
+//! match a topic name only walks the levels of that name instead of testing every filter.
+//!
+//! ```rust
+//! use mqtt::{TopicFilter, TopicNameRef, TopicTrie};
+//!
+//! let mut trie = TopicTrie::new();
+//! trie.insert(&TopicFilter::new("sport/+/player1").unwrap(), "plus");
+//! trie.insert(&TopicFilter::new("sport/#").unwrap(), "hash");
+//! trie.insert(&TopicFilter::new("sport/tennis").unwrap(), "exact");
+//!
+//! let mut matched: Vec<_> = trie.matches(TopicNameRef::new("sport/tennis/player1").unwrap()).collect();
+//! matched.sort();
+//! assert_eq!(matched, vec![&"hash", &"plus"]);
+//! ```
+
+use std::collections::HashMap;
+
+use topic_filter::TopicFilterRef;
+use topic_name::TopicNameRef;
+
+#[derive(Debug, Clone)]
+struct Node<V> {
+    value: Option<V>,
+    children: HashMap<String, Node<V>>,
+}
+
+impl<V> Node<V> {
+    fn new() -> Node<V> {
+        Node {
+            value: None,
+            children: HashMap::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.value.is_none() && self.children.is_empty()
+    }
+
+    fn remove(&mut self, levels: &[&str]) -> Option<V> {
+        match levels.split_first() {
+            None => self.value.take(),
+            Some((level, rest)) => {
+                let (value, prune) = {
+                    let child = self.children.get_mut(*level)?;
+                    let value = child.remove(rest);
+                    (value, child.is_empty())
+                };
+                if prune {
+                    self.children.remove(*level);
+                }
+                value
+            }
+        }
+    }
+
+    fn collect<'a>(&'a self, levels: &[&str], first: bool, matched: &mut Vec<&'a V>) {
+        let (level, rest) = match levels.split_first() {
+            Some(split) => split,
+            None => {
+                matched.extend(self.value.as_ref());
+                // "sport/#" also matches "sport"
+                if let Some(child) = self.children.get("#") {
+                    matched.extend(child.value.as_ref());
+                }
+                return;
+            }
+        };
+
+        // The Server MUST NOT match Topic Filters starting with a wildcard character (# or +)
+        // with Topic Names beginning with a $ character [MQTT-4.7.2-1].
+        if !(first && level.starts_with('$')) {
+            if let Some(child) = self.children.get("#") {
+                matched.extend(child.value.as_ref());
+            }
+            if let Some(child) = self.children.get("+") {
+                child.collect(rest, false, matched);
+            }
+        }
+
+        if let Some(child) = self.children.get(*level) {
+            child.collect(rest, false, matched);
+        }
+    }
+}
+
+/// Values keyed by topic filter, looked up by the topic names they match
+#[derive(Debug, Clone)]
+pub struct TopicTrie<V> {
+    root: Node<V>,
+    len: usize,
+}
+
+impl<V> TopicTrie<V> {
+    pub fn new() -> TopicTrie<V> {
+        TopicTrie {
+            root: Node::new(),
+            len: 0,
+        }
+    }
+
+    /// Number of topic filters
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Sets the value of `topic_filter`, returning the previous one
+    pub fn insert(&mut self, topic_filter: &TopicFilterRef, value: V) -> Option<V> {
+        let node = topic_filter
+            .split('/')
+            .fold(&mut self.root, |node, level| node.children.entry(level.to_owned()).or_insert_with(Node::new));
+
+        let old = node.value.replace(value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    /// Value of exactly `topic_filter`
+    pub fn get(&self, topic_filter: &TopicFilterRef) -> Option<&V> {
+        topic_filter
+            .split('/')
+            .try_fold(&self.root, |node, level| node.children.get(level))
+            .and_then(|node| node.value.as_ref())
+    }
+
+    /// Mutable value of exactly `topic_filter`
+    pub fn get_mut(&mut self, topic_filter: &TopicFilterRef) -> Option<&mut V> {
+        topic_filter
+            .split('/')
+            .try_fold(&mut self.root, |node, level| node.children.get_mut(level))
+            .and_then(|node| node.value.as_mut())
+    }
+
+    /// Removes `topic_filter`, returning its value
+    pub fn remove(&mut self, topic_filter: &TopicFilterRef) -> Option<V> {
+        let levels: Vec<&str> = topic_filter.split('/').collect();
+        let old = self.root.remove(&levels);
+        if old.is_some() {
+            self.len -= 1;
+        }
+        old
+    }
+
+    /// Values of all topic filters matching `topic_name`
+    pub fn matches<'a>(&'a self, topic_name: &TopicNameRef) -> impl Iterator<Item = &'a V> {
+        let levels: Vec<&str> = topic_name.split('/').collect();
+        let mut matched = Vec::new();
+        self.root.collect(&levels, true, &mut matched);
+        matched.into_iter()
+    }
+}
+
+impl<V> Default for TopicTrie<V> {
+    fn default() -> TopicTrie<V> {
+        TopicTrie::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use topic_filter::TopicFilter;
+
+    fn matched(trie: &TopicTrie<&'static str>, topic_name: &str) -> Vec<&'static str> {
+        let mut matched: Vec<_> = trie.matches(TopicNameRef::new(topic_name).unwrap()).cloned().collect();
+        matched.sort();
+        matched
+    }
+
+    #[test]
+    fn test_topic_trie_matches_like_filter() {
+        let filters = ["#", "+", "+/+", "/+", "sport/#", "sport/+", "sport/tennis/+", "sport/+/player1",
+                       "+/tennis/#", "sport/tennis/player1", "$SYS/#", "$SYS/+/clients", "a//b", "/finance"];
+        let topic_names = ["sport", "sport/", "sport/tennis", "sport/tennis/player1", "sport/tennis/player1/ranking",
+                           "/finance", "finance", "$SYS", "$SYS/broker/clients", "$SYS/monitor", "a//b", "a/b"];
+
+        let mut trie = TopicTrie::new();
+        for filter in filters.iter() {
+            assert_eq!(trie.insert(TopicFilterRef::new(*filter).unwrap(), *filter), None);
+        }
+        assert_eq!(trie.len(), filters.len());
+
+        for topic_name in topic_names.iter() {
+            let mut expected: Vec<_> = filters
+                .iter()
+                .cloned()
+                .filter(|filter| {
+                            TopicFilterRef::new(*filter)
+                                .unwrap()
+                                .get_matcher()
+                                .is_match(TopicNameRef::new(*topic_name).unwrap())
+                        })
+                .collect();
+            expected.sort();
+            assert_eq!(matched(&trie, topic_name), expected, "topic name {}", topic_name);
+        }
+    }
+
+    #[test]
+    fn test_topic_trie_insert_remove() {
+        let mut trie = TopicTrie::new();
+        let filter = TopicFilter::new("a/+/c").unwrap();
+
+        assert_eq!(trie.insert(&filter, "first"), None);
+        assert_eq!(trie.insert(&filter, "second"), Some("first"));
+        assert_eq!(trie.insert(&TopicFilter::new("a/#").unwrap(), "hash"), None);
+        assert_eq!(trie.len(), 2);
+        assert_eq!(trie.get(&filter), Some(&"second"));
+        assert_eq!(trie.get(&TopicFilter::new("a/+").unwrap()), None);
+
+        assert_eq!(trie.remove(&filter), Some("second"));
+        assert_eq!(trie.remove(&filter), None);
+        assert_eq!(trie.len(), 1);
+        assert_eq!(matched(&trie, "a/b/c"), vec!["hash"]);
+
+        *trie.get_mut(&TopicFilter::new("a/#").unwrap()).unwrap() = "changed";
+        assert_eq!(trie.remove(&TopicFilter::new("a/#").unwrap()), Some("changed"));
+        assert!(trie.is_empty());
+        assert!(trie.root.is_empty());
+    }
+}